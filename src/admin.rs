@@ -0,0 +1,397 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::http::uri::Authority;
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::auth::{self, HttpAuth};
+use crate::config::{self, Device};
+use crate::scheduler::{Poller, Pollers};
+
+#[derive(Debug, Serialize)]
+struct DeviceInfo {
+    name: String,
+    url: String,
+}
+
+impl DeviceInfo {
+    async fn of(poller: &Poller) -> Self {
+        Self {
+            name: poller.name().to_string(),
+            url: poller.url().await,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RetargetRequest {
+    /// New device address, `host` or `host:port`
+    host: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AddRequest {
+    /// Value of the `device` label
+    name: String,
+    /// Device address, `host` or `host:port`
+    host: String,
+    /// Product to poll; detected from `/api` when unset
+    product: Option<String>,
+}
+
+#[derive(Clone)]
+struct Admin {
+    pollers: Pollers,
+    /// Config file changes are written back to, with `--admin-persist`
+    persist: Option<Arc<PathBuf>>,
+}
+
+type Rejection = (StatusCode, String);
+
+/// Routes for managing device targets at runtime, all behind `token`.
+/// Changes are written to the config file at `persist` when set.
+pub fn router<S>(pollers: Pollers, token: HttpAuth, persist: Option<PathBuf>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/admin/devices", get(list_devices).post(add_device))
+        .route(
+            "/admin/devices/{name}",
+            put(retarget_device).delete(remove_device),
+        )
+        .route_layer(axum::middleware::from_fn_with_state(
+            Arc::new(token),
+            auth::require_auth,
+        ))
+        .with_state(Admin {
+            pollers,
+            persist: persist.map(Arc::new),
+        })
+}
+
+async fn list_devices(State(admin): State<Admin>) -> Json<Vec<DeviceInfo>> {
+    let pollers = admin.pollers.pollers();
+    let mut devices = Vec::with_capacity(pollers.len());
+    for poller in &pollers {
+        devices.push(DeviceInfo::of(poller).await);
+    }
+    Json(devices)
+}
+
+async fn add_device(
+    State(admin): State<Admin>,
+    Json(request): Json<AddRequest>,
+) -> Result<(StatusCode, Json<DeviceInfo>), Rejection> {
+    parse_host(&request.host)?;
+    let mut target = format!("{}={}", request.name, request.host);
+    if let Some(product) = &request.product {
+        target = format!("{target}/{product}");
+    }
+    let product = admin.pollers.config().and_then(|config| config.device_type);
+    let device = Device::parse_target(&target, product)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e}\n")))?;
+    if admin.pollers.get(&device.name).is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Device {} already exists\n", device.name),
+        ));
+    }
+
+    let poller = admin
+        .pollers
+        .add(device)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}\n")))?;
+    info!(device = %request.name, "Added device through the admin API");
+    admin.changed(
+        &request.name,
+        Some(&request.host),
+        request.product.as_deref(),
+    )?;
+    Ok((StatusCode::CREATED, Json(DeviceInfo::of(&poller).await)))
+}
+
+async fn retarget_device(
+    State(admin): State<Admin>,
+    Path(name): Path<String>,
+    Json(request): Json<RetargetRequest>,
+) -> Result<Json<DeviceInfo>, Rejection> {
+    let poller = admin
+        .pollers
+        .get(&name)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Unknown device {name}\n")))?;
+    parse_host(&request.host)?;
+
+    poller.retarget(&request.host).await;
+    admin.changed(&name, Some(&request.host), None)?;
+    Ok(Json(DeviceInfo::of(&poller).await))
+}
+
+async fn remove_device(
+    State(admin): State<Admin>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, Rejection> {
+    admin
+        .pollers
+        .remove(&name)
+        .await
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Unknown device {name}\n")))?;
+    info!(device = %name, "Removed device through the admin API");
+    admin.changed(&name, None, None)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn parse_host(host: &str) -> Result<(), Rejection> {
+    host.parse::<Authority>().map(drop).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid host {host:?}: {e}\n"),
+        )
+    })
+}
+
+impl Admin {
+    /// Records that `name` now polls `host`, or was removed, so reloads
+    /// keep the change, and writes it to the config file when persisting.
+    /// `product` is only saved for new devices.
+    fn changed(
+        &self,
+        name: &str,
+        host: Option<&str>,
+        product: Option<&str>,
+    ) -> Result<(), Rejection> {
+        self.pollers
+            .set_override(name, host.map(|host| host.to_string()));
+        let Some(path) = &self.persist else {
+            return Ok(());
+        };
+        let persist = || -> anyhow::Result<()> {
+            let specs = match config::read_setting(path, "HOMEWIZARD_HOST")? {
+                Some(value) => value
+                    .split(',')
+                    .map(|spec| spec.trim().to_string())
+                    .collect(),
+                None => self
+                    .pollers
+                    .config()
+                    .map(|config| config.host.clone())
+                    .unwrap_or_default(),
+            };
+            let target = host.map(|host| match product {
+                Some(product) => format!("{host}/{product}"),
+                None => host.to_string(),
+            });
+            let specs = config::set_device_host(&specs, name, target.as_deref());
+            config::save_setting(path, "HOMEWIZARD_HOST", &specs.join(","))
+        };
+        persist().map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Applied, but not saved to the config file: {e:#}\n"),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SharedMetrics;
+    use crate::config::Config;
+    use crate::homewizard::HomeWizardClient;
+    use crate::metrics::{DeviceMetrics, Metrics, MetricsOptions};
+    use crate::scheduler::{Fleet, PollerFactory};
+    use axum::body::Body;
+    use axum::http::Request;
+    use clap::Parser;
+    use std::time::Duration;
+    use tokio::sync::RwLock;
+    use tower::ServiceExt;
+
+    fn poller(config: &Config, device: Device) -> Poller {
+        let client = HomeWizardClient::new(device.url(), Duration::from_secs(5)).unwrap();
+        let metrics = Arc::new(Metrics::with_options(MetricsOptions::default()).unwrap());
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        Poller::new(device.name, client, metrics, output, config.clone())
+    }
+
+    fn fleet() -> Pollers {
+        let config = Config::parse_from(["homewizard-p1-exporter", "--host", "192.168.1.100"]);
+        let device = config.devices().unwrap().remove(0);
+        let house = Arc::new(poller(&config, device));
+        let factory: PollerFactory =
+            Arc::new(|config, device| Box::pin(async move { Ok(poller(&config, device)) }));
+        Arc::new(Fleet::new(vec![house], DeviceMetrics::default()).with_factory(factory, config))
+    }
+
+    fn admin_app() -> Router {
+        router(fleet(), HttpAuth::new(vec!["admin".to_string()]), None)
+    }
+
+    fn request(method: &str, uri: &str, body: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", "Bearer admin")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn retarget(name: &str, body: &str, token: &str) -> Request<Body> {
+        Request::builder()
+            .method("PUT")
+            .uri(format!("/admin/devices/{name}"))
+            .header("Authorization", format!("Bearer {token}"))
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retarget_device() {
+        let response = admin_app()
+            .oneshot(retarget(
+                "192.168.1.100",
+                r#"{"host": "192.168.1.50"}"#,
+                "admin",
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let device: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(device["url"], "http://192.168.1.50/api/v1/data");
+    }
+
+    #[tokio::test]
+    async fn test_retarget_requires_admin_token() {
+        let response = admin_app()
+            .oneshot(retarget(
+                "192.168.1.100",
+                r#"{"host": "192.168.1.50"}"#,
+                "wrong",
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_retarget_rejects_unknown_device_and_bad_host() {
+        let response = admin_app()
+            .oneshot(retarget("other", r#"{"host": "192.168.1.50"}"#, "admin"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = admin_app()
+            .oneshot(retarget(
+                "192.168.1.100",
+                r#"{"host": "http://x/y"}"#,
+                "admin",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_add_and_remove_device() {
+        let fleet = fleet();
+        let app = router(
+            fleet.clone(),
+            HttpAuth::new(vec!["admin".to_string()]),
+            None,
+        );
+        let add = r#"{"name": "water", "host": "192.168.1.60", "product": "watermeter"}"#;
+
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/admin/devices", add))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(
+            fleet.get("water").unwrap().url().await,
+            "http://192.168.1.60/api/v1/data"
+        );
+
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/admin/devices", add))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", "/admin/devices/water", ""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(fleet.get("water").is_none());
+        // Reloads keep the device removed until the config drops it
+        assert!(fleet.overridden("water", Some("192.168.1.60")));
+
+        let response = app
+            .oneshot(request("DELETE", "/admin/devices/water", ""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_persists_changes_to_config_file() {
+        let path = std::env::temp_dir().join(format!(
+            "homewizard-admin-persist-{}.env",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "HOMEWIZARD_HOST=192.168.1.100/p1;interval=5\nPOLL_INTERVAL=10\n",
+        )
+        .unwrap();
+        let app = router(
+            fleet(),
+            HttpAuth::new(vec!["admin".to_string()]),
+            Some(path.clone()),
+        );
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                "/admin/devices/192.168.1.100",
+                r#"{"host": "192.168.1.50"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .oneshot(request(
+                "POST",
+                "/admin/devices",
+                r#"{"name": "water", "host": "192.168.1.60"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            "HOMEWIZARD_HOST=192.168.1.100=192.168.1.50/p1;interval=5,water=192.168.1.60\nPOLL_INTERVAL=10\n"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Networks allowed to reach protected endpoints.
+#[derive(Debug, Clone)]
+pub struct IpAllowlist {
+    networks: Vec<IpNet>,
+}
+
+impl IpAllowlist {
+    /// Parses CIDR entries; a bare address is treated as a single host.
+    pub fn parse(entries: &[String]) -> Result<Self> {
+        let networks = entries
+            .iter()
+            .map(|entry| {
+                entry
+                    .parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                    .with_context(|| format!("Invalid CIDR in allowlist: {entry}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { networks })
+    }
+
+    pub fn allows(&self, addr: IpAddr) -> bool {
+        // Dual-stack listeners report IPv4 clients as IPv4-mapped IPv6.
+        let addr = addr.to_canonical();
+        self.networks.iter().any(|net| net.contains(&addr))
+    }
+}
+
+pub async fn require_allowed_ip(
+    State(allowlist): State<Arc<IpAllowlist>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let allowed = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .is_some_and(|ConnectInfo(addr)| allowlist.allows(addr.ip()));
+
+    if allowed {
+        next.run(request).await
+    } else {
+        (StatusCode::FORBIDDEN, "Forbidden\n").into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowlist(entries: &[&str]) -> IpAllowlist {
+        IpAllowlist::parse(&entries.iter().map(|e| e.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_allows_addresses_in_networks() {
+        let list = allowlist(&["192.168.1.0/24", "127.0.0.1", "fd00::/8"]);
+
+        assert!(list.allows("192.168.1.42".parse().unwrap()));
+        assert!(list.allows("127.0.0.1".parse().unwrap()));
+        assert!(list.allows("fd12::1".parse().unwrap()));
+        assert!(!list.allows("192.168.2.1".parse().unwrap()));
+        assert!(!list.allows("127.0.0.2".parse().unwrap()));
+        assert!(!list.allows("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_allows_ipv4_mapped_addresses() {
+        let list = allowlist(&["10.0.0.0/8"]);
+
+        assert!(list.allows("::ffff:10.1.2.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_entries() {
+        let result = IpAllowlist::parse(&["192.168.1.0/33".to_string()]);
+        assert!(result.is_err());
+
+        let result = IpAllowlist::parse(&["prometheus.local".to_string()]);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Credentials accepted on protected endpoints: static bearer tokens and
+/// HTTP basic auth users.
+#[derive(Debug, Clone, Default)]
+pub struct HttpAuth {
+    tokens: Vec<String>,
+    /// Base64 encoded `username:password` pairs, as sent by clients
+    basic: Vec<String>,
+}
+
+impl HttpAuth {
+    pub fn new(tokens: Vec<String>) -> Self {
+        Self {
+            tokens,
+            basic: Vec::new(),
+        }
+    }
+
+    /// Also accepts HTTP basic auth with `username` and `password`.
+    pub fn with_basic(mut self, username: &str, password: &str) -> Self {
+        self.basic
+            .push(BASE64.encode(format!("{username}:{password}")));
+        self
+    }
+
+    /// Builds the credentials from an optional inline token, an optional
+    /// tokens file and an optional basic auth user. Returns `None` when none
+    /// is configured.
+    pub fn from_sources(
+        token: Option<&str>,
+        tokens_file: Option<&Path>,
+        basic: Option<(&str, &str)>,
+    ) -> Result<Option<Self>> {
+        let mut tokens = Vec::new();
+
+        if let Some(token) = token.filter(|t| !t.is_empty()) {
+            tokens.push(token.to_string());
+        }
+
+        if let Some(path) = tokens_file {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read tokens file {}", path.display()))?;
+            tokens.extend(parse_tokens(&contents));
+        }
+
+        let mut auth = Self::new(tokens);
+        if let Some((username, password)) = basic {
+            auth = auth.with_basic(username, password);
+        }
+        Ok((!auth.tokens.is_empty() || !auth.basic.is_empty()).then_some(auth))
+    }
+
+    pub fn authorize(&self, headers: &HeaderMap) -> bool {
+        let Some(value) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+        else {
+            return false;
+        };
+        let (accepted, presented) = if let Some(token) = value.strip_prefix("Bearer ") {
+            (&self.tokens, token)
+        } else if let Some(credentials) = value.strip_prefix("Basic ") {
+            (&self.basic, credentials)
+        } else {
+            return false;
+        };
+
+        // Check every credential so the response time does not reveal which
+        // one (if any) matched.
+        accepted.iter().fold(false, |ok, expected| {
+            constant_time_eq(expected, presented) | ok
+        })
+    }
+
+    /// `WWW-Authenticate` challenges for the configured schemes.
+    fn challenges(&self) -> impl Iterator<Item = HeaderValue> {
+        let bearer = (!self.tokens.is_empty()).then_some(HeaderValue::from_static("Bearer"));
+        let basic = (!self.basic.is_empty()).then_some(HeaderValue::from_static(
+            "Basic realm=\"homewizard-p1-exporter\"",
+        ));
+        bearer.into_iter().chain(basic)
+    }
+}
+
+/// One token per line; blank lines and `#` comments are ignored.
+fn parse_tokens(contents: &str) -> impl Iterator<Item = String> + '_ {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub async fn require_auth(
+    State(auth): State<Arc<HttpAuth>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if auth.authorize(request.headers()) {
+        return next.run(request).await;
+    }
+
+    let mut response = (StatusCode::UNAUTHORIZED, "Unauthorized\n").into_response();
+    for challenge in auth.challenges() {
+        response
+            .headers_mut()
+            .append(header::WWW_AUTHENTICATE, challenge);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers_with(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_authorize_accepts_configured_tokens() {
+        let auth = HttpAuth::new(vec!["first".to_string(), "second".to_string()]);
+
+        assert!(auth.authorize(&headers_with("Bearer first")));
+        assert!(auth.authorize(&headers_with("Bearer second")));
+    }
+
+    #[test]
+    fn test_authorize_rejects_missing_or_wrong_tokens() {
+        let auth = HttpAuth::new(vec!["secret".to_string()]);
+
+        assert!(!auth.authorize(&HeaderMap::new()));
+        assert!(!auth.authorize(&headers_with("Bearer wrong")));
+        assert!(!auth.authorize(&headers_with("Bearer secre")));
+        assert!(!auth.authorize(&headers_with("Basic secret")));
+        assert!(!auth.authorize(&headers_with("secret")));
+    }
+
+    #[test]
+    fn test_authorize_basic_auth() {
+        let auth = HttpAuth::from_sources(None, None, Some(("prometheus", "s3cret")))
+            .unwrap()
+            .unwrap();
+
+        // "prometheus:s3cret" and "prometheus:wrong"
+        assert!(auth.authorize(&headers_with("Basic cHJvbWV0aGV1czpzM2NyZXQ=")));
+        assert!(!auth.authorize(&headers_with("Basic cHJvbWV0aGV1czp3cm9uZw==")));
+        // Basic credentials are not accepted as a bearer token
+        assert!(!auth.authorize(&headers_with("Bearer cHJvbWV0aGV1czpzM2NyZXQ=")));
+        assert_eq!(auth.challenges().count(), 1);
+    }
+
+    #[test]
+    fn test_parse_tokens_skips_comments_and_blank_lines() {
+        let tokens: Vec<String> =
+            parse_tokens("# scrape proxies\nalpha\n\n  beta  \n# old\n").collect();
+        assert_eq!(tokens, vec!["alpha", "beta"]);
+    }
+
+    #[test]
+    fn test_from_sources_without_tokens() {
+        assert!(HttpAuth::from_sources(None, None, None).unwrap().is_none());
+        assert!(
+            HttpAuth::from_sources(Some(""), None, None)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_from_sources_missing_file() {
+        let result = HttpAuth::from_sources(None, Some(Path::new("/nonexistent/tokens")), None);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,324 @@
+//! `check` subcommand: a connectivity self-test that validates the
+//! configuration and walks every device from name resolution to a reading,
+//! explaining what is misconfigured.
+
+use anyhow::Result;
+use axum::http::uri::Authority;
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, Device};
+use crate::homewizard::{DeviceInfo, HomeWizardClient, HomeWizardError, ProductType, Source};
+
+/// API v2 arrived with P1 meter firmware 6.00.
+const MIN_V2_FIRMWARE: u32 = 6;
+
+/// Outcome of one check, printed as a line of the report.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Ok(String),
+    Warn(String),
+    Fail(String),
+}
+
+/// Runs every check and prints the report, returning 0 when nothing
+/// failed and 1 otherwise.
+pub async fn run(config: &Config) -> i32 {
+    let mut failed = false;
+    let mut report = |outcome: Outcome| {
+        let line = match &outcome {
+            Outcome::Ok(message) => format!("  OK    {message}"),
+            Outcome::Warn(message) => format!("  WARN  {message}"),
+            Outcome::Fail(message) => format!("  FAIL  {message}"),
+        };
+        failed |= matches!(outcome, Outcome::Fail(_));
+        println!("{line}");
+    };
+
+    println!("Configuration");
+    let devices = match validate(config) {
+        Ok(devices) => {
+            report(Outcome::Ok(format!(
+                "{} device(s) configured",
+                devices.len()
+            )));
+            devices
+        }
+        Err(e) => {
+            report(Outcome::Fail(format!("{e:#}")));
+            return 1;
+        }
+    };
+
+    for device in &devices {
+        println!("Device {} ({})", device.name, device.host);
+        for outcome in check_device(config, device).await {
+            report(outcome);
+        }
+    }
+    i32::from(failed)
+}
+
+/// Everything the exporter validates at startup.
+fn validate(config: &Config) -> Result<Vec<Device>> {
+    config.validate_output()?;
+    config.validate_sources()?;
+    config.static_labels()?;
+    config.probe_labels()?;
+    config.metric_names()?;
+    config.sensor_names()?;
+    config.retry_policy()?;
+    config.influx_target()?;
+    config.remote_write_headers()?;
+    config.wal("remote_write")?;
+    config.metrics_bind_address()?;
+    config.tls_server_config()?;
+    config.readiness_policy()?;
+    config.fuse_limit()?;
+    config.overload_policy()?;
+    config.contract()?;
+    config.net_metering()?;
+    config.weather_location()?;
+    config.failover_lease()?;
+    config.devices()
+}
+
+async fn check_device(config: &Config, device: &Device) -> Vec<Outcome> {
+    let mut outcomes = Vec::new();
+    match resolve(&device.host).await {
+        Ok(addresses) => outcomes.push(Outcome::Ok(format!("resolves to {addresses}"))),
+        Err(e) => {
+            outcomes.push(Outcome::Fail(format!("cannot resolve the host: {e}")));
+            return outcomes;
+        }
+    }
+
+    let mut client = match HomeWizardClient::new(device.url(), config.device_http_timeout(device)) {
+        Ok(client) => client.parse_mode(config.parse_mode),
+        Err(e) => {
+            outcomes.push(Outcome::Fail(e.to_string()));
+            return outcomes;
+        }
+    };
+    match config.device_api_token(device) {
+        Ok(Some(token)) => client = client.api_v2(device.v2_url(), token),
+        Ok(None) => {}
+        Err(e) => {
+            outcomes.push(Outcome::Fail(e.to_string()));
+            return outcomes;
+        }
+    }
+
+    let started = Instant::now();
+    let info = match client.fetch_device_info().await {
+        Ok(info) => {
+            outcomes.push(Outcome::Ok(format!(
+                "/api: {} ({}), firmware {}, API {} in {}",
+                info.product_name,
+                info.product_type,
+                info.firmware_version,
+                info.api_version,
+                latency(started.elapsed())
+            )));
+            Some(info)
+        }
+        Err(e) => {
+            outcomes.push(Outcome::Fail(format!(
+                "/api: {}; {}",
+                summary(&e),
+                hint(Source::V1, &e)
+            )));
+            None
+        }
+    };
+    if let Some(info) = &info {
+        outcomes.extend(check_product(config, device, info));
+    }
+
+    let product = device
+        .product
+        .or_else(|| info.as_ref().and_then(DeviceInfo::product))
+        .unwrap_or_default();
+    let client = client.product(product);
+    for &source in &config.sources {
+        let started = Instant::now();
+        match client.fetch_from(source).await {
+            Ok(_) => outcomes.push(Outcome::Ok(format!(
+                "{} reading in {}",
+                source.as_str(),
+                latency(started.elapsed())
+            ))),
+            Err(e) => outcomes.push(Outcome::Fail(format!(
+                "{} reading: {}; {}",
+                source.as_str(),
+                summary(&e),
+                hint(source, &e)
+            ))),
+        }
+    }
+    outcomes
+}
+
+/// The device type and firmware against what the configuration expects.
+fn check_product(config: &Config, device: &Device, info: &DeviceInfo) -> Vec<Outcome> {
+    let mut outcomes = Vec::new();
+    match (device.product, info.product()) {
+        (_, None) => outcomes.push(Outcome::Warn(format!(
+            "{} is not a device this exporter knows; it is polled as {}",
+            info.product_type,
+            product_name(device.product.unwrap_or_default())
+        ))),
+        (Some(configured), Some(actual)) if configured != actual => {
+            outcomes.push(Outcome::Fail(format!(
+                "configured as {} but the device is {}; fix --device-type or the /product suffix",
+                product_name(configured),
+                product_name(actual)
+            )))
+        }
+        _ => {}
+    }
+
+    let major = info
+        .firmware_version
+        .split('.')
+        .next()
+        .and_then(|major| major.parse::<u32>().ok());
+    if config.sources.contains(&Source::V2)
+        && info.product() == Some(ProductType::P1)
+        && major.is_some_and(|major| major < MIN_V2_FIRMWARE)
+    {
+        outcomes.push(Outcome::Fail(format!(
+            "firmware {} is too old for API v2 (needs {MIN_V2_FIRMWARE}.00 or newer); update the meter or use --sources v1",
+            info.firmware_version
+        )));
+    }
+    outcomes
+}
+
+fn product_name(product: ProductType) -> String {
+    clap::ValueEnum::to_possible_value(&product)
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// The addresses `host` resolves to, comma-separated.
+async fn resolve(host: &str) -> Result<String> {
+    let authority: Authority = host.parse()?;
+    let name = authority
+        .host()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = authority.port_u16().unwrap_or(80);
+    let addresses: Vec<String> = tokio::net::lookup_host((name, port))
+        .await?
+        .map(|address| address.ip().to_string())
+        .collect();
+    Ok(addresses.join(", "))
+}
+
+fn latency(elapsed: Duration) -> String {
+    format!("{} ms", elapsed.as_millis())
+}
+
+/// The first line of `error`, leaving out response bodies.
+fn summary(error: &HomeWizardError) -> String {
+    error
+        .to_string()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// What most likely causes `error` when reading `source`.
+fn hint(source: Source, error: &HomeWizardError) -> &'static str {
+    let message = error.to_string();
+    let status = |code: &str| message.contains(&format!("HTTP status: {code}"));
+    match (error.class(), source) {
+        ("timeout", _) => "the device did not answer within --http-timeout",
+        ("connection", Source::V2) => {
+            "API v2 is served over HTTPS; check the address and that the firmware supports API v2"
+        }
+        ("connection", _) => "check the address and that the device is on the network",
+        (_, Source::V2) if status("401") || status("403") => {
+            "the API token was rejected; create one with `pair`"
+        }
+        (_, Source::V2) if message.contains("needs an API token") => {
+            "set --api-token, or create one with `pair`"
+        }
+        (_, Source::V1) if status("403") => {
+            "the local API is disabled; enable it in the HomeWizard Energy app"
+        }
+        (_, Source::Telegram) if status("404") => "only the P1 meter serves telegrams",
+        (_, _) if status("404") => {
+            "the device does not serve this endpoint; is it the right device type?"
+        }
+        ("parse", _) => "unexpected response; check --device-type or the /product suffix",
+        _ => "see the error above",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn info(product_type: &str, firmware_version: &str) -> DeviceInfo {
+        DeviceInfo {
+            product_type: product_type.to_string(),
+            product_name: String::new(),
+            serial: String::new(),
+            firmware_version: firmware_version.to_string(),
+            api_version: "v1".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_check_product() {
+        let config = Config::parse_from(["homewizard-p1-exporter", "--sources", "v2,v1"]);
+        let watermeter: Device = "192.168.1.10/watermeter".parse().unwrap();
+        let p1: Device = "192.168.1.10".parse().unwrap();
+
+        let outcomes = check_product(&config, &watermeter, &info("HWE-P1", "6.02"));
+        assert!(
+            matches!(&outcomes[..], [Outcome::Fail(message)] if message.contains("configured as watermeter but the device is p1"))
+        );
+
+        let outcomes = check_product(&config, &p1, &info("HWE-P1", "5.18"));
+        assert!(
+            matches!(&outcomes[..], [Outcome::Fail(message)] if message.contains("too old for API v2"))
+        );
+
+        assert!(check_product(&config, &p1, &info("HWE-P1", "6.02")).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_device_with_local_api_disabled() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"product_type": "HWE-P1", "product_name": "P1 meter", "serial": "3c39e7aabbcc", "firmware_version": "5.18", "api_version": "v1"}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&mock_server)
+            .await;
+
+        let host = mock_server.address().to_string();
+        let config = Config::parse_from(["homewizard-p1-exporter", "--host", &host]);
+        let outcomes = check_device(&config, &config.devices().unwrap()[0]).await;
+
+        assert!(
+            matches!(&outcomes[0], Outcome::Ok(message) if message.starts_with("resolves to 127.0.0.1"))
+        );
+        assert!(matches!(&outcomes[1], Outcome::Ok(message) if message.contains("firmware 5.18")));
+        assert!(
+            matches!(&outcomes[2], Outcome::Fail(message) if message.contains("the local API is disabled"))
+        );
+    }
+}
@@ -1,80 +1,2395 @@
-use clap::Parser;
+use anyhow::{Context, Result, bail, ensure};
+use chrono_tz::Tz;
+use clap::builder::BoolishValueParser;
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::cost::Contract;
+use crate::discover::DiscoverFormat;
+use crate::fetch::FetchFormat;
+use crate::fuse::{FuseLimit, FuseRating, OverloadPolicy};
+use crate::graphite::GraphiteProtocol;
+use crate::homewizard::{ParseMode, ProductType, SmrCapabilities, Source};
+use crate::influx::InfluxTarget;
+use crate::leader::LeaseFile;
+use crate::logfile::{LogRotation, RotationPolicy};
+use crate::metrics::{DEFAULT_METRIC_PREFIX, MetricNames, SensorFilter};
+use crate::mqtt::MqttSettings;
+use crate::netmetering::{self, NetMeteringConfig};
+use crate::readiness::ReadinessPolicy;
+use crate::retry::{CircuitBreaker, RetryPolicy};
+use crate::statsd::StatsdFormat;
+use crate::tls;
+use crate::wal::Wal;
+
+/// Poll interval used until the meter's SMR version is known.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How external water meter readings are exported.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaterMode {
+    /// Cumulative volume counter in m³
+    #[default]
+    Volume,
+    /// Instantaneous flow gauge in L/min
+    Flow,
+    /// Both, deriving whichever the meter does not report
+    Both,
+}
+
+impl WaterMode {
+    pub fn exports_volume(self) -> bool {
+        matches!(self, Self::Volume | Self::Both)
+    }
+
+    pub fn exports_flow(self) -> bool {
+        matches!(self, Self::Flow | Self::Both)
+    }
+}
+
+/// Where the rendered metrics go.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Serve `/metrics` and the other endpoints over HTTP
+    #[default]
+    Http,
+    /// Only write `--textfile-output`; no listening socket
+    Textfile,
+    /// Run as a Telegraf `execd` input, writing line protocol to stdout
+    Execd,
+}
+
+/// How log lines are written.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line with timestamp, level, target and fields
+    Json,
+}
+
+/// Where log lines go.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogTarget {
+    /// Standard output (standard error with `--output execd`), or
+    /// `--log-file` when set
+    #[default]
+    Stdout,
+    /// The systemd journal's native protocol, with structured fields
+    Journald,
+    /// The local syslog daemon at `/dev/log`
+    Syslog,
+}
+
+/// What triggers a poll of the devices.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScrapeMode {
+    /// Poll on a fixed interval and serve the latest reading
+    #[default]
+    Interval,
+    /// Poll when `/metrics` is scraped, reusing readings younger than
+    /// `--scrape-cache-ttl`
+    OnDemand,
+}
+
+/// How Telegraf asks an `execd` input for data (its `signal` setting).
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecdSignal {
+    /// Emit a line after every poll
+    #[default]
+    None,
+    /// Emit the latest reading whenever Telegraf writes a line to stdin
+    Stdin,
+}
+
+/// Tools run instead of the exporter.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Poll the devices once, print the result and exit: 0 when every
+    /// device was fetched, 1 when one failed, 2 on invalid configuration
+    Fetch {
+        #[arg(long, value_enum, default_value_t = FetchFormat::Prom)]
+        format: FetchFormat,
+    },
+    /// Find HomeWizard devices on the local network through mDNS and print
+    /// the `--host` value for each
+    Discover {
+        #[arg(long, value_enum, default_value_t = DiscoverFormat::Table)]
+        format: DiscoverFormat,
+        /// Seconds to wait for devices to answer
+        #[arg(long, default_value = "5")]
+        timeout: u64,
+    },
+    /// Test the configuration and the connection to every device, explaining
+    /// what is wrong; exits 1 when a check fails
+    Check,
+    /// Create an API v2 token for `--host`: press the device's button when
+    /// asked. The token is printed, or stored in `--config-file` with
+    /// `--save`
+    Pair {
+        /// User name the token is registered under, as `local/<name>`
+        #[arg(long, default_value = "homewizard-p1-exporter")]
+        name: String,
+        /// Seconds to wait for the button
+        #[arg(long, default_value = "60")]
+        timeout: u64,
+        /// Write `HOMEWIZARD_API_TOKEN` to `--config-file`
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print a Grafana dashboard for the exported metrics, with a variable
+    /// to select the devices
+    GrafanaDashboard,
+    /// Inspect the configuration format
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Print the completion script for `shell`, e.g.
+    /// `homewizard-p1-exporter completions bash > /etc/bash_completion.d/homewizard-p1-exporter`
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Print a JSON Schema of the `--config-file` settings, for editors and
+    /// CI to validate config files against
+    Schema,
+}
+
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub struct Config {
-    /// HomeWizard P1 Meter IP address or hostname
-    #[arg(long, env = "HOMEWIZARD_HOST")]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// HomeWizard device IP address or hostname; repeatable to poll several
+    /// devices. `name=host` sets the `device` label, which otherwise is the
+    /// host, and a `/watermeter`, `/energy-socket`, `/kwh-meter` or
+    /// `/plugin-battery` suffix polls that product instead of
+    /// `--device-type`. `;token=...` or `;token_file=path` after the spec
+    /// sets this device's API v2 token instead of `--api-token`, and
+    /// `;interval=seconds` and `;timeout=seconds` its poll interval and
+    /// HTTP timeout. Required
+    #[arg(long, env = "HOMEWIZARD_HOST", value_delimiter = ',')]
+    pub host: Vec<String>,
+
+    /// File of `NAME=value` lines using the environment variable names of
+    /// these options, re-read on SIGHUP. Its settings override the
+    /// environment; command line options override both
+    #[arg(long, env = "CONFIG_FILE")]
+    pub config_file: Option<PathBuf>,
+
+    /// Constant `name=value` label added to every metric, e.g. `site=attic`;
+    /// repeatable. Tells exporters apart when one Prometheus scrapes
+    /// several houses
+    #[arg(long = "label", env = "HOMEWIZARD_LABELS", value_delimiter = ',')]
+    pub labels: Vec<String>,
+
+    /// Product polled at hosts without a `/product` suffix. Detected from
+    /// the device's `/api` endpoint when unset
+    #[arg(long, env = "HOMEWIZARD_DEVICE_TYPE", value_enum)]
+    pub device_type: Option<ProductType>,
+
+    /// Port to expose Prometheus metrics on
+    #[arg(long, env = "METRICS_PORT", default_value = "9898")]
+    pub port: u16,
+
+    /// Address to expose Prometheus metrics on: `0.0.0.0` for all IPv4
+    /// interfaces, `::` (or `[::]`) for IPv6 and, on most systems, IPv4 too,
+    /// or a loopback address such as `127.0.0.1` to only serve local clients
+    #[arg(long, env = "BIND_ADDRESS", default_value = "0.0.0.0")]
+    pub bind_address: String,
+
+    /// PEM certificate chain to serve HTTPS with; requires `--tls-key`
+    #[arg(long, env = "TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of `--tls-cert`
+    #[arg(long, env = "TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM bundle of CAs whose client certificates may connect; clients
+    /// without one are refused during the TLS handshake
+    #[arg(long, env = "TLS_CLIENT_CA", requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Interval in seconds between polling the HomeWizard API. Defaults to
+    /// the meter's telegram interval (1s for SMR 5, 10s for SMR 4)
+    #[arg(long, env = "POLL_INTERVAL")]
+    pub poll_interval: Option<u64>,
+
+    /// Log level (trace, debug, info, warn, error)
+    #[arg(long, env = "LOG_LEVEL", default_value = "info")]
+    pub log_level: String,
+
+    /// Log line format: `text`, or `json` for log pipelines such as Loki
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Where logs go: `stdout`, `journald` or `syslog`, both of which map
+    /// log levels to priorities
+    #[arg(long, env = "LOG_TARGET", value_enum, default_value_t = LogTarget::Stdout)]
+    pub log_target: LogTarget,
+
+    /// Write logs to this file instead of stdout, rotating it by size and/or
+    /// time
+    #[arg(long, env = "LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// Size in MB the log file may reach before it is rotated; 0 disables
+    /// size-based rotation
+    #[arg(long, env = "LOG_MAX_SIZE", default_value = "10")]
+    pub log_max_size: u64,
+
+    /// Also rotate the log file every hour or day
+    #[arg(long, env = "LOG_ROTATION", value_enum, default_value_t = LogRotation::Never)]
+    pub log_rotation: LogRotation,
+
+    /// Number of rotated log files kept next to the current one
+    #[arg(long, env = "LOG_RETENTION", default_value = "5")]
+    pub log_retention: usize,
+
+    /// Bearer token for HomeWizard API v2, required by the `v2` source
+    #[arg(long, env = "HOMEWIZARD_API_TOKEN")]
+    pub api_token: Option<String>,
+
+    /// Timeout in seconds for HTTP requests to HomeWizard
+    #[arg(long, env = "HTTP_TIMEOUT", default_value = "5")]
+    pub http_timeout: u64,
+
+    /// Seconds between reads of `/api` to refresh
+    /// `homewizard_device_info`, e.g. after a firmware update; 0 reads it
+    /// only at startup
+    #[arg(long, env = "DEVICE_INFO_INTERVAL", default_value = "3600")]
+    pub device_info_interval: u64,
+
+    /// Seconds between reads of the device's system endpoint for the cloud
+    /// setting and uptime; 0 disables them
+    #[arg(long, env = "SYSTEM_INTERVAL", default_value = "60")]
+    pub system_interval: u64,
+
+    /// Seconds a gas reading may stay unchanged before it is reported as
+    /// stale. Defaults to two gas update periods for the meter's SMR version
+    #[arg(long, env = "GAS_STALE_THRESHOLD")]
+    pub gas_stale_threshold: Option<u64>,
+
+    /// How to export external water meter readings: as a cumulative volume
+    /// counter, an instantaneous flow gauge, or both
+    #[arg(long, env = "WATER_MODE", value_enum, default_value_t = WaterMode::Volume)]
+    pub water_mode: WaterMode,
+
+    /// Friendly name of an external sensor as `unique_id=name`, exported
+    /// as the `name` label of `homewizard_p1_external_sensor_*`; repeatable.
+    /// Unmapped sensors are named by their ID
+    #[arg(long = "sensor-name", env = "SENSOR_NAMES", value_delimiter = ',')]
+    pub sensor_names: Vec<String>,
+
+    /// External sensors to export, by unique ID or type (e.g.
+    /// `gas_meter`); all when empty
+    #[arg(long, env = "SENSOR_INCLUDE", value_delimiter = ',')]
+    pub sensor_include: Vec<String>,
+
+    /// External sensors not to export, by unique ID or type
+    #[arg(long, env = "SENSOR_EXCLUDE", value_delimiter = ',')]
+    pub sensor_exclude: Vec<String>,
+
+    /// Refuse every request that changes device state (identify, system
+    /// settings, token creation). Pass `--read-only false` to allow them
+    #[arg(long, env = "READ_ONLY", default_value_t = true, action = ArgAction::Set)]
+    pub read_only: bool,
+
+    /// How device JSON that does not match the data model is handled:
+    /// ignored, reported in logs and `homewizard_p1_schema_drift_fields`,
+    /// or treated as a failed poll
+    #[arg(long, env = "PARSE_MODE", value_enum, default_value_t = ParseMode::Lenient)]
+    pub parse_mode: ParseMode,
+
+    /// Export numeric fields in the device JSON that this exporter does not
+    /// know yet as `homewizard_p1_raw_<field>` gauges
+    #[arg(long, env = "RAW_PASSTHROUGH")]
+    pub raw_passthrough: bool,
+
+    /// Prefix of the P1 meter's metric names, replacing `homewizard_p1_`
+    /// (e.g. `p1_` exports `p1_active_power_watts`)
+    #[arg(long, env = "METRIC_PREFIX", default_value = DEFAULT_METRIC_PREFIX)]
+    pub metric_prefix: String,
+
+    /// Rename a metric family, as `old=new`; repeatable. `old` is the name
+    /// the family is otherwise exported under, after `--metric-prefix`
+    #[arg(long = "metric-rename", env = "METRIC_RENAMES", value_delimiter = ',')]
+    pub metric_renames: Vec<String>,
+
+    /// Also export `homewizard_p1_gas_timestamp`, the gas reading's time as
+    /// the DSMR `YYMMDDhhmmss` number, next to
+    /// `homewizard_p1_gas_timestamp_seconds`
+    #[arg(long, env = "GAS_TIMESTAMP_RAW")]
+    pub gas_timestamp_raw: bool,
+
+    /// IANA time zone (e.g. `Europe/Amsterdam`) of the meter's timestamps
+    /// and of daily and monthly rollovers such as cost and net metering.
+    /// Defaults to the host's local time, and to `Europe/Amsterdam` for
+    /// meter timestamps
+    #[arg(long, env = "TIMEZONE")]
+    pub timezone: Option<Tz>,
+
+    /// Blink the device's status light at startup to locate it physically.
+    /// Requires `--read-only false`
+    #[arg(long, env = "IDENTIFY")]
+    pub identify: bool,
+
+    /// Bearer token required on protected endpoints such as `/metrics`
+    #[arg(long, env = "AUTH_TOKEN", alias = "metrics-auth-token")]
+    pub auth_token: Option<String>,
+
+    /// File with accepted bearer tokens, one per line (`#` starts a comment)
+    #[arg(long, env = "AUTH_TOKENS_FILE")]
+    pub auth_tokens_file: Option<PathBuf>,
+
+    /// Username accepted through HTTP basic auth on protected endpoints;
+    /// requires `--basic-auth-password`
+    #[arg(long, env = "BASIC_AUTH_USERNAME", requires = "basic_auth_password")]
+    pub basic_auth_username: Option<String>,
+
+    /// Password of `--basic-auth-username`
+    #[arg(long, env = "BASIC_AUTH_PASSWORD", requires = "basic_auth_username")]
+    pub basic_auth_password: Option<String>,
+
+    /// Network (CIDR) or address allowed to reach protected endpoints such as
+    /// `/metrics`; repeatable. Other clients get 403. Unset allows everyone
+    #[arg(long, env = "ALLOW_CIDR", value_delimiter = ',')]
+    pub allow_cidr: Vec<String>,
+
+    /// Ordered chain of endpoints to read from. When a source fails, the
+    /// next one is tried within the same poll
+    #[arg(
+        long,
+        env = "SOURCES",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "v1"
+    )]
+    pub sources: Vec<Source>,
+
+    /// OTLP/HTTP receiver (such as `http://collector:4318`) the metrics
+    /// are pushed to every `--otlp-interval` seconds
+    #[arg(long, env = "OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Seconds between pushes to `--otlp-endpoint`
+    #[arg(long, env = "OTLP_INTERVAL", default_value = "60")]
+    pub otlp_interval: u64,
+
+    /// Prometheus remote_write endpoint (Grafana Cloud, Mimir,
+    /// VictoriaMetrics) the metrics are pushed to every
+    /// `--remote-write-interval` seconds
+    #[arg(long, env = "REMOTE_WRITE_URL")]
+    pub remote_write_url: Option<String>,
+
+    /// Seconds between pushes to `--remote-write-url`
+    #[arg(long, env = "REMOTE_WRITE_INTERVAL", default_value = "30")]
+    pub remote_write_interval: u64,
+
+    /// Username for basic auth at `--remote-write-url`
+    #[arg(
+        long,
+        env = "REMOTE_WRITE_USERNAME",
+        requires = "remote_write_password"
+    )]
+    pub remote_write_username: Option<String>,
+
+    /// Password or API token for basic auth at `--remote-write-url`
+    #[arg(
+        long,
+        env = "REMOTE_WRITE_PASSWORD",
+        requires = "remote_write_username"
+    )]
+    pub remote_write_password: Option<String>,
+
+    /// Bearer token sent to `--remote-write-url`, instead of basic auth
+    #[arg(
+        long,
+        env = "REMOTE_WRITE_BEARER_TOKEN",
+        conflicts_with = "remote_write_username"
+    )]
+    pub remote_write_bearer_token: Option<String>,
+
+    /// Tenant sent to `--remote-write-url` in the `X-Scope-OrgID` header,
+    /// for multi-tenant Mimir, Cortex or Loki-style receivers
+    #[arg(long, env = "REMOTE_WRITE_TENANT")]
+    pub remote_write_tenant: Option<String>,
+
+    /// Extra `Name=value` header sent to `--remote-write-url`; repeatable
+    #[arg(
+        long = "remote-write-header",
+        env = "REMOTE_WRITE_HEADERS",
+        value_delimiter = ','
+    )]
+    pub remote_write_headers: Vec<String>,
+
+    /// Accept any certificate from `--remote-write-url`, such as a
+    /// self-signed one
+    #[arg(long, env = "REMOTE_WRITE_INSECURE_SKIP_VERIFY")]
+    pub remote_write_insecure_skip_verify: bool,
+
+    /// Graphite (Carbon) host the metrics are sent to every
+    /// `--graphite-interval` seconds, in the plaintext protocol
+    #[arg(long, env = "GRAPHITE_HOST")]
+    pub graphite_host: Option<String>,
+
+    /// Carbon plaintext port
+    #[arg(long, env = "GRAPHITE_PORT", default_value = "2003")]
+    pub graphite_port: u16,
+
+    /// Transport to Carbon
+    #[arg(long, env = "GRAPHITE_PROTOCOL", value_enum, default_value = "tcp")]
+    pub graphite_protocol: GraphiteProtocol,
+
+    /// First component of every metric path
+    #[arg(long, env = "GRAPHITE_PREFIX", default_value = "homewizard")]
+    pub graphite_prefix: String,
+
+    /// Seconds between sends to `--graphite-host`
+    #[arg(long, env = "GRAPHITE_INTERVAL", default_value = "60")]
+    pub graphite_interval: u64,
+
+    /// StatsD or DogStatsD host the metrics are sent to over UDP every
+    /// `--statsd-interval` seconds
+    #[arg(long, env = "STATSD_HOST")]
+    pub statsd_host: Option<String>,
+
+    /// StatsD port
+    #[arg(long, env = "STATSD_PORT", default_value = "8125")]
+    pub statsd_port: u16,
+
+    /// How labels are sent: as DogStatsD tags, or appended to the name
+    #[arg(long, env = "STATSD_FORMAT", value_enum, default_value = "dogstatsd")]
+    pub statsd_format: StatsdFormat,
+
+    /// Prepended to every metric name, separated by a dot
+    #[arg(long, env = "STATSD_PREFIX", default_value = "")]
+    pub statsd_prefix: String,
+
+    /// Extra `key:value` tags sent with every metric (DogStatsD format)
+    #[arg(long, env = "STATSD_TAGS", value_delimiter = ',')]
+    pub statsd_tags: Vec<String>,
+
+    /// Seconds between sends to `--statsd-host`
+    #[arg(long, env = "STATSD_INTERVAL", default_value = "10")]
+    pub statsd_interval: u64,
+
+    /// Prometheus Pushgateway base URL. When set, the metrics are pushed
+    /// after every poll
+    #[arg(long, env = "PUSHGATEWAY_URL")]
+    pub pushgateway_url: Option<String>,
+
+    /// `job` grouping label on the Pushgateway
+    #[arg(
+        long,
+        env = "PUSHGATEWAY_JOB",
+        default_value = "homewizard-p1-exporter"
+    )]
+    pub pushgateway_job: String,
+
+    /// `instance` grouping label on the Pushgateway; leave unset to group by
+    /// job only
+    #[arg(long, env = "PUSHGATEWAY_INSTANCE")]
+    pub pushgateway_instance: Option<String>,
+
+    /// InfluxDB base URL. When set, every reading is written as line
+    /// protocol, to `--influx-bucket` (InfluxDB 2) or `--influx-database`
+    /// (InfluxDB 1)
+    #[arg(long, env = "INFLUX_URL")]
+    pub influx_url: Option<String>,
+
+    /// InfluxDB 1 database
+    #[arg(long, env = "INFLUX_DATABASE")]
+    pub influx_database: Option<String>,
+
+    /// InfluxDB 1 username
+    #[arg(long, env = "INFLUX_USERNAME", requires = "influx_password")]
+    pub influx_username: Option<String>,
+
+    /// InfluxDB 1 password
+    #[arg(long, env = "INFLUX_PASSWORD", requires = "influx_username")]
+    pub influx_password: Option<String>,
+
+    /// InfluxDB 2 organization
+    #[arg(long, env = "INFLUX_ORG")]
+    pub influx_org: Option<String>,
+
+    /// InfluxDB 2 bucket
+    #[arg(long, env = "INFLUX_BUCKET", requires = "influx_org")]
+    pub influx_bucket: Option<String>,
+
+    /// InfluxDB 2 API token
+    #[arg(long, env = "INFLUX_TOKEN")]
+    pub influx_token: Option<String>,
+
+    /// MQTT broker host. When set, every reading is published field by
+    /// field, with Home Assistant discovery config
+    #[arg(long, env = "MQTT_HOST")]
+    pub mqtt_host: Option<String>,
+
+    /// MQTT broker port
+    #[arg(long, env = "MQTT_PORT", default_value = "1883")]
+    pub mqtt_port: u16,
+
+    /// MQTT username
+    #[arg(long, env = "MQTT_USERNAME", requires = "mqtt_password")]
+    pub mqtt_username: Option<String>,
+
+    /// MQTT password
+    #[arg(long, env = "MQTT_PASSWORD", requires = "mqtt_username")]
+    pub mqtt_password: Option<String>,
+
+    /// Readings are published to `<prefix>/<device>/<field>`
+    #[arg(long, env = "MQTT_TOPIC_PREFIX", default_value = "homewizard")]
+    pub mqtt_topic_prefix: String,
+
+    /// Home Assistant MQTT discovery prefix
+    #[arg(long, env = "MQTT_DISCOVERY_PREFIX", default_value = "homeassistant")]
+    pub mqtt_discovery_prefix: String,
+
+    /// Publish Home Assistant MQTT discovery config
+    #[arg(long, env = "MQTT_DISCOVERY", default_value_t = true, action = ArgAction::Set)]
+    pub mqtt_discovery: bool,
+
+    /// Directory where remote_write, InfluxDB and MQTT keep what they fail
+    /// to deliver, replayed in order once the sink is reachable again.
+    /// Unset, undeliverable data is dropped
+    #[arg(long, env = "WAL_DIR")]
+    pub wal_dir: Option<PathBuf>,
+
+    /// Largest size in bytes of each sink's buffer in `--wal-dir`; the
+    /// oldest data is dropped beyond it
+    #[arg(long, env = "WAL_MAX_BYTES", default_value = "16777216")]
+    pub wal_max_bytes: u64,
+
+    /// Buffered data older than this many seconds is dropped
+    #[arg(long, env = "WAL_MAX_AGE", default_value = "86400")]
+    pub wal_max_age: u64,
+
+    /// Grafana base URL. When set, power failures and voltage sags/swells
+    /// are posted as annotations
+    #[arg(long, env = "GRAFANA_URL")]
+    pub grafana_url: Option<String>,
+
+    /// Grafana service account token used to post annotations
+    #[arg(long, env = "GRAFANA_TOKEN")]
+    pub grafana_token: Option<String>,
+
+    /// Tags added to every Grafana annotation, next to the event kind
+    #[arg(
+        long,
+        env = "GRAFANA_ANNOTATION_TAGS",
+        value_delimiter = ',',
+        default_value = "homewizard"
+    )]
+    pub grafana_annotation_tags: Vec<String>,
+
+    /// Seconds of recent polls kept in memory and served at `/api/recent`
+    #[arg(long, env = "RECENT_WINDOW", default_value = "600")]
+    pub recent_window: u64,
+
+    /// Upper bound on the number of polls kept for `/api/recent`
+    #[arg(long, env = "RECENT_MAX_SAMPLES", default_value = "3600")]
+    pub recent_max_samples: usize,
+
+    /// File the recent polls are saved to every minute and restored from on
+    /// startup, so `/api/recent` survives restarts
+    #[arg(long, env = "STATE_FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Bearer token for the admin API (`/admin/...`), which can retarget
+    /// the device at runtime. The admin API is disabled when unset
+    #[arg(long, env = "ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// Write devices added, removed or retargeted through the admin API
+    /// back to `HOMEWIZARD_HOST` in `--config-file`, so they survive a
+    /// restart
+    #[arg(long, env = "ADMIN_PERSIST", requires = "config_file")]
+    pub admin_persist: bool,
+
+    /// Where the rendered metrics go. `textfile` and `execd` skip the HTTP
+    /// server
+    #[arg(long, env = "OUTPUT", value_enum, default_value_t = OutputMode::Http)]
+    pub output: OutputMode,
+
+    /// `on-demand` polls the devices when `/metrics` is scraped instead of
+    /// on `--poll-interval`
+    #[arg(long, env = "SCRAPE_MODE", value_enum, default_value_t = ScrapeMode::Interval)]
+    pub scrape_mode: ScrapeMode,
+
+    /// Seconds a reading is reused for scrapes in `--scrape-mode on-demand`
+    #[arg(long, env = "SCRAPE_CACHE_TTL", default_value = "2")]
+    pub scrape_cache_ttl: u64,
+
+    /// Serve `/probe?target=host[/product]`, which polls an arbitrary
+    /// device per request (blackbox-exporter style)
+    #[arg(long, env = "ENABLE_PROBE")]
+    pub enable_probe: bool,
+
+    /// Label a `/probe` request may set with a `label_<name>=value` query
+    /// parameter, e.g. `site`; repeatable. Other `label_` parameters are
+    /// refused
+    #[arg(long = "probe-label", env = "PROBE_LABELS", value_delimiter = ',')]
+    pub probe_labels: Vec<String>,
+
+    /// Write the metrics atomically to this file after every poll, for
+    /// node_exporter's textfile collector. Required with `--output textfile`
+    #[arg(long, env = "TEXTFILE_OUTPUT")]
+    pub textfile_output: Option<PathBuf>,
+
+    /// Price per imported kWh. Setting any price enables the cost metrics
+    #[arg(long, env = "PRICE_IMPORT_KWH")]
+    pub price_import_kwh: Option<f64>,
+
+    /// Price per imported kWh on one tariff, as `tariff=price` (e.g.
+    /// `2=0.21`); repeatable. Other tariffs use `--price-import-kwh`
+    #[arg(
+        long = "price-import-tariff",
+        env = "PRICE_IMPORT_TARIFFS",
+        value_delimiter = ','
+    )]
+    pub price_import_tariffs: Vec<String>,
+
+    /// Compensation per exported kWh, subtracted from the electricity cost
+    #[arg(long, env = "PRICE_EXPORT_KWH")]
+    pub price_export_kwh: Option<f64>,
+
+    /// Compensation per exported kWh on one tariff, as `tariff=price`;
+    /// repeatable. Other tariffs use `--price-export-kwh`
+    #[arg(
+        long = "price-export-tariff",
+        env = "PRICE_EXPORT_TARIFFS",
+        value_delimiter = ','
+    )]
+    pub price_export_tariffs: Vec<String>,
+
+    /// Price per m³ of gas
+    #[arg(long, env = "PRICE_GAS_M3")]
+    pub price_gas_m3: Option<f64>,
+
+    /// Fixed costs per month (standing charges, network fees, minus any
+    /// fixed tax credit)
+    #[arg(long, env = "FIXED_COST_MONTH")]
+    pub fixed_cost_month: Option<f64>,
+
+    /// Fixed costs per day, for contracts with daily standing charges
+    #[arg(long, env = "FIXED_COST_DAY")]
+    pub fixed_cost_day: Option<f64>,
+
+    /// Capacity tariff per kW per month, charged on the month's highest
+    /// quarter-hour average import power
+    #[arg(long, env = "CAPACITY_PRICE_KW_MONTH")]
+    pub capacity_price_kw_month: Option<f64>,
+
+    /// Lowest peak in kW the capacity tariff is computed on
+    #[arg(long, env = "CAPACITY_MIN_KW", default_value = "0")]
+    pub capacity_min_kw: f64,
+
+    /// First day (`MM-DD`) of the net metering (saldering) contract year.
+    /// Enables the net metering balance metrics
+    #[arg(long, env = "CONTRACT_YEAR_START")]
+    pub contract_year_start: Option<String>,
+
+    /// Import meter total (kWh) at the start of the current contract year,
+    /// from the annual bill. Defaults to the first reading after startup
+    #[arg(long, env = "CONTRACT_YEAR_START_IMPORT_KWH")]
+    pub contract_year_start_import_kwh: Option<f64>,
+
+    /// Export meter total (kWh) at the start of the current contract year
+    #[arg(long, env = "CONTRACT_YEAR_START_EXPORT_KWH")]
+    pub contract_year_start_export_kwh: Option<f64>,
+
+    /// Latitude of the location whose outdoor temperature normalizes gas
+    /// use. Together with `--weather-longitude` enables the degree-day metrics
+    #[arg(long, env = "WEATHER_LATITUDE", allow_hyphen_values = true)]
+    pub weather_latitude: Option<f64>,
+
+    /// Longitude of the weather location
+    #[arg(long, env = "WEATHER_LONGITUDE", allow_hyphen_values = true)]
+    pub weather_longitude: Option<f64>,
+
+    /// Base URL of the Open-Meteo API
+    #[arg(
+        long,
+        env = "WEATHER_URL",
+        default_value = "https://api.open-meteo.com"
+    )]
+    pub weather_url: String,
+
+    /// How often to refresh the outdoor temperatures, in seconds
+    #[arg(long, env = "WEATHER_INTERVAL", default_value = "3600")]
+    pub weather_interval: u64,
+
+    /// Daily mean outdoor temperature (°C) above which no heating is needed
+    #[arg(long, env = "HEATING_BASE_TEMPERATURE", default_value = "18")]
+    pub heating_base_temperature: f64,
+
+    /// Rating of the main fuse, e.g. `1x35A` or `3x25A`. Enables the fuse
+    /// utilization metrics
+    #[arg(long, env = "FUSE_RATING")]
+    pub fuse_rating: Option<String>,
+
+    /// Utilization (%) from which a phase is reported as near its limit
+    #[arg(long, env = "FUSE_NEAR_LIMIT_PERCENT", default_value = "80")]
+    pub fuse_near_limit_percent: f64,
+
+    /// Seconds a phase must stay near its limit before an overload warning
+    /// is raised
+    #[arg(long, env = "FUSE_OVERLOAD_DURATION", default_value = "60")]
+    pub fuse_overload_duration: u64,
+
+    /// Lease file on storage shared with a second instance. Only the
+    /// instance holding the lease polls the device and feeds the sinks;
+    /// the other stands by
+    #[arg(long, env = "FAILOVER_LEASE_FILE")]
+    pub failover_lease_file: Option<PathBuf>,
+
+    /// Seconds a lease stays valid without renewal before a standby takes
+    /// over
+    #[arg(long, env = "FAILOVER_LEASE_TTL", default_value = "15")]
+    pub failover_lease_ttl: u64,
+
+    /// Name this instance writes into the lease file. Defaults to the
+    /// hostname and process id
+    #[arg(long, env = "FAILOVER_INSTANCE_ID")]
+    pub failover_instance_id: Option<String>,
+
+    /// Must match the `signal` setting of the Telegraf execd input when
+    /// running with `--output execd`
+    #[arg(long, env = "EXECD_SIGNAL", value_enum, default_value_t = ExecdSignal::None)]
+    pub execd_signal: ExecdSignal,
+
+    /// Fetch attempts per poll, the first one included. Timeouts, connection
+    /// failures and error statuses are retried; 1 disables retries
+    #[arg(long, env = "RETRY_MAX_ATTEMPTS", default_value = "1")]
+    pub retry_max_attempts: u32,
+
+    /// Milliseconds before the first retry; doubles with every further retry
+    #[arg(long, env = "RETRY_BASE_DELAY_MS", default_value = "250")]
+    pub retry_base_delay_ms: u64,
+
+    /// Random extra retry delay, as a fraction (0 to 1) of the backoff delay
+    #[arg(long, env = "RETRY_JITTER", default_value = "0.2")]
+    pub retry_jitter: f64,
+
+    /// Consecutive failed polls after which the device is only probed every
+    /// `--breaker-probe-interval` seconds. 0 disables the circuit breaker
+    #[arg(long, env = "BREAKER_THRESHOLD", default_value = "0")]
+    pub breaker_threshold: u32,
+
+    /// Seconds between probes of a device while the circuit is open
+    #[arg(long, env = "BREAKER_PROBE_INTERVAL", default_value = "60")]
+    pub breaker_probe_interval: u64,
+
+    /// Consecutive failed polls after which the device is looked up by
+    /// serial number through mDNS and polled at the address found, again
+    /// after every as many failures. 0 disables rediscovery
+    #[arg(long, env = "REDISCOVER_AFTER", default_value = "0")]
+    pub rediscover_after: u32,
+
+    /// Successful polls required among the last `--ready-window` polls
+    /// before `/readyz` reports ready
+    #[arg(long, env = "READY_MIN_SUCCESSES", default_value = "1")]
+    pub ready_min_successes: usize,
+
+    /// Number of most recent polls `/readyz` considers
+    #[arg(long, env = "READY_WINDOW", default_value = "3")]
+    pub ready_window: usize,
+
+    /// Maximum age in seconds of the last successful poll for `/readyz` to
+    /// report ready
+    #[arg(long, env = "READY_MAX_DATA_AGE")]
+    pub ready_max_data_age: Option<u64>,
+}
+
+impl Config {
+    /// Parses the command line and environment, merged with
+    /// `--config-file` when given. Exits on invalid arguments like
+    /// [`Parser::parse`].
+    pub fn load() -> Result<Self> {
+        Ok(Self::try_parse_args(with_config_file(std::env::args_os())?)
+            .unwrap_or_else(|e| e.exit()))
+    }
+
+    /// Loads the configuration again to apply it to a running exporter.
+    /// Unlike [`Config::load`], invalid arguments are returned as errors.
+    pub fn reload() -> Result<Self> {
+        Ok(Self::try_parse_args(
+            with_config_file(std::env::args_os())?,
+        )?)
+    }
+
+    /// Like [`Parser::try_parse_from`], but options are also accepted
+    /// after a subcommand (`fetch --host ...`).
+    fn try_parse_args(args: Vec<OsString>) -> Result<Self, clap::Error> {
+        let matches = global_command().try_get_matches_from(args)?;
+        Self::from_arg_matches(&matches)
+    }
+
+    /// The completion script for `shell`, covering every option and
+    /// subcommand.
+    pub fn completions(shell: Shell) -> Vec<u8> {
+        let mut command = global_command();
+        let name = command.get_name().to_string();
+        let mut script = Vec::new();
+        clap_complete::generate(shell, &mut command, name, &mut script);
+        script
+    }
+
+    pub fn poll_interval_duration(&self) -> Duration {
+        self.poll_interval
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_POLL_INTERVAL)
+    }
+
+    /// The poll interval once the meter's capabilities are known. An
+    /// explicitly configured interval always wins.
+    pub fn effective_poll_interval(&self, capabilities: &SmrCapabilities) -> Duration {
+        self.poll_interval
+            .map(Duration::from_secs)
+            .unwrap_or(capabilities.electricity_interval)
+    }
+
+    pub fn gas_stale_threshold_duration(&self) -> Option<Duration> {
+        self.gas_stale_threshold.map(Duration::from_secs)
+    }
+
+    pub fn recent_window_duration(&self) -> Duration {
+        Duration::from_secs(self.recent_window)
+    }
+
+    /// The configured energy contract, if any price is set.
+    pub fn contract(&self) -> Result<Option<Contract>> {
+        let import_tariffs = tariff_prices("--price-import-tariff", &self.price_import_tariffs)?;
+        let export_tariffs = tariff_prices("--price-export-tariff", &self.price_export_tariffs)?;
+        let prices = [
+            self.price_import_kwh,
+            self.price_export_kwh,
+            self.price_gas_m3,
+            self.fixed_cost_month,
+            self.fixed_cost_day,
+            self.capacity_price_kw_month,
+        ];
+        let any_tariff = import_tariffs
+            .iter()
+            .chain(&export_tariffs)
+            .any(Option::is_some);
+        Ok(
+            (any_tariff || prices.iter().any(Option::is_some)).then(|| Contract {
+                import_per_kwh: self.price_import_kwh.unwrap_or_default(),
+                import_per_kwh_tariff: import_tariffs,
+                export_per_kwh: self.price_export_kwh.unwrap_or_default(),
+                export_per_kwh_tariff: export_tariffs,
+                gas_per_m3: self.price_gas_m3.unwrap_or_default(),
+                fixed_per_month: self.fixed_cost_month.unwrap_or_default(),
+                fixed_per_day: self.fixed_cost_day.unwrap_or_default(),
+                capacity_per_kw_month: self.capacity_price_kw_month.unwrap_or_default(),
+                capacity_min_kw: self.capacity_min_kw,
+            }),
+        )
+    }
+
+    pub fn net_metering(&self) -> Result<Option<NetMeteringConfig>> {
+        self.contract_year_start
+            .as_deref()
+            .map(|start| {
+                Ok(NetMeteringConfig {
+                    year_start: netmetering::parse_year_start(start)?,
+                    start_import_kwh: self.contract_year_start_import_kwh,
+                    start_export_kwh: self.contract_year_start_export_kwh,
+                })
+            })
+            .transpose()
+    }
+
+    /// The configured weather location as `(latitude, longitude)`.
+    pub fn weather_location(&self) -> Result<Option<(f64, f64)>> {
+        match (self.weather_latitude, self.weather_longitude) {
+            (Some(latitude), Some(longitude)) => {
+                ensure!(
+                    (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude),
+                    "Invalid weather location {latitude}, {longitude}"
+                );
+                Ok(Some((latitude, longitude)))
+            }
+            (None, None) => Ok(None),
+            _ => anyhow::bail!("--weather-latitude and --weather-longitude must be set together"),
+        }
+    }
+
+    pub fn weather_interval_duration(&self) -> Duration {
+        Duration::from_secs(self.weather_interval)
+    }
+
+    pub fn fuse_limit(&self) -> Result<Option<FuseLimit>> {
+        self.fuse_rating
+            .as_deref()
+            .map(|rating| {
+                Ok(FuseLimit {
+                    rating: rating.parse::<FuseRating>()?,
+                    near_limit_percent: self.fuse_near_limit_percent,
+                })
+            })
+            .transpose()
+    }
+
+    pub fn overload_policy(&self) -> Result<Option<OverloadPolicy>> {
+        Ok(self.fuse_limit()?.map(|limit| OverloadPolicy {
+            limit,
+            duration: Duration::from_secs(self.fuse_overload_duration),
+        }))
+    }
+
+    /// HTTP basic auth user, when configured.
+    pub fn basic_auth(&self) -> Option<(&str, &str)> {
+        self.basic_auth_username
+            .as_deref()
+            .zip(self.basic_auth_password.as_deref())
+    }
+
+    /// TLS configuration of the metrics server, when HTTPS is enabled.
+    pub fn tls_server_config(&self) -> Result<Option<rustls::ServerConfig>> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => {
+                tls::server_config(cert, key, self.tls_client_ca.as_deref()).map(Some)
+            }
+            (None, None) => {
+                ensure!(
+                    self.tls_client_ca.is_none(),
+                    "--tls-client-ca requires --tls-cert and --tls-key"
+                );
+                Ok(None)
+            }
+            _ => bail!("--tls-cert and --tls-key must be set together"),
+        }
+    }
+
+    pub fn failover_lease(&self) -> Result<Option<LeaseFile>> {
+        let Some(path) = &self.failover_lease_file else {
+            return Ok(None);
+        };
+        ensure!(
+            self.failover_lease_ttl >= 3,
+            "--failover-lease-ttl must be at least 3 seconds"
+        );
+        let holder = self.failover_instance_id.clone().unwrap_or_else(|| {
+            let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "exporter".to_string());
+            format!("{}-{}", host, std::process::id())
+        });
+        Ok(Some(LeaseFile::new(
+            path.clone(),
+            holder,
+            Duration::from_secs(self.failover_lease_ttl),
+        )))
+    }
+
+    /// The InfluxDB write target, when `--influx-url` is set.
+    pub fn influx_target(&self) -> Result<Option<InfluxTarget>> {
+        if self.influx_url.is_none() {
+            return Ok(None);
+        }
+        if let (Some(org), Some(bucket)) = (&self.influx_org, &self.influx_bucket) {
+            return Ok(Some(InfluxTarget::V2 {
+                org: org.clone(),
+                bucket: bucket.clone(),
+                token: self.influx_token.clone(),
+            }));
+        }
+        let Some(database) = &self.influx_database else {
+            bail!(
+                "--influx-url requires --influx-bucket (InfluxDB 2) or --influx-database (InfluxDB 1)"
+            );
+        };
+        Ok(Some(InfluxTarget::V1 {
+            database: database.clone(),
+            credentials: self
+                .influx_username
+                .clone()
+                .zip(self.influx_password.clone()),
+        }))
+    }
+
+    pub fn mqtt_settings(&self) -> Option<MqttSettings> {
+        Some(MqttSettings {
+            host: self.mqtt_host.clone()?,
+            port: self.mqtt_port,
+            credentials: self.mqtt_username.clone().zip(self.mqtt_password.clone()),
+            topic_prefix: self.mqtt_topic_prefix.trim_end_matches('/').to_string(),
+            discovery_prefix: self
+                .mqtt_discovery
+                .then(|| self.mqtt_discovery_prefix.trim_end_matches('/').to_string()),
+        })
+    }
+
+    /// The on-disk buffer of `sink`, when `--wal-dir` is set.
+    pub fn wal(&self, sink: &str) -> Result<Option<Arc<Wal>>> {
+        let Some(dir) = &self.wal_dir else {
+            return Ok(None);
+        };
+        ensure!(self.wal_max_bytes > 0, "--wal-max-bytes must be positive");
+        ensure!(self.wal_max_age > 0, "--wal-max-age must be positive");
+        Ok(Some(Arc::new(Wal::open(
+            dir,
+            sink,
+            self.wal_max_bytes,
+            Duration::from_secs(self.wal_max_age),
+        )?)))
+    }
+
+    pub fn log_rotation_policy(&self) -> RotationPolicy {
+        RotationPolicy {
+            max_size: self.log_max_size.saturating_mul(1024 * 1024),
+            rotation: self.log_rotation,
+            retention: self.log_retention,
+        }
+    }
+
+    pub fn validate_output(&self) -> Result<()> {
+        ensure!(
+            self.output != OutputMode::Textfile || self.textfile_output.is_some(),
+            "--output textfile requires --textfile-output"
+        );
+        ensure!(
+            self.scrape_mode != ScrapeMode::OnDemand || self.output == OutputMode::Http,
+            "--scrape-mode on-demand requires --output http"
+        );
+        ensure!(
+            self.log_file.is_none() || self.log_target == LogTarget::Stdout,
+            "--log-file cannot be combined with --log-target {:?}",
+            self.log_target
+        );
+        Ok(())
+    }
+
+    pub fn metric_names(&self) -> Result<MetricNames> {
+        let prefix = &self.metric_prefix;
+        ensure!(
+            prefix.is_empty() || is_metric_name(prefix),
+            "Invalid --metric-prefix {prefix:?}: use letters, digits, underscores and colons, not starting with a digit"
+        );
+
+        let mut renames = HashMap::new();
+        let mut targets = HashSet::new();
+        for spec in &self.metric_renames {
+            let Some((old, new)) = spec.split_once('=') else {
+                bail!("Invalid --metric-rename {spec:?}: expected old=new");
+            };
+            let (old, new) = (old.trim(), new.trim());
+            for name in [old, new] {
+                ensure!(
+                    is_metric_name(name),
+                    "Invalid metric name {name:?} in --metric-rename {spec:?}"
+                );
+            }
+            ensure!(
+                renames.insert(old.to_string(), new.to_string()).is_none(),
+                "--metric-rename renames {old:?} more than once"
+            );
+            ensure!(
+                targets.insert(new),
+                "--metric-rename gives more than one metric the name {new:?}"
+            );
+        }
+        Ok(MetricNames::new(prefix).with_renames(renames))
+    }
+
+    /// The `--sensor-name` mapping of external sensor IDs to names.
+    pub fn sensor_names(&self) -> Result<HashMap<String, String>> {
+        let mut names = HashMap::new();
+        for spec in &self.sensor_names {
+            let Some((id, name)) = spec.split_once('=') else {
+                bail!("Invalid --sensor-name {spec:?}: expected unique_id=name");
+            };
+            let (id, name) = (id.trim(), name.trim().trim_matches('"'));
+            ensure!(
+                !id.is_empty() && !name.is_empty(),
+                "Invalid --sensor-name {spec:?}: expected unique_id=name"
+            );
+            ensure!(
+                names.insert(id.to_string(), name.to_string()).is_none(),
+                "--sensor-name names {id:?} more than once"
+            );
+        }
+        Ok(names)
+    }
+
+    pub fn sensor_filter(&self) -> SensorFilter {
+        let entries = |list: &[String]| {
+            list.iter()
+                .map(|entry| entry.trim().to_string())
+                .filter(|entry| !entry.is_empty())
+                .collect()
+        };
+        SensorFilter {
+            include: entries(&self.sensor_include),
+            exclude: entries(&self.sensor_exclude),
+        }
+    }
+
+    /// The `--label` pairs, in the order given.
+    pub fn static_labels(&self) -> Result<Vec<(String, String)>> {
+        let mut labels: Vec<(String, String)> = Vec::with_capacity(self.labels.len());
+        for spec in &self.labels {
+            let Some((name, value)) = spec.split_once('=') else {
+                bail!("Invalid --label {spec:?}: expected name=value");
+            };
+            let name = name.trim();
+            ensure!(
+                is_label_name(name),
+                "Invalid --label name {name:?}: use letters, digits and underscores, not starting with a digit"
+            );
+            ensure!(
+                !name.starts_with("__") && name != "device",
+                "--label {name:?} is reserved"
+            );
+            ensure!(
+                labels.iter().all(|(existing, _)| existing != name),
+                "--label {name:?} is given more than once"
+            );
+            labels.push((name.to_string(), value.trim().to_string()));
+        }
+        Ok(labels)
+    }
+
+    /// The `--probe-label` names `/probe` requests may set.
+    pub fn probe_labels(&self) -> Result<Vec<String>> {
+        let static_labels = self.static_labels()?;
+        let mut names: Vec<String> = Vec::with_capacity(self.probe_labels.len());
+        for name in &self.probe_labels {
+            let name = name.trim();
+            ensure!(
+                is_label_name(name),
+                "Invalid --probe-label {name:?}: use letters, digits and underscores, not starting with a digit"
+            );
+            ensure!(
+                !name.starts_with("__")
+                    && name != "device"
+                    && static_labels.iter().all(|(existing, _)| existing != name),
+                "--probe-label {name:?} is reserved or already set by --label"
+            );
+            if !names.iter().any(|existing| existing == name) {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    /// Headers sent to `--remote-write-url` besides authentication: the
+    /// tenant and every `--remote-write-header`.
+    pub fn remote_write_headers(&self) -> Result<Vec<(String, String)>> {
+        let mut headers = Vec::new();
+        if let Some(tenant) = &self.remote_write_tenant {
+            headers.push(("X-Scope-OrgID".to_string(), tenant.clone()));
+        }
+        for spec in &self.remote_write_headers {
+            let Some((name, value)) = spec.split_once('=') else {
+                bail!("Invalid --remote-write-header {spec:?}, expected Name=value");
+            };
+            let (name, value) = (name.trim(), value.trim());
+            ensure!(
+                ::http::HeaderName::from_str(name).is_ok(),
+                "Invalid --remote-write-header name {name:?}"
+            );
+            ensure!(
+                ::http::HeaderValue::from_str(value).is_ok(),
+                "Invalid --remote-write-header value for {name:?}"
+            );
+            ensure!(
+                !name.eq_ignore_ascii_case("authorization"),
+                "Set --remote-write-bearer-token or --remote-write-username instead of an \
+                 Authorization --remote-write-header"
+            );
+            headers.push((name.to_string(), value.to_string()));
+        }
+        Ok(headers)
+    }
+
+    pub fn validate_sources(&self) -> Result<()> {
+        for device in self.devices()? {
+            let has_token = self.device_api_token(&device)?.is_some();
+            ensure!(
+                !self.sources.contains(&Source::V2) || has_token,
+                "--sources v2 requires --api-token or a token for device {:?}",
+                device.name
+            );
+            ensure!(
+                device.product != Some(ProductType::PluginBattery) || has_token,
+                "Plug-In Battery {:?} is only served by API v2, which requires --api-token \
+                 or a token for the device",
+                device.name
+            );
+        }
+        Ok(())
+    }
+
+    /// The API v2 token of `device`: its own `token` or `token_file`,
+    /// otherwise `--api-token`.
+    pub fn device_api_token(&self, device: &Device) -> Result<Option<String>> {
+        if let Some(token) = &device.token {
+            return Ok(Some(token.clone()));
+        }
+        if let Some(path) = &device.token_file {
+            let token = std::fs::read_to_string(path).with_context(|| {
+                format!(
+                    "Failed to read token file {} of device {:?}",
+                    path.display(),
+                    device.name
+                )
+            })?;
+            let token = token.trim();
+            ensure!(
+                !token.is_empty(),
+                "Token file {} of device {:?} is empty",
+                path.display(),
+                device.name
+            );
+            return Ok(Some(token.to_string()));
+        }
+        Ok(self.api_token.clone())
+    }
+
+    pub fn retry_policy(&self) -> Result<RetryPolicy> {
+        ensure!(
+            self.retry_max_attempts > 0,
+            "--retry-max-attempts must be at least 1"
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.retry_jitter),
+            "--retry-jitter must be between 0 and 1"
+        );
+        Ok(RetryPolicy {
+            max_attempts: self.retry_max_attempts,
+            base_delay: Duration::from_millis(self.retry_base_delay_ms),
+            jitter: self.retry_jitter,
+        })
+    }
+
+    /// The circuit breaker, unless disabled with `--breaker-threshold 0`.
+    pub fn circuit_breaker(&self) -> Option<CircuitBreaker> {
+        (self.breaker_threshold > 0).then(|| CircuitBreaker {
+            threshold: self.breaker_threshold,
+            probe_interval: Duration::from_secs(self.breaker_probe_interval),
+        })
+    }
+
+    /// Whether `consecutive_failures` failed polls call for a lookup of the
+    /// device through mDNS.
+    pub fn rediscover_due(&self, consecutive_failures: u32) -> bool {
+        self.rediscover_after > 0 && consecutive_failures.is_multiple_of(self.rediscover_after)
+    }
+
+    pub fn readiness_policy(&self) -> Result<ReadinessPolicy> {
+        ensure!(self.ready_window > 0, "--ready-window must be at least 1");
+        ensure!(
+            self.ready_min_successes <= self.ready_window,
+            "--ready-min-successes ({}) cannot exceed --ready-window ({})",
+            self.ready_min_successes,
+            self.ready_window
+        );
+
+        Ok(ReadinessPolicy {
+            min_successes: self.ready_min_successes,
+            window: self.ready_window,
+            max_data_age: self.ready_max_data_age.map(Duration::from_secs),
+        })
+    }
+
+    pub fn http_timeout_duration(&self) -> Duration {
+        Duration::from_secs(self.http_timeout)
+    }
+
+    /// The HTTP timeout of `device`: its own `timeout`, otherwise
+    /// `--http-timeout`.
+    pub fn device_http_timeout(&self, device: &Device) -> Duration {
+        device
+            .http_timeout
+            .unwrap_or_else(|| self.http_timeout_duration())
+    }
+
+    /// The poll interval of the device named `name`: its own `interval`,
+    /// otherwise as [`Config::effective_poll_interval`] once the meter's
+    /// capabilities are known, or the configured interval before that.
+    pub fn device_poll_interval(
+        &self,
+        name: &str,
+        capabilities: Option<&SmrCapabilities>,
+    ) -> Duration {
+        let own = self.device(name).and_then(|device| device.poll_interval);
+        own.unwrap_or_else(|| match capabilities {
+            Some(capabilities) => self.effective_poll_interval(capabilities),
+            None => self.poll_interval_duration(),
+        })
+    }
+
+    /// How often the device information is refreshed; `None` when only
+    /// read at startup.
+    pub fn device_info_interval_duration(&self) -> Option<Duration> {
+        (self.device_info_interval > 0).then(|| Duration::from_secs(self.device_info_interval))
+    }
+
+    /// How often the cloud setting and uptime are read; `None` when
+    /// disabled.
+    pub fn system_interval_duration(&self) -> Option<Duration> {
+        (self.system_interval > 0).then(|| Duration::from_secs(self.system_interval))
+    }
+
+    pub fn scrape_cache_ttl_duration(&self) -> Duration {
+        Duration::from_secs(self.scrape_cache_ttl)
+    }
+
+    /// Socket address of the metrics server. The port always comes from
+    /// `--port`, so an address that carries one of its own is rejected.
+    pub fn metrics_bind_address(&self) -> Result<SocketAddr> {
+        let address = self.bind_address.trim();
+        let unbracketed = address
+            .strip_prefix('[')
+            .and_then(|address| address.strip_suffix(']'))
+            .unwrap_or(address);
+        match unbracketed.parse::<IpAddr>() {
+            Ok(ip) => Ok(SocketAddr::new(ip, self.port)),
+            Err(_) if address.parse::<SocketAddr>().is_ok() => {
+                bail!("--bind-address {address:?} includes a port; set the port with --port")
+            }
+            Err(e) => bail!("Invalid --bind-address {address:?}: {e}"),
+        }
+    }
+
+    /// The configured device named `name`.
+    pub fn device(&self, name: &str) -> Option<Device> {
+        self.devices()
+            .ok()?
+            .into_iter()
+            .find(|device| device.name == name)
+    }
+
+    /// The configured devices, in `--host` order.
+    pub fn devices(&self) -> Result<Vec<Device>> {
+        ensure!(
+            !self.host.is_empty(),
+            "--host (HOMEWIZARD_HOST) is required"
+        );
+        let devices: Vec<Device> = self
+            .host
+            .iter()
+            .map(|spec| Device::parse(spec, self.device_type))
+            .collect::<Result<_>>()?;
+        let mut names = HashSet::new();
+        for device in &devices {
+            ensure!(
+                names.insert(device.name.as_str()),
+                "Device name {:?} is used more than once",
+                device.name
+            );
+        }
+        Ok(devices)
+    }
+}
+
+/// Whether `name` is a valid Prometheus metric name.
+fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Whether `name` is a valid Prometheus label name.
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The command line parser with every option global, so that subcommands
+/// take the exporter's options too. Switches set through the environment
+/// take the same values as in `--config-file`.
+pub fn global_command() -> clap::Command {
+    Config::command().mut_args(|arg| {
+        let arg = arg.global(true);
+        if matches!(arg.get_action(), ArgAction::SetTrue) {
+            arg.value_parser(BoolishValueParser::new())
+        } else {
+            arg
+        }
+    })
+}
+
+/// Value of a switch: `true`, `yes`, `on`, `1` and their negations, as
+/// clap reads them from the environment.
+fn switch_value(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "y" | "yes" | "t" | "true" | "on" | "1" => Some(true),
+        "n" | "no" | "f" | "false" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// The command line with the settings of `--config-file` added before any
+/// subcommand, except those already given on the command line.
+fn with_config_file(args: impl IntoIterator<Item = OsString>) -> Result<Vec<OsString>> {
+    let mut args: Vec<OsString> = args.into_iter().collect();
+    let command = global_command().ignore_errors(true);
+    // Errors and `--help` are left to the real parse
+    let Ok(matches) = command.clone().try_get_matches_from(&args) else {
+        return Ok(args);
+    };
+    let Some(path) = matches.get_one::<PathBuf>("config_file") else {
+        return Ok(args);
+    };
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let settings = parse_config_file(&contents)
+        .with_context(|| format!("Invalid config file {}", path.display()))?;
+
+    let mut settings_args: Vec<OsString> = Vec::new();
+    for arg in command.get_arguments() {
+        let (Some(env), Some(long)) = (arg.get_env(), arg.get_long()) else {
+            continue;
+        };
+        let Some(value) = env.to_str().and_then(|env| settings.get(env)) else {
+            continue;
+        };
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        match arg.get_action() {
+            ArgAction::SetTrue => {
+                if switch_value(value).unwrap_or(false) {
+                    settings_args.push(format!("--{long}").into());
+                }
+            }
+            _ => settings_args.push(format!("--{long}={value}").into()),
+        }
+    }
+    let at = args.len().min(1);
+    args.splice(at..at, settings_args);
+    Ok(args)
+}
+
+/// Parses `tariff=price` entries of `option` into prices of tariffs 1-4.
+fn tariff_prices(option: &str, specs: &[String]) -> Result<[Option<f64>; 4]> {
+    let mut prices = [None; 4];
+    for spec in specs {
+        let parsed = spec.split_once('=').and_then(|(tariff, price)| {
+            let tariff = tariff.trim().parse::<usize>().ok()?;
+            let price = price.trim().parse::<f64>().ok()?;
+            (1..=4).contains(&tariff).then_some((tariff, price))
+        });
+        let Some((tariff, price)) = parsed else {
+            bail!("Invalid {option} {spec:?}: expected tariff=price with a tariff from 1 to 4");
+        };
+        ensure!(
+            prices[tariff - 1].replace(price).is_none(),
+            "{option} prices tariff {tariff} more than once"
+        );
+    }
+    Ok(prices)
+}
+
+/// `NAME=value` lines; blank lines and `#` comments are ignored and values
+/// may be quoted. Names must be the environment variables of options, each
+/// set once, and switches take the values [`switch_value`] accepts, so a
+/// typo is reported with its line instead of being ignored.
+fn parse_config_file(contents: &str) -> Result<HashMap<String, String>> {
+    let command = Config::command();
+    // Environment variable name -> whether the option is a switch
+    let options: HashMap<&str, bool> = command
+        .get_arguments()
+        .filter_map(|arg| {
+            let env = arg.get_env()?.to_str()?;
+            Some((env, matches!(arg.get_action(), ArgAction::SetTrue)))
+        })
+        .collect();
+
+    let mut settings = HashMap::new();
+    for (index, line) in contents.lines().map(str::trim).enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line_number = index + 1;
+        let (name, value) = line
+            .split_once('=')
+            .with_context(|| format!("line {line_number}: expected NAME=value"))?;
+        let name = name.trim();
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(*quote)
+                    .and_then(|value| value.strip_suffix(*quote))
+            })
+            .unwrap_or(value);
+        let Some(switch) = options.get(name) else {
+            bail!("line {line_number}: unknown setting {name:?}");
+        };
+        ensure!(
+            !switch || switch_value(value).is_some(),
+            "line {line_number}: {name} must be true or false, not {value:?}"
+        );
+        ensure!(
+            settings
+                .insert(name.to_string(), value.to_string())
+                .is_none(),
+            "line {line_number}: {name} is set more than once"
+        );
+    }
+    Ok(settings)
+}
+
+/// Value of `name` in the config file at `path`, if set.
+pub fn read_setting(path: &Path, name: &str) -> Result<Option<String>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let mut settings = parse_config_file(&contents)
+        .with_context(|| format!("Invalid config file {}", path.display()))?;
+    Ok(settings.remove(name))
+}
+
+/// Sets `name` to `value` in the config file at `path`, keeping its other
+/// lines and creating it when missing.
+pub fn save_setting(path: &Path, name: &str, value: &str) -> Result<()> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    std::fs::write(path, set_setting(&contents, name, value))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// `contents` with `name` set to `value`, replacing an existing line.
+fn set_setting(contents: &str, name: &str, value: &str) -> String {
+    let line = format!("{name}={value}");
+    let mut replaced = false;
+    let mut lines: Vec<&str> = contents
+        .lines()
+        .map(|existing| {
+            let is_setting = existing
+                .split_once('=')
+                .is_some_and(|(key, _)| key.trim() == name);
+            if is_setting && !replaced {
+                replaced = true;
+                line.as_str()
+            } else {
+                existing
+            }
+        })
+        .collect();
+    if !replaced {
+        lines.push(&line);
+    }
+    lines.join("\n") + "\n"
+}
+
+/// `specs` with the device named `name` pointed at `host`, keeping the
+/// product and options of its spec. A missing device is appended as
+/// `name=host`; `None` removes the device.
+pub fn set_device_host(specs: &[String], name: &str, host: Option<&str>) -> Vec<String> {
+    let mut specs = specs.to_vec();
+    let position = specs
+        .iter()
+        .position(|spec| Device::parse(spec, None).is_ok_and(|device| device.name == name));
+    match (position, host) {
+        (Some(index), Some(host)) => {
+            let (target, options) = match specs[index].split_once(';') {
+                Some((target, options)) => (target, Some(options)),
+                None => (specs[index].as_str(), None),
+            };
+            let mut spec = format!("{name}={host}");
+            if let Some((_, product)) = target.rsplit_once('/')
+                && !host.contains('/')
+            {
+                spec = format!("{spec}/{product}");
+            }
+            if let Some(options) = options {
+                spec = format!("{spec};{options}");
+            }
+            specs[index] = spec;
+        }
+        (Some(index), None) => {
+            specs.remove(index);
+        }
+        (None, Some(host)) => specs.push(format!("{name}={host}")),
+        (None, None) => {}
+    }
+    specs
+}
+
+/// A device to poll, as configured with
+/// `--host [name=]host[/product][;option=value...]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    /// Value of the `device` label on this device's metrics
+    pub name: String,
     pub host: String,
+    /// Configured product; `None` until detected from `/api`
+    pub product: Option<ProductType>,
+    /// API v2 token of this device, overriding `--api-token`
+    pub token: Option<String>,
+    /// File holding the API v2 token of this device, read when a client
+    /// is created
+    pub token_file: Option<PathBuf>,
+    /// Poll interval of this device, overriding `--poll-interval`
+    pub poll_interval: Option<Duration>,
+    /// HTTP timeout of this device, overriding `--http-timeout`
+    pub http_timeout: Option<Duration>,
+}
+
+impl Device {
+    pub fn url(&self) -> String {
+        device_url(&self.host)
+    }
+
+    pub fn v2_url(&self) -> String {
+        device_v2_url(&self.host)
+    }
+
+    /// Parses `[name=]host[/product][;option=value...]`, polling `product`
+    /// when the spec has no suffix.
+    pub fn parse(spec: &str, product: Option<ProductType>) -> Result<Self> {
+        let mut parts = spec.split(';');
+        let target = parts.next().unwrap_or_default();
+        let mut device = Self::parse_target(target, product)?;
+        for option in parts {
+            let Some((key, value)) = option.split_once('=') else {
+                bail!("Invalid option {option:?} of device {spec:?}, expected option=value");
+            };
+            let value = value.trim();
+            ensure!(
+                !value.is_empty(),
+                "Option {key:?} of device {spec:?} has no value"
+            );
+            match key.trim() {
+                "token" => device.token = Some(value.to_string()),
+                "token_file" => device.token_file = Some(PathBuf::from(value)),
+                "interval" => device.poll_interval = Some(seconds(spec, key, value)?),
+                "timeout" => device.http_timeout = Some(seconds(spec, key, value)?),
+                key => bail!("Unknown option {key:?} of device {spec:?}"),
+            }
+        }
+        ensure!(
+            device.token.is_none() || device.token_file.is_none(),
+            "Device {spec:?} sets both token and token_file"
+        );
+        Ok(device)
+    }
+
+    /// Parses `[name=]host[/product]`, the part of a spec before any
+    /// options.
+    pub fn parse_target(spec: &str, product: Option<ProductType>) -> Result<Self> {
+        let (host, product) = match spec.rsplit_once('/') {
+            Some((host, product)) => (
+                host,
+                Some(
+                    ProductType::from_str(product.trim(), true)
+                        .map_err(|e| anyhow::anyhow!("Invalid product in {spec:?}: {e}"))?,
+                ),
+            ),
+            None => (spec, product),
+        };
+        let (name, host) = match host.split_once('=') {
+            Some((name, host)) => (name.trim(), host.trim()),
+            None => (host.trim(), host.trim()),
+        };
+        ensure!(
+            !name.is_empty() && !host.is_empty(),
+            "Invalid device {spec:?}, expected [name=]host[/product]"
+        );
+        Ok(Self {
+            name: name.to_string(),
+            host: host.to_string(),
+            product,
+            token: None,
+            token_file: None,
+            poll_interval: None,
+            http_timeout: None,
+        })
+    }
+}
+
+/// Parses the whole, positive number of seconds of device option `key`.
+fn seconds(spec: &str, key: &str, value: &str) -> Result<Duration> {
+    match value.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+        _ => bail!("Option {key:?} of device {spec:?} must be a positive number of seconds"),
+    }
+}
+
+impl FromStr for Device {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        Self::parse(spec, None)
+    }
+}
+
+/// Data endpoint of the device at `host`.
+pub fn device_url(host: &str) -> String {
+    format!("http://{host}/api/v1/data")
+}
+
+/// API v2 measurement endpoint of the device at `host`.
+pub fn device_v2_url(host: &str) -> String {
+    format!("https://{host}/api/measurement")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn test_config() -> Config {
+        Config {
+            command: None,
+            host: vec!["192.168.1.100".to_string()],
+            config_file: None,
+            labels: Vec::new(),
+            device_type: None,
+            port: 9898,
+            bind_address: "0.0.0.0".to_string(),
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            poll_interval: Some(10),
+            log_level: "info".to_string(),
+            log_format: LogFormat::Text,
+            log_target: LogTarget::Stdout,
+            log_file: None,
+            log_max_size: 10,
+            log_rotation: LogRotation::Never,
+            log_retention: 5,
+            api_token: None,
+            http_timeout: 5,
+            device_info_interval: 3600,
+            system_interval: 60,
+            gas_stale_threshold: None,
+            water_mode: WaterMode::Volume,
+            read_only: true,
+            parse_mode: ParseMode::Lenient,
+            raw_passthrough: false,
+            gas_timestamp_raw: false,
+            timezone: None,
+            metric_prefix: DEFAULT_METRIC_PREFIX.to_string(),
+            metric_renames: Vec::new(),
+            sensor_names: Vec::new(),
+            sensor_include: Vec::new(),
+            sensor_exclude: Vec::new(),
+            identify: false,
+            auth_token: None,
+            auth_tokens_file: None,
+            basic_auth_username: None,
+            basic_auth_password: None,
+            allow_cidr: Vec::new(),
+            sources: vec![Source::V1],
+            otlp_endpoint: None,
+            otlp_interval: 60,
+            remote_write_url: None,
+            remote_write_interval: 30,
+            remote_write_username: None,
+            remote_write_password: None,
+            remote_write_bearer_token: None,
+            remote_write_tenant: None,
+            remote_write_headers: Vec::new(),
+            remote_write_insecure_skip_verify: false,
+            graphite_host: None,
+            graphite_port: 2003,
+            graphite_protocol: GraphiteProtocol::Tcp,
+            graphite_prefix: "homewizard".to_string(),
+            graphite_interval: 60,
+            statsd_host: None,
+            statsd_port: 8125,
+            statsd_format: StatsdFormat::Dogstatsd,
+            statsd_prefix: String::new(),
+            statsd_tags: Vec::new(),
+            statsd_interval: 10,
+            pushgateway_url: None,
+            pushgateway_job: "homewizard-p1-exporter".to_string(),
+            pushgateway_instance: None,
+            influx_url: None,
+            influx_database: None,
+            influx_username: None,
+            influx_password: None,
+            influx_org: None,
+            influx_bucket: None,
+            influx_token: None,
+            mqtt_host: None,
+            mqtt_port: 1883,
+            mqtt_username: None,
+            mqtt_password: None,
+            mqtt_topic_prefix: "homewizard".to_string(),
+            mqtt_discovery_prefix: "homeassistant".to_string(),
+            mqtt_discovery: true,
+            wal_dir: None,
+            wal_max_bytes: 16 * 1024 * 1024,
+            wal_max_age: 86400,
+            grafana_url: None,
+            grafana_token: None,
+            grafana_annotation_tags: vec!["homewizard".to_string()],
+            recent_window: 600,
+            recent_max_samples: 3600,
+            state_file: None,
+            admin_token: None,
+            admin_persist: false,
+            output: OutputMode::Http,
+            scrape_mode: ScrapeMode::Interval,
+            scrape_cache_ttl: 2,
+            enable_probe: false,
+            probe_labels: Vec::new(),
+            textfile_output: None,
+            price_import_kwh: None,
+            price_import_tariffs: Vec::new(),
+            price_export_kwh: None,
+            price_export_tariffs: Vec::new(),
+            price_gas_m3: None,
+            fixed_cost_month: None,
+            fixed_cost_day: None,
+            capacity_price_kw_month: None,
+            capacity_min_kw: 0.0,
+            contract_year_start: None,
+            contract_year_start_import_kwh: None,
+            contract_year_start_export_kwh: None,
+            weather_latitude: None,
+            weather_longitude: None,
+            weather_url: "https://api.open-meteo.com".to_string(),
+            weather_interval: 3600,
+            heating_base_temperature: 18.0,
+            fuse_rating: None,
+            fuse_near_limit_percent: 80.0,
+            fuse_overload_duration: 60,
+            failover_lease_file: None,
+            failover_lease_ttl: 15,
+            failover_instance_id: None,
+            execd_signal: ExecdSignal::None,
+            retry_max_attempts: 1,
+            retry_base_delay_ms: 250,
+            retry_jitter: 0.2,
+            breaker_threshold: 0,
+            breaker_probe_interval: 60,
+            rediscover_after: 0,
+            ready_min_successes: 1,
+            ready_window: 3,
+            ready_max_data_age: None,
+        }
+    }
+
+    #[test]
+    fn test_poll_interval_duration() {
+        let config = Config {
+            poll_interval: Some(30),
+            ..test_config()
+        };
+
+        assert_eq!(config.poll_interval_duration(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_contract_from_prices() {
+        assert_eq!(test_config().contract().unwrap(), None);
+
+        let contract = Config {
+            price_import_kwh: Some(0.25),
+            capacity_price_kw_month: Some(4.0),
+            capacity_min_kw: 2.5,
+            ..test_config()
+        }
+        .contract()
+        .unwrap()
+        .unwrap();
+        assert_eq!(contract.import_per_kwh, 0.25);
+        assert_eq!(contract.gas_per_m3, 0.0);
+        assert_eq!(contract.capacity_min_kw, 2.5);
+
+        let contract = Config {
+            price_import_tariffs: vec!["1=0.30".to_string(), " 2 = 0.21".to_string()],
+            fixed_cost_day: Some(0.5),
+            ..test_config()
+        }
+        .contract()
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            contract.import_per_kwh_tariff,
+            [Some(0.30), Some(0.21), None, None]
+        );
+        assert_eq!(contract.fixed_per_day, 0.5);
+
+        for tariffs in [vec!["5=0.30"], vec!["1:0.30"], vec!["1=0.30", "1=0.31"]] {
+            let config = Config {
+                price_export_tariffs: tariffs.iter().map(ToString::to_string).collect(),
+                ..test_config()
+            };
+            assert!(config.contract().is_err(), "{tariffs:?}");
+        }
+    }
+
+    #[test]
+    fn test_net_metering_config() {
+        assert_eq!(test_config().net_metering().unwrap(), None);
+
+        let config = Config {
+            contract_year_start: Some("03-01".to_string()),
+            contract_year_start_import_kwh: Some(1234.5),
+            ..test_config()
+        };
+        let net_metering = config.net_metering().unwrap().unwrap();
+        assert_eq!(net_metering.year_start, (3, 1));
+        assert_eq!(net_metering.start_import_kwh, Some(1234.5));
+        assert_eq!(net_metering.start_export_kwh, None);
+
+        let invalid = Config {
+            contract_year_start: Some("31-12".to_string()),
+            ..test_config()
+        };
+        assert!(invalid.net_metering().is_err());
+    }
+
+    #[test]
+    fn test_weather_location() {
+        assert_eq!(test_config().weather_location().unwrap(), None);
+
+        let config = Config {
+            weather_latitude: Some(52.37),
+            weather_longitude: Some(-4.9),
+            ..test_config()
+        };
+        assert_eq!(config.weather_location().unwrap(), Some((52.37, -4.9)));
+
+        let incomplete = Config {
+            weather_latitude: Some(52.37),
+            ..test_config()
+        };
+        assert!(incomplete.weather_location().is_err());
+
+        let out_of_range = Config {
+            weather_latitude: Some(152.0),
+            weather_longitude: Some(4.9),
+            ..test_config()
+        };
+        assert!(out_of_range.weather_location().is_err());
+    }
+
+    #[test]
+    fn test_fuse_limit() {
+        assert_eq!(test_config().fuse_limit().unwrap(), None);
+
+        let config = Config {
+            fuse_rating: Some("3x25A".to_string()),
+            ..test_config()
+        };
+        let limit = config.fuse_limit().unwrap().unwrap();
+        assert_eq!(limit.rating.phases, 3);
+        assert_eq!(limit.near_limit_percent, 80.0);
+
+        let invalid = Config {
+            fuse_rating: Some("25A".to_string()),
+            ..test_config()
+        };
+        assert!(invalid.fuse_limit().is_err());
+    }
+
+    #[test]
+    fn test_failover_lease() {
+        assert!(test_config().failover_lease().unwrap().is_none());
+
+        let config = Config {
+            failover_lease_file: Some(PathBuf::from("/shared/homewizard.lease")),
+            failover_instance_id: Some("exporter-a".to_string()),
+            ..test_config()
+        };
+        assert!(config.failover_lease().unwrap().is_some());
+
+        let too_short = Config {
+            failover_lease_ttl: 2,
+            ..config
+        };
+        assert!(too_short.failover_lease().is_err());
+    }
+
+    #[test]
+    fn test_config_file_settings() {
+        let path =
+            std::env::temp_dir().join(format!("homewizard-config-{}.env", std::process::id()));
+        std::fs::write(
+            &path,
+            "# exporter settings\nHOMEWIZARD_HOST=house=192.168.1.10,annex=192.168.1.11\n\
+             POLL_INTERVAL=\"5\"\nMETRICS_PORT=9000\nENABLE_PROBE=yes\n",
+        )
+        .unwrap();
+        let args = |extra: &[&str]| {
+            let mut args: Vec<OsString> = ["homewizard-p1-exporter", "--config-file"]
+                .iter()
+                .map(OsString::from)
+                .collect();
+            args.push(path.clone().into());
+            args.extend(extra.iter().map(OsString::from));
+            Config::try_parse_from(with_config_file(args).unwrap()).unwrap()
+        };
+
+        let config = args(&[]);
+        assert_eq!(
+            config.host,
+            vec!["house=192.168.1.10", "annex=192.168.1.11"]
+        );
+        assert_eq!(config.poll_interval, Some(5));
+        assert_eq!(config.port, 9000);
+        assert!(config.enable_probe);
+
+        // The command line wins over the file
+        let config = args(&["--port", "9100"]);
+        assert_eq!(config.port, 9100);
+
+        // Settings go before a subcommand, so its options still parse
+        let config = args(&["fetch", "--format", "json"]);
+        assert_eq!(config.port, 9000);
+        assert!(matches!(
+            config.command,
+            Some(Command::Fetch {
+                format: FetchFormat::Json
+            })
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_options_after_subcommand() {
+        let config = Config::try_parse_args(
+            [
+                "homewizard-p1-exporter",
+                "check",
+                "--host",
+                "192.168.1.10",
+                "--sources",
+                "v1,telegram",
+            ]
+            .map(OsString::from)
+            .to_vec(),
+        )
+        .unwrap();
+        assert!(matches!(config.command, Some(Command::Check)));
+        assert_eq!(config.host, ["192.168.1.10"]);
+        assert_eq!(config.sources, [Source::V1, Source::Telegram]);
+    }
+
+    #[test]
+    fn test_timezone() {
+        let parse = |zone: &str| {
+            Config::try_parse_args(
+                ["homewizard-p1-exporter", "--timezone", zone]
+                    .map(OsString::from)
+                    .to_vec(),
+            )
+        };
+        assert_eq!(
+            parse("Europe/Brussels").unwrap().timezone,
+            Some(chrono_tz::Europe::Brussels)
+        );
+        assert!(parse("Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn test_write_completions() {
+        let script = String::from_utf8(Config::completions(Shell::Bash)).unwrap();
+        assert!(script.contains("--host"));
+        assert!(script.contains("homewizard__p1__exporter__subcmd__check"));
+    }
+
+    #[test]
+    fn test_parse_config_file_rejects_lines_without_value() {
+        assert!(parse_config_file("POLL_INTERVAL=5\n").is_ok());
+        let err = parse_config_file("POLL_INTERVAL=5\nPOLL_INTERVAL\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
 
-    /// Port to expose Prometheus metrics on
-    #[arg(long, env = "METRICS_PORT", default_value = "9898")]
-    pub port: u16,
+    #[test]
+    fn test_parse_config_file_validates_settings() {
+        let error = |contents: &str| parse_config_file(contents).unwrap_err().to_string();
+        assert_eq!(
+            error("# settings\nPOLL_INTERVAL=5\nPOLL_INTERVL=5\n"),
+            "line 3: unknown setting \"POLL_INTERVL\""
+        );
+        assert_eq!(
+            error("POLL_INTERVAL=5\n\nPOLL_INTERVAL=10\n"),
+            "line 3: POLL_INTERVAL is set more than once"
+        );
+        assert_eq!(
+            error("ENABLE_PROBE=maybe\n"),
+            "line 1: ENABLE_PROBE must be true or false, not \"maybe\""
+        );
+        assert!(parse_config_file("ENABLE_PROBE=off\nCONFIG_FILE=/etc/other.env\n").is_ok());
+    }
 
-    /// Interval in seconds between polling the HomeWizard API
-    #[arg(long, env = "POLL_INTERVAL", default_value = "10")]
-    pub poll_interval: u64,
+    #[test]
+    fn test_static_labels() {
+        let config = Config {
+            labels: vec!["site=attic".to_string(), " tenant = main ".to_string()],
+            ..test_config()
+        };
+        assert_eq!(
+            config.static_labels().unwrap(),
+            [
+                ("site".to_string(), "attic".to_string()),
+                ("tenant".to_string(), "main".to_string())
+            ]
+        );
 
-    /// Log level (trace, debug, info, warn, error)
-    #[arg(long, env = "LOG_LEVEL", default_value = "info")]
-    pub log_level: String,
+        for invalid in ["site", "1site=attic", "device=house", "__name__=x"] {
+            let config = Config {
+                labels: vec![invalid.to_string()],
+                ..test_config()
+            };
+            assert!(config.static_labels().is_err(), "{invalid}");
+        }
+        let duplicate = Config {
+            labels: vec!["site=attic".to_string(), "site=shed".to_string()],
+            ..test_config()
+        };
+        assert!(duplicate.static_labels().is_err());
+    }
 
-    /// Optional API token for HomeWizard API v2
-    #[arg(long, env = "HOMEWIZARD_API_TOKEN")]
-    pub api_token: Option<String>,
+    #[test]
+    fn test_metric_names() {
+        let names = test_config().metric_names().unwrap();
+        assert_eq!(
+            names.p1("active_power_watts"),
+            "homewizard_p1_active_power_watts"
+        );
 
-    /// Timeout in seconds for HTTP requests to HomeWizard
-    #[arg(long, env = "HTTP_TIMEOUT", default_value = "5")]
-    pub http_timeout: u64,
-}
+        let config = Config {
+            metric_prefix: "p1_".to_string(),
+            ..test_config()
+        };
+        assert_eq!(
+            config.metric_names().unwrap().p1("active_power_watts"),
+            "p1_active_power_watts"
+        );
 
-impl Config {
-    pub fn poll_interval_duration(&self) -> Duration {
-        Duration::from_secs(self.poll_interval)
+        let config = Config {
+            metric_prefix: "p1_".to_string(),
+            metric_renames: vec![
+                "p1_active_power_watts=power_watts".to_string(),
+                "homewizard_exporter_up = up_p1".to_string(),
+            ],
+            ..test_config()
+        };
+        let names = config.metric_names().unwrap();
+        assert_eq!(names.p1("active_power_watts"), "power_watts");
+        assert_eq!(names.p1("gas_total_m3"), "p1_gas_total_m3");
+        assert_eq!(names.name("homewizard_exporter_up"), "up_p1");
+
+        for invalid in [
+            "homewizard_exporter_up",
+            "homewizard_exporter_up=up-p1",
+            "a=c,b=c",
+        ] {
+            let config = Config {
+                metric_renames: invalid.split(',').map(str::to_string).collect(),
+                ..test_config()
+            };
+            assert!(config.metric_names().is_err(), "{invalid}");
+        }
+
+        for invalid in ["1p1_", "p1-", "p1 "] {
+            let config = Config {
+                metric_prefix: invalid.to_string(),
+                ..test_config()
+            };
+            assert!(config.metric_names().is_err(), "{invalid}");
+        }
     }
 
-    pub fn http_timeout_duration(&self) -> Duration {
-        Duration::from_secs(self.http_timeout)
+    #[test]
+    fn test_sensor_names() {
+        let config = Config {
+            sensor_names: vec![r#"4a6f686e446f65 = "water_garden""#.to_string()],
+            ..test_config()
+        };
+        assert_eq!(
+            config.sensor_names().unwrap(),
+            HashMap::from([("4a6f686e446f65".to_string(), "water_garden".to_string())])
+        );
+
+        for invalid in ["4a6f686e446f65", "=water_garden", "a=x,a=y"] {
+            let config = Config {
+                sensor_names: invalid.split(',').map(str::to_string).collect(),
+                ..test_config()
+            };
+            assert!(config.sensor_names().is_err(), "{invalid}");
+        }
     }
 
-    pub fn metrics_bind_address(&self) -> String {
-        format!("0.0.0.0:{}", self.port)
+    #[test]
+    fn test_influx_target() {
+        assert_eq!(test_config().influx_target().unwrap(), None);
+
+        let v2 = Config {
+            influx_url: Some("http://influx:8086".to_string()),
+            influx_org: Some("home".to_string()),
+            influx_bucket: Some("p1".to_string()),
+            influx_token: Some("abc".to_string()),
+            ..test_config()
+        };
+        assert!(matches!(
+            v2.influx_target().unwrap(),
+            Some(InfluxTarget::V2 { token: Some(_), .. })
+        ));
+
+        let v1 = Config {
+            influx_url: Some("http://influx:8086".to_string()),
+            influx_database: Some("energy".to_string()),
+            ..test_config()
+        };
+        assert!(matches!(
+            v1.influx_target().unwrap(),
+            Some(InfluxTarget::V1 {
+                credentials: None,
+                ..
+            })
+        ));
+
+        let neither = Config {
+            influx_url: Some("http://influx:8086".to_string()),
+            ..test_config()
+        };
+        assert!(neither.influx_target().is_err());
     }
 
-    pub fn homewizard_url(&self) -> String {
-        format!("http://{}/api/v1/data", self.host)
+    #[test]
+    fn test_retry_policy() {
+        let config = Config {
+            retry_max_attempts: 3,
+            retry_base_delay_ms: 100,
+            ..test_config()
+        };
+        let policy = config.retry_policy().unwrap();
+        assert_eq!(policy.max_attempts, 3);
+        assert_eq!(policy.base_delay, Duration::from_millis(100));
+
+        let no_attempts = Config {
+            retry_max_attempts: 0,
+            ..test_config()
+        };
+        assert!(no_attempts.retry_policy().is_err());
+        let too_much_jitter = Config {
+            retry_jitter: 1.5,
+            ..test_config()
+        };
+        assert!(too_much_jitter.retry_policy().is_err());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::time::Duration;
+    #[test]
+    fn test_rediscover_due() {
+        assert!(!test_config().rediscover_due(3));
+        let config = Config {
+            rediscover_after: 3,
+            ..test_config()
+        };
+        assert!(!config.rediscover_due(2));
+        assert!(config.rediscover_due(3));
+        assert!(!config.rediscover_due(4));
+        assert!(config.rediscover_due(6));
+    }
 
     #[test]
-    fn test_poll_interval_duration() {
+    fn test_tls_requires_cert_and_key() {
+        let parse = |args: &[&str]| {
+            Config::try_parse_from(
+                ["homewizard-p1-exporter", "--host", "127.0.0.1"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+
+        assert!(parse(&["--tls-cert", "cert.pem"]).is_err());
+        assert!(parse(&["--tls-key", "key.pem"]).is_err());
+        assert!(parse(&["--tls-client-ca", "ca.pem"]).is_err());
+        let config = parse(&["--tls-cert", "cert.pem", "--tls-key", "key.pem"]).unwrap();
+        assert!(config.tls_server_config().is_err());
+        assert!(test_config().tls_server_config().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_textfile_output_mode() {
+        let config = Config::parse_from([
+            "homewizard-p1-exporter",
+            "--host",
+            "192.168.1.100",
+            "--output",
+            "textfile",
+        ]);
+        assert!(config.validate_output().is_err());
+
         let config = Config {
-            host: "192.168.1.100".to_string(),
-            port: 9898,
-            poll_interval: 30,
-            log_level: "info".to_string(),
-            api_token: None,
-            http_timeout: 5,
+            textfile_output: Some(PathBuf::from("/tmp/homewizard.prom")),
+            ..config
         };
+        assert!(config.validate_output().is_ok());
+        assert!(test_config().validate_output().is_ok());
 
-        assert_eq!(config.poll_interval_duration(), Duration::from_secs(30));
+        let config = Config {
+            scrape_mode: ScrapeMode::OnDemand,
+            ..config
+        };
+        assert!(config.validate_output().is_err());
+    }
+
+    #[test]
+    fn test_readiness_policy_validation() {
+        let policy = Config {
+            ready_min_successes: 2,
+            ready_window: 5,
+            ready_max_data_age: Some(60),
+            ..test_config()
+        }
+        .readiness_policy()
+        .unwrap();
+        assert_eq!(policy.min_successes, 2);
+        assert_eq!(policy.max_data_age, Some(Duration::from_secs(60)));
+
+        let too_many = Config {
+            ready_min_successes: 4,
+            ready_window: 3,
+            ..test_config()
+        };
+        assert!(too_many.readiness_policy().is_err());
+
+        let empty_window = Config {
+            ready_min_successes: 0,
+            ready_window: 0,
+            ..test_config()
+        };
+        assert!(empty_window.readiness_policy().is_err());
     }
 
     #[test]
     fn test_http_timeout_duration() {
         let config = Config {
-            host: "192.168.1.100".to_string(),
-            port: 9898,
-            poll_interval: 10,
-            log_level: "info".to_string(),
-            api_token: None,
             http_timeout: 15,
+            ..test_config()
         };
 
         assert_eq!(config.http_timeout_duration(), Duration::from_secs(15));
@@ -83,57 +2398,156 @@ mod tests {
     #[test]
     fn test_metrics_bind_address() {
         let config = Config {
-            host: "192.168.1.100".to_string(),
             port: 3000,
-            poll_interval: 10,
-            log_level: "info".to_string(),
-            api_token: None,
-            http_timeout: 5,
+            ..test_config()
+        };
+
+        assert_eq!(
+            config.metrics_bind_address().unwrap().to_string(),
+            "0.0.0.0:3000"
+        );
+    }
+
+    #[test]
+    fn test_metrics_bind_address_ipv6_and_loopback() {
+        let bind = |address: &str| {
+            Config {
+                bind_address: address.to_string(),
+                ..test_config()
+            }
+            .metrics_bind_address()
         };
 
-        assert_eq!(config.metrics_bind_address(), "0.0.0.0:3000");
+        assert_eq!(bind("::").unwrap().to_string(), "[::]:9898");
+        assert_eq!(bind("[::]").unwrap().to_string(), "[::]:9898");
+        assert_eq!(bind("127.0.0.1").unwrap().to_string(), "127.0.0.1:9898");
+        assert_eq!(bind("::1").unwrap().to_string(), "[::1]:9898");
+
+        let err = bind("127.0.0.1:9000").unwrap_err();
+        assert!(err.to_string().contains("--port"));
+        assert!(bind("localhost").is_err());
+    }
+
+    #[test]
+    fn test_device_url() {
+        let devices = test_config().devices().unwrap();
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "192.168.1.100");
+        assert_eq!(devices[0].url(), "http://192.168.1.100/api/v1/data");
+    }
+
+    #[test]
+    fn test_device_url_with_hostname() {
+        let device: Device = "homewizard.local".parse().unwrap();
+
+        assert_eq!(device.url(), "http://homewizard.local/api/v1/data");
+    }
+
+    #[test]
+    fn test_multiple_named_devices() {
+        let config = Config::parse_from([
+            "homewizard-p1-exporter",
+            "--host",
+            "house=192.168.1.100",
+            "--host",
+            "annex=192.168.1.101:8080",
+        ]);
+        let devices = config.devices().unwrap();
+
+        assert_eq!(devices[0].name, "house");
+        assert_eq!(devices[0].host, "192.168.1.100");
+        assert_eq!(devices[1].name, "annex");
+        assert_eq!(devices[1].url(), "http://192.168.1.101:8080/api/v1/data");
+
+        let duplicate = Config {
+            host: vec!["a=192.168.1.100".to_string(), "a=192.168.1.101".to_string()],
+            ..test_config()
+        };
+        assert!(duplicate.devices().is_err());
+        assert!("house=".parse::<Device>().is_err());
     }
 
     #[test]
-    fn test_homewizard_url() {
+    fn test_device_product_suffix() {
+        let device: Device = "garden=192.168.1.60/watermeter".parse().unwrap();
+        assert_eq!(device.name, "garden");
+        assert_eq!(device.host, "192.168.1.60");
+        assert_eq!(device.product, Some(ProductType::Watermeter));
+
+        let device: Device = "heater=192.168.1.61/energy-socket".parse().unwrap();
+        assert_eq!(device.product, Some(ProductType::EnergySocket));
+
         let config = Config {
-            host: "192.168.1.100".to_string(),
-            port: 9898,
-            poll_interval: 10,
-            log_level: "info".to_string(),
-            api_token: None,
-            http_timeout: 5,
+            host: vec![
+                "a=192.168.1.62".to_string(),
+                "b=192.168.1.63/p1".to_string(),
+            ],
+            device_type: Some(ProductType::KwhMeter),
+            ..test_config()
         };
+        let devices = config.devices().unwrap();
+        assert_eq!(devices[0].product, Some(ProductType::KwhMeter));
+        assert_eq!(devices[1].product, Some(ProductType::P1));
 
-        assert_eq!(config.homewizard_url(), "http://192.168.1.100/api/v1/data");
+        let device: Device = "192.168.1.60".parse().unwrap();
+        assert_eq!(device.product, None);
+        assert!("192.168.1.60/toaster".parse::<Device>().is_err());
     }
 
     #[test]
-    fn test_homewizard_url_with_hostname() {
+    fn test_device_api_token() {
+        let token_file =
+            std::env::temp_dir().join(format!("homewizard-token-{}", std::process::id()));
+        std::fs::write(&token_file, "file-token\n").unwrap();
         let config = Config {
-            host: "homewizard.local".to_string(),
-            port: 9898,
-            poll_interval: 10,
-            log_level: "info".to_string(),
+            host: vec![
+                "house=192.168.1.100/p1;token=house-token".to_string(),
+                format!("annex=192.168.1.101;token_file={}", token_file.display()),
+                "garage=192.168.1.102".to_string(),
+            ],
+            api_token: Some("global".to_string()),
+            sources: vec![Source::V2],
+            ..test_config()
+        };
+        let devices = config.devices().unwrap();
+        assert_eq!(devices[0].product, Some(ProductType::P1));
+        assert_eq!(
+            config.device_api_token(&devices[0]).unwrap().as_deref(),
+            Some("house-token")
+        );
+        assert_eq!(
+            config.device_api_token(&devices[1]).unwrap().as_deref(),
+            Some("file-token")
+        );
+        assert_eq!(
+            config.device_api_token(&devices[2]).unwrap().as_deref(),
+            Some("global")
+        );
+        assert!(config.validate_sources().is_ok());
+
+        let without_global = Config {
             api_token: None,
-            http_timeout: 5,
+            ..config
         };
+        assert!(without_global.validate_sources().is_err());
+        std::fs::remove_file(&token_file).unwrap();
 
-        assert_eq!(
-            config.homewizard_url(),
-            "http://homewizard.local/api/v1/data"
+        assert!("192.168.1.100;colour=red".parse::<Device>().is_err());
+        assert!("192.168.1.100;token=".parse::<Device>().is_err());
+        assert!(
+            "192.168.1.100;token=a;token_file=/tmp/b"
+                .parse::<Device>()
+                .is_err()
         );
     }
 
     #[test]
     fn test_config_with_api_token() {
         let config = Config {
-            host: "192.168.1.100".to_string(),
-            port: 9898,
-            poll_interval: 10,
             log_level: "debug".to_string(),
             api_token: Some("secret_token".to_string()),
-            http_timeout: 5,
+            ..test_config()
         };
 
         assert_eq!(config.api_token, Some("secret_token".to_string()));
@@ -142,14 +2556,7 @@ mod tests {
 
     #[test]
     fn test_config_without_api_token() {
-        let config = Config {
-            host: "192.168.1.100".to_string(),
-            port: 9898,
-            poll_interval: 10,
-            log_level: "info".to_string(),
-            api_token: None,
-            http_timeout: 5,
-        };
+        let config = test_config();
 
         assert_eq!(config.api_token, None);
     }
@@ -157,19 +2564,277 @@ mod tests {
     #[test]
     fn test_config_edge_cases() {
         let config = Config {
-            host: "192.168.1.100".to_string(),
             port: 1,
-            poll_interval: 1,
+            poll_interval: Some(1),
             log_level: "trace".to_string(),
             api_token: Some("".to_string()),
             http_timeout: 1,
+            ..test_config()
         };
 
         assert_eq!(config.port, 1);
-        assert_eq!(config.poll_interval, 1);
+        assert_eq!(config.poll_interval, Some(1));
         assert_eq!(config.http_timeout, 1);
-        assert_eq!(config.metrics_bind_address(), "0.0.0.0:1");
+        assert_eq!(
+            config.metrics_bind_address().unwrap().to_string(),
+            "0.0.0.0:1"
+        );
         assert_eq!(config.poll_interval_duration(), Duration::from_secs(1));
         assert_eq!(config.http_timeout_duration(), Duration::from_secs(1));
     }
+
+    #[test]
+    fn test_poll_interval_defaults_before_detection() {
+        let config = Config {
+            poll_interval: None,
+            ..test_config()
+        };
+
+        assert_eq!(config.poll_interval_duration(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_effective_poll_interval_follows_smr_version() {
+        let config = Config {
+            poll_interval: None,
+            ..test_config()
+        };
+
+        assert_eq!(
+            config.effective_poll_interval(&SmrCapabilities::from_smr_version(50)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            config.effective_poll_interval(&SmrCapabilities::from_smr_version(42)),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn test_probe_labels() {
+        let config = Config {
+            labels: vec!["region=north".to_string()],
+            probe_labels: vec!["site".to_string(), "rack".to_string()],
+            ..test_config()
+        };
+        assert_eq!(config.probe_labels().unwrap(), ["site", "rack"]);
+
+        for reserved in ["region", "device", "__name__", "1st"] {
+            let config = Config {
+                probe_labels: vec![reserved.to_string()],
+                ..config.clone()
+            };
+            assert!(config.probe_labels().is_err(), "{reserved}");
+        }
+    }
+
+    #[test]
+    fn test_remote_write_headers() {
+        let config = Config {
+            remote_write_tenant: Some("house".to_string()),
+            remote_write_headers: vec!["X-Source = attic".to_string()],
+            ..test_config()
+        };
+        assert_eq!(
+            config.remote_write_headers().unwrap(),
+            [
+                ("X-Scope-OrgID".to_string(), "house".to_string()),
+                ("X-Source".to_string(), "attic".to_string()),
+            ]
+        );
+
+        let headers = |spec: &str| {
+            Config {
+                remote_write_headers: vec![spec.to_string()],
+                ..test_config()
+            }
+            .remote_write_headers()
+        };
+        assert!(headers("X-Source").is_err());
+        assert!(headers("Bad Name=1").is_err());
+        assert!(headers("Authorization=Bearer x").is_err());
+    }
+
+    #[test]
+    fn test_device_poll_interval_and_timeout() {
+        let config = Config {
+            host: vec![
+                "house=192.168.1.10;interval=2;timeout=1".to_string(),
+                "garden=192.168.1.11/watermeter".to_string(),
+            ],
+            poll_interval: None,
+            ..test_config()
+        };
+        let smr5 = SmrCapabilities::from_smr_version(50);
+        let devices = config.devices().unwrap();
+
+        assert_eq!(
+            config.device_poll_interval("house", Some(&smr5)),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            config.device_poll_interval("garden", Some(&smr5)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            config.device_poll_interval("garden", None),
+            DEFAULT_POLL_INTERVAL
+        );
+        assert_eq!(
+            config.device_http_timeout(&devices[0]),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            config.device_http_timeout(&devices[1]),
+            Duration::from_secs(5)
+        );
+
+        assert!("192.168.1.10;interval=0".parse::<Device>().is_err());
+        assert!("192.168.1.10;timeout=soon".parse::<Device>().is_err());
+    }
+
+    #[test]
+    fn test_effective_poll_interval_prefers_configured_value() {
+        let config = Config {
+            poll_interval: Some(30),
+            ..test_config()
+        };
+
+        assert_eq!(
+            config.effective_poll_interval(&SmrCapabilities::from_smr_version(50)),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn test_gas_stale_threshold_duration() {
+        assert_eq!(test_config().gas_stale_threshold_duration(), None);
+
+        let config = Config {
+            gas_stale_threshold: Some(900),
+            ..test_config()
+        };
+        assert_eq!(
+            config.gas_stale_threshold_duration(),
+            Some(Duration::from_secs(900))
+        );
+    }
+
+    #[test]
+    fn test_water_mode_exports() {
+        assert!(WaterMode::Volume.exports_volume());
+        assert!(!WaterMode::Volume.exports_flow());
+        assert!(!WaterMode::Flow.exports_volume());
+        assert!(WaterMode::Flow.exports_flow());
+        assert!(WaterMode::Both.exports_volume());
+        assert!(WaterMode::Both.exports_flow());
+    }
+
+    #[test]
+    fn test_read_only_is_default() {
+        let config = Config::parse_from(["homewizard-p1-exporter", "--host", "192.168.1.100"]);
+        assert!(config.read_only);
+
+        let config = Config::parse_from([
+            "homewizard-p1-exporter",
+            "--host",
+            "192.168.1.100",
+            "--read-only",
+            "false",
+        ]);
+        assert!(!config.read_only);
+    }
+
+    #[test]
+    fn test_sources_chain_parsing() {
+        let config = Config::parse_from(["homewizard-p1-exporter", "--host", "192.168.1.100"]);
+        assert_eq!(config.sources, vec![Source::V1]);
+
+        let config = Config::parse_from([
+            "homewizard-p1-exporter",
+            "--host",
+            "192.168.1.100",
+            "--sources",
+            "v1,telegram",
+        ]);
+        assert_eq!(config.sources, vec![Source::V1, Source::Telegram]);
+    }
+
+    #[test]
+    fn test_v2_source_requires_api_token() {
+        let config = Config {
+            sources: vec![Source::V2, Source::V1],
+            ..test_config()
+        };
+        assert!(config.validate_sources().is_err());
+
+        let config = Config {
+            api_token: Some("token".to_string()),
+            ..config
+        };
+        assert!(config.validate_sources().is_ok());
+        assert_eq!(
+            config.devices().unwrap()[0].v2_url(),
+            "https://192.168.1.100/api/measurement"
+        );
+    }
+
+    #[test]
+    fn test_plugin_battery_requires_api_token() {
+        let config = Config {
+            host: vec!["battery=192.168.1.64/plugin-battery".to_string()],
+            ..test_config()
+        };
+        assert!(config.validate_sources().is_err());
+
+        let config = Config {
+            api_token: Some("token".to_string()),
+            ..config
+        };
+        assert!(config.validate_sources().is_ok());
+    }
+
+    #[test]
+    fn test_set_setting() {
+        assert_eq!(
+            set_setting(
+                "HOMEWIZARD_HOST=192.168.1.10\n",
+                "HOMEWIZARD_API_TOKEN",
+                "abc"
+            ),
+            "HOMEWIZARD_HOST=192.168.1.10\nHOMEWIZARD_API_TOKEN=abc\n"
+        );
+        assert_eq!(
+            set_setting(
+                "# v2\nHOMEWIZARD_API_TOKEN=old\nPOLL_INTERVAL=5",
+                "HOMEWIZARD_API_TOKEN",
+                "new"
+            ),
+            "# v2\nHOMEWIZARD_API_TOKEN=new\nPOLL_INTERVAL=5\n"
+        );
+    }
+
+    #[test]
+    fn test_set_device_host() {
+        let specs = vec![
+            "meter=192.168.1.10/p1;interval=5".to_string(),
+            "water=192.168.1.11".to_string(),
+        ];
+        assert_eq!(
+            set_device_host(&specs, "meter", Some("192.168.1.20")),
+            ["meter=192.168.1.20/p1;interval=5", "water=192.168.1.11"]
+        );
+        assert_eq!(
+            set_device_host(&specs, "water", None),
+            ["meter=192.168.1.10/p1;interval=5"]
+        );
+        assert_eq!(
+            set_device_host(&specs, "socket", Some("192.168.1.12/energy-socket")),
+            [
+                "meter=192.168.1.10/p1;interval=5",
+                "water=192.168.1.11",
+                "socket=192.168.1.12/energy-socket"
+            ]
+        );
+    }
 }
@@ -0,0 +1,610 @@
+//! Month-to-date energy cost, end-of-month projection and running cost
+//! totals from a configured contract.
+
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::homewizard::HomeWizardData;
+
+/// Length of a demand measurement period for the capacity tariff.
+const QUARTER_HOUR_SECS: i64 = 15 * 60;
+
+/// Prices of an energy contract, in the user's currency.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Contract {
+    pub import_per_kwh: f64,
+    /// Import prices of tariffs 1-4 that differ from `import_per_kwh`
+    pub import_per_kwh_tariff: [Option<f64>; 4],
+    /// Compensation for exported energy, subtracted from the bill
+    pub export_per_kwh: f64,
+    pub export_per_kwh_tariff: [Option<f64>; 4],
+    pub gas_per_m3: f64,
+    pub fixed_per_month: f64,
+    pub fixed_per_day: f64,
+    /// Charged on the month's highest quarter-hour average import power
+    pub capacity_per_kw_month: f64,
+    /// Lowest peak the capacity charge is computed on
+    pub capacity_min_kw: f64,
+}
+
+/// Cost split by contract component.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CostBreakdown {
+    /// Imported energy minus export compensation
+    pub electricity: f64,
+    pub gas: f64,
+    pub fixed: f64,
+    pub capacity: f64,
+}
+
+impl CostBreakdown {
+    pub fn components(&self) -> [(&'static str, f64); 4] {
+        [
+            ("electricity", self.electricity),
+            ("gas", self.gas),
+            ("fixed", self.fixed),
+            ("capacity", self.capacity),
+        ]
+    }
+}
+
+impl Contract {
+    /// Import cost and export compensation of the energy metered between
+    /// two readings. Tariffs reported in both are priced individually.
+    fn energy_cost(&self, from: &Totals, to: &Totals) -> (f64, f64) {
+        (
+            priced(
+                to.import_kwh - from.import_kwh,
+                &from.import_tariffs_kwh,
+                &to.import_tariffs_kwh,
+                self.import_per_kwh,
+                &self.import_per_kwh_tariff,
+            ),
+            priced(
+                to.export_kwh - from.export_kwh,
+                &from.export_tariffs_kwh,
+                &to.export_tariffs_kwh,
+                self.export_per_kwh,
+                &self.export_per_kwh_tariff,
+            ),
+        )
+    }
+
+    /// Fixed costs of a whole month of `days` days.
+    fn fixed_for_month(&self, days: i64) -> f64 {
+        self.fixed_per_month + self.fixed_per_day * days as f64
+    }
+}
+
+/// Cost of `kwh` at `price`, or per tariff when both readings report the
+/// same tariffs.
+fn priced(
+    kwh: f64,
+    from: &[Option<f64>; 4],
+    to: &[Option<f64>; 4],
+    price: f64,
+    tariff_prices: &[Option<f64>; 4],
+) -> f64 {
+    if to.iter().all(Option::is_none) {
+        return kwh * price;
+    }
+    from.iter()
+        .zip(to)
+        .zip(tariff_prices)
+        .map(|((from, to), tariff_price)| match (from, to) {
+            (Some(from), Some(to)) => Some((to - from) * tariff_price.unwrap_or(price)),
+            (None, None) => Some(0.0),
+            _ => None,
+        })
+        .sum::<Option<f64>>()
+        .unwrap_or(kwh * price)
+}
+
+/// Running cost since tracking started, by what it is charged for.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CostTotals {
+    pub import: f64,
+    /// Compensation for exported energy
+    pub export: f64,
+    pub gas: f64,
+    pub fixed: f64,
+}
+
+impl CostTotals {
+    pub fn components(&self) -> [(&'static str, f64); 4] {
+        [
+            ("import", self.import),
+            ("export", self.export),
+            ("gas", self.gas),
+            ("fixed", self.fixed),
+        ]
+    }
+}
+
+/// Meter totals costs are computed from.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Totals {
+    import_kwh: f64,
+    import_tariffs_kwh: [Option<f64>; 4],
+    export_kwh: f64,
+    export_tariffs_kwh: [Option<f64>; 4],
+    gas_m3: f64,
+}
+
+impl Totals {
+    fn of(data: &HomeWizardData) -> Self {
+        Self {
+            import_kwh: data.total_power_import_kwh,
+            import_tariffs_kwh: [
+                data.total_power_import_t1_kwh,
+                data.total_power_import_t2_kwh,
+                data.total_power_import_t3_kwh,
+                data.total_power_import_t4_kwh,
+            ],
+            export_kwh: data.total_power_export_kwh,
+            export_tariffs_kwh: [
+                data.total_power_export_t1_kwh,
+                data.total_power_export_t2_kwh,
+                data.total_power_export_t3_kwh,
+                data.total_power_export_t4_kwh,
+            ],
+            gas_m3: data.total_gas_m3,
+        }
+    }
+
+    /// Totals only drop when the meter is swapped.
+    fn follows(&self, earlier: &Totals) -> bool {
+        self.import_kwh >= earlier.import_kwh
+            && self.export_kwh >= earlier.export_kwh
+            && self.gas_m3 >= earlier.gas_m3
+    }
+}
+
+/// Meter totals when tracking of the current month started.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct MonthStart {
+    month: (i32, u32),
+    started_at: DateTime<Utc>,
+    totals: Totals,
+}
+
+/// The reading the running totals were last updated with.
+#[derive(Debug, Clone, Copy)]
+struct LastReading {
+    at: DateTime<Utc>,
+    totals: Totals,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct QuarterStart {
+    index: i64,
+    at: DateTime<Utc>,
+    import_kwh: f64,
+    /// Tracking began mid-quarter, so its average is not representative
+    partial: bool,
+}
+
+/// The month tracked so far, saved across restarts with `--state-file`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MonthBaseline {
+    start: MonthStart,
+    peak_kw: f64,
+}
+
+#[derive(Debug)]
+pub struct CostTracker {
+    contract: Contract,
+    month: Option<MonthStart>,
+    quarter: Option<QuarterStart>,
+    peak_kw: f64,
+    last: Option<LastReading>,
+    totals: CostTotals,
+}
+
+impl CostTracker {
+    pub fn new(contract: Contract) -> Self {
+        Self {
+            contract,
+            month: None,
+            quarter: None,
+            peak_kw: 0.0,
+            last: None,
+            totals: CostTotals::default(),
+        }
+    }
+
+    /// Switches to new prices, keeping the month tracked so far.
+    pub fn set_contract(&mut self, contract: Contract) {
+        self.contract = contract;
+    }
+
+    /// The month tracked so far, once a reading has been seen.
+    pub fn baseline(&self) -> Option<MonthBaseline> {
+        Some(MonthBaseline {
+            start: self.month?,
+            peak_kw: self.peak_kw,
+        })
+    }
+
+    /// Continues a month tracked before a restart, so the month-to-date
+    /// cost includes what was used while the exporter was down. The first
+    /// reading discards it when it is of another month or meter.
+    pub fn restore(&mut self, baseline: MonthBaseline) {
+        if self.month.is_none() {
+            self.month = Some(baseline.start);
+            self.peak_kw = baseline.peak_kw;
+        }
+    }
+
+    /// Highest quarter-hour average import power seen this month, in kW.
+    #[cfg(test)]
+    pub fn peak_kw(&self) -> f64 {
+        self.peak_kw
+    }
+
+    /// Running cost since tracking started, at the prices in effect when
+    /// the energy was metered.
+    pub fn totals(&self) -> CostTotals {
+        self.totals
+    }
+
+    /// Returns the month-to-date cost and the projected cost for the whole
+    /// month. Usage is only known since tracking started, so after a
+    /// mid-month start the month-to-date figure covers that period and the
+    /// projection extrapolates its rate. Months follow the time zone of
+    /// `now`.
+    pub fn update<Tz: TimeZone>(
+        &mut self,
+        data: &HomeWizardData,
+        now: DateTime<Tz>,
+    ) -> (CostBreakdown, CostBreakdown) {
+        let totals = Totals::of(data);
+        let month = self.observe_month(totals, &now);
+        let (month_begin, month_end) = month_bounds(&now);
+        let now = now.to_utc();
+        self.observe_quarter(data, now);
+
+        let month_secs = (month_end - month_begin).num_seconds() as f64;
+        let elapsed_secs = (now - month_begin).num_seconds() as f64;
+        let tracked_secs = (now - month.started_at).num_seconds() as f64;
+        let fixed = self
+            .contract
+            .fixed_for_month(month_days(month.month.0, month.month.1));
+        self.accumulate(totals, now, fixed / month_secs);
+
+        let contract = &self.contract;
+        let (import, export) = contract.energy_cost(&month.totals, &totals);
+        let electricity = import - export;
+        let gas = (totals.gas_m3 - month.totals.gas_m3) * contract.gas_per_m3;
+        let capacity = if contract.capacity_per_kw_month > 0.0 {
+            self.peak_kw.max(contract.capacity_min_kw) * contract.capacity_per_kw_month
+        } else {
+            0.0
+        };
+
+        let month_to_date = CostBreakdown {
+            electricity,
+            gas,
+            fixed: fixed * elapsed_secs / month_secs,
+            capacity,
+        };
+
+        let scale = if tracked_secs > 0.0 {
+            month_secs / tracked_secs
+        } else {
+            1.0
+        };
+        let projected = CostBreakdown {
+            electricity: electricity * scale,
+            gas: gas * scale,
+            fixed,
+            capacity,
+        };
+
+        (month_to_date, projected)
+    }
+
+    /// Adds the cost since the last reading to the running totals. Fixed
+    /// costs accrue at `fixed_per_sec`; after a meter swap only the
+    /// reading is recorded.
+    fn accumulate(&mut self, totals: Totals, now: DateTime<Utc>, fixed_per_sec: f64) {
+        if let Some(last) = self.last
+            && totals.follows(&last.totals)
+        {
+            let (import, export) = self.contract.energy_cost(&last.totals, &totals);
+            self.totals.import += import;
+            self.totals.export += export;
+            self.totals.gas += (totals.gas_m3 - last.totals.gas_m3) * self.contract.gas_per_m3;
+            self.totals.fixed += fixed_per_sec * (now - last.at).num_seconds().max(0) as f64;
+        }
+        self.last = Some(LastReading { at: now, totals });
+    }
+
+    fn observe_month<Tz: TimeZone>(&mut self, totals: Totals, now: &DateTime<Tz>) -> MonthStart {
+        let current = (now.year(), now.month());
+        // Start counting afresh when the meter is swapped.
+        match self.month {
+            Some(start) if start.month == current && totals.follows(&start.totals) => start,
+            previous => {
+                if previous.is_none_or(|start| start.month != current) {
+                    self.peak_kw = 0.0;
+                }
+                let start = MonthStart {
+                    month: current,
+                    started_at: now.to_utc(),
+                    totals,
+                };
+                self.month = Some(start);
+                start
+            }
+        }
+    }
+
+    fn observe_quarter(&mut self, data: &HomeWizardData, now: DateTime<Utc>) {
+        let index = now.timestamp().div_euclid(QUARTER_HOUR_SECS);
+        match self.quarter {
+            Some(quarter) if quarter.index == index => {}
+            Some(quarter) => {
+                let hours = (now - quarter.at).num_seconds() as f64 / 3600.0;
+                let consumed = data.total_power_import_kwh - quarter.import_kwh;
+                // Only quarters observed from start to end, back to back.
+                if !quarter.partial && index == quarter.index + 1 && hours > 0.0 && consumed >= 0.0
+                {
+                    self.peak_kw = self.peak_kw.max(consumed / hours);
+                }
+                self.quarter = Some(QuarterStart {
+                    index,
+                    at: now,
+                    import_kwh: data.total_power_import_kwh,
+                    partial: false,
+                });
+            }
+            None => {
+                self.quarter = Some(QuarterStart {
+                    index,
+                    at: now,
+                    import_kwh: data.total_power_import_kwh,
+                    partial: true,
+                });
+            }
+        }
+    }
+}
+
+/// Start of the month containing `now` and of the next month, in the time
+/// zone of `now`.
+fn month_bounds<Tz: TimeZone>(now: &DateTime<Tz>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let (year, month) = (now.year(), now.month());
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    let zone = now.timezone();
+    (
+        local_midnight(&zone, year, month),
+        local_midnight(&zone, next_year, next_month),
+    )
+}
+
+/// Number of days in `month` of `year`.
+fn month_days(year: i32, month: u32) -> i64 {
+    let first = |year, month| NaiveDate::from_ymd_opt(year, month, 1).unwrap_or_default();
+    let next = if month == 12 {
+        first(year + 1, 1)
+    } else {
+        first(year, month + 1)
+    };
+    (next - first(year, month)).num_days()
+}
+
+fn local_midnight<Tz: TimeZone>(zone: &Tz, year: i32, month: u32) -> DateTime<Utc> {
+    let midnight = NaiveDate::from_ymd_opt(year, month, 1)
+        .unwrap_or_default()
+        .and_time(chrono::NaiveTime::MIN);
+    zone.from_local_datetime(&midnight)
+        .earliest()
+        .map_or_else(|| midnight.and_utc(), |time| time.to_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2026, 4, day, hour, minute, 0)
+            .single()
+            .unwrap()
+    }
+
+    fn reading(import_kwh: f64, export_kwh: f64, gas_m3: f64) -> HomeWizardData {
+        HomeWizardData {
+            total_power_import_kwh: import_kwh,
+            total_power_export_kwh: export_kwh,
+            total_gas_m3: gas_m3,
+            ..HomeWizardData::default()
+        }
+    }
+
+    #[test]
+    fn test_month_to_date_and_projection() {
+        let mut tracker = CostTracker::new(Contract {
+            import_per_kwh: 0.30,
+            export_per_kwh: 0.10,
+            gas_per_m3: 1.50,
+            fixed_per_month: 30.0,
+            ..Contract::default()
+        });
+
+        // Tracking starts at the beginning of April (30 days).
+        tracker.update(&reading(1000.0, 500.0, 100.0), at(1, 0, 0));
+        let (mtd, projected) = tracker.update(&reading(1100.0, 520.0, 110.0), at(16, 0, 0));
+
+        assert!((mtd.electricity - (100.0 * 0.30 - 20.0 * 0.10)).abs() < 1e-9);
+        assert!((mtd.gas - 15.0).abs() < 1e-9);
+        assert!((mtd.fixed - 15.0).abs() < 1e-9);
+        assert!((projected.electricity - 2.0 * mtd.electricity).abs() < 1e-9);
+        assert!((projected.gas - 30.0).abs() < 1e-9);
+        assert_eq!(projected.fixed, 30.0);
+    }
+
+    #[test]
+    fn test_restored_month_includes_downtime() {
+        let contract = Contract {
+            import_per_kwh: 1.0,
+            ..Contract::default()
+        };
+        let mut before = CostTracker::new(contract);
+        before.update(&reading(1000.0, 0.0, 0.0), at(1, 0, 0));
+        before.update(&reading(1010.0, 0.0, 0.0), at(2, 0, 0));
+
+        // 5 kWh were used while the exporter was down
+        let mut after = CostTracker::new(contract);
+        after.restore(before.baseline().unwrap());
+        let (mtd, _) = after.update(&reading(1015.0, 0.0, 0.0), at(3, 0, 0));
+        assert_eq!(mtd.electricity, 15.0);
+
+        // A baseline of another month is discarded
+        let mut next_month = CostTracker::new(contract);
+        next_month.restore(before.baseline().unwrap());
+        let may = Local
+            .with_ymd_and_hms(2026, 5, 2, 0, 0, 0)
+            .single()
+            .unwrap();
+        let (mtd, _) = next_month.update(&reading(1050.0, 0.0, 0.0), may);
+        assert_eq!(mtd.electricity, 0.0);
+    }
+
+    #[test]
+    fn test_new_month_restarts_tracking() {
+        let mut tracker = CostTracker::new(Contract {
+            import_per_kwh: 1.0,
+            ..Contract::default()
+        });
+
+        tracker.update(&reading(100.0, 0.0, 0.0), at(30, 23, 0));
+        let may = Local
+            .with_ymd_and_hms(2026, 5, 1, 0, 30, 0)
+            .single()
+            .unwrap();
+        let (mtd, _) = tracker.update(&reading(101.0, 0.0, 0.0), may);
+
+        assert_eq!(mtd.electricity, 0.0);
+    }
+
+    #[test]
+    fn test_months_follow_the_time_zone() {
+        let mut tracker = CostTracker::new(Contract {
+            import_per_kwh: 1.0,
+            ..Contract::default()
+        });
+        let amsterdam = chrono_tz::Europe::Amsterdam;
+
+        // 22:30 UTC on April 30 is already May in Amsterdam.
+        let april = Utc.with_ymd_and_hms(2026, 4, 30, 21, 0, 0).unwrap();
+        let may = Utc.with_ymd_and_hms(2026, 4, 30, 22, 30, 0).unwrap();
+        tracker.update(&reading(100.0, 0.0, 0.0), april.with_timezone(&amsterdam));
+        let (mtd, _) = tracker.update(&reading(101.0, 0.0, 0.0), may.with_timezone(&amsterdam));
+        assert_eq!(mtd.electricity, 0.0);
+
+        let mut tracker = CostTracker::new(Contract {
+            import_per_kwh: 1.0,
+            ..Contract::default()
+        });
+        tracker.update(&reading(100.0, 0.0, 0.0), april);
+        let (mtd, _) = tracker.update(&reading(101.0, 0.0, 0.0), may);
+        assert_eq!(mtd.electricity, 1.0);
+    }
+
+    #[test]
+    fn test_tariff_prices_and_daily_fees() {
+        let mut tracker = CostTracker::new(Contract {
+            import_per_kwh: 0.30,
+            import_per_kwh_tariff: [None, Some(0.20), None, None],
+            fixed_per_day: 1.0,
+            ..Contract::default()
+        });
+        let tariffs = |t1: f64, t2: f64| HomeWizardData {
+            total_power_import_kwh: t1 + t2,
+            total_power_import_t1_kwh: Some(t1),
+            total_power_import_t2_kwh: Some(t2),
+            ..HomeWizardData::default()
+        };
+
+        tracker.update(&tariffs(100.0, 100.0), at(1, 0, 0));
+        let (mtd, projected) = tracker.update(&tariffs(110.0, 120.0), at(16, 0, 0));
+
+        assert!((mtd.electricity - (10.0 * 0.30 + 20.0 * 0.20)).abs() < 1e-9);
+        assert!((mtd.fixed - 15.0).abs() < 1e-9);
+        assert!((projected.fixed - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_running_totals() {
+        let mut tracker = CostTracker::new(Contract {
+            import_per_kwh: 0.30,
+            export_per_kwh: 0.10,
+            gas_per_m3: 1.50,
+            ..Contract::default()
+        });
+
+        tracker.update(&reading(1000.0, 500.0, 100.0), at(10, 0, 0));
+        tracker.update(&reading(1010.0, 520.0, 102.0), at(10, 1, 0));
+        assert_eq!(
+            tracker
+                .totals()
+                .components()
+                .map(|(_, value)| value.round()),
+            [3.0, 2.0, 3.0, 0.0]
+        );
+
+        // A new meter starts from zero; only usage from then on counts.
+        tracker.update(&reading(0.0, 0.0, 0.0), at(10, 2, 0));
+        tracker.update(&reading(10.0, 0.0, 0.0), at(10, 3, 0));
+        let totals = tracker.totals();
+        assert!((totals.import - 6.0).abs() < 1e-9);
+        assert!((totals.export - 2.0).abs() < 1e-9);
+
+        // The running totals continue across months.
+        let may = Local
+            .with_ymd_and_hms(2026, 5, 1, 0, 30, 0)
+            .single()
+            .unwrap();
+        tracker.update(&reading(20.0, 0.0, 0.0), may);
+        assert!((tracker.totals().import - 9.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_capacity_uses_complete_quarter_hours() {
+        let mut tracker = CostTracker::new(Contract {
+            capacity_per_kw_month: 4.0,
+            capacity_min_kw: 2.5,
+            ..Contract::default()
+        });
+
+        // Started mid-quarter: that quarter is ignored.
+        tracker.update(&reading(0.0, 0.0, 0.0), at(2, 10, 5));
+        tracker.update(&reading(0.5, 0.0, 0.0), at(2, 10, 15));
+        assert_eq!(tracker.peak_kw(), 0.0);
+
+        // 1.5 kWh in a full quarter hour is a 6 kW average.
+        let (mtd, projected) = tracker.update(&reading(2.0, 0.0, 0.0), at(2, 10, 30));
+        assert!((tracker.peak_kw() - 6.0).abs() < 1e-9);
+        assert!((mtd.capacity - 24.0).abs() < 1e-9);
+        assert_eq!(projected.capacity, mtd.capacity);
+    }
+
+    #[test]
+    fn test_capacity_minimum_peak() {
+        let mut tracker = CostTracker::new(Contract {
+            capacity_per_kw_month: 4.0,
+            capacity_min_kw: 2.5,
+            ..Contract::default()
+        });
+
+        let (mtd, _) = tracker.update(&reading(0.0, 0.0, 0.0), at(2, 10, 0));
+        assert!((mtd.capacity - 10.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,195 @@
+//! `grafana-dashboard` subcommand: prints the bundled Grafana dashboard with
+//! its queries using this exporter's (prefixed or renamed) metric names and
+//! limited to its `--label` values, and a `device` variable to pick the
+//! devices shown.
+
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+
+use crate::config::Config;
+use crate::metrics::{DEFAULT_METRIC_PREFIX, MetricNames};
+
+const DASHBOARD: &str = include_str!("../grafana-dashboard.json");
+
+/// Metric whose `device` label values fill the `device` variable.
+const DEVICE_METRIC: &str = "homewizard_exporter_up";
+
+pub fn run(config: &Config) -> Result<()> {
+    let dashboard = dashboard(&config.static_labels()?, &config.metric_names()?)?;
+    println!("{}", serde_json::to_string_pretty(&dashboard)?);
+    Ok(())
+}
+
+fn dashboard(labels: &[(String, String)], names: &MetricNames) -> Result<Value> {
+    let mut dashboard: Value =
+        serde_json::from_str(DASHBOARD).context("The bundled dashboard is invalid")?;
+    let static_matchers: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{name}={value:?}"))
+        .collect();
+    let static_matchers = static_matchers.join(",");
+    let matchers = if static_matchers.is_empty() {
+        r#"device=~"$device""#.to_string()
+    } else {
+        format!(r#"device=~"$device",{static_matchers}"#)
+    };
+
+    if let Some(panels) = dashboard["panels"].as_array_mut() {
+        for target in panels
+            .iter_mut()
+            .filter_map(|panel| panel["targets"].as_array_mut())
+            .flatten()
+        {
+            if let Some(expr) = target["expr"].as_str() {
+                target["expr"] = Value::String(select(expr, names, &matchers));
+            }
+            if let Some(legend) = target["legendFormat"].as_str() {
+                target["legendFormat"] = Value::String(format!("{{{{device}}}} {legend}"));
+            }
+        }
+    }
+    if let Some(variables) = dashboard["templating"]["list"].as_array_mut() {
+        let query = format!(
+            "label_values({}, device)",
+            select(DEVICE_METRIC, names, &static_matchers)
+        );
+        variables.push(json!({
+            "datasource": { "type": "prometheus", "uid": "${datasource}" },
+            "definition": query,
+            "includeAll": true,
+            "label": "Device",
+            "multi": true,
+            "name": "device",
+            "query": {
+                "query": query,
+                "refId": "PrometheusVariableQueryEditor-VariableQuery"
+            },
+            "refresh": 2,
+            "sort": 1,
+            "type": "query"
+        }));
+    }
+    Ok(dashboard)
+}
+
+/// `expr` with the exporter's metrics named as in `names` and `matchers`
+/// added to their selectors.
+fn select(expr: &str, names: &MetricNames, matchers: &str) -> String {
+    let mut out = String::with_capacity(expr.len());
+    let mut rest = expr;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        let (before, from) = rest.split_at(start);
+        out.push_str(before);
+        let end = from
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+            .unwrap_or(from.len());
+        let (name, after) = from.split_at(end);
+        match name.strip_prefix(DEFAULT_METRIC_PREFIX) {
+            Some(p1) => out.push_str(&names.p1(p1)),
+            None if name.starts_with("homewizard_") => out.push_str(&names.name(name)),
+            None => out.push_str(name),
+        }
+        rest = after;
+        if !name.starts_with("homewizard_") || matchers.is_empty() {
+            continue;
+        }
+        match rest.strip_prefix('{') {
+            Some(selector) if selector.trim_start().starts_with('}') => {
+                out.push('{');
+                out.push_str(matchers);
+                rest = selector;
+            }
+            Some(selector) => {
+                out.push('{');
+                out.push_str(matchers);
+                out.push(',');
+                rest = selector;
+            }
+            None => {
+                out.push('{');
+                out.push_str(matchers);
+                out.push('}');
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homewizard::HomeWizardData;
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn test_select() {
+        assert_eq!(
+            select(
+                "homewizard_p1_active_power_watts",
+                &MetricNames::default(),
+                "device=~\"$device\""
+            ),
+            r#"homewizard_p1_active_power_watts{device=~"$device"}"#
+        );
+        assert_eq!(
+            select(
+                r#"sum by (device) (rate(homewizard_p1_power_import_total_kwh{tariff="1"}[5m]))"#,
+                &MetricNames::default(),
+                r#"device="house""#
+            ),
+            r#"sum by (device) (rate(homewizard_p1_power_import_total_kwh{device="house",tariff="1"}[5m]))"#
+        );
+        assert_eq!(select("up{}", &MetricNames::default(), "a=\"b\""), "up{}");
+    }
+
+    #[test]
+    fn test_dashboard_queries_exported_metrics() {
+        let dashboard = dashboard(&[], &MetricNames::default()).unwrap();
+
+        let metrics = Metrics::new().unwrap();
+        let data: HomeWizardData =
+            serde_json::from_str(include_str!("../example-response.json")).unwrap();
+        metrics.update(&data).unwrap();
+        metrics.record_poll_success();
+        let exposition = metrics.gather().unwrap();
+
+        let exprs: Vec<&str> = dashboard["panels"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|panel| panel["targets"].as_array())
+            .flatten()
+            .filter_map(|target| target["expr"].as_str())
+            .collect();
+        assert!(!exprs.is_empty());
+        for expr in exprs {
+            let name = expr.split('{').next().unwrap();
+            assert!(expr.ends_with(r#"{device=~"$device"}"#), "{expr}");
+            assert!(
+                exposition.lines().any(|line| line.starts_with(name)),
+                "{name} is not exported"
+            );
+        }
+        let variable = &dashboard["templating"]["list"][1];
+        assert_eq!(variable["name"], "device");
+        assert!(exposition.contains(DEVICE_METRIC));
+    }
+
+    #[test]
+    fn test_dashboard_with_prefix_and_static_labels() {
+        let dashboard = dashboard(
+            &[("site".to_string(), "attic".to_string())],
+            &MetricNames::new("p1_"),
+        )
+        .unwrap();
+        assert_eq!(
+            dashboard["panels"][0]["targets"][0]["expr"],
+            r#"p1_active_power_watts{device=~"$device",site="attic"}"#
+        );
+        assert_eq!(
+            dashboard["templating"]["list"][1]["definition"],
+            r#"label_values(homewizard_exporter_up{site="attic"}, device)"#
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! `discover` subcommand: finds HomeWizard devices on the LAN through mDNS
+//! and lists them with the `--host` value that polls each. Pollers use the
+//! same lookup to follow a device to a new address (`--rediscover-after`).
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use mdns_sd::{ResolvedService, ServiceDaemon, ServiceEvent};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config::{Config, device_url};
+use crate::homewizard::{HomeWizardClient, ProductType};
+
+/// Service types announced by devices with API v1 and API v2 enabled.
+const SERVICES: &[&str] = &["_hwenergy._tcp.local.", "_homewizard._tcp.local."];
+
+const REDISCOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiscoverFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+struct Discovered {
+    product_type: String,
+    product_name: String,
+    serial: String,
+    ip: String,
+    /// `None` when the local API is disabled
+    firmware_version: Option<String>,
+    /// `--host` value that polls this device
+    host: String,
+}
+
+/// Finds the host of the device with a serial number, `None` when it does
+/// not answer.
+pub type Rediscover = Arc<
+    dyn Fn(String) -> Pin<Box<dyn Future<Output = Result<Option<String>>> + Send>> + Send + Sync,
+>;
+
+/// Looks devices up through mDNS, browsing for as long as `discover` does
+/// by default.
+pub fn mdns() -> Rediscover {
+    Arc::new(|serial| {
+        Box::pin(async move {
+            let devices = browse(REDISCOVER_TIMEOUT).await?;
+            Ok(devices
+                .into_iter()
+                .find(|device| device.serial == serial)
+                .map(|device| device.ip))
+        })
+    })
+}
+
+/// Browses for `timeout` and prints the devices found. Fails when there
+/// are none.
+pub async fn run(config: &Config, format: DiscoverFormat, timeout: Duration) -> Result<()> {
+    let mut devices = browse(timeout).await?;
+    anyhow::ensure!(!devices.is_empty(), "No HomeWizard devices found");
+    for device in &mut devices {
+        device.firmware_version = firmware_version(&device.ip, config).await;
+    }
+
+    match format {
+        DiscoverFormat::Table => print!("{}", table(&devices)),
+        DiscoverFormat::Json => println!("{}", serde_json::to_string_pretty(&devices)?),
+    }
+    Ok(())
+}
+
+/// Devices answering within `timeout`, by serial number.
+async fn browse(timeout: Duration) -> Result<Vec<Discovered>> {
+    let daemon = ServiceDaemon::new().context("Failed to start mDNS")?;
+    let (sender, mut resolved) = tokio::sync::mpsc::unbounded_channel();
+    for service in SERVICES {
+        let events = daemon.browse(service)?;
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            while let Ok(event) = events.recv_async().await {
+                if let ServiceEvent::ServiceResolved(service) = event
+                    && let Some(device) = discovered(&service)
+                {
+                    let _ = sender.send(device);
+                }
+            }
+        });
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut found = BTreeMap::new();
+    while let Ok(Some(device)) = tokio::time::timeout_at(deadline, resolved.recv()).await {
+        // Devices with both APIs enabled answer for each
+        found.insert(device.serial.clone(), device);
+    }
+    let _ = daemon.shutdown();
+    Ok(found.into_values().collect())
+}
+
+/// A device from its mDNS record. The TXT record carries the product and
+/// serial number; records without them are not HomeWizard devices.
+fn discovered(service: &ResolvedService) -> Option<Discovered> {
+    let product_type = service.get_property_val_str("product_type")?.to_string();
+    let serial = service.get_property_val_str("serial")?.to_string();
+    // Prefer IPv4: it is what users put in `--host`
+    let ip = service
+        .addresses
+        .iter()
+        .map(|address| address.to_ip_addr())
+        .min_by_key(IpAddr::is_ipv6)?;
+    let host = match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{ip}]"),
+    };
+    Some(Discovered {
+        host: host_spec(&host, &product_type),
+        product_name: service
+            .get_property_val_str("product_name")
+            .unwrap_or_default()
+            .to_string(),
+        product_type,
+        serial,
+        ip: host,
+        firmware_version: None,
+    })
+}
+
+/// `host` with the product suffix `--host` needs for anything but a P1
+/// meter.
+fn host_spec(host: &str, product_type: &str) -> String {
+    match ProductType::from_product_type(product_type) {
+        Some(ProductType::P1) | None => host.to_string(),
+        Some(product) => match product.to_possible_value() {
+            Some(value) => format!("{host}/{}", value.get_name()),
+            None => host.to_string(),
+        },
+    }
+}
+
+async fn firmware_version(host: &str, config: &Config) -> Option<String> {
+    let client = HomeWizardClient::new(device_url(host), config.http_timeout_duration()).ok()?;
+    let info = client.fetch_device_info().await.ok()?;
+    Some(info.firmware_version)
+}
+
+fn table(devices: &[Discovered]) -> String {
+    let rows: Vec<[&str; 6]> = devices
+        .iter()
+        .map(|device| {
+            [
+                &device.product_type,
+                &device.product_name,
+                &device.serial,
+                &device.ip,
+                device.firmware_version.as_deref().unwrap_or("-"),
+                &device.host,
+            ]
+        })
+        .collect();
+    let header = ["PRODUCT", "NAME", "SERIAL", "IP", "FIRMWARE", "--host"];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdns_sd::ServiceInfo;
+
+    #[test]
+    fn test_discovered_from_txt_record() {
+        let properties = [
+            ("api_enabled", "1"),
+            ("path", "/api/v1"),
+            ("product_name", "P1 meter"),
+            ("product_type", "HWE-P1"),
+            ("serial", "3c39e7aabbcc"),
+        ];
+        let service = ServiceInfo::new(
+            "_hwenergy._tcp.local.",
+            "p1meter-aabbcc",
+            "p1meter-aabbcc.local.",
+            "fe80::1,192.168.1.10",
+            80,
+            &properties[..],
+        )
+        .unwrap()
+        .as_resolved_service();
+
+        let device = discovered(&service).unwrap();
+        assert_eq!(device.product_type, "HWE-P1");
+        assert_eq!(device.product_name, "P1 meter");
+        assert_eq!(device.serial, "3c39e7aabbcc");
+        assert_eq!(device.ip, "192.168.1.10");
+        assert_eq!(device.host, "192.168.1.10");
+
+        let other = ServiceInfo::new(
+            "_hwenergy._tcp.local.",
+            "printer",
+            "printer.local.",
+            "192.168.1.20",
+            80,
+            None,
+        )
+        .unwrap()
+        .as_resolved_service();
+        assert!(discovered(&other).is_none());
+    }
+
+    #[test]
+    fn test_host_spec() {
+        assert_eq!(host_spec("192.168.1.10", "HWE-P1"), "192.168.1.10");
+        assert_eq!(
+            host_spec("192.168.1.11", "HWE-WTR"),
+            "192.168.1.11/watermeter"
+        );
+        assert_eq!(host_spec("192.168.1.12", "HWE-XYZ"), "192.168.1.12");
+    }
+
+    #[test]
+    fn test_table() {
+        let devices = [Discovered {
+            product_type: "HWE-WTR".to_string(),
+            product_name: "Watermeter".to_string(),
+            serial: "3c39e7aabbcc".to_string(),
+            ip: "192.168.1.11".to_string(),
+            firmware_version: None,
+            host: "192.168.1.11/watermeter".to_string(),
+        }];
+        assert_eq!(
+            table(&devices),
+            "PRODUCT  NAME        SERIAL        IP            FIRMWARE  --host\n\
+             HWE-WTR  Watermeter  3c39e7aabbcc  192.168.1.11  -         192.168.1.11/watermeter\n"
+        );
+    }
+}
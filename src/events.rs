@@ -0,0 +1,333 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::fuse::OverloadChange;
+use crate::homewizard::HomeWizardData;
+use crate::http;
+
+/// Something noteworthy that happened on the grid or the device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceEvent {
+    /// Short machine-friendly kind, also used as an annotation tag
+    pub kind: &'static str,
+    pub description: String,
+}
+
+impl DeviceEvent {
+    fn new(kind: &'static str, description: impl Into<String>) -> Self {
+        Self {
+            kind,
+            description: description.into(),
+        }
+    }
+}
+
+impl DeviceEvent {
+    /// The device rebooted: its uptime dropped to `uptime_secs`.
+    pub fn restart(uptime_secs: f64) -> Self {
+        Self::new(
+            "device_restart",
+            format!("Device restarted {uptime_secs:.0}s ago"),
+        )
+    }
+}
+
+impl From<OverloadChange> for DeviceEvent {
+    fn from(change: OverloadChange) -> Self {
+        let phase = change.phase.to_uppercase();
+        if change.active {
+            Self::new(
+                "overload",
+                format!(
+                    "{phase} at {:.0}% of the fuse rating for too long",
+                    change.percent
+                ),
+            )
+        } else {
+            Self::new(
+                "overload_cleared",
+                format!("{phase} back below the fuse limit"),
+            )
+        }
+    }
+}
+
+/// Derives events from increases of the meter's event counters between
+/// consecutive readings.
+#[derive(Debug, Default)]
+pub struct EventDetector {
+    previous: Option<HomeWizardData>,
+}
+
+impl EventDetector {
+    pub fn detect(&mut self, data: &HomeWizardData) -> Vec<DeviceEvent> {
+        let Some(previous) = self.previous.replace(data.clone()) else {
+            return Vec::new();
+        };
+
+        let counters = [
+            (
+                "power_failure",
+                "Power failure",
+                previous.any_power_fail_count,
+                data.any_power_fail_count,
+            ),
+            (
+                "long_power_failure",
+                "Long power failure",
+                previous.long_power_fail_count,
+                data.long_power_fail_count,
+            ),
+            (
+                "voltage_sag",
+                "Voltage sag on L1",
+                previous.voltage_sag_l1_count,
+                data.voltage_sag_l1_count,
+            ),
+            (
+                "voltage_sag",
+                "Voltage sag on L2",
+                previous.voltage_sag_l2_count,
+                data.voltage_sag_l2_count,
+            ),
+            (
+                "voltage_sag",
+                "Voltage sag on L3",
+                previous.voltage_sag_l3_count,
+                data.voltage_sag_l3_count,
+            ),
+            (
+                "voltage_swell",
+                "Voltage swell on L1",
+                previous.voltage_swell_l1_count,
+                data.voltage_swell_l1_count,
+            ),
+            (
+                "voltage_swell",
+                "Voltage swell on L2",
+                previous.voltage_swell_l2_count,
+                data.voltage_swell_l2_count,
+            ),
+            (
+                "voltage_swell",
+                "Voltage swell on L3",
+                previous.voltage_swell_l3_count,
+                data.voltage_swell_l3_count,
+            ),
+        ];
+
+        // A decrease means the meter was reset or replaced; that is not an
+        // event, the new value simply becomes the baseline.
+        counters
+            .into_iter()
+            .filter(|(_, _, before, after)| after > before)
+            .map(|(kind, label, before, after)| {
+                let count = after - before;
+                let description = if count > 1.0 {
+                    format!("{label} ({count} events)")
+                } else {
+                    label.to_string()
+                };
+                DeviceEvent::new(kind, description)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Annotation<'a> {
+    time: i64,
+    tags: Vec<&'a str>,
+    text: String,
+}
+
+/// Posts events as annotations through the Grafana HTTP API.
+#[derive(Debug, Clone)]
+pub struct GrafanaAnnotator {
+    client: http::Client,
+    url: String,
+    token: Option<String>,
+    tags: Vec<String>,
+}
+
+impl GrafanaAnnotator {
+    pub fn new(
+        base_url: &str,
+        token: Option<String>,
+        tags: Vec<String>,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let client = http::Client::new(timeout)?;
+        Ok(Self {
+            client,
+            url: format!("{}/api/annotations", base_url.trim_end_matches('/')),
+            token,
+            tags,
+        })
+    }
+
+    pub async fn annotate(&self, device: &str, event: &DeviceEvent) -> Result<()> {
+        let mut tags: Vec<&str> = self.tags.iter().map(String::as_str).collect();
+        tags.push(event.kind);
+
+        let annotation = Annotation {
+            time: chrono::Utc::now().timestamp_millis(),
+            tags,
+            text: format!("{}: {}", device, event.description),
+        };
+
+        let mut request = self.client.post(&self.url).json(&annotation);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Fans detected events out to the log and the configured sinks.
+#[derive(Debug, Clone, Default)]
+pub struct EventPublisher {
+    grafana: Option<GrafanaAnnotator>,
+}
+
+impl EventPublisher {
+    pub fn new(grafana: Option<GrafanaAnnotator>) -> Self {
+        Self { grafana }
+    }
+
+    /// Logs the events and pushes them to the sinks in the background so a
+    /// slow sink never delays polling.
+    pub fn publish(&self, device: &str, events: Vec<DeviceEvent>) {
+        for event in events {
+            warn!("[{}] {}", device, event.description);
+
+            if let Some(grafana) = self.grafana.clone() {
+                let device = device.to_string();
+                tokio::spawn(async move {
+                    match grafana.annotate(&device, &event).await {
+                        Ok(()) => debug!("[{}] Annotated {} in Grafana", device, event.kind),
+                        Err(e) => warn!("[{}] Failed to post Grafana annotation: {}", device, e),
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_first_reading_sets_baseline() {
+        let mut detector = EventDetector::default();
+        let data = HomeWizardData {
+            any_power_fail_count: 5.0,
+            ..HomeWizardData::default()
+        };
+
+        assert!(detector.detect(&data).is_empty());
+        assert!(detector.detect(&data).is_empty());
+    }
+
+    #[test]
+    fn test_detects_counter_increases() {
+        let mut detector = EventDetector::default();
+        let mut data = HomeWizardData {
+            any_power_fail_count: 5.0,
+            voltage_sag_l2_count: 1.0,
+            ..HomeWizardData::default()
+        };
+        detector.detect(&data);
+
+        data.any_power_fail_count = 6.0;
+        data.voltage_sag_l2_count = 3.0;
+        let events = detector.detect(&data);
+
+        assert_eq!(
+            events,
+            vec![
+                DeviceEvent::new("power_failure", "Power failure"),
+                DeviceEvent::new("voltage_sag", "Voltage sag on L2 (2 events)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_counter_reset_is_not_an_event() {
+        let mut detector = EventDetector::default();
+        let mut data = HomeWizardData {
+            voltage_swell_l1_count: 10.0,
+            ..HomeWizardData::default()
+        };
+        detector.detect(&data);
+
+        data.voltage_swell_l1_count = 0.0;
+        assert!(detector.detect(&data).is_empty());
+
+        data.voltage_swell_l1_count = 1.0;
+        assert_eq!(detector.detect(&data).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_grafana_annotation_request() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/api/annotations"))
+            .and(header("Authorization", "Bearer glsa_token"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let annotator = GrafanaAnnotator::new(
+            &format!("{}/", mock_server.uri()),
+            Some("glsa_token".to_string()),
+            vec!["homewizard".to_string()],
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        annotator
+            .annotate("meter", &DeviceEvent::new("power_failure", "Power failure"))
+            .await
+            .unwrap();
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(
+            body["tags"],
+            serde_json::json!(["homewizard", "power_failure"])
+        );
+        assert_eq!(body["text"], "meter: Power failure");
+        assert!(body["time"].as_i64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_grafana_annotation_error_status() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        let annotator =
+            GrafanaAnnotator::new(&mock_server.uri(), None, vec![], Duration::from_secs(5))
+                .unwrap();
+
+        let result = annotator
+            .annotate(
+                "meter",
+                &DeviceEvent::new("voltage_sag", "Voltage sag on L1"),
+            )
+            .await;
+        assert!(result.is_err());
+    }
+}
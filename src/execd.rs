@@ -0,0 +1,135 @@
+//! Telegraf `execd` input mode: readings are written to stdout as Influx
+//! line protocol, and the process exits when Telegraf closes stdin.
+
+use anyhow::Result;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+use tracing::info;
+
+use crate::config::ExecdSignal;
+use crate::influx;
+use crate::scheduler::Reading;
+
+pub async fn run(readings: watch::Receiver<Option<Reading>>, signal: ExecdSignal) -> Result<()> {
+    let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+    run_with(stdin, tokio::io::stdout(), readings, signal).await
+}
+
+async fn run_with<R, W>(
+    input: R,
+    mut output: W,
+    mut readings: watch::Receiver<Option<Reading>>,
+    signal: ExecdSignal,
+) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = input.lines();
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                if line?.is_none() {
+                    info!("stdin closed, stopping");
+                    return Ok(());
+                }
+                // With signal = "STDIN" every line asks for the current reading.
+                if signal == ExecdSignal::Stdin {
+                    let reading = readings.borrow_and_update().clone();
+                    emit(&mut output, reading.as_ref()).await?;
+                }
+            }
+            changed = readings.changed(), if signal == ExecdSignal::None => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+                let reading = readings.borrow_and_update().clone();
+                emit(&mut output, reading.as_ref()).await?;
+            }
+        }
+    }
+}
+
+async fn emit<W: AsyncWrite + Unpin>(output: &mut W, reading: Option<&Reading>) -> Result<()> {
+    let Some(reading) = reading else {
+        return Ok(());
+    };
+    let line = influx::render(&reading.data, reading.timestamp_ms * 1_000_000);
+    output.write_all(line.as_bytes()).await?;
+    output.write_all(b"\n").await?;
+    output.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homewizard::HomeWizardData;
+
+    fn reading(timestamp_ms: i64, power: f64) -> Option<Reading> {
+        Some(Reading {
+            timestamp_ms,
+            data: HomeWizardData {
+                active_power_w: power,
+                ..HomeWizardData::default()
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn test_stdin_signal_emits_current_reading() {
+        let (_tx, rx) = watch::channel(reading(1000, 250.0));
+        let mut output = Vec::new();
+
+        // Two gather signals, then Telegraf closes stdin.
+        run_with(&b"\n\n"[..], &mut output, rx, ExecdSignal::Stdin)
+            .await
+            .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("homewizard_p1 active_power_w=250,"));
+        assert!(lines[0].ends_with(" 1000000000"));
+    }
+
+    #[tokio::test]
+    async fn test_stdin_signal_without_reading_emits_nothing() {
+        let (_tx, rx) = watch::channel(None);
+        let mut output = Vec::new();
+
+        run_with(&b"\n"[..], &mut output, rx, ExecdSignal::Stdin)
+            .await
+            .unwrap();
+
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_no_signal_emits_each_poll() {
+        let (tx, rx) = watch::channel(None);
+        let (input, mut telegraf) = tokio::io::duplex(64);
+        let (output, mut received) = tokio::io::duplex(1024);
+
+        let execd = tokio::spawn(run_with(
+            tokio::io::BufReader::new(input),
+            output,
+            rx,
+            ExecdSignal::None,
+        ));
+
+        tx.send(reading(2000, 100.0)).unwrap();
+        let mut buffer = vec![0u8; 1024];
+        let n = tokio::io::AsyncReadExt::read(&mut received, &mut buffer)
+            .await
+            .unwrap();
+        assert!(
+            String::from_utf8_lossy(&buffer[..n]).starts_with("homewizard_p1 active_power_w=100,")
+        );
+
+        telegraf.shutdown().await.unwrap();
+        drop(telegraf);
+        execd.await.unwrap().unwrap();
+    }
+}
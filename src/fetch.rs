@@ -0,0 +1,212 @@
+//! `fetch` subcommand: polls every device once, prints the result to stdout
+//! and exits, for checking connectivity and for cron jobs.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::config::{Config, Device};
+use crate::homewizard::{DeviceInfo, HomeWizardClient, HomeWizardData, ParseMode};
+use crate::metrics::{self, Metrics, MetricsOptions};
+
+/// Every device was fetched.
+pub const EXIT_OK: i32 = 0;
+/// At least one device could not be fetched.
+pub const EXIT_FETCH_FAILED: i32 = 1;
+/// The configuration is invalid.
+pub const EXIT_CONFIG: i32 = 2;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FetchFormat {
+    /// Prometheus text format, as served at `/metrics`
+    #[default]
+    Prom,
+    /// The device reading as JSON; keyed by device name when there are
+    /// several devices
+    Json,
+}
+
+/// Fetches the devices and prints them in `format`, returning the exit
+/// status.
+pub async fn run(config: &Config, format: FetchFormat) -> i32 {
+    let (devices, options) = match setup(config) {
+        Ok(setup) => setup,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            return EXIT_CONFIG;
+        }
+    };
+
+    let mut status = EXIT_OK;
+    let mut device_metrics = Vec::with_capacity(devices.len());
+    let mut readings = Map::new();
+    for device in &devices {
+        let (metrics, reading) = match fetch(config, device, &options).await {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                eprintln!("Error: [{}] {e:#}", device.name);
+                return EXIT_CONFIG;
+            }
+        };
+        match reading {
+            Ok(data) => {
+                if let Ok(value) = serde_json::to_value(&data) {
+                    readings.insert(device.name.clone(), value);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: [{}] {e}", device.name);
+                status = EXIT_FETCH_FAILED;
+            }
+        }
+        device_metrics.push(Arc::new(metrics));
+    }
+
+    let output = match format {
+        FetchFormat::Prom => metrics::gather_all(&device_metrics),
+        FetchFormat::Json => {
+            let json = if devices.len() == 1 {
+                readings.into_iter().next().map(|(_, value)| value)
+            } else {
+                Some(Value::Object(readings))
+            };
+            match json {
+                Some(json) => serde_json::to_string_pretty(&json)
+                    .map(|json| json + "\n")
+                    .map_err(Into::into),
+                None => Ok(String::new()),
+            }
+        }
+    };
+    match output {
+        Ok(output) => {
+            print!("{output}");
+            let _ = std::io::stdout().flush();
+        }
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            return EXIT_FETCH_FAILED;
+        }
+    }
+    status
+}
+
+fn setup(config: &Config) -> Result<(Vec<Device>, MetricsOptions)> {
+    config.validate_sources()?;
+    // History-based trackers (cost, net metering, degree days) have
+    // nothing to report after a single poll
+    let options = MetricsOptions {
+        gas_stale_threshold: config.gas_stale_threshold_duration(),
+        water_mode: config.water_mode,
+        fuse: config.fuse_limit()?,
+        raw_passthrough: config.raw_passthrough,
+        gas_timestamp_raw: config.gas_timestamp_raw,
+        schema_report: config.parse_mode == ParseMode::Report,
+        labels: config.static_labels()?,
+        names: config.metric_names()?,
+        sensor_names: config.sensor_names()?,
+        sensor_filter: config.sensor_filter(),
+        timezone: config.timezone,
+        ..MetricsOptions::default()
+    };
+    Ok((config.devices()?, options))
+}
+
+/// Polls `device` once. The metrics record a failed poll as well.
+async fn fetch(
+    config: &Config,
+    device: &Device,
+    options: &MetricsOptions,
+) -> Result<(Metrics, Result<HomeWizardData, String>)> {
+    let mut client = HomeWizardClient::new(device.url(), config.device_http_timeout(device))?
+        .parse_mode(config.parse_mode);
+    if let Some(token) = config.device_api_token(device)? {
+        client = client.api_v2(device.v2_url(), token);
+    }
+    let info = client.fetch_device_info().await.ok();
+    let product = device
+        .product
+        .or_else(|| info.as_ref().and_then(DeviceInfo::product))
+        .unwrap_or_default();
+    let client = client.product(product);
+    let metrics = Metrics::with_options(MetricsOptions {
+        device: Some(device.name.clone()),
+        product,
+        ..options.clone()
+    })?;
+    if let Some(info) = &info {
+        metrics.set_device_info(info);
+    }
+
+    let started = Instant::now();
+    let fetched = client.fetch_with_fallback(&config.sources).await;
+    metrics.observe_fetch_duration(started.elapsed());
+    let reading = match fetched {
+        Ok((data, source)) => {
+            metrics.record_poll_success();
+            metrics.set_active_source(source.as_str());
+            metrics.update(&data)?;
+            Ok(data)
+        }
+        Err(e) => {
+            metrics.record_poll_error(e.class());
+            Err(e.to_string())
+        }
+    };
+    Ok((metrics, reading))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn device() -> MockServer {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../example-response.json")),
+            )
+            .mount(&mock_server)
+            .await;
+        mock_server
+    }
+
+    #[tokio::test]
+    async fn test_fetch_records_reading() {
+        let mock_server = device().await;
+        let host = format!("house={}/p1", mock_server.address());
+        let config = Config::parse_from(["homewizard-p1-exporter", "--host", &host]);
+        let (devices, options) = setup(&config).unwrap();
+
+        let (metrics, reading) = fetch(&config, &devices[0], &options).await.unwrap();
+        assert!(reading.unwrap().active_power_w > 0.0);
+        let output = metrics::gather_all(&[Arc::new(metrics)]).unwrap();
+        assert!(output.contains(r#"homewizard_exporter_up{device="house"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_unreachable_device() {
+        let config = Config::parse_from([
+            "homewizard-p1-exporter",
+            "--host",
+            "127.0.0.1:1/p1",
+            "--http-timeout",
+            "1",
+        ]);
+        assert_eq!(run(&config, FetchFormat::Json).await, EXIT_FETCH_FAILED);
+    }
+
+    #[test]
+    fn test_fetch_without_host() {
+        let config = Config::parse_from(["homewizard-p1-exporter", "fetch"]);
+        assert!(setup(&config).is_err());
+    }
+}
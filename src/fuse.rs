@@ -0,0 +1,245 @@
+//! Per-phase load relative to the main fuse of the grid connection.
+
+use anyhow::{Context, Result, bail};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::homewizard::HomeWizardData;
+
+/// Connection rating such as `1x35A` or `3x25A`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FuseRating {
+    pub phases: usize,
+    pub amperes: f64,
+}
+
+impl FromStr for FuseRating {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (phases, amperes) = value
+            .to_ascii_lowercase()
+            .split_once('x')
+            .map(|(phases, amperes)| (phases.trim().to_string(), amperes.trim().to_string()))
+            .with_context(|| format!("Invalid fuse rating {value:?}, expected e.g. 3x25A"))?;
+        let phases: usize = phases
+            .parse()
+            .with_context(|| format!("Invalid phase count in {value:?}"))?;
+        let amperes: f64 = amperes
+            .trim_end_matches('a')
+            .parse()
+            .with_context(|| format!("Invalid current in {value:?}"))?;
+        if !matches!(phases, 1 | 3) {
+            bail!("Fuse rating {value:?} must have 1 or 3 phases");
+        }
+        if amperes.is_nan() || amperes <= 0.0 {
+            bail!("Fuse rating {value:?} must have a positive current");
+        }
+        Ok(Self { phases, amperes })
+    }
+}
+
+/// Settings for the fuse utilization metrics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FuseLimit {
+    pub rating: FuseRating,
+    /// Utilization (%) from which a phase counts as near its limit
+    pub near_limit_percent: f64,
+}
+
+impl FuseRating {
+    /// Current per phase as a percentage of the fuse rating. Currents are
+    /// taken as absolute values, so heavy export counts too.
+    pub fn utilization(&self, data: &HomeWizardData) -> Vec<(&'static str, f64)> {
+        [
+            ("l1", data.active_current_l1_a),
+            ("l2", data.active_current_l2_a),
+            ("l3", data.active_current_l3_a),
+        ]
+        .into_iter()
+        .take(self.phases)
+        .map(|(phase, current)| (phase, current.abs() / self.amperes * 100.0))
+        .collect()
+    }
+}
+
+/// When a phase above the near-limit threshold becomes an overload warning.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverloadPolicy {
+    pub limit: FuseLimit,
+    /// How long a phase must stay near its limit before warning
+    pub duration: Duration,
+}
+
+/// A phase entering or leaving the overload state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverloadChange {
+    pub phase: &'static str,
+    pub percent: f64,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PhaseOverload {
+    /// Since when the phase has been near its limit
+    since: Option<Instant>,
+    active: bool,
+}
+
+/// Tracks sustained load near the fuse rating per phase.
+#[derive(Debug)]
+pub struct OverloadDetector {
+    policy: OverloadPolicy,
+    phases: [PhaseOverload; 3],
+}
+
+impl OverloadDetector {
+    pub fn new(policy: OverloadPolicy) -> Self {
+        Self {
+            policy,
+            phases: Default::default(),
+        }
+    }
+
+    pub fn observe(&mut self, data: &HomeWizardData, now: Instant) -> Vec<OverloadChange> {
+        let mut changes = Vec::new();
+        let utilization = self.policy.limit.rating.utilization(data);
+
+        for (state, (phase, percent)) in self.phases.iter_mut().zip(utilization) {
+            if percent < self.policy.limit.near_limit_percent {
+                state.since = None;
+                if state.active {
+                    state.active = false;
+                    changes.push(OverloadChange {
+                        phase,
+                        percent,
+                        active: false,
+                    });
+                }
+                continue;
+            }
+
+            let since = *state.since.get_or_insert(now);
+            if !state.active && now.duration_since(since) >= self.policy.duration {
+                state.active = true;
+                changes.push(OverloadChange {
+                    phase,
+                    percent,
+                    active: true,
+                });
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fuse_rating() {
+        assert_eq!(
+            "3x25A".parse::<FuseRating>().unwrap(),
+            FuseRating {
+                phases: 3,
+                amperes: 25.0
+            }
+        );
+        assert_eq!(
+            "1 x 35".parse::<FuseRating>().unwrap(),
+            FuseRating {
+                phases: 1,
+                amperes: 35.0
+            }
+        );
+        assert!("2x25A".parse::<FuseRating>().is_err());
+        assert!("3x0A".parse::<FuseRating>().is_err());
+        assert!("25A".parse::<FuseRating>().is_err());
+    }
+
+    #[test]
+    fn test_utilization_per_phase() {
+        let data = HomeWizardData {
+            active_current_l1_a: 20.0,
+            active_current_l2_a: -5.0,
+            active_current_l3_a: 25.0,
+            ..HomeWizardData::default()
+        };
+
+        let rating: FuseRating = "3x25A".parse().unwrap();
+        assert_eq!(
+            rating.utilization(&data),
+            vec![("l1", 80.0), ("l2", 20.0), ("l3", 100.0)]
+        );
+
+        let single: FuseRating = "1x40A".parse().unwrap();
+        assert_eq!(single.utilization(&data), vec![("l1", 50.0)]);
+    }
+
+    #[test]
+    fn test_overload_after_sustained_load() {
+        let mut detector = OverloadDetector::new(OverloadPolicy {
+            limit: FuseLimit {
+                rating: "1x25A".parse().unwrap(),
+                near_limit_percent: 80.0,
+            },
+            duration: Duration::from_secs(60),
+        });
+        let load = |current| HomeWizardData {
+            active_current_l1_a: current,
+            ..HomeWizardData::default()
+        };
+        let start = Instant::now();
+
+        assert!(detector.observe(&load(22.0), start).is_empty());
+        assert!(
+            detector
+                .observe(&load(23.0), start + Duration::from_secs(30))
+                .is_empty()
+        );
+
+        let changes = detector.observe(&load(24.0), start + Duration::from_secs(60));
+        assert_eq!(
+            changes,
+            vec![OverloadChange {
+                phase: "l1",
+                percent: 96.0,
+                active: true
+            }]
+        );
+        assert!(
+            detector
+                .observe(&load(24.0), start + Duration::from_secs(90))
+                .is_empty()
+        );
+
+        let changes = detector.observe(&load(10.0), start + Duration::from_secs(120));
+        assert_eq!(changes.len(), 1);
+        assert!(!changes[0].active);
+    }
+
+    #[test]
+    fn test_short_peaks_do_not_warn() {
+        let mut detector = OverloadDetector::new(OverloadPolicy {
+            limit: FuseLimit {
+                rating: "1x25A".parse().unwrap(),
+                near_limit_percent: 80.0,
+            },
+            duration: Duration::from_secs(60),
+        });
+        let load = |current| HomeWizardData {
+            active_current_l1_a: current,
+            ..HomeWizardData::default()
+        };
+        let start = Instant::now();
+
+        detector.observe(&load(24.0), start);
+        detector.observe(&load(5.0), start + Duration::from_secs(30));
+        assert!(
+            detector
+                .observe(&load(24.0), start + Duration::from_secs(70))
+                .is_empty()
+        );
+    }
+}
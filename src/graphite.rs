@@ -0,0 +1,235 @@
+//! Graphite plaintext protocol sink (`--graphite-host`): every
+//! `--graphite-interval` seconds each series is sent to Carbon as
+//! `<prefix>.<metric>.<label values> <value> <timestamp>`.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use prometheus::proto::MetricFamily;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::{debug, warn};
+
+use crate::metrics::{self, DeviceMetrics};
+use crate::remote_write;
+
+/// Keeps datagrams below a typical MTU.
+const MAX_DATAGRAM: usize = 1400;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GraphiteProtocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphiteSender {
+    /// `host:port` of the Carbon receiver
+    address: String,
+    protocol: GraphiteProtocol,
+    prefix: String,
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl GraphiteSender {
+    pub fn new(
+        address: String,
+        protocol: GraphiteProtocol,
+        prefix: &str,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            address,
+            protocol,
+            prefix: prefix.trim_matches('.').to_string(),
+            interval,
+            timeout,
+        }
+    }
+
+    pub async fn run(self, devices: DeviceMetrics) {
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await; // Skip the immediate tick, before the first poll
+        loop {
+            ticker.tick().await;
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let lines = render(
+                &metrics::gather_families(&devices.snapshot()),
+                &self.prefix,
+                timestamp,
+            );
+            match tokio::time::timeout(self.timeout, self.send(&lines)).await {
+                Ok(Ok(())) => debug!("Sent {} metrics to {}", lines.len(), self.address),
+                Ok(Err(e)) => warn!("Failed to send metrics to {}: {}", self.address, e),
+                Err(_) => warn!("Timed out sending metrics to {}", self.address),
+            }
+        }
+    }
+
+    async fn send(&self, lines: &[String]) -> Result<()> {
+        match self.protocol {
+            GraphiteProtocol::Tcp => {
+                let mut stream = TcpStream::connect(&self.address).await?;
+                stream.write_all(lines.concat().as_bytes()).await?;
+                stream.shutdown().await?;
+            }
+            GraphiteProtocol::Udp => {
+                let address = tokio::net::lookup_host(&self.address)
+                    .await?
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("{} did not resolve", self.address))?;
+                let local = if address.is_ipv6() {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                };
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(address).await?;
+                for datagram in datagrams(lines) {
+                    socket.send(datagram.as_bytes()).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One line per series. The path is the prefix, the metric name and the
+/// label values in label name order, e.g.
+/// `homewizard.homewizard_p1_power_watts.house.l1`.
+fn render(families: &[MetricFamily], prefix: &str, timestamp: u64) -> Vec<String> {
+    remote_write::series(families)
+        .into_iter()
+        .filter(|series| series.value.is_finite())
+        .map(|series| {
+            let mut path = prefix.to_string();
+            let name = series.labels.iter().find(|(label, _)| label == "__name__");
+            for (_, value) in name.into_iter().chain(
+                series
+                    .labels
+                    .iter()
+                    .filter(|(label, _)| label != "__name__"),
+            ) {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&component(value));
+            }
+            format!("{path} {} {timestamp}\n", series.value)
+        })
+        .collect()
+}
+
+/// A path component: dots and anything else Carbon would misread become
+/// underscores.
+fn component(value: &str) -> String {
+    let component: String = value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '+') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if component.is_empty() {
+        "_".to_string()
+    } else {
+        component
+    }
+}
+
+/// Packs whole lines into datagrams of at most [`MAX_DATAGRAM`] bytes.
+fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + line.len() > MAX_DATAGRAM {
+            datagrams.push(std::mem::take(&mut current));
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+    datagrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{GaugeVec, Opts, Registry};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let power = GaugeVec::new(
+            Opts::new("homewizard_p1_power_watts", "Power").const_label("device", "house"),
+            &["phase"],
+        )
+        .unwrap();
+        registry.register(Box::new(power.clone())).unwrap();
+        power.with_label_values(&["l1"]).set(250.5);
+        registry.gather()
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(&families(), "homewizard", 1700000000),
+            ["homewizard.homewizard_p1_power_watts.house.l1 250.5 1700000000\n"]
+        );
+    }
+
+    #[test]
+    fn test_component() {
+        assert_eq!(component("192.168.1.10"), "192_168_1_10");
+        assert_eq!(component("+Inf"), "+Inf");
+        assert_eq!(component(""), "_");
+    }
+
+    #[test]
+    fn test_datagrams_keep_lines_whole() {
+        let lines = vec![
+            "a".repeat(1000) + "\n",
+            "b".repeat(1000) + "\n",
+            "c\n".to_string(),
+        ];
+        let datagrams = datagrams(&lines);
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(datagrams[1], format!("{}\nc\n", "b".repeat(1000)));
+    }
+
+    #[tokio::test]
+    async fn test_send_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sender = GraphiteSender::new(
+            listener.local_addr().unwrap().to_string(),
+            GraphiteProtocol::Tcp,
+            "homewizard.",
+            Duration::from_secs(60),
+            Duration::from_secs(5),
+        );
+        let lines = render(&families(), &sender.prefix, 1700000000);
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).await.unwrap();
+            received
+        });
+        sender.send(&lines).await.unwrap();
+        assert_eq!(
+            received.await.unwrap(),
+            "homewizard.homewizard_p1_power_watts.house.l1 250.5 1700000000\n"
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Flat JSON for Home Assistant's `rest` sensor platform. The keys are part
+//! of the public interface: add new ones, never rename or remove them.
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::homewizard::HomeWizardData;
+
+pub type SharedHomeAssistant = Arc<RwLock<HomeAssistantState>>;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HomeAssistantSensors {
+    pub power_w: f64,
+    pub power_l1_w: f64,
+    pub power_l2_w: f64,
+    pub power_l3_w: f64,
+    pub voltage_l1_v: f64,
+    pub voltage_l2_v: f64,
+    pub voltage_l3_v: f64,
+    pub current_l1_a: f64,
+    pub current_l2_a: f64,
+    pub current_l3_a: f64,
+    pub energy_import_today_kwh: f64,
+    pub energy_export_today_kwh: f64,
+    pub gas_today_m3: f64,
+    pub energy_import_total_kwh: f64,
+    pub energy_export_total_kwh: f64,
+    pub gas_total_m3: f64,
+    pub tariff: i32,
+    /// Unix time of the reading in seconds
+    pub timestamp: i64,
+}
+
+/// Meter totals at the first reading of a local calendar day, saved across
+/// restarts with `--state-file`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DayStart {
+    day: NaiveDate,
+    import_kwh: f64,
+    export_kwh: f64,
+    gas_m3: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct HomeAssistantState {
+    day_start: Option<DayStart>,
+    sensors: Option<HomeAssistantSensors>,
+}
+
+impl HomeAssistantState {
+    /// Updates the sensors from a reading taken on local day `day`. "Today"
+    /// values count from the first reading of the day, so after a restart
+    /// they only cover the time since the restart unless the day start was
+    /// restored.
+    pub fn update(&mut self, data: &HomeWizardData, day: NaiveDate, timestamp: i64) {
+        // Totals only drop when the meter is swapped; start counting afresh.
+        let day_start = match self.day_start {
+            Some(start)
+                if start.day == day
+                    && data.total_power_import_kwh >= start.import_kwh
+                    && data.total_power_export_kwh >= start.export_kwh
+                    && data.total_gas_m3 >= start.gas_m3 =>
+            {
+                start
+            }
+            _ => DayStart {
+                day,
+                import_kwh: data.total_power_import_kwh,
+                export_kwh: data.total_power_export_kwh,
+                gas_m3: data.total_gas_m3,
+            },
+        };
+        self.day_start = Some(day_start);
+
+        self.sensors = Some(HomeAssistantSensors {
+            power_w: data.active_power_w,
+            power_l1_w: data.active_power_l1_w,
+            power_l2_w: data.active_power_l2_w,
+            power_l3_w: data.active_power_l3_w,
+            voltage_l1_v: data.active_voltage_l1_v,
+            voltage_l2_v: data.active_voltage_l2_v,
+            voltage_l3_v: data.active_voltage_l3_v,
+            current_l1_a: data.active_current_l1_a,
+            current_l2_a: data.active_current_l2_a,
+            current_l3_a: data.active_current_l3_a,
+            energy_import_today_kwh: data.total_power_import_kwh - day_start.import_kwh,
+            energy_export_today_kwh: data.total_power_export_kwh - day_start.export_kwh,
+            gas_today_m3: data.total_gas_m3 - day_start.gas_m3,
+            energy_import_total_kwh: data.total_power_import_kwh,
+            energy_export_total_kwh: data.total_power_export_kwh,
+            gas_total_m3: data.total_gas_m3,
+            tariff: data.active_tariff,
+            timestamp,
+        });
+    }
+
+    pub fn day_start(&self) -> Option<DayStart> {
+        self.day_start
+    }
+
+    /// Continues a day started before a restart, so "today" values include
+    /// what was used while the exporter was down. The first reading
+    /// discards it when it is of another day or meter.
+    pub fn restore(&mut self, day_start: DayStart) {
+        if self.day_start.is_none() {
+            self.day_start = Some(day_start);
+        }
+    }
+
+    pub fn sensors(&self) -> Option<&HomeAssistantSensors> {
+        self.sensors.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(import_kwh: f64, gas_m3: f64) -> HomeWizardData {
+        HomeWizardData {
+            total_power_import_kwh: import_kwh,
+            total_gas_m3: gas_m3,
+            ..HomeWizardData::default()
+        }
+    }
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, d).unwrap()
+    }
+
+    #[test]
+    fn test_today_counts_from_first_reading_of_the_day() {
+        let mut state = HomeAssistantState::default();
+        assert!(state.sensors().is_none());
+
+        state.update(&reading(100.0, 50.0), day(1), 0);
+        state.update(&reading(102.5, 51.25), day(1), 60);
+
+        let sensors = state.sensors().unwrap();
+        assert_eq!(sensors.energy_import_today_kwh, 2.5);
+        assert_eq!(sensors.gas_today_m3, 1.25);
+        assert_eq!(sensors.energy_import_total_kwh, 102.5);
+        assert_eq!(sensors.timestamp, 60);
+    }
+
+    #[test]
+    fn test_resets_at_day_change() {
+        let mut state = HomeAssistantState::default();
+        state.update(&reading(100.0, 50.0), day(1), 0);
+        state.update(&reading(110.0, 55.0), day(1), 60);
+        state.update(&reading(111.0, 55.5), day(2), 120);
+
+        let sensors = state.sensors().unwrap();
+        assert_eq!(sensors.energy_import_today_kwh, 0.0);
+        assert_eq!(sensors.gas_today_m3, 0.0);
+    }
+
+    #[test]
+    fn test_restored_day_start_includes_downtime() {
+        let mut before = HomeAssistantState::default();
+        before.update(&reading(100.0, 50.0), day(1), 0);
+
+        let mut after = HomeAssistantState::default();
+        after.restore(before.day_start().unwrap());
+        after.update(&reading(104.0, 52.0), day(1), 3600);
+        assert_eq!(after.sensors().unwrap().energy_import_today_kwh, 4.0);
+        assert_eq!(after.sensors().unwrap().gas_today_m3, 2.0);
+
+        let mut next_day = HomeAssistantState::default();
+        next_day.restore(before.day_start().unwrap());
+        next_day.update(&reading(110.0, 55.0), day(2), 86400);
+        assert_eq!(next_day.sensors().unwrap().energy_import_today_kwh, 0.0);
+    }
+
+    #[test]
+    fn test_meter_swap_restarts_today_counters() {
+        let mut state = HomeAssistantState::default();
+        state.update(&reading(100.0, 50.0), day(1), 0);
+        state.update(&reading(1.0, 0.5), day(1), 60);
+        assert_eq!(state.sensors().unwrap().energy_import_today_kwh, 0.0);
+
+        state.update(&reading(1.5, 0.5), day(1), 120);
+        assert_eq!(state.sensors().unwrap().energy_import_today_kwh, 0.5);
+    }
+}
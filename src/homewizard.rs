@@ -1,6 +1,15 @@
 use anyhow::Result;
-use serde::{Deserialize, Deserializer};
+use chrono::TimeZone;
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
 use thiserror::Error;
+use tracing::debug;
+
+use crate::http;
+use crate::telegram::Telegram;
+use crate::v2::Measurement;
 
 fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
 where
@@ -13,14 +22,35 @@ where
 #[derive(Error, Debug)]
 pub enum HomeWizardError {
     #[error("HTTP request failed: {0}")]
-    RequestFailed(#[from] reqwest::Error),
+    RequestFailed(#[from] http::Error),
 
     #[error("Failed to parse response: {0}")]
     ParseError(String),
+
+    #[error("Refusing to {0}: exporter is in read-only mode")]
+    ReadOnly(&'static str),
+}
+
+impl HomeWizardError {
+    /// Coarse error class, used as the label of
+    /// `homewizard_exporter_poll_errors_total`.
+    pub fn class(&self) -> &'static str {
+        match self {
+            Self::RequestFailed(http::Error::Timeout(_)) => "timeout",
+            Self::RequestFailed(http::Error::Status(_)) => "http_status",
+            Self::RequestFailed(_) => "connection",
+            Self::ParseError(msg) if msg.starts_with("HTTP status") => "http_status",
+            Self::ParseError(_) => "parse",
+            Self::ReadOnly(_) => "read_only",
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct HomeWizardData {
+    /// Kind of device the reading came from
+    #[serde(skip)]
+    pub product: ProductType,
     pub wifi_ssid: String,
     pub wifi_strength: f64,
     pub smr_version: i32,
@@ -28,11 +58,24 @@ pub struct HomeWizardData {
     pub unique_id: String,
     pub active_tariff: i32,
     pub total_power_import_kwh: f64,
-    pub total_power_import_t1_kwh: f64,
-    pub total_power_import_t2_kwh: f64,
+    /// Per-tariff totals; meters report between one and four tariffs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_power_import_t1_kwh: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_power_import_t2_kwh: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_power_import_t3_kwh: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_power_import_t4_kwh: Option<f64>,
     pub total_power_export_kwh: f64,
-    pub total_power_export_t1_kwh: f64,
-    pub total_power_export_t2_kwh: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_power_export_t1_kwh: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_power_export_t2_kwh: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_power_export_t3_kwh: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_power_export_t4_kwh: Option<f64>,
     #[serde(default, deserialize_with = "null_as_default")]
     pub active_power_w: f64,
     #[serde(default, deserialize_with = "null_as_default")]
@@ -56,6 +99,8 @@ pub struct HomeWizardData {
     #[serde(default, deserialize_with = "null_as_default")]
     pub active_current_l3_a: f64,
     #[serde(default, deserialize_with = "null_as_default")]
+    pub active_frequency_hz: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub voltage_sag_l1_count: f64,
     #[serde(default, deserialize_with = "null_as_default")]
     pub voltage_sag_l2_count: f64,
@@ -71,6 +116,21 @@ pub struct HomeWizardData {
     pub any_power_fail_count: f64,
     #[serde(default, deserialize_with = "null_as_default")]
     pub long_power_fail_count: f64,
+    /// Highest quarter-hour average import power this month; Belgian
+    /// meters only. API v1 misspells the field
+    #[serde(
+        default,
+        rename = "montly_power_peak_w",
+        deserialize_with = "null_as_default"
+    )]
+    pub monthly_power_peak_w: f64,
+    /// When the monthly peak was set, as a DSMR `YYMMDDhhmmss` number
+    #[serde(
+        default,
+        rename = "montly_power_peak_timestamp",
+        deserialize_with = "null_as_default"
+    )]
+    pub monthly_power_peak_timestamp: i64,
     #[serde(default, deserialize_with = "null_as_default")]
     pub total_gas_m3: f64,
     #[serde(default, deserialize_with = "null_as_default")]
@@ -79,9 +139,322 @@ pub struct HomeWizardData {
     pub gas_unique_id: String,
     #[serde(default, deserialize_with = "null_as_default")]
     pub external: Vec<ExternalSensor>,
+    /// Cumulative water volume; only the Watermeter reports it
+    #[serde(skip_deserializing)]
+    pub total_liter_m3: f64,
+    /// Current water flow; only the Watermeter reports it
+    #[serde(skip_deserializing)]
+    pub active_liter_lpm: f64,
+    /// Relay state; only the Energy Socket reports it, from `/api/v1/state`
+    #[serde(skip_deserializing)]
+    pub socket_state: Option<SocketState>,
+    /// Battery charge; only the Plug-In Battery reports it, on API v2
+    #[serde(skip_deserializing)]
+    pub state_of_charge_pct: f64,
+    /// Full charge cycles of the Plug-In Battery
+    #[serde(skip_deserializing)]
+    pub battery_cycles: f64,
+    /// Long power failure event log; only the telegram carries it
+    #[serde(skip_deserializing)]
+    pub power_failure_log: Vec<PowerFailure>,
+    /// Unix time the meter stamped on the telegram; only the telegram
+    /// carries it
+    #[serde(skip_deserializing)]
+    pub meter_time: Option<i64>,
+    /// Top-level JSON fields this exporter does not know about yet
+    #[serde(flatten)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
+    /// Optional JSON fields the device left out; only filled in
+    /// [`ParseMode::Report`]
+    #[serde(skip)]
+    pub missing_fields: Vec<&'static str>,
+}
+
+/// JSON fields that decode to a default when the device leaves them out.
+const OPTIONAL_FIELDS: &[&str] = &[
+    "total_power_import_t1_kwh",
+    "total_power_import_t2_kwh",
+    "total_power_export_t1_kwh",
+    "total_power_export_t2_kwh",
+    "active_power_w",
+    "active_power_l1_w",
+    "active_power_l2_w",
+    "active_power_l3_w",
+    "active_voltage_l1_v",
+    "active_voltage_l2_v",
+    "active_voltage_l3_v",
+    "active_current_a",
+    "active_current_l1_a",
+    "active_current_l2_a",
+    "active_current_l3_a",
+    "active_frequency_hz",
+    "voltage_sag_l1_count",
+    "voltage_sag_l2_count",
+    "voltage_sag_l3_count",
+    "voltage_swell_l1_count",
+    "voltage_swell_l2_count",
+    "voltage_swell_l3_count",
+    "any_power_fail_count",
+    "long_power_fail_count",
+    "montly_power_peak_w",
+    "montly_power_peak_timestamp",
+    "total_gas_m3",
+    "gas_timestamp",
+    "gas_unique_id",
+    "external",
+];
+
+/// How strictly `/api/v1/data` responses are checked against the data
+/// model.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Ignore unknown fields and default missing ones
+    #[default]
+    Lenient,
+    /// Like lenient, but record unknown and missing fields
+    Report,
+    /// Fail the poll on unknown or missing fields
+    Strict,
+}
+
+/// Kind of HomeWizard device. Every product serves `/api/v1/data` with its
+/// own subset of fields.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProductType {
+    /// P1 meter (HWE-P1)
+    #[default]
+    P1,
+    /// Watermeter (HWE-WTR)
+    Watermeter,
+    /// Energy Socket (HWE-SKT)
+    EnergySocket,
+    /// kWh Meter, 1-phase (HWE-KWH1, SDM230) or 3-phase (HWE-KWH3, SDM630)
+    KwhMeter,
+    /// Plug-In Battery (HWE-BAT); only served by API v2
+    PluginBattery,
+}
+
+impl ProductType {
+    /// Maps the `product_type` reported by `/api` to a product, or `None`
+    /// for devices this exporter does not know.
+    pub fn from_product_type(product_type: &str) -> Option<Self> {
+        match product_type {
+            "HWE-P1" => Some(Self::P1),
+            "HWE-WTR" => Some(Self::Watermeter),
+            "HWE-SKT" => Some(Self::EnergySocket),
+            "HWE-KWH1" | "HWE-KWH3" | "SDM230-wifi" | "SDM630-wifi" => Some(Self::KwhMeter),
+            "HWE-BAT" => Some(Self::PluginBattery),
+            _ => None,
+        }
+    }
+}
+
+/// Response of `GET /api`, which every HomeWizard device serves to
+/// identify itself.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct DeviceInfo {
+    pub product_type: String,
+    pub product_name: String,
+    pub serial: String,
+    pub firmware_version: String,
+    pub api_version: String,
+}
+
+impl DeviceInfo {
+    pub fn product(&self) -> Option<ProductType> {
+        ProductType::from_product_type(&self.product_type)
+    }
+}
+
+/// Response of `GET /api/v1/system`, or of `GET /api/system` over API v2,
+/// which also reports the uptime and Wi-Fi signal strength.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct SystemStatus {
+    pub cloud_enabled: Option<bool>,
+    #[serde(rename = "uptime_s")]
+    pub uptime_secs: Option<f64>,
+    #[serde(rename = "wifi_rssi_db")]
+    pub wifi_rssi_dbm: Option<f64>,
+}
+
+/// `/api/v1/data` of the Watermeter.
+#[derive(Debug, Deserialize)]
+struct WaterMeterData {
+    wifi_ssid: String,
+    wifi_strength: f64,
+    total_liter_m3: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_liter_lpm: f64,
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, serde_json::Value>,
+}
+
+impl From<WaterMeterData> for HomeWizardData {
+    fn from(data: WaterMeterData) -> Self {
+        Self {
+            product: ProductType::Watermeter,
+            wifi_ssid: data.wifi_ssid,
+            wifi_strength: data.wifi_strength,
+            total_liter_m3: data.total_liter_m3,
+            active_liter_lpm: data.active_liter_lpm,
+            unknown_fields: data.unknown_fields,
+            ..Default::default()
+        }
+    }
+}
+
+/// `/api/v1/data` of the Energy Socket. Older firmware only reports the
+/// tariff 1 totals.
+#[derive(Debug, Deserialize)]
+struct EnergySocketData {
+    wifi_ssid: String,
+    wifi_strength: f64,
+    #[serde(default)]
+    total_power_import_kwh: Option<f64>,
+    #[serde(default)]
+    total_power_import_t1_kwh: Option<f64>,
+    #[serde(default)]
+    total_power_export_kwh: Option<f64>,
+    #[serde(default)]
+    total_power_export_t1_kwh: Option<f64>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_power_w: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_voltage_v: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_current_a: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_frequency_hz: f64,
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, serde_json::Value>,
+}
+
+impl From<EnergySocketData> for HomeWizardData {
+    fn from(data: EnergySocketData) -> Self {
+        Self {
+            product: ProductType::EnergySocket,
+            wifi_ssid: data.wifi_ssid,
+            wifi_strength: data.wifi_strength,
+            total_power_import_kwh: data
+                .total_power_import_kwh
+                .or(data.total_power_import_t1_kwh)
+                .unwrap_or_default(),
+            total_power_import_t1_kwh: data.total_power_import_t1_kwh,
+            total_power_export_kwh: data
+                .total_power_export_kwh
+                .or(data.total_power_export_t1_kwh)
+                .unwrap_or_default(),
+            total_power_export_t1_kwh: data.total_power_export_t1_kwh,
+            active_power_w: data.active_power_w,
+            active_power_l1_w: data.active_power_w,
+            active_voltage_l1_v: data.active_voltage_v,
+            active_current_a: data.active_current_a,
+            active_current_l1_a: data.active_current_a,
+            active_frequency_hz: data.active_frequency_hz,
+            unknown_fields: data.unknown_fields,
+            ..Default::default()
+        }
+    }
+}
+
+/// `/api/v1/data` of the kWh Meter. The 1-phase meter reports a single
+/// `active_voltage_v`, the 3-phase meter per-phase voltages and currents.
+#[derive(Debug, Deserialize)]
+struct KwhMeterData {
+    wifi_ssid: String,
+    wifi_strength: f64,
+    #[serde(default)]
+    total_power_import_kwh: Option<f64>,
+    #[serde(default)]
+    total_power_import_t1_kwh: Option<f64>,
+    #[serde(default)]
+    total_power_export_kwh: Option<f64>,
+    #[serde(default)]
+    total_power_export_t1_kwh: Option<f64>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_power_w: f64,
+    #[serde(default)]
+    active_power_l1_w: Option<f64>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_power_l2_w: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_power_l3_w: f64,
+    #[serde(default)]
+    active_voltage_v: Option<f64>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_voltage_l1_v: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_voltage_l2_v: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_voltage_l3_v: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_current_a: f64,
+    #[serde(default)]
+    active_current_l1_a: Option<f64>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_current_l2_a: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_current_l3_a: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
+    active_frequency_hz: f64,
+    #[serde(flatten)]
+    unknown_fields: BTreeMap<String, serde_json::Value>,
+}
+
+impl From<KwhMeterData> for HomeWizardData {
+    fn from(data: KwhMeterData) -> Self {
+        Self {
+            product: ProductType::KwhMeter,
+            wifi_ssid: data.wifi_ssid,
+            wifi_strength: data.wifi_strength,
+            total_power_import_kwh: data
+                .total_power_import_kwh
+                .or(data.total_power_import_t1_kwh)
+                .unwrap_or_default(),
+            total_power_import_t1_kwh: data.total_power_import_t1_kwh,
+            total_power_export_kwh: data
+                .total_power_export_kwh
+                .or(data.total_power_export_t1_kwh)
+                .unwrap_or_default(),
+            total_power_export_t1_kwh: data.total_power_export_t1_kwh,
+            active_power_w: data.active_power_w,
+            active_power_l1_w: data.active_power_l1_w.unwrap_or(data.active_power_w),
+            active_power_l2_w: data.active_power_l2_w,
+            active_power_l3_w: data.active_power_l3_w,
+            active_voltage_l1_v: data.active_voltage_v.unwrap_or(data.active_voltage_l1_v),
+            active_voltage_l2_v: data.active_voltage_l2_v,
+            active_voltage_l3_v: data.active_voltage_l3_v,
+            active_current_a: data.active_current_a,
+            active_current_l1_a: data.active_current_l1_a.unwrap_or(data.active_current_a),
+            active_current_l2_a: data.active_current_l2_a,
+            active_current_l3_a: data.active_current_l3_a,
+            active_frequency_hz: data.active_frequency_hz,
+            unknown_fields: data.unknown_fields,
+            ..Default::default()
+        }
+    }
+}
+
+/// `/api/v1/state` of the Energy Socket.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+pub struct SocketState {
+    pub power_on: bool,
+    pub switch_lock: bool,
+    /// Status light brightness, 0-255
+    pub brightness: f64,
+}
+
+/// One entry of the meter's long power failure log.
+#[derive(Debug, Serialize, Clone, Copy, Default, PartialEq)]
+pub struct PowerFailure {
+    /// End of the outage as a DSMR timestamp (`YYMMDDhhmmss`)
+    pub end_timestamp: i64,
+    pub duration_s: f64,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct ExternalSensor {
     pub unique_id: String,
     #[serde(rename = "type")]
@@ -91,19 +464,387 @@ pub struct ExternalSensor {
     pub unit: String,
 }
 
+impl ExternalSensor {
+    /// Heat meter reading converted to gigajoules, the unit district heating
+    /// is billed in. Returns `None` for other sensor types or unknown units.
+    pub fn heat_energy_gj(&self) -> Option<f64> {
+        if self.sensor_type != "heat_meter" {
+            return None;
+        }
+        match self.unit.as_str() {
+            "GJ" => Some(self.value),
+            "MJ" => Some(self.value / 1_000.0),
+            "kWh" => Some(self.value * 0.0036),
+            "MWh" => Some(self.value * 3.6),
+            _ => None,
+        }
+    }
+
+    /// Warm water meter reading in cubic meters. Returns `None` for other
+    /// sensor types or unknown units.
+    pub fn warm_water_m3(&self) -> Option<f64> {
+        if self.sensor_type != "warm_water_meter" {
+            return None;
+        }
+        match self.unit.as_str() {
+            "m3" | "m³" => Some(self.value),
+            "l" | "L" => Some(self.value / 1_000.0),
+            _ => None,
+        }
+    }
+}
+
+/// A water meter reading in whichever representation the firmware reports.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaterReading {
+    /// Cumulative volume in m³
+    Volume(f64),
+    /// Instantaneous flow in L/min
+    Flow(f64),
+}
+
+impl ExternalSensor {
+    /// Water meter reading normalized by unit. Returns `None` for other
+    /// sensor types or unknown units.
+    pub fn water_reading(&self) -> Option<WaterReading> {
+        if self.sensor_type != "water_meter" {
+            return None;
+        }
+        match self.unit.as_str() {
+            "m3" | "m³" => Some(WaterReading::Volume(self.value)),
+            "l" | "L" => Some(WaterReading::Volume(self.value / 1_000.0)),
+            "l/min" | "L/min" | "lpm" => Some(WaterReading::Flow(self.value)),
+            "m3/h" | "m³/h" => Some(WaterReading::Flow(self.value * 1_000.0 / 60.0)),
+            _ => None,
+        }
+    }
+}
+
+/// A single gas meter reading. The P1 port can carry several M-Bus devices,
+/// so besides the primary meter reported at the top level, additional gas
+/// meters show up as `gas_meter` entries in `external`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GasMeterReading {
+    pub unique_id: String,
+    pub total_m3: f64,
+    pub timestamp: i64,
+}
+
+impl HomeWizardData {
+    /// Import totals of the tariffs the meter reports, by tariff number.
+    pub fn import_tariffs(&self) -> Vec<(&'static str, f64)> {
+        tariffs([
+            self.total_power_import_t1_kwh,
+            self.total_power_import_t2_kwh,
+            self.total_power_import_t3_kwh,
+            self.total_power_import_t4_kwh,
+        ])
+    }
+
+    /// Export totals of the tariffs the meter reports, by tariff number.
+    pub fn export_tariffs(&self) -> Vec<(&'static str, f64)> {
+        tariffs([
+            self.total_power_export_t1_kwh,
+            self.total_power_export_t2_kwh,
+            self.total_power_export_t3_kwh,
+            self.total_power_export_t4_kwh,
+        ])
+    }
+
+    /// All gas meters present in the reading, primary meter first. External
+    /// entries that duplicate the primary meter are skipped.
+    pub fn gas_meters(&self) -> Vec<GasMeterReading> {
+        let mut meters = Vec::new();
+
+        if !self.gas_unique_id.is_empty() {
+            meters.push(GasMeterReading {
+                unique_id: self.gas_unique_id.clone(),
+                total_m3: self.total_gas_m3,
+                timestamp: self.gas_timestamp,
+            });
+        }
+
+        for sensor in self
+            .external
+            .iter()
+            .filter(|s| s.sensor_type == "gas_meter")
+        {
+            if meters.iter().any(|m| m.unique_id == sensor.unique_id) {
+                continue;
+            }
+            meters.push(GasMeterReading {
+                unique_id: sensor.unique_id.clone(),
+                total_m3: sensor.value,
+                timestamp: sensor.timestamp,
+            });
+        }
+
+        meters
+    }
+}
+
+fn tariffs(totals: [Option<f64>; 4]) -> Vec<(&'static str, f64)> {
+    ["1", "2", "3", "4"]
+        .into_iter()
+        .zip(totals)
+        .filter_map(|(tariff, total)| Some((tariff, total?)))
+        .collect()
+}
+
+/// Converts a DSMR `YYMMDDhhmmss` number, as the JSON API reports it, to
+/// Unix time. Meters keep the local time of `timezone`, and the API drops
+/// the `S`/`W` suffix that tells summer from winter time, so the hour
+/// repeated when the clocks go back resolves to summer time.
+pub fn dsmr_unix_time(timestamp: i64, timezone: Tz) -> Option<i64> {
+    if timestamp <= 0 {
+        return None;
+    }
+    let local =
+        chrono::NaiveDateTime::parse_from_str(&format!("{timestamp:012}"), "%y%m%d%H%M%S").ok()?;
+    let time = timezone.from_local_datetime(&local).earliest()?;
+    Some(time.timestamp())
+}
+
+/// Update cadence of a meter as defined by its DSMR/SMR version.
+///
+/// SMR 5 meters send a telegram every second and refresh gas every five
+/// minutes; SMR 4 and older send every ten seconds and refresh gas hourly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmrCapabilities {
+    pub electricity_interval: Duration,
+    pub gas_interval: Duration,
+}
+
+impl SmrCapabilities {
+    pub fn from_smr_version(smr_version: i32) -> Self {
+        if smr_version >= 50 {
+            Self {
+                electricity_interval: Duration::from_secs(1),
+                gas_interval: Duration::from_secs(5 * 60),
+            }
+        } else {
+            Self {
+                electricity_interval: Duration::from_secs(10),
+                gas_interval: Duration::from_secs(60 * 60),
+            }
+        }
+    }
+
+    /// How long a gas reading may remain unchanged before it is considered
+    /// stale: two missed gas updates.
+    pub fn gas_stale_threshold(&self) -> Duration {
+        self.gas_interval * 2
+    }
+}
+
+/// Endpoints a reading can be taken from, in the order they are tried.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// JSON measurements from `/api/v1/data`
+    V1,
+    /// Raw DSMR telegram from `/api/v1/telegram`
+    Telegram,
+    /// JSON measurements from `/api/measurement` over HTTPS; needs
+    /// `--api-token`
+    V2,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::V1 => "v1",
+            Self::Telegram => "telegram",
+            Self::V2 => "v2",
+        }
+    }
+}
+
+/// Where and how to reach the device's API v2.
+#[derive(Clone)]
+struct ApiV2 {
+    url: String,
+    token: String,
+}
+
+#[derive(Clone)]
 pub struct HomeWizardClient {
-    client: reqwest::Client,
+    client: http::Client,
     url: String,
+    v2: Option<ApiV2>,
+    product: ProductType,
+    read_only: bool,
+    parse_mode: ParseMode,
 }
 
 impl HomeWizardClient {
+    /// Creates a client for the given data URL. Clients start in read-only
+    /// mode; see [`HomeWizardClient::read_only`].
     pub fn new(url: String, timeout: std::time::Duration) -> Result<Self> {
-        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        let client = http::Client::for_device(timeout)?;
+
+        Ok(Self {
+            client,
+            url,
+            v2: None,
+            product: ProductType::default(),
+            read_only: true,
+            parse_mode: ParseMode::default(),
+        })
+    }
+
+    /// Kind of device behind the data URL, which decides how its data is
+    /// parsed.
+    pub fn product(mut self, product: ProductType) -> Self {
+        self.product = product;
+        self
+    }
+
+    /// How strictly data responses are checked for schema drift.
+    pub fn parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = parse_mode;
+        self
+    }
+
+    /// Whether requests that change device state are refused.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Enables [`Source::V2`], reading `url` with the bearer `token`.
+    pub fn api_v2(mut self, url: String, token: String) -> Self {
+        self.v2 = Some(ApiV2 { url, token });
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
 
-        Ok(Self { client, url })
+    /// The same client pointed at another device's data URL and, when
+    /// API v2 is enabled, measurement URL.
+    pub fn with_urls(&self, url: String, v2_url: String) -> Self {
+        Self {
+            url,
+            v2: self.v2.as_ref().map(|v2| ApiV2 {
+                url: v2_url,
+                token: v2.token.clone(),
+            }),
+            ..self.clone()
+        }
+    }
+
+    /// Applies another request `timeout` and API v2 `token`, `None` turning
+    /// API v2 off, and returns whether either changed. The HTTP client, and
+    /// with it the pinned certificates, is only replaced for a new timeout.
+    pub fn reconfigure(
+        &mut self,
+        timeout: std::time::Duration,
+        v2_url: String,
+        token: Option<String>,
+    ) -> Result<bool> {
+        let mut changed = false;
+        if self.client.timeout() != timeout {
+            self.client = http::Client::for_device(timeout)?;
+            changed = true;
+        }
+        if self.v2.as_ref().map(|v2| &v2.token) != token.as_ref() {
+            self.v2 = token.map(|token| ApiV2 { url: v2_url, token });
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    fn api_url(&self, endpoint: &str) -> String {
+        let base = self.url.strip_suffix("/data").unwrap_or(&self.url);
+        format!("{base}/{endpoint}")
+    }
+
+    /// Every request that mutates device state goes through here so the
+    /// read-only guard cannot be bypassed.
+    fn write_request(
+        &self,
+        operation: &'static str,
+        method: http::Method,
+        url: String,
+    ) -> Result<http::RequestBuilder<'_>, HomeWizardError> {
+        if self.read_only {
+            return Err(HomeWizardError::ReadOnly(operation));
+        }
+        Ok(self.client.request(method, url))
+    }
+
+    /// Fetches and parses the raw DSMR telegram.
+    pub async fn fetch_telegram(&self) -> Result<HomeWizardData, HomeWizardError> {
+        let response = self.client.get(self.api_url("telegram")).send().await?;
+
+        if !response.status().is_success() {
+            return Err(HomeWizardError::ParseError(format!(
+                "HTTP status: {}",
+                response.status()
+            )));
+        }
+
+        let body = response.text();
+        Telegram::parse(&body)
+            .and_then(|telegram| telegram.to_data())
+            .map_err(|e| HomeWizardError::ParseError(format!("Telegram decode error: {e}")))
+    }
+
+    pub async fn fetch_from(&self, source: Source) -> Result<HomeWizardData, HomeWizardError> {
+        match source {
+            Source::V1 => self.fetch_data().await,
+            Source::Telegram => self.fetch_telegram().await,
+            Source::V2 => self.fetch_v2().await,
+        }
+    }
+
+    /// Tries each source in order within the same poll and returns the first
+    /// reading along with the source that produced it. When every source
+    /// fails, the last error is returned.
+    pub async fn fetch_with_fallback(
+        &self,
+        sources: &[Source],
+    ) -> Result<(HomeWizardData, Source), HomeWizardError> {
+        let mut last_error = None;
+        for &source in sources {
+            match self.fetch_from(source).await {
+                Ok(data) => return Ok((data, source)),
+                Err(e) => {
+                    debug!("Source {} failed: {}", source.as_str(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| HomeWizardError::ParseError("no sources configured".to_string())))
+    }
+
+    /// Blinks the device's status light so it can be located physically.
+    pub async fn identify(&self) -> Result<(), HomeWizardError> {
+        let response = self
+            .write_request(
+                "identify device",
+                http::Method::PUT,
+                self.api_url("identify"),
+            )?
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(HomeWizardError::ParseError(format!(
+                "HTTP status: {}",
+                response.status()
+            )));
+        }
+
+        Ok(())
     }
 
     pub async fn fetch_data(&self) -> Result<HomeWizardData, HomeWizardError> {
+        if self.product == ProductType::PluginBattery {
+            return self.fetch_v2().await;
+        }
         let response = self.client.get(&self.url).send().await?;
 
         if !response.status().is_success() {
@@ -113,20 +854,182 @@ impl HomeWizardClient {
             )));
         }
 
-        let body = response.text().await?;
-        serde_json::from_str::<HomeWizardData>(&body).map_err(|e| {
+        let body = response.text();
+        let decode_error = |e: serde_json::Error| {
+            HomeWizardError::ParseError(format!("JSON decode error: {e}\nResponse body: {body}"))
+        };
+        match self.product {
+            // The Plug-In Battery returned through API v2 above
+            ProductType::P1 | ProductType::PluginBattery => {}
+            ProductType::Watermeter => {
+                let data = serde_json::from_str::<WaterMeterData>(&body).map_err(decode_error)?;
+                return self.check_schema(data.into());
+            }
+            ProductType::EnergySocket => {
+                let data = serde_json::from_str::<EnergySocketData>(&body).map_err(decode_error)?;
+                let mut data: HomeWizardData = self.check_schema(data.into())?;
+                data.socket_state = Some(self.fetch_socket_state().await?);
+                return Ok(data);
+            }
+            ProductType::KwhMeter => {
+                let data = serde_json::from_str::<KwhMeterData>(&body).map_err(decode_error)?;
+                return self.check_schema(data.into());
+            }
+        }
+        let mut data = serde_json::from_str::<HomeWizardData>(&body).map_err(decode_error)?;
+
+        if self.parse_mode != ParseMode::Lenient {
+            let fields: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(&body).unwrap_or_default();
+            data.missing_fields = OPTIONAL_FIELDS
+                .iter()
+                .copied()
+                .filter(|field| !fields.contains_key(*field))
+                .collect();
+        }
+
+        self.check_schema(data)
+    }
+
+    /// Fetches the product and firmware information from `/api`.
+    pub async fn fetch_device_info(&self) -> Result<DeviceInfo, HomeWizardError> {
+        let url = self.url.strip_suffix("/v1/data").unwrap_or(&self.url);
+        let response = self.client.get(url).send().await?;
+
+        if !response.status().is_success() {
+            return Err(HomeWizardError::ParseError(format!(
+                "HTTP status: {}",
+                response.status()
+            )));
+        }
+
+        let body = response.text();
+        serde_json::from_str(&body).map_err(|e| {
+            HomeWizardError::ParseError(format!("JSON decode error: {e}\nResponse body: {body}"))
+        })
+    }
+
+    /// Fetches the cloud communication setting and, over API v2, the
+    /// uptime and Wi-Fi RSSI.
+    pub async fn fetch_system(&self) -> Result<SystemStatus, HomeWizardError> {
+        let request = match &self.v2 {
+            Some(v2) => {
+                let base = v2.url.strip_suffix("/measurement").unwrap_or(&v2.url);
+                self.client
+                    .get(format!("{base}/system"))
+                    .bearer_auth(&v2.token)
+                    .header("x-api-version", "2")
+            }
+            None => self.client.get(self.api_url("system")),
+        };
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            return Err(HomeWizardError::ParseError(format!(
+                "HTTP status: {}",
+                response.status()
+            )));
+        }
+
+        let body = response.text();
+        serde_json::from_str(&body).map_err(|e| {
+            HomeWizardError::ParseError(format!("JSON decode error: {e}\nResponse body: {body}"))
+        })
+    }
+
+    /// Fetches the relay state of an Energy Socket.
+    pub async fn fetch_socket_state(&self) -> Result<SocketState, HomeWizardError> {
+        let response = self.client.get(self.api_url("state")).send().await?;
+
+        if !response.status().is_success() {
+            return Err(HomeWizardError::ParseError(format!(
+                "HTTP status: {}",
+                response.status()
+            )));
+        }
+
+        let body = response.text();
+        serde_json::from_str(&body).map_err(|e| {
             HomeWizardError::ParseError(format!("JSON decode error: {e}\nResponse body: {body}"))
         })
     }
+
+    /// Fetches the API v2 measurement and maps it into the v1 data model.
+    pub async fn fetch_v2(&self) -> Result<HomeWizardData, HomeWizardError> {
+        let v2 = self
+            .v2
+            .as_ref()
+            .ok_or_else(|| HomeWizardError::ParseError("API v2 needs an API token".to_string()))?;
+        let response = self
+            .client
+            .get(&v2.url)
+            .bearer_auth(&v2.token)
+            .header("x-api-version", "2")
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(HomeWizardError::ParseError(format!(
+                "HTTP status: {}",
+                response.status()
+            )));
+        }
+
+        let body = response.text();
+        let measurement = serde_json::from_str::<Measurement>(&body).map_err(|e| {
+            HomeWizardError::ParseError(format!("JSON decode error: {e}\nResponse body: {body}"))
+        })?;
+
+        let mut data = measurement.into_data();
+        data.product = self.product;
+        self.check_schema(data)
+    }
+
+    /// In [`ParseMode::Strict`], fails readings with unknown or missing
+    /// fields.
+    fn check_schema(&self, data: HomeWizardData) -> Result<HomeWizardData, HomeWizardError> {
+        if self.parse_mode == ParseMode::Strict
+            && (!data.unknown_fields.is_empty() || !data.missing_fields.is_empty())
+        {
+            let unknown: Vec<&str> = data.unknown_fields.keys().map(String::as_str).collect();
+            return Err(HomeWizardError::ParseError(format!(
+                "Schema mismatch: unknown fields [{}], missing fields [{}]",
+                unknown.join(", "),
+                data.missing_fields.join(", ")
+            )));
+        }
+
+        Ok(data)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::time::Duration;
-    use wiremock::matchers::{method, path};
+    use wiremock::matchers::{header, method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
+    #[test]
+    fn test_dsmr_unix_time() {
+        let amsterdam = chrono_tz::Europe::Amsterdam;
+        // 2025-07-01 14:30:00 CEST and 2025-01-15 12:00:00 CET
+        assert_eq!(dsmr_unix_time(250701143000, amsterdam), Some(1751373000));
+        assert_eq!(dsmr_unix_time(250115120000, amsterdam), Some(1736938800));
+        // Around the switch to summer time on 2025-03-30
+        assert_eq!(dsmr_unix_time(250330015959, amsterdam), Some(1743296399));
+        assert_eq!(dsmr_unix_time(250330030000, amsterdam), Some(1743296400));
+        // The repeated hour on 2025-10-26 is taken as summer time
+        assert_eq!(dsmr_unix_time(251026023000, amsterdam), Some(1761438600));
+        // 2025-07-01 14:30:00 BST
+        assert_eq!(
+            dsmr_unix_time(250701143000, chrono_tz::Europe::London),
+            Some(1751376600)
+        );
+        assert_eq!(dsmr_unix_time(0, amsterdam), None);
+        assert_eq!(dsmr_unix_time(251399000000, amsterdam), None);
+    }
+
     #[test]
     fn test_homewizard_client_creation() {
         let client = HomeWizardClient::new(
@@ -145,6 +1048,50 @@ mod tests {
         assert!(client.is_ok());
     }
 
+    #[test]
+    fn test_reconfigure_applies_timeout_and_token() {
+        let mut client = HomeWizardClient::new(
+            "http://192.168.1.100/api/v1/data".to_string(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let v2_url = "https://192.168.1.100/api/measurement".to_string();
+
+        assert!(
+            !client
+                .reconfigure(Duration::from_secs(5), v2_url.clone(), None)
+                .unwrap()
+        );
+        assert!(
+            client
+                .reconfigure(Duration::from_secs(9), v2_url.clone(), None)
+                .unwrap()
+        );
+        assert_eq!(client.client.timeout(), Duration::from_secs(9));
+
+        let token = Some("0123456789ABCDEF".to_string());
+        assert!(
+            client
+                .reconfigure(Duration::from_secs(9), v2_url.clone(), token.clone())
+                .unwrap()
+        );
+        assert_eq!(
+            client.v2.as_ref().map(|v2| v2.url.as_str()),
+            Some(v2_url.as_str())
+        );
+        assert!(
+            !client
+                .reconfigure(Duration::from_secs(9), v2_url.clone(), token)
+                .unwrap()
+        );
+        assert!(
+            client
+                .reconfigure(Duration::from_secs(9), v2_url, None)
+                .unwrap()
+        );
+        assert!(client.v2.is_none());
+    }
+
     #[test]
     fn test_homewizard_error_display() {
         let error = HomeWizardError::ParseError("Invalid JSON".to_string());
@@ -178,6 +1125,7 @@ mod tests {
             "active_current_l1_a": 4.0,
             "active_current_l2_a": 1.0,
             "active_current_l3_a": 1.8,
+            "active_frequency_hz": 49.98,
             "voltage_sag_l1_count": 2.0,
             "voltage_sag_l2_count": 2.0,
             "voltage_sag_l3_count": 2.0,
@@ -212,11 +1160,11 @@ mod tests {
         assert_eq!(data.unique_id, "3c39e7aabbccddee");
         assert_eq!(data.active_tariff, 1);
         assert_eq!(data.total_power_import_kwh, 1234.567);
-        assert_eq!(data.total_power_import_t1_kwh, 800.123);
-        assert_eq!(data.total_power_import_t2_kwh, 434.444);
+        assert_eq!(data.total_power_import_t1_kwh, Some(800.123));
+        assert_eq!(data.total_power_import_t2_kwh, Some(434.444));
         assert_eq!(data.total_power_export_kwh, 89.012);
-        assert_eq!(data.total_power_export_t1_kwh, 60.789);
-        assert_eq!(data.total_power_export_t2_kwh, 28.223);
+        assert_eq!(data.total_power_export_t1_kwh, Some(60.789));
+        assert_eq!(data.total_power_export_t2_kwh, Some(28.223));
         assert_eq!(data.active_power_w, 1500.0);
         assert_eq!(data.active_power_l1_w, 750.0);
         assert_eq!(data.active_power_l2_w, 500.0);
@@ -228,6 +1176,7 @@ mod tests {
         assert_eq!(data.active_current_l1_a, 4.0);
         assert_eq!(data.active_current_l2_a, 1.0);
         assert_eq!(data.active_current_l3_a, 1.8);
+        assert_eq!(data.active_frequency_hz, 49.98);
         assert_eq!(data.voltage_sag_l1_count, 2.0);
         assert_eq!(data.voltage_sag_l2_count, 2.0);
         assert_eq!(data.voltage_sag_l3_count, 2.0);
@@ -300,6 +1249,34 @@ mod tests {
         assert_eq!(data.external.len(), 0);
     }
 
+    #[test]
+    fn test_homewizard_data_deserialization_with_four_tariffs() {
+        let data: HomeWizardData = serde_json::from_str(
+            r#"{
+                "wifi_ssid": "Test",
+                "wifi_strength": 50.0,
+                "smr_version": 50,
+                "meter_model": "Test Model",
+                "unique_id": "test123",
+                "active_tariff": 3,
+                "total_power_import_kwh": 100.0,
+                "total_power_import_t1_kwh": 10.0,
+                "total_power_import_t2_kwh": 20.0,
+                "total_power_import_t3_kwh": 30.0,
+                "total_power_import_t4_kwh": 40.0,
+                "total_power_export_kwh": 5.0,
+                "total_power_export_t3_kwh": 5.0
+            }"#,
+        )
+        .unwrap();
+        assert!(data.unknown_fields.is_empty());
+        assert_eq!(
+            data.import_tariffs(),
+            [("1", 10.0), ("2", 20.0), ("3", 30.0), ("4", 40.0)]
+        );
+        assert_eq!(data.export_tariffs(), [("3", 5.0)]);
+    }
+
     #[test]
     fn test_homewizard_data_deserialization_with_null_fields() {
         // Some HomeWizard P1 firmwares return `null` for fields the meter
@@ -408,7 +1385,34 @@ mod tests {
     }
 
     #[test]
-    fn test_external_sensor_deserialization() {
+    fn test_homewizard_data_keeps_unknown_fields() {
+        let json_data = r#"
+        {
+            "wifi_ssid": "Test",
+            "wifi_strength": 50.0,
+            "smr_version": 50,
+            "meter_model": "Test Model",
+            "unique_id": "test123",
+            "active_tariff": 1,
+            "total_power_import_kwh": 100.0,
+            "total_power_import_t1_kwh": 60.0,
+            "total_power_import_t2_kwh": 40.0,
+            "total_power_export_kwh": 10.0,
+            "total_power_export_t1_kwh": 6.0,
+            "total_power_export_t2_kwh": 4.0,
+            "active_power_average_w": 412.5,
+            "active_power_average_l1_w": 206.0
+        }
+        "#;
+
+        let data: HomeWizardData = serde_json::from_str(json_data).unwrap();
+        assert_eq!(data.total_power_import_kwh, 100.0);
+        assert_eq!(data.unknown_fields.len(), 2);
+        assert_eq!(data.unknown_fields["active_power_average_w"], 412.5);
+    }
+
+    #[test]
+    fn test_external_sensor_deserialization() {
         let json_data = r#"
         {
             "unique_id": "sensor123",
@@ -433,6 +1437,7 @@ mod tests {
     #[test]
     fn test_homewizard_data_clone() {
         let data = HomeWizardData {
+            product: ProductType::P1,
             wifi_ssid: "Test".to_string(),
             wifi_strength: 50.0,
             smr_version: 40,
@@ -440,11 +1445,15 @@ mod tests {
             unique_id: "test123".to_string(),
             active_tariff: 1,
             total_power_import_kwh: 100.0,
-            total_power_import_t1_kwh: 60.0,
-            total_power_import_t2_kwh: 40.0,
+            total_power_import_t1_kwh: Some(60.0),
+            total_power_import_t2_kwh: Some(40.0),
+            total_power_import_t3_kwh: None,
+            total_power_import_t4_kwh: None,
             total_power_export_kwh: 10.0,
-            total_power_export_t1_kwh: 6.0,
-            total_power_export_t2_kwh: 4.0,
+            total_power_export_t1_kwh: Some(6.0),
+            total_power_export_t2_kwh: Some(4.0),
+            total_power_export_t3_kwh: None,
+            total_power_export_t4_kwh: None,
             active_power_w: 500.0,
             active_power_l1_w: 200.0,
             active_power_l2_w: 200.0,
@@ -456,6 +1465,7 @@ mod tests {
             active_current_l1_a: 1.0,
             active_current_l2_a: 1.0,
             active_current_l3_a: 0.3,
+            active_frequency_hz: 50.0,
             voltage_sag_l1_count: 0.0,
             voltage_sag_l2_count: 0.0,
             voltage_sag_l3_count: 0.0,
@@ -464,10 +1474,21 @@ mod tests {
             voltage_swell_l3_count: 0.0,
             any_power_fail_count: 0.0,
             long_power_fail_count: 0.0,
+            monthly_power_peak_w: 0.0,
+            monthly_power_peak_timestamp: 0,
             total_gas_m3: 50.0,
             gas_timestamp: 1234567890,
             gas_unique_id: "gas123".to_string(),
             external: vec![],
+            total_liter_m3: 0.0,
+            active_liter_lpm: 0.0,
+            socket_state: None,
+            state_of_charge_pct: 0.0,
+            battery_cycles: 0.0,
+            power_failure_log: vec![],
+            meter_time: None,
+            unknown_fields: BTreeMap::new(),
+            missing_fields: vec![],
         };
 
         let cloned = data.clone();
@@ -493,9 +1514,172 @@ mod tests {
     }
 
     #[test]
-    fn test_homewizard_error_from_reqwest() {
-        // Create a reqwest error by making a request to an invalid URL
-        let client = reqwest::Client::new();
+    fn test_gas_meters_combines_primary_and_external() {
+        let json_data = r#"
+        {
+            "wifi_ssid": "Test",
+            "wifi_strength": 50.0,
+            "smr_version": 50,
+            "meter_model": "Test Model",
+            "unique_id": "test123",
+            "active_tariff": 1,
+            "total_power_import_kwh": 100.0,
+            "total_power_import_t1_kwh": 60.0,
+            "total_power_import_t2_kwh": 40.0,
+            "total_power_export_kwh": 10.0,
+            "total_power_export_t1_kwh": 6.0,
+            "total_power_export_t2_kwh": 4.0,
+            "total_gas_m3": 567.89,
+            "gas_timestamp": 1234567890,
+            "gas_unique_id": "gas-primary",
+            "external": [
+                {
+                    "unique_id": "gas-primary",
+                    "type": "gas_meter",
+                    "timestamp": 1234567890,
+                    "value": 567.89,
+                    "unit": "m3"
+                },
+                {
+                    "unique_id": "gas-annex",
+                    "type": "gas_meter",
+                    "timestamp": 1234560000,
+                    "value": 42.5,
+                    "unit": "m3"
+                },
+                {
+                    "unique_id": "water",
+                    "type": "water_meter",
+                    "timestamp": 1234567890,
+                    "value": 12.0,
+                    "unit": "m3"
+                }
+            ]
+        }
+        "#;
+
+        let data: HomeWizardData = serde_json::from_str(json_data).unwrap();
+        let meters = data.gas_meters();
+
+        assert_eq!(meters.len(), 2);
+        assert_eq!(meters[0].unique_id, "gas-primary");
+        assert_eq!(meters[0].total_m3, 567.89);
+        assert_eq!(meters[1].unique_id, "gas-annex");
+        assert_eq!(meters[1].total_m3, 42.5);
+        assert_eq!(meters[1].timestamp, 1234560000);
+    }
+
+    #[test]
+    fn test_gas_meters_without_gas() {
+        let json_data = r#"
+        {
+            "wifi_ssid": "Test",
+            "wifi_strength": 50.0,
+            "smr_version": 50,
+            "meter_model": "Test Model",
+            "unique_id": "test123",
+            "active_tariff": 1,
+            "total_power_import_kwh": 100.0,
+            "total_power_import_t1_kwh": 60.0,
+            "total_power_import_t2_kwh": 40.0,
+            "total_power_export_kwh": 10.0,
+            "total_power_export_t1_kwh": 6.0,
+            "total_power_export_t2_kwh": 4.0
+        }
+        "#;
+
+        let data: HomeWizardData = serde_json::from_str(json_data).unwrap();
+        assert!(data.gas_meters().is_empty());
+    }
+
+    #[test]
+    fn test_smr_capabilities_smr5() {
+        let caps = SmrCapabilities::from_smr_version(50);
+        assert_eq!(caps.electricity_interval, Duration::from_secs(1));
+        assert_eq!(caps.gas_interval, Duration::from_secs(300));
+        assert_eq!(caps.gas_stale_threshold(), Duration::from_secs(600));
+    }
+
+    #[test]
+    fn test_smr_capabilities_smr4_and_older() {
+        for version in [22, 40, 42] {
+            let caps = SmrCapabilities::from_smr_version(version);
+            assert_eq!(caps.electricity_interval, Duration::from_secs(10));
+            assert_eq!(caps.gas_interval, Duration::from_secs(3600));
+        }
+    }
+
+    fn external(sensor_type: &str, value: f64, unit: &str) -> ExternalSensor {
+        ExternalSensor {
+            unique_id: "sensor".to_string(),
+            sensor_type: sensor_type.to_string(),
+            timestamp: 1234567890,
+            value,
+            unit: unit.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_heat_energy_gj_unit_conversion() {
+        assert_eq!(
+            external("heat_meter", 12.5, "GJ").heat_energy_gj(),
+            Some(12.5)
+        );
+        assert_eq!(
+            external("heat_meter", 2500.0, "MJ").heat_energy_gj(),
+            Some(2.5)
+        );
+        assert_eq!(
+            external("heat_meter", 1000.0, "kWh").heat_energy_gj(),
+            Some(3.6)
+        );
+        assert_eq!(
+            external("heat_meter", 1.0, "MWh").heat_energy_gj(),
+            Some(3.6)
+        );
+        assert_eq!(external("heat_meter", 1.0, "BTU").heat_energy_gj(), None);
+        assert_eq!(external("water_meter", 1.0, "GJ").heat_energy_gj(), None);
+    }
+
+    #[test]
+    fn test_warm_water_m3_unit_conversion() {
+        assert_eq!(
+            external("warm_water_meter", 12.5, "m3").warm_water_m3(),
+            Some(12.5)
+        );
+        assert_eq!(
+            external("warm_water_meter", 1500.0, "l").warm_water_m3(),
+            Some(1.5)
+        );
+        assert_eq!(external("water_meter", 12.5, "m3").warm_water_m3(), None);
+    }
+
+    #[test]
+    fn test_water_reading_by_unit() {
+        assert_eq!(
+            external("water_meter", 123.456, "m3").water_reading(),
+            Some(WaterReading::Volume(123.456))
+        );
+        assert_eq!(
+            external("water_meter", 1500.0, "l").water_reading(),
+            Some(WaterReading::Volume(1.5))
+        );
+        assert_eq!(
+            external("water_meter", 7.5, "l/min").water_reading(),
+            Some(WaterReading::Flow(7.5))
+        );
+        assert_eq!(
+            external("water_meter", 0.6, "m3/h").water_reading(),
+            Some(WaterReading::Flow(10.0))
+        );
+        assert_eq!(external("water_meter", 1.0, "gal").water_reading(), None);
+        assert_eq!(external("gas_meter", 1.0, "m3").water_reading(), None);
+    }
+
+    #[test]
+    fn test_homewizard_error_from_http_error() {
+        // Create a client error by making a request to an invalid URL
+        let client = http::Client::new(Duration::from_secs(5)).unwrap();
         let runtime = tokio::runtime::Runtime::new().unwrap();
 
         runtime.block_on(async {
@@ -505,8 +1689,8 @@ mod tests {
                 .await;
             assert!(result.is_err());
 
-            let reqwest_error = result.unwrap_err();
-            let hw_error = HomeWizardError::from(reqwest_error);
+            let http_error = result.unwrap_err();
+            let hw_error = HomeWizardError::from(http_error);
 
             match hw_error {
                 HomeWizardError::RequestFailed(_) => {
@@ -586,6 +1770,59 @@ mod tests {
         assert_eq!(data.active_power_w, 1500.0);
     }
 
+    #[tokio::test]
+    async fn test_fetch_data_parse_modes() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "wifi_ssid": "TestNetwork",
+                "wifi_strength": 75.5,
+                "smr_version": 50,
+                "meter_model": "Test Meter",
+                "unique_id": "abc123",
+                "active_tariff": 1,
+                "total_power_import_kwh": 1234.567,
+                "total_power_import_t1_kwh": 600.0,
+                "total_power_import_t2_kwh": 634.567,
+                "total_power_export_kwh": 0.0,
+                "total_power_export_t1_kwh": 0.0,
+                "total_power_export_t2_kwh": 0.0,
+                "active_power_w": 1500.0,
+                "active_power_average_w": 1200.0
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let client = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        let lenient = client.fetch_data().await.unwrap();
+        assert!(lenient.missing_fields.is_empty());
+        assert!(
+            lenient
+                .unknown_fields
+                .contains_key("active_power_average_w")
+        );
+
+        let report = client
+            .clone()
+            .parse_mode(ParseMode::Report)
+            .fetch_data()
+            .await
+            .unwrap();
+        assert!(report.missing_fields.contains(&"active_power_l1_w"));
+        assert!(!report.missing_fields.contains(&"active_power_w"));
+
+        let strict = client.parse_mode(ParseMode::Strict).fetch_data().await;
+        assert!(matches!(strict, Err(HomeWizardError::ParseError(message))
+            if message.contains("active_power_average_w")));
+    }
+
     #[tokio::test]
     async fn test_fetch_data_http_error() {
         let mock_server = MockServer::start().await;
@@ -724,6 +1961,422 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_identify_refused_in_read_only_mode() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("PUT"))
+            .and(path("/api/v1/identify"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let client = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        match client.identify().await.unwrap_err() {
+            HomeWizardError::ReadOnly(operation) => assert_eq!(operation, "identify device"),
+            _ => panic!("Expected ReadOnly error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identify_when_writes_allowed() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("PUT"))
+            .and(path("/api/v1/identify"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+        .read_only(false);
+
+        assert!(client.identify().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_fallback_uses_next_source() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/telegram"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "/ISK5\\2M550T-1012\r\n\r\n1-3:0.2.8(50)\r\n1-0:1.8.1(000100.000*kWh)\r\n1-0:1.8.2(000050.000*kWh)\r\n!\r\n",
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let client = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        let (data, source) = client
+            .fetch_with_fallback(&[Source::V1, Source::Telegram])
+            .await
+            .unwrap();
+        assert_eq!(source, Source::Telegram);
+        assert_eq!(data.total_power_import_kwh, 150.0);
+        assert_eq!(data.smr_version, 50);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_fallback_prefers_first_source() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../example-response.json")),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/telegram"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let client = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        let (_, source) = client
+            .fetch_with_fallback(&[Source::V1, Source::Telegram])
+            .await
+            .unwrap();
+        assert_eq!(source, Source::V1);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_fallback_all_sources_fail() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+
+        let client = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        let result = client
+            .fetch_with_fallback(&[Source::V1, Source::Telegram])
+            .await;
+        match result.unwrap_err() {
+            HomeWizardError::ParseError(msg) => assert!(msg.contains("HTTP status: 404")),
+            _ => panic!("Expected ParseError"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_v2_sends_bearer_token() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/measurement"))
+            .and(header("authorization", "Bearer secret"))
+            .and(header("x-api-version", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"protocol_version": 50, "tariff": 1, "energy_import_kwh": 12.5, "power_w": 400}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let client = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert!(client.fetch_v2().await.is_err());
+
+        let client = client.api_v2(
+            format!("{}/api/measurement", mock_server.uri()),
+            "secret".to_string(),
+        );
+        let (data, source) = client
+            .fetch_with_fallback(&[Source::V2, Source::V1])
+            .await
+            .unwrap();
+        assert_eq!(source, Source::V2);
+        assert_eq!(data.total_power_import_kwh, 12.5);
+        assert_eq!(data.active_power_w, 400.0);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_system() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v1/system"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"cloud_enabled": false}"#))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/system"))
+            .and(header("authorization", "Bearer secret"))
+            .and(header("x-api-version", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"wifi_ssid": "home", "wifi_rssi_db": -67, "cloud_enabled": true, "uptime_s": 356, "status_led_brightness_pct": 100, "api_v1_enabled": true}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let client = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(
+            client.fetch_system().await.unwrap(),
+            SystemStatus {
+                cloud_enabled: Some(false),
+                ..SystemStatus::default()
+            }
+        );
+
+        let client = client.api_v2(
+            format!("{}/api/measurement", mock_server.uri()),
+            "secret".to_string(),
+        );
+        assert_eq!(
+            client.fetch_system().await.unwrap(),
+            SystemStatus {
+                cloud_enabled: Some(true),
+                uptime_secs: Some(356.0),
+                wifi_rssi_dbm: Some(-67.0),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_plugin_battery_uses_v2() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/measurement"))
+            .and(header("authorization", "Bearer secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"energy_import_kwh": 123.456, "energy_export_kwh": 98.765, "power_w": 636, "state_of_charge_pct": 42, "cycles": 17}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let data = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+        .api_v2(
+            format!("{}/api/measurement", mock_server.uri()),
+            "secret".to_string(),
+        )
+        .product(ProductType::PluginBattery)
+        .fetch_data()
+        .await
+        .unwrap();
+        assert_eq!(data.product, ProductType::PluginBattery);
+        assert_eq!(data.state_of_charge_pct, 42.0);
+        assert_eq!(data.battery_cycles, 17.0);
+        assert_eq!(data.total_power_export_kwh, 98.765);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_device_info() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"product_type": "HWE-KWH3", "product_name": "kWh meter", "serial": "3c39e7aabbcc", "firmware_version": "3.06", "api_version": "v1"}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let info = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+        .fetch_device_info()
+        .await
+        .unwrap();
+        assert_eq!(info.product_type, "HWE-KWH3");
+        assert_eq!(info.firmware_version, "3.06");
+        assert_eq!(info.api_version, "v1");
+        assert_eq!(info.product(), Some(ProductType::KwhMeter));
+    }
+
+    #[test]
+    fn test_product_from_product_type() {
+        assert_eq!(
+            ProductType::from_product_type("HWE-P1"),
+            Some(ProductType::P1)
+        );
+        assert_eq!(
+            ProductType::from_product_type("HWE-SKT"),
+            Some(ProductType::EnergySocket)
+        );
+        assert_eq!(ProductType::from_product_type("HWE-THERMOMETER"), None);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_watermeter() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"wifi_ssid": "Home", "wifi_strength": 84, "total_liter_m3": 17.014, "active_liter_lpm": 3.5, "total_liter_offset_m3": 0}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let client = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert!(client.fetch_data().await.is_err());
+
+        let data = client
+            .product(ProductType::Watermeter)
+            .fetch_data()
+            .await
+            .unwrap();
+        assert_eq!(data.product, ProductType::Watermeter);
+        assert_eq!(data.total_liter_m3, 17.014);
+        assert_eq!(data.active_liter_lpm, 3.5);
+        assert!(data.unknown_fields.contains_key("total_liter_offset_m3"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_energy_socket() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"wifi_ssid": "Home", "wifi_strength": 92, "total_power_import_t1_kwh": 30.511, "total_power_export_t1_kwh": 85.951, "active_power_w": 543, "active_power_l1_w": 543, "active_voltage_v": 231.5, "active_current_a": 2.35, "active_frequency_hz": 50.01}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/state"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(
+                    r#"{"power_on": true, "switch_lock": false, "brightness": 255}"#,
+                ),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let data = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+        .product(ProductType::EnergySocket)
+        .fetch_data()
+        .await
+        .unwrap();
+        assert_eq!(data.product, ProductType::EnergySocket);
+        assert_eq!(data.total_power_import_kwh, 30.511);
+        assert_eq!(data.total_power_export_kwh, 85.951);
+        assert_eq!(data.active_power_w, 543.0);
+        assert_eq!(data.active_voltage_l1_v, 231.5);
+        assert_eq!(data.active_current_a, 2.35);
+        let state = data.socket_state.unwrap();
+        assert!(state.power_on);
+        assert!(!state.switch_lock);
+        assert_eq!(state.brightness, 255.0);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_kwh_meter() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"wifi_ssid": "Home", "wifi_strength": 100, "total_power_import_kwh": 1234.5, "total_power_import_t1_kwh": 1234.5, "total_power_export_kwh": 0, "total_power_export_t1_kwh": 0, "active_power_w": 1800, "active_power_l1_w": 600, "active_power_l2_w": 700, "active_power_l3_w": 500, "active_voltage_l1_v": 231, "active_voltage_l2_v": 232, "active_voltage_l3_v": 230, "active_current_a": 7.8, "active_current_l1_a": 2.6, "active_current_l2_a": 3.0, "active_current_l3_a": 2.2, "active_frequency_hz": 50}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let data = HomeWizardClient::new(
+            format!("{}/api/v1/data", mock_server.uri()),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+        .product(ProductType::KwhMeter)
+        .fetch_data()
+        .await
+        .unwrap();
+        assert_eq!(data.product, ProductType::KwhMeter);
+        assert_eq!(data.total_power_import_kwh, 1234.5);
+        assert_eq!(data.active_power_l2_w, 700.0);
+        assert_eq!(data.active_voltage_l3_v, 230.0);
+        assert_eq!(data.active_current_l1_a, 2.6);
+        assert!(data.unknown_fields.is_empty());
+    }
+
+    #[test]
+    fn test_kwh_meter_single_phase() {
+        let data: KwhMeterData = serde_json::from_str(
+            r#"{"wifi_ssid": "Home", "wifi_strength": 100, "total_power_import_t1_kwh": 12.5, "total_power_export_t1_kwh": 1.5, "active_power_w": 450, "active_voltage_v": 229.8, "active_current_a": 1.9}"#,
+        )
+        .unwrap();
+        let data = HomeWizardData::from(data);
+
+        assert_eq!(data.total_power_import_kwh, 12.5);
+        assert_eq!(data.total_power_export_kwh, 1.5);
+        assert_eq!(data.active_power_l1_w, 450.0);
+        assert_eq!(data.active_voltage_l1_v, 229.8);
+        assert_eq!(data.active_current_l1_a, 1.9);
+        assert_eq!(data.active_voltage_l2_v, 0.0);
+    }
+
+    #[test]
+    fn test_error_class() {
+        assert_eq!(
+            HomeWizardError::RequestFailed(http::Error::Timeout(Duration::from_secs(5))).class(),
+            "timeout"
+        );
+        assert_eq!(
+            HomeWizardError::ParseError("HTTP status: 503".to_string()).class(),
+            "http_status"
+        );
+        assert_eq!(
+            HomeWizardError::ParseError("JSON decode error".to_string()).class(),
+            "parse"
+        );
+    }
+
     #[tokio::test]
     async fn test_fetch_data_different_status_codes() {
         let mock_server = MockServer::start().await;
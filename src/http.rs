@@ -0,0 +1,562 @@
+//! Outbound HTTP client used for the device API and push sinks.
+//!
+//! Backed by reqwest by default. The `lite-http` feature swaps in a minimal
+//! hyper client (HTTP/1.1, no cookies, no redirects, rustls only) for
+//! devices where binary size and memory matter.
+
+use serde::Serialize;
+use std::time::Duration;
+use thiserror::Error;
+use verify::Certificates;
+
+pub use ::http::{Method, StatusCode};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[cfg(not(feature = "lite-http"))]
+    #[error(transparent)]
+    Reqwest(#[from] reqwest::Error),
+
+    #[cfg(feature = "lite-http")]
+    #[error(transparent)]
+    Client(#[from] hyper_util::client::legacy::Error),
+
+    #[cfg(feature = "lite-http")]
+    #[error(transparent)]
+    Body(#[from] hyper::Error),
+
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("request timed out after {0:?}")]
+    Timeout(Duration),
+
+    #[error("unexpected HTTP status: {0}")]
+    Status(StatusCode),
+}
+
+/// A fully read response.
+#[derive(Debug)]
+pub struct Response {
+    status: StatusCode,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn text(self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn error_for_status(self) -> Result<Self, Error> {
+        if self.status.is_success() {
+            Ok(self)
+        } else {
+            Err(Error::Status(self.status))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: backend::Inner,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(timeout: Duration) -> Result<Self, Error> {
+        Ok(Self {
+            inner: backend::Inner::new(timeout, Certificates::Verify)?,
+            timeout,
+        })
+    }
+
+    /// Client for the device API. HomeWizard devices serve HTTPS with a
+    /// self-signed certificate, so the first certificate a host presents is
+    /// pinned and any later, different certificate from that host is
+    /// rejected. The bearer token is never sent to an impostor once the
+    /// real device has been seen.
+    pub fn for_device(timeout: Duration) -> Result<Self, Error> {
+        Ok(Self {
+            inner: backend::Inner::new(timeout, Certificates::pin_first_use())?,
+            timeout,
+        })
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Client that accepts any server certificate, for push targets the
+    /// user explicitly opted out of verifying.
+    pub fn unverified(timeout: Duration) -> Result<Self, Error> {
+        Ok(Self {
+            inner: backend::Inner::new(timeout, Certificates::AcceptAny)?,
+            timeout,
+        })
+    }
+
+    pub fn get(&self, url: impl Into<String>) -> RequestBuilder<'_> {
+        self.request(Method::GET, url)
+    }
+
+    pub fn post(&self, url: impl Into<String>) -> RequestBuilder<'_> {
+        self.request(Method::POST, url)
+    }
+
+    pub fn request(&self, method: Method, url: impl Into<String>) -> RequestBuilder<'_> {
+        RequestBuilder {
+            client: self,
+            method,
+            url: url.into(),
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+}
+
+pub struct RequestBuilder<'a> {
+    client: &'a Client,
+    method: Method,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<Result<Vec<u8>, String>>,
+}
+
+impl RequestBuilder<'_> {
+    pub fn bearer_auth(mut self, token: &str) -> Self {
+        self.headers
+            .push(("authorization".to_string(), format!("Bearer {token}")));
+        self
+    }
+
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+        use base64::Engine;
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"));
+        self.headers
+            .push(("authorization".to_string(), format!("Basic {credentials}")));
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn json<T: Serialize + ?Sized>(mut self, value: &T) -> Self {
+        self.headers
+            .push(("content-type".to_string(), "application/json".to_string()));
+        self.body = Some(serde_json::to_vec(value).map_err(|e| e.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = Some(Ok(body));
+        self
+    }
+
+    pub async fn send(self) -> Result<Response, Error> {
+        let body = self
+            .body
+            .transpose()
+            .map_err(Error::InvalidRequest)?
+            .unwrap_or_default();
+        let timeout = self.client.timeout;
+
+        tokio::time::timeout(
+            timeout,
+            self.client
+                .inner
+                .execute(self.method, &self.url, self.headers, body),
+        )
+        .await
+        .map_err(|_| Error::Timeout(timeout))?
+    }
+}
+
+#[cfg(not(feature = "lite-http"))]
+mod backend {
+    use super::verify::{self, Certificates};
+    use super::{Error, Method, Response};
+    use std::time::Duration;
+
+    #[derive(Debug, Clone)]
+    pub struct Inner(reqwest::Client);
+
+    impl Inner {
+        pub fn new(timeout: Duration, certificates: Certificates) -> Result<Self, Error> {
+            let builder = reqwest::Client::builder().timeout(timeout);
+            let builder = match verify::tls_config(certificates)? {
+                Some(config) => builder.use_preconfigured_tls(config),
+                None => builder,
+            };
+            Ok(Self(builder.build()?))
+        }
+
+        pub async fn execute(
+            &self,
+            method: Method,
+            url: &str,
+            headers: Vec<(String, String)>,
+            body: Vec<u8>,
+        ) -> Result<Response, Error> {
+            let mut request = self.0.request(method, url);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            if !body.is_empty() {
+                request = request.body(body);
+            }
+
+            let response = request.send().await?;
+            let status = response.status();
+            let body = response.bytes().await?.to_vec();
+            Ok(Response { status, body })
+        }
+    }
+}
+
+#[cfg(feature = "lite-http")]
+mod backend {
+    use super::verify::{self, Certificates};
+    use super::{Error, Method, Response};
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::Bytes;
+    use hyper_rustls::HttpsConnector;
+    use hyper_util::client::legacy::Client;
+    use hyper_util::client::legacy::connect::HttpConnector;
+    use hyper_util::rt::TokioExecutor;
+    use std::time::Duration;
+
+    #[derive(Debug, Clone)]
+    pub struct Inner(Client<HttpsConnector<HttpConnector>, Full<Bytes>>);
+
+    impl Inner {
+        pub fn new(timeout: Duration, certificates: Certificates) -> Result<Self, Error> {
+            let mut http = HttpConnector::new();
+            http.enforce_http(false);
+            http.set_connect_timeout(Some(timeout));
+
+            let builder = hyper_rustls::HttpsConnectorBuilder::new();
+            let builder = match verify::tls_config(certificates)? {
+                Some(config) => builder.with_tls_config(config),
+                None => builder.with_webpki_roots(),
+            };
+            let connector = builder.https_or_http().enable_http1().wrap_connector(http);
+
+            Ok(Self(Client::builder(TokioExecutor::new()).build(connector)))
+        }
+
+        pub async fn execute(
+            &self,
+            method: Method,
+            url: &str,
+            headers: Vec<(String, String)>,
+            body: Vec<u8>,
+        ) -> Result<Response, Error> {
+            let mut request = hyper::Request::builder().method(method).uri(url);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let request = request
+                .body(Full::new(Bytes::from(body)))
+                .map_err(|e| Error::InvalidRequest(e.to_string()))?;
+
+            let response = self.0.request(request).await?;
+            let status = response.status();
+            let body = response.into_body().collect().await?.to_bytes().to_vec();
+            Ok(Response { status, body })
+        }
+    }
+}
+
+/// Server certificate checks shared by both backends.
+mod verify {
+    use super::Error;
+    use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+    use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+    use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Certificates pinned per host, as presented on first contact.
+    pub type Pins = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    #[derive(Debug, Clone)]
+    pub enum Certificates {
+        /// Verify against the bundled web PKI roots.
+        Verify,
+        /// Trust the first certificate each host presents, then only that.
+        PinFirstUse(Pins),
+        /// Accept any certificate.
+        AcceptAny,
+    }
+
+    impl Certificates {
+        pub fn pin_first_use() -> Self {
+            Self::PinFirstUse(Pins::default())
+        }
+    }
+
+    /// The rustls configuration for `certificates`, or `None` when the
+    /// backend's default web PKI verification applies.
+    pub fn tls_config(certificates: Certificates) -> Result<Option<ClientConfig>, Error> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier: Arc<dyn ServerCertVerifier> = match certificates {
+            Certificates::Verify => return Ok(None),
+            Certificates::PinFirstUse(pins) => Arc::new(PinFirstUse {
+                provider: provider.clone(),
+                pins,
+            }),
+            Certificates::AcceptAny => Arc::new(AcceptAnyCertificate(provider.clone())),
+        };
+        Ok(Some(
+            ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .map_err(|e| Error::InvalidRequest(e.to_string()))?
+                .dangerous()
+                .with_custom_certificate_verifier(verifier)
+                .with_no_client_auth(),
+        ))
+    }
+
+    /// Pins the certificate a host presents on first contact and rejects
+    /// any other certificate from that host afterwards. Handshake
+    /// signatures are still checked, so a peer must hold the pinned key.
+    #[derive(Debug)]
+    struct PinFirstUse {
+        provider: Arc<CryptoProvider>,
+        pins: Pins,
+    }
+
+    impl ServerCertVerifier for PinFirstUse {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            let host = server_name.to_str().into_owned();
+            let mut pins = self.pins.lock().unwrap_or_else(|e| e.into_inner());
+            let pinned = pins
+                .entry(host.clone())
+                .or_insert_with(|| end_entity.to_vec());
+            if pinned.as_slice() == end_entity.as_ref() {
+                Ok(ServerCertVerified::assertion())
+            } else {
+                Err(rustls::Error::General(format!(
+                    "certificate of {host} changed since it was first seen; \
+                     restart the exporter if the device was reset"
+                )))
+            }
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            verify_tls12_signature(
+                message,
+                cert,
+                dss,
+                &self.provider.signature_verification_algorithms,
+            )
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            verify_tls13_signature(
+                message,
+                cert,
+                dss,
+                &self.provider.signature_verification_algorithms,
+            )
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.provider
+                .signature_verification_algorithms
+                .supported_schemes()
+        }
+    }
+
+    /// Accepts any server certificate while still checking handshake
+    /// signatures.
+    #[derive(Debug)]
+    struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+    impl ServerCertVerifier for AcceptAnyCertificate {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            verify_tls12_signature(
+                message,
+                cert,
+                dss,
+                &self.0.signature_verification_algorithms,
+            )
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            verify_tls13_signature(
+                message,
+                cert,
+                dss,
+                &self.0.signature_verification_algorithms,
+            )
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.0.signature_verification_algorithms.supported_schemes()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn verify(pins: &Pins, host: &'static str, cert: &'static [u8]) -> bool {
+            let verifier = PinFirstUse {
+                provider: Arc::new(rustls::crypto::ring::default_provider()),
+                pins: pins.clone(),
+            };
+            verifier
+                .verify_server_cert(
+                    &CertificateDer::from(cert),
+                    &[],
+                    &ServerName::try_from(host).unwrap(),
+                    &[],
+                    UnixTime::now(),
+                )
+                .is_ok()
+        }
+
+        #[test]
+        fn test_pin_first_use_rejects_changed_certificate() {
+            let pins = Pins::default();
+            assert!(verify(&pins, "192.168.1.10", b"device"));
+            assert!(verify(&pins, "192.168.1.10", b"device"));
+            assert!(!verify(&pins, "192.168.1.10", b"impostor"));
+        }
+
+        #[test]
+        fn test_pins_are_per_host() {
+            let pins = Pins::default();
+            assert!(verify(&pins, "192.168.1.10", b"first"));
+            assert!(verify(&pins, "192.168.1.11", b"second"));
+            assert!(!verify(&pins, "192.168.1.11", b"first"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_get_reads_status_and_body() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("hello"))
+            .mount(&mock_server)
+            .await;
+
+        let client = Client::new(Duration::from_secs(5)).unwrap();
+        let response = client
+            .get(format!("{}/api", mock_server.uri()))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text(), "hello");
+    }
+
+    #[tokio::test]
+    async fn test_post_json_with_bearer_token() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("authorization", "Bearer token"))
+            .and(header("content-type", "application/json"))
+            .and(body_json(serde_json::json!({"a": 1})))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = Client::new(Duration::from_secs(5)).unwrap();
+        let response = client
+            .post(mock_server.uri())
+            .bearer_auth("token")
+            .json(&serde_json::json!({"a": 1}))
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.error_for_status().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_error_for_status() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let client = Client::new(Duration::from_secs(5)).unwrap();
+        let response = client.get(mock_server.uri()).send().await.unwrap();
+
+        assert!(matches!(
+            response.error_for_status(),
+            Err(Error::Status(StatusCode::SERVICE_UNAVAILABLE))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+            .mount(&mock_server)
+            .await;
+
+        let client = Client::new(Duration::from_millis(50)).unwrap();
+        assert!(client.get(mock_server.uri()).send().await.is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! InfluxDB line protocol rendering, and the writer posting readings to the
+//! InfluxDB write API (`--influx-url`).
+
+use anyhow::Result;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::homewizard::HomeWizardData;
+use crate::http;
+use crate::wal::Wal;
+
+pub const MEASUREMENT: &str = "homewizard_p1";
+
+/// Escapes commas, spaces and equals signs in tag keys and values.
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | ' ' | '=' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Renders one reading as a single line (without trailing newline).
+pub fn render(data: &HomeWizardData, timestamp_ns: i64) -> String {
+    let mut line = String::from(MEASUREMENT);
+
+    for (key, value) in [
+        ("unique_id", data.unique_id.as_str()),
+        ("meter_model", data.meter_model.as_str()),
+    ] {
+        if !value.is_empty() {
+            let _ = write!(line, ",{}={}", key, escape_tag(value));
+        }
+    }
+
+    let float_fields = [
+        ("active_power_w", data.active_power_w),
+        ("active_power_l1_w", data.active_power_l1_w),
+        ("active_power_l2_w", data.active_power_l2_w),
+        ("active_power_l3_w", data.active_power_l3_w),
+        ("active_voltage_l1_v", data.active_voltage_l1_v),
+        ("active_voltage_l2_v", data.active_voltage_l2_v),
+        ("active_voltage_l3_v", data.active_voltage_l3_v),
+        ("active_current_a", data.active_current_a),
+        ("active_current_l1_a", data.active_current_l1_a),
+        ("active_current_l2_a", data.active_current_l2_a),
+        ("active_current_l3_a", data.active_current_l3_a),
+        ("active_frequency_hz", data.active_frequency_hz),
+        ("total_power_import_kwh", data.total_power_import_kwh),
+        ("total_power_export_kwh", data.total_power_export_kwh),
+        ("total_gas_m3", data.total_gas_m3),
+        ("wifi_strength", data.wifi_strength),
+    ];
+
+    let mut separator = ' ';
+    for (key, value) in float_fields {
+        if value.is_finite() {
+            let _ = write!(line, "{separator}{key}={value}");
+            separator = ',';
+        }
+    }
+    // Only the tariffs the meter reports
+    for (direction, tariffs) in [
+        ("import", data.import_tariffs()),
+        ("export", data.export_tariffs()),
+    ] {
+        for (tariff, value) in tariffs {
+            if value.is_finite() {
+                let _ = write!(
+                    line,
+                    "{separator}total_power_{direction}_t{tariff}_kwh={value}"
+                );
+                separator = ',';
+            }
+        }
+    }
+    let _ = write!(line, "{separator}active_tariff={}i", data.active_tariff);
+
+    let _ = write!(line, " {timestamp_ns}");
+    line
+}
+
+/// Where and how readings are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InfluxTarget {
+    /// InfluxDB 1.x `/write`, optionally with basic auth
+    V1 {
+        database: String,
+        credentials: Option<(String, String)>,
+    },
+    /// InfluxDB 2.x `/api/v2/write` with an API token
+    V2 {
+        org: String,
+        bucket: String,
+        token: Option<String>,
+    },
+}
+
+/// Posts every reading as a line of line protocol.
+#[derive(Debug, Clone)]
+pub struct InfluxWriter {
+    client: http::Client,
+    url: String,
+    target: InfluxTarget,
+    /// Keeps the lines that failed, written again before the next one
+    wal: Option<Arc<Wal>>,
+}
+
+impl InfluxWriter {
+    pub fn new(base_url: &str, target: InfluxTarget, timeout: Duration) -> Result<Self> {
+        let base_url = base_url.trim_end_matches('/');
+        let url = match &target {
+            InfluxTarget::V1 { database, .. } => {
+                format!(
+                    "{base_url}/write?db={}&precision=ns",
+                    query_escape(database)
+                )
+            }
+            InfluxTarget::V2 { org, bucket, .. } => format!(
+                "{base_url}/api/v2/write?org={}&bucket={}&precision=ns",
+                query_escape(org),
+                query_escape(bucket)
+            ),
+        };
+        Ok(Self {
+            client: http::Client::new(timeout)?,
+            url,
+            target,
+            wal: None,
+        })
+    }
+
+    pub fn wal(mut self, wal: Arc<Wal>) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    pub async fn write(&self, line: String) -> Result<()> {
+        match &self.wal {
+            Some(wal) => wal.deliver(line.into_bytes(), |body| self.send(body)).await,
+            None => self.send(line.into_bytes()).await,
+        }
+    }
+
+    async fn send(&self, body: Vec<u8>) -> Result<()> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("content-type", "text/plain; charset=utf-8")
+            .body(body);
+        match &self.target {
+            InfluxTarget::V1 {
+                credentials: Some((username, password)),
+                ..
+            } => request = request.basic_auth(username, password),
+            InfluxTarget::V2 {
+                token: Some(token), ..
+            } => request = request.header("authorization", format!("Token {token}")),
+            _ => {}
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Percent-encodes a query parameter value.
+fn query_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            escaped.push(byte as char);
+        } else {
+            let _ = write!(escaped, "%{byte:02X}");
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_line() {
+        let data = HomeWizardData {
+            unique_id: "00112233".to_string(),
+            meter_model: "ISKRA  2M550T-101".to_string(),
+            active_power_w: -543.0,
+            total_power_import_kwh: 13779.338,
+            active_tariff: 2,
+            ..HomeWizardData::default()
+        };
+
+        let line = render(&data, 1_700_000_000_000_000_000);
+
+        assert!(line.starts_with(
+            "homewizard_p1,unique_id=00112233,meter_model=ISKRA\\ \\ 2M550T-101 active_power_w=-543,"
+        ));
+        assert!(line.contains(",total_power_import_kwh=13779.338,"));
+        assert!(line.ends_with(",active_tariff=2i 1700000000000000000"));
+    }
+
+    #[test]
+    fn test_render_without_tags() {
+        let line = render(&HomeWizardData::default(), 0);
+        assert!(line.starts_with("homewizard_p1 active_power_w=0,"));
+    }
+
+    #[test]
+    fn test_escape_tag() {
+        assert_eq!(escape_tag("a,b=c d"), "a\\,b\\=c\\ d");
+    }
+
+    #[test]
+    fn test_query_escape() {
+        assert_eq!(query_escape("home energy/p1"), "home%20energy%2Fp1");
+    }
+
+    #[tokio::test]
+    async fn test_write_v1_and_v2() {
+        use wiremock::matchers::{body_string, header, method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/write"))
+            .and(query_param("db", "energy"))
+            .and(header("authorization", "Basic dXNlcjpzZWNyZXQ="))
+            .and(body_string("homewizard_p1 active_tariff=1i 0"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v2/write"))
+            .and(query_param("org", "home"))
+            .and(query_param("bucket", "p1"))
+            .and(query_param("precision", "ns"))
+            .and(header("authorization", "Token abc"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let v1 = InfluxWriter::new(
+            &mock_server.uri(),
+            InfluxTarget::V1 {
+                database: "energy".to_string(),
+                credentials: Some(("user".to_string(), "secret".to_string())),
+            },
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        v1.write("homewizard_p1 active_tariff=1i 0".to_string())
+            .await
+            .unwrap();
+
+        let v2 = InfluxWriter::new(
+            &mock_server.uri(),
+            InfluxTarget::V2 {
+                org: "home".to_string(),
+                bucket: "p1".to_string(),
+                token: Some("abc".to_string()),
+            },
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        v2.write("homewizard_p1 active_tariff=1i 0".to_string())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+//! HTML index served at `/`, like the landing pages of other Prometheus
+//! exporters: links to the endpoints and the state of every device.
+
+use axum::extract::State;
+use axum::response::Html;
+use std::fmt::Write;
+
+use crate::scheduler::{PollStatus, PollerState, Pollers};
+
+const ENDPOINTS: &[(&str, &str)] = &[
+    ("/metrics", "Prometheus metrics"),
+    ("/json", "Latest reading as JSON"),
+    ("/api/recent", "Recent polls as JSON"),
+    (
+        "/api/homeassistant",
+        "Flat JSON for Home Assistant's rest sensor",
+    ),
+    ("/healthz", "Liveness check"),
+    ("/ready", "Readiness check: recent polls reached the meter"),
+];
+
+pub async fn handler(State(pollers): State<Pollers>) -> Html<String> {
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>HomeWizard P1 Prometheus Exporter</title></head>\n\
+         <body>\n<h1>HomeWizard P1 Prometheus Exporter</h1>\n<p>Version {}</p>\n<ul>\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    for (path, description) in ENDPOINTS {
+        let _ = writeln!(
+            page,
+            "<li><a href=\"{path}\">{path}</a> - {description}</li>"
+        );
+    }
+    page.push_str("</ul>\n<h2>Devices</h2>\n<table>\n");
+    page.push_str("<tr><th>Name</th><th>Target</th><th>Status</th><th>Last success</th></tr>\n");
+    for poller in pollers.pollers() {
+        let status = poller.status();
+        let _ = writeln!(
+            page,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(poller.name()),
+            escape(&poller.url().await),
+            describe(&status),
+            status
+                .last_success
+                .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| "never".to_string()),
+        );
+    }
+    page.push_str("</table>\n</body>\n</html>\n");
+    Html(page)
+}
+
+fn describe(status: &PollStatus) -> String {
+    match status.state {
+        PollerState::Starting => "starting".to_string(),
+        PollerState::Healthy => "ok".to_string(),
+        PollerState::Failing {
+            consecutive_failures,
+        } => format!("failing ({consecutive_failures} polls)"),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_status() {
+        let failing = PollStatus {
+            state: PollerState::Failing {
+                consecutive_failures: 3,
+            },
+            last_success: None,
+        };
+        assert_eq!(describe(&failing), "failing (3 polls)");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape("<a href=\"x\">&"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;"
+        );
+    }
+}
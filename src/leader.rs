@@ -0,0 +1,151 @@
+//! Active/passive failover between exporter instances through a lease file
+//! on storage they share. Only the lease holder polls the device and feeds
+//! the sinks; standbys keep serving their last metrics, marked by
+//! `homewizard_p1_leader 0`.
+
+use anyhow::Result;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::scheduler::Pollers;
+use crate::textfile;
+
+/// A lease file holding `<holder>\n<expiry in Unix milliseconds>\n`.
+#[derive(Debug, Clone)]
+pub struct LeaseFile {
+    path: PathBuf,
+    holder: String,
+    ttl: Duration,
+}
+
+impl LeaseFile {
+    pub fn new(path: PathBuf, holder: String, ttl: Duration) -> Self {
+        Self { path, holder, ttl }
+    }
+
+    /// Takes or renews the lease if it is free, expired or already ours.
+    /// Returns whether this instance holds the lease afterwards.
+    pub async fn try_acquire(&self, now: SystemTime) -> Result<bool> {
+        let now_ms = unix_ms(now);
+        if let Some((holder, expires_ms)) = self.read().await?
+            && holder != self.holder
+            && expires_ms > now_ms
+        {
+            return Ok(false);
+        }
+
+        let expires_ms = now_ms + self.ttl.as_millis() as i64;
+        textfile::write_atomic(&self.path, &format!("{}\n{}\n", self.holder, expires_ms)).await?;
+
+        // Another instance may have written at the same time; the file
+        // decides.
+        Ok(self
+            .read()
+            .await?
+            .is_some_and(|(holder, _)| holder == self.holder))
+    }
+
+    async fn read(&self) -> Result<Option<(String, i64)>> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut lines = contents.lines();
+        let holder = lines.next().unwrap_or_default().to_string();
+        // An unreadable expiry counts as expired.
+        let expires_ms = lines
+            .next()
+            .and_then(|line| line.trim().parse().ok())
+            .unwrap_or_default();
+        Ok(Some((holder, expires_ms)))
+    }
+}
+
+fn unix_ms(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Renews the lease three times per TTL and switches the pollers between
+/// active and standby as leadership changes. Pollers added since follow
+/// along at the next renewal.
+pub async fn run(lease: LeaseFile, pollers: Pollers) {
+    let mut ticker = tokio::time::interval(lease.ttl / 3);
+    let mut leader = None;
+    info!(
+        "Standing by for lease {} as {:?}",
+        lease.path.display(),
+        lease.holder
+    );
+
+    loop {
+        ticker.tick().await;
+        // Without access to the lease, stepping down is the safe choice.
+        let acquired = match lease.try_acquire(SystemTime::now()).await {
+            Ok(acquired) => acquired,
+            Err(e) => {
+                warn!("Failed to renew lease {}: {:#}", lease.path.display(), e);
+                false
+            }
+        };
+
+        if leader != Some(acquired) {
+            if acquired {
+                info!("Acquired lease {}, polling", lease.path.display());
+            } else if leader.is_some() {
+                info!("Lost lease {}, standing by", lease.path.display());
+            }
+            leader = Some(acquired);
+        }
+        for poller in pollers.pollers() {
+            if poller.is_leader() != acquired {
+                poller.set_leader(acquired).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lease_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("homewizard-lease-{}-{}", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_lease_excludes_other_holders_until_expiry() {
+        let path = lease_path("expiry");
+        let ttl = Duration::from_secs(15);
+        let a = LeaseFile::new(path.clone(), "a".to_string(), ttl);
+        let b = LeaseFile::new(path.clone(), "b".to_string(), ttl);
+        let now = SystemTime::now();
+
+        assert!(a.try_acquire(now).await.unwrap());
+        assert!(!b.try_acquire(now + Duration::from_secs(5)).await.unwrap());
+        // Renewal by the holder.
+        assert!(a.try_acquire(now + Duration::from_secs(10)).await.unwrap());
+        assert!(!b.try_acquire(now + Duration::from_secs(20)).await.unwrap());
+        // a stopped renewing.
+        assert!(b.try_acquire(now + Duration::from_secs(26)).await.unwrap());
+        assert!(!a.try_acquire(now + Duration::from_secs(27)).await.unwrap());
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_lease_is_taken_over() {
+        let path = lease_path("corrupt");
+        tokio::fs::write(&path, "someone\nnot-a-number\n")
+            .await
+            .unwrap();
+
+        let lease = LeaseFile::new(path.clone(), "a".to_string(), Duration::from_secs(15));
+        assert!(lease.try_acquire(SystemTime::now()).await.unwrap());
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+//! Log file with rotation (`--log-file`), for installs without journald
+//! where stdout goes nowhere. Rotated files are renamed `<file>.1` (newest)
+//! up to `<file>.<retention>`, like logrotate does.
+
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Time-based rotation, next to the size limit.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogRotation {
+    /// Only rotate on size
+    #[default]
+    Never,
+    /// Start a new file every hour
+    Hourly,
+    /// Start a new file every day at midnight
+    Daily,
+}
+
+impl LogRotation {
+    /// Identifies the period `time` falls in; a new period starts a new file.
+    fn period(self, time: DateTime<Local>) -> Option<String> {
+        match self {
+            Self::Never => None,
+            Self::Hourly => Some(time.format("%Y-%m-%d %H").to_string()),
+            Self::Daily => Some(time.format("%Y-%m-%d").to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// Size in bytes a file may reach before it is rotated; 0 for no limit
+    pub max_size: u64,
+    pub rotation: LogRotation,
+    /// Number of rotated files kept
+    pub retention: usize,
+}
+
+/// Shared handle to the log file; every clone writes to the same file.
+#[derive(Clone)]
+pub struct LogFile(Arc<Mutex<State>>);
+
+struct State {
+    path: PathBuf,
+    policy: RotationPolicy,
+    file: File,
+    size: u64,
+    period: Option<String>,
+}
+
+impl LogFile {
+    /// Opens `path` for appending, rotating it first if it belongs to an
+    /// earlier period.
+    pub fn open(path: &Path, policy: RotationPolicy) -> io::Result<Self> {
+        let (file, size) = open_append(path)?;
+        let modified = file.metadata()?.modified().map(DateTime::<Local>::from);
+        let mut state = State {
+            path: path.to_path_buf(),
+            policy,
+            file,
+            size,
+            period: policy
+                .rotation
+                .period(modified.unwrap_or_else(|_| Local::now())),
+        };
+        if state.period != policy.rotation.period(Local::now()) {
+            state.rotate()?;
+        }
+        Ok(Self(Arc::new(Mutex::new(state))))
+    }
+}
+
+impl State {
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.policy.retention == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.policy.retention).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    std::fs::rename(&from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        (self.file, self.size) = open_append(&self.path)?;
+        self.period = self.policy.rotation.period(Local::now());
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let full = state.policy.max_size > 0
+            && state.size > 0
+            && state.size + buf.len() as u64 > state.policy.max_size;
+        if full || state.period != state.policy.rotation.period(Local::now()) {
+            state.rotate()?;
+        }
+        let written = state.file.write(buf)?;
+        state.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .file
+            .flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{index}"));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotated_path() {
+        assert_eq!(
+            rotated_path(Path::new("/var/log/homewizard.log"), 2),
+            Path::new("/var/log/homewizard.log.2")
+        );
+    }
+
+    #[test]
+    fn test_rotates_on_size_and_keeps_retention() {
+        let dir = std::env::temp_dir().join(format!("homewizard-logfile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("exporter.log");
+        let policy = RotationPolicy {
+            max_size: 10,
+            rotation: LogRotation::Never,
+            retention: 2,
+        };
+
+        let mut log = LogFile::open(&path, policy).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        log.flush().unwrap();
+
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "third\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "second\n");
+        assert!(!rotated_path(&path, 3).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_period() {
+        let time = Local::now();
+        assert_eq!(LogRotation::Never.period(time), None);
+        assert_eq!(
+            LogRotation::Daily.period(time),
+            Some(time.format("%Y-%m-%d").to_string())
+        );
+    }
+}
@@ -1,115 +1,885 @@
+mod admin;
+mod allowlist;
+mod auth;
+mod check;
 mod config;
+mod cost;
+mod dashboard;
+mod discover;
+mod events;
+mod execd;
+mod fetch;
+mod fuse;
+mod graphite;
+mod homeassistant;
 mod homewizard;
+mod http;
+mod influx;
+mod landing;
+mod leader;
+mod logfile;
 mod metrics;
+mod mqtt;
+mod netmetering;
+mod openmetrics;
+mod otlp;
+mod pair;
+mod probe;
+mod pushgateway;
+mod readiness;
+mod recent;
+mod reload;
+mod remote_write;
+mod retry;
+mod scheduler;
+mod schema;
+mod state;
+mod statsd;
+#[cfg(unix)]
+mod syslog;
+mod systemd;
+mod telegram;
+mod textfile;
+mod timezone;
+mod tls;
+mod total;
+mod v2;
+mod wal;
+mod weather;
 
-use anyhow::Result;
-use axum::{Router, routing::get};
-use clap::Parser;
+use anyhow::{Context, Result};
+use axum::extract::{FromRef, Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Json, Router, routing::get};
+use serde::Deserialize;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use tokio::time::interval;
-use tracing::{error, info, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tower_http::compression::CompressionLayer;
+use tower_http::trace::TraceLayer;
+use tracing::{info, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{Layer, layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::config::Config;
-use crate::homewizard::HomeWizardClient;
-use crate::metrics::Metrics;
+use crate::allowlist::IpAllowlist;
+use crate::auth::HttpAuth;
+use crate::config::{
+    Command, Config, ConfigCommand, Device, LogFormat, LogTarget, OutputMode, ScrapeMode,
+};
+use crate::events::{EventPublisher, GrafanaAnnotator};
+use crate::homeassistant::{HomeAssistantSensors, SharedHomeAssistant};
+use crate::homewizard::{DeviceInfo, HomeWizardClient, ParseMode, ProductType};
+use crate::metrics::{DeviceMetrics, Metrics, MetricsOptions};
+use crate::probe::Prober;
+use crate::readiness::{ReadinessGate, SharedReadiness};
+use crate::recent::{RecentSamples, SharedRecent};
+use crate::scheduler::{Fleet, Poller, PollerFactory, Pollers, Scheduler};
+use crate::weather::{DegreeDayOptions, OpenMeteo};
 
 type SharedMetrics = Arc<RwLock<String>>;
+type LatestReading = tokio::sync::watch::Receiver<Option<scheduler::Reading>>;
+
+/// State shared by the HTTP handlers.
+#[derive(Clone)]
+struct AppState {
+    metrics: SharedMetrics,
+    recent: SharedRecent,
+    readiness: SharedReadiness,
+    home_assistant: SharedHomeAssistant,
+    pollers: Pollers,
+    /// Metrics of every device, for OpenMetrics scrapes
+    device_metrics: DeviceMetrics,
+    latest_reading: LatestReading,
+    /// Serves `/probe` when set
+    prober: Option<Arc<Prober>>,
+}
+
+impl FromRef<AppState> for SharedMetrics {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
+impl FromRef<AppState> for DeviceMetrics {
+    fn from_ref(state: &AppState) -> Self {
+        state.device_metrics.clone()
+    }
+}
+
+impl FromRef<AppState> for SharedRecent {
+    fn from_ref(state: &AppState) -> Self {
+        state.recent.clone()
+    }
+}
+
+impl FromRef<AppState> for SharedReadiness {
+    fn from_ref(state: &AppState) -> Self {
+        state.readiness.clone()
+    }
+}
+
+impl FromRef<AppState> for SharedHomeAssistant {
+    fn from_ref(state: &AppState) -> Self {
+        state.home_assistant.clone()
+    }
+}
+
+impl FromRef<AppState> for Pollers {
+    fn from_ref(state: &AppState) -> Self {
+        state.pollers.clone()
+    }
+}
+
+impl FromRef<AppState> for LatestReading {
+    fn from_ref(state: &AppState) -> Self {
+        state.latest_reading.clone()
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Parse configuration
-    let config = Config::parse();
+    let config = Config::load()?;
+    if let Some(command) = &config.command {
+        init_logging(&config)?;
+        let status = match command {
+            Command::Fetch { format } => fetch::run(&config, *format).await,
+            Command::Check => check::run(&config).await,
+            Command::Pair {
+                name,
+                timeout,
+                save,
+            } => {
+                pair::run(
+                    &config,
+                    name,
+                    std::time::Duration::from_secs(*timeout),
+                    *save,
+                )
+                .await?;
+                0
+            }
+            Command::GrafanaDashboard => {
+                dashboard::run(&config)?;
+                0
+            }
+            Command::Config {
+                command: ConfigCommand::Schema,
+            } => {
+                schema::run()?;
+                0
+            }
+            Command::Completions { shell } => {
+                // Ignore a closed pipe, as in `completions bash | head`
+                let _ =
+                    std::io::Write::write_all(&mut std::io::stdout(), &Config::completions(*shell));
+                0
+            }
+            Command::Discover { format, timeout } => {
+                discover::run(&config, *format, std::time::Duration::from_secs(*timeout)).await?;
+                0
+            }
+        };
+        std::process::exit(status);
+    }
+    config.validate_output()?;
+    config.validate_sources()?;
+    config.retry_policy()?;
+    config.probe_labels()?;
+    let influx_target = config.influx_target()?;
+    let addr = config.metrics_bind_address()?;
+    let tls = config.tls_server_config()?;
 
-    // Initialize logging
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| config.log_level.clone().into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    init_logging(&config)?;
 
     info!("Starting HomeWizard P1 Prometheus Exporter");
-    info!("HomeWizard host: {}", config.host);
-    info!("Metrics port: {}", config.port);
-    info!("Poll interval: {}s", config.poll_interval);
+    let devices = config.devices()?;
+    for device in &devices {
+        info!("HomeWizard device {}: {}", device.name, device.host);
+    }
+    if config.output == OutputMode::Http {
+        info!("Metrics port: {}", config.port);
+    }
+    if let Some(path) = &config.textfile_output {
+        info!("Writing metrics to textfile {}", path.display());
+    }
+    match (config.scrape_mode, config.poll_interval) {
+        (ScrapeMode::OnDemand, _) => info!(
+            "Polling on scrape, reusing readings for {}s",
+            config.scrape_cache_ttl
+        ),
+        (ScrapeMode::Interval, Some(seconds)) => info!("Poll interval: {}s", seconds),
+        (ScrapeMode::Interval, None) => {
+            info!("Poll interval: auto (based on the meter's SMR version)")
+        }
+    }
+
+    // Fetch outdoor temperatures for the degree-day metrics
+    let degree_days = match config.weather_location()? {
+        Some((latitude, longitude)) => {
+            info!(
+                "Fetching outdoor temperatures for {}, {} from {}",
+                latitude, longitude, config.weather_url
+            );
+            let options = DegreeDayOptions {
+                base_temperature: config.heating_base_temperature,
+                ..DegreeDayOptions::default()
+            };
+            let open_meteo = OpenMeteo::new(
+                &config.weather_url,
+                latitude,
+                longitude,
+                config.http_timeout_duration(),
+            )?;
+            tokio::spawn(weather::run(
+                open_meteo,
+                options.temperatures.clone(),
+                config.weather_interval_duration(),
+            ));
+            Some(options)
+        }
+        None => None,
+    };
+
+    let failover = config.failover_lease()?;
 
-    // Initialize metrics
-    let metrics = Arc::new(Metrics::new()?);
+    // Initialize metrics, one registry per device
+    let options = MetricsOptions {
+        gas_stale_threshold: config.gas_stale_threshold_duration(),
+        water_mode: config.water_mode,
+        contract: config.contract()?,
+        net_metering: config.net_metering()?,
+        degree_days,
+        fuse: config.fuse_limit()?,
+        raw_passthrough: config.raw_passthrough,
+        gas_timestamp_raw: config.gas_timestamp_raw,
+        schema_report: config.parse_mode == ParseMode::Report,
+        failover: failover.is_some(),
+        device: None,
+        labels: config.static_labels()?,
+        names: config.metric_names()?,
+        sensor_names: config.sensor_names()?,
+        sensor_filter: config.sensor_filter(),
+        timezone: config.timezone,
+        product: ProductType::default(),
+    };
     let shared_metrics: SharedMetrics = Arc::new(RwLock::new(String::new()));
+    let recent: SharedRecent = Arc::new(RwLock::new(RecentSamples::new(
+        config.recent_window_duration(),
+        config.recent_max_samples,
+    )));
+    let readiness: SharedReadiness =
+        Arc::new(RwLock::new(ReadinessGate::new(config.readiness_policy()?)));
+    let home_assistant = SharedHomeAssistant::default();
 
-    // Initialize HomeWizard client
-    let client = HomeWizardClient::new(config.homewizard_url(), config.http_timeout_duration())?;
-
-    // Start polling task
-    let poll_metrics = metrics.clone();
-    let poll_shared_metrics = shared_metrics.clone();
-    let poll_interval = config.poll_interval_duration();
-
-    tokio::spawn(async move {
-        let mut interval = interval(poll_interval);
-        interval.tick().await; // First tick completes immediately
-
-        loop {
-            interval.tick().await;
-
-            match client.fetch_data().await {
-                Ok(data) => {
-                    info!("Successfully fetched data from HomeWizard");
-
-                    if let Err(e) = poll_metrics.update(&data) {
-                        error!("Failed to update metrics: {}", e);
-                        continue;
-                    }
-
-                    match poll_metrics.gather() {
-                        Ok(metrics_text) => {
-                            let mut metrics_guard = poll_shared_metrics.write().await;
-                            *metrics_guard = metrics_text;
-                        }
-                        Err(e) => {
-                            error!("Failed to gather metrics: {}", e);
-                        }
-                    }
-                }
-                Err(e) => {
-                    warn!("Failed to fetch data from HomeWizard: {}", e);
-                }
+    if config.read_only {
+        info!("Read-only mode: requests that change device state are disabled");
+    }
+
+    // Initialize event sinks
+    let grafana = match &config.grafana_url {
+        Some(url) => {
+            info!("Posting power events as Grafana annotations to {}", url);
+            Some(GrafanaAnnotator::new(
+                url,
+                config.grafana_token.clone(),
+                config.grafana_annotation_tags.clone(),
+                config.http_timeout_duration(),
+            )?)
+        }
+        None => None,
+    };
+    let events = EventPublisher::new(grafana);
+    let influx = match (&config.influx_url, influx_target) {
+        (Some(url), Some(target)) => {
+            info!("Writing readings to InfluxDB at {}", url);
+            let mut writer =
+                influx::InfluxWriter::new(url, target, config.http_timeout_duration())?;
+            if let Some(wal) = config.wal("influx")? {
+                writer = writer.wal(wal);
+            }
+            Some(writer)
+        }
+        _ => None,
+    };
+    let mqtt = match config.mqtt_settings() {
+        Some(settings) => {
+            info!(
+                "Publishing readings to MQTT broker {}:{}",
+                settings.host, settings.port
+            );
+            let (mut publisher, event_loop) = mqtt::MqttPublisher::new(settings);
+            if let Some(wal) = config.wal("mqtt")? {
+                publisher = publisher.wal(wal);
             }
+            tokio::spawn(publisher.clone().run(event_loop));
+            Some(publisher)
         }
+        None => None,
+    };
+    let pushgateway = match &config.pushgateway_url {
+        Some(url) => {
+            info!(
+                "Pushing metrics to the Pushgateway at {} after every poll",
+                url
+            );
+            Some(pushgateway::Pushgateway::new(
+                url,
+                &config.pushgateway_job,
+                config.pushgateway_instance.as_deref(),
+                config.http_timeout_duration(),
+            )?)
+        }
+        None => None,
+    };
+
+    // Start polling. The JSON endpoints and execd output follow the first
+    // device; metrics, readiness and events cover all of them.
+    let device_metrics = DeviceMetrics::default();
+    let wiring = PollerWiring {
+        options: options.clone(),
+        output: shared_metrics.clone(),
+        exposition: device_metrics.clone(),
+        events,
+        readiness: readiness.clone(),
+        overload: config.overload_policy()?,
+        influx,
+        mqtt,
+        pushgateway,
+        standby: failover.is_some(),
+        on_demand: (config.scrape_mode == ScrapeMode::OnDemand)
+            .then(|| config.scrape_cache_ttl_duration()),
+        rediscover: discover::mdns(),
+    };
+    let (readings, latest_reading) = tokio::sync::watch::channel(None);
+    let mut pollers = Vec::with_capacity(devices.len());
+    for (index, device) in devices.into_iter().enumerate() {
+        let mut poller = wiring.build(&config, device).await?;
+        if index == 0 {
+            poller = poller
+                .with_recent(recent.clone())
+                .with_readings(readings.clone())
+                .with_home_assistant(home_assistant.clone());
+        }
+        device_metrics.add(poller.metrics().clone());
+        pollers.push(Arc::new(poller));
+    }
+    let factory: PollerFactory = Arc::new(move |config, device| {
+        let wiring = wiring.clone();
+        Box::pin(async move { wiring.build(&config, device).await })
     });
+    let pollers: Pollers =
+        Arc::new(Fleet::new(pollers, device_metrics.clone()).with_factory(factory, config.clone()));
+    if let Some(path) = &config.state_file {
+        let file = state::StateFile::new(path);
+        let tracked = state::Tracked {
+            recent: recent.clone(),
+            home_assistant: home_assistant.clone(),
+            pollers: pollers.clone(),
+        };
+        file.restore(&tracked).await?;
+        tokio::spawn(file.run(tracked));
+    }
+    if let Some(lease) = failover {
+        tokio::spawn(leader::run(lease, pollers.clone()));
+    }
+    tokio::spawn(reload::run(pollers.clone()));
+    tokio::spawn(systemd::run(pollers.clone()));
+    if let Some(endpoint) = &config.otlp_endpoint {
+        let exporter = otlp::OtlpExporter::new(
+            endpoint,
+            std::time::Duration::from_secs(config.otlp_interval),
+            config.http_timeout_duration(),
+        )?;
+        info!(
+            "Pushing metrics to {} every {}s",
+            endpoint, config.otlp_interval
+        );
+        tokio::spawn(exporter.run(device_metrics.clone()));
+    }
+    if let Some(url) = &config.remote_write_url {
+        let mut writer = remote_write::RemoteWriter::new(
+            url,
+            std::time::Duration::from_secs(config.remote_write_interval),
+            config.http_timeout_duration(),
+            !config.remote_write_insecure_skip_verify,
+        )?;
+        if let (Some(username), Some(password)) =
+            (&config.remote_write_username, &config.remote_write_password)
+        {
+            writer = writer.basic_auth(username, password);
+        }
+        if let Some(token) = &config.remote_write_bearer_token {
+            writer = writer.bearer_token(token);
+        }
+        writer = writer.headers(config.remote_write_headers()?);
+        if let Some(wal) = config.wal("remote_write")? {
+            writer = writer.wal(wal);
+        }
+        info!(
+            "Pushing metrics to {} every {}s",
+            url, config.remote_write_interval
+        );
+        tokio::spawn(writer.run(device_metrics.clone()));
+    }
+    if let Some(host) = &config.graphite_host {
+        let sender = graphite::GraphiteSender::new(
+            format!("{}:{}", host, config.graphite_port),
+            config.graphite_protocol,
+            &config.graphite_prefix,
+            std::time::Duration::from_secs(config.graphite_interval),
+            config.http_timeout_duration(),
+        );
+        info!(
+            "Sending metrics to Graphite at {}:{} every {}s",
+            host, config.graphite_port, config.graphite_interval
+        );
+        tokio::spawn(sender.run(device_metrics.clone()));
+    }
+    if let Some(host) = &config.statsd_host {
+        let emitter = statsd::StatsdEmitter::new(
+            format!("{}:{}", host, config.statsd_port),
+            config.statsd_format,
+            &config.statsd_prefix,
+            config.statsd_tags.clone(),
+            std::time::Duration::from_secs(config.statsd_interval),
+        );
+        info!(
+            "Sending metrics to StatsD at {}:{} every {}s",
+            host, config.statsd_port, config.statsd_interval
+        );
+        tokio::spawn(emitter.run(device_metrics.clone()));
+    }
+    let scheduler = tokio::spawn(Scheduler::new(pollers.clone()).run());
+
+    match config.output {
+        OutputMode::Http => {}
+        OutputMode::Textfile => {
+            scheduler.await?;
+            return Ok(());
+        }
+        OutputMode::Execd => return execd::run(latest_reading, config.execd_signal).await,
+    }
 
     // Initialize HTTP server
-    let app = Router::new()
-        .route("/metrics", get(metrics_handler))
-        .route("/health", get(health_handler))
-        .route("/", get(root_handler))
-        .with_state(shared_metrics);
+    let auth = HttpAuth::from_sources(
+        config.auth_token.as_deref(),
+        config.auth_tokens_file.as_deref(),
+        config.basic_auth(),
+    )?;
+    if auth.is_some() {
+        info!("Authentication enabled for /metrics");
+    }
+    let allowlist = if config.allow_cidr.is_empty() {
+        None
+    } else {
+        info!("Restricting /metrics to {}", config.allow_cidr.join(", "));
+        Some(IpAllowlist::parse(&config.allow_cidr)?)
+    };
+    let state = AppState {
+        metrics: shared_metrics,
+        recent,
+        readiness,
+        home_assistant,
+        pollers: pollers.clone(),
+        device_metrics,
+        latest_reading,
+        prober: config.enable_probe.then(|| {
+            info!("Probing arbitrary devices at /probe?target=host");
+            Arc::new(Prober::new(config.clone(), options))
+        }),
+    };
+    let admin = config.admin_token.as_deref().map(|token| {
+        info!("Admin API enabled at /admin/devices");
+        let persist = config
+            .admin_persist
+            .then(|| config.config_file.clone())
+            .flatten();
+        admin::router(pollers, HttpAuth::new(vec![token.to_string()]), persist)
+    });
+    let app = router(state, auth, allowlist, admin);
+
+    let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+    match tls {
+        Some(tls) => {
+            info!("Starting metrics server on https://{}", addr);
+            let tls = axum_server::tls_rustls::RustlsConfig::from_config(Arc::new(tls));
+            axum_server::bind_rustls(addr, tls)
+                .serve(make_service)
+                .await?;
+        }
+        None => {
+            info!("Starting metrics server on {}", addr);
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            axum::serve(listener, make_service).await?;
+        }
+    }
 
-    let addr = config.metrics_bind_address();
-    info!("Starting metrics server on {}", &addr);
+    Ok(())
+}
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+/// Sets up logging to the configured target. In execd mode and for
+/// subcommands stdout carries the output, so logs go to stderr.
+fn init_logging(config: &Config) -> Result<()> {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| config.log_level.clone().into());
+    if config.log_target == LogTarget::Journald {
+        let journald = tracing_journald::layer().context("Failed to connect to journald")?;
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(journald)
+            .init();
+        return Ok(());
+    }
 
+    let log_writer = if config.log_target == LogTarget::Syslog {
+        #[cfg(unix)]
+        {
+            BoxMakeWriter::new(syslog::Syslog::connect().context("Failed to connect to syslog")?)
+        }
+        #[cfg(not(unix))]
+        anyhow::bail!("--log-target syslog is only supported on Unix")
+    } else if let Some(path) = &config.log_file {
+        let file = logfile::LogFile::open(path, config.log_rotation_policy())
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+        BoxMakeWriter::new(move || file.clone())
+    } else if config.output == OutputMode::Execd || config.command.is_some() {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    // Files and syslog get plain text; syslog adds its own timestamps
+    let terminal = config.log_target == LogTarget::Stdout && config.log_file.is_none();
+    let timestamps = config.log_target != LogTarget::Syslog;
+    let layer = match (config.log_format, timestamps) {
+        (LogFormat::Text, true) => tracing_subscriber::fmt::layer()
+            .with_ansi(terminal)
+            .with_writer(log_writer)
+            .boxed(),
+        (LogFormat::Text, false) => tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .without_time()
+            .with_writer(log_writer)
+            .boxed(),
+        (LogFormat::Json, _) => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_writer(log_writer)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .init();
     Ok(())
 }
 
+/// What every device's poller is wired to, so devices added at runtime get
+/// the same sinks as those polled since startup.
+#[derive(Clone)]
+struct PollerWiring {
+    options: MetricsOptions,
+    output: SharedMetrics,
+    exposition: DeviceMetrics,
+    events: EventPublisher,
+    readiness: SharedReadiness,
+    overload: Option<fuse::OverloadPolicy>,
+    influx: Option<influx::InfluxWriter>,
+    mqtt: Option<mqtt::MqttPublisher>,
+    pushgateway: Option<pushgateway::Pushgateway>,
+    standby: bool,
+    on_demand: Option<std::time::Duration>,
+    rediscover: discover::Rediscover,
+}
+
+impl PollerWiring {
+    /// Identifies `device` and builds its poller. A device without a
+    /// configured product is polled as whatever `/api` reports.
+    async fn build(&self, config: &Config, mut device: Device) -> Result<Poller> {
+        let mut client = HomeWizardClient::new(device.url(), config.device_http_timeout(&device))?;
+        let info = match client.fetch_device_info().await {
+            Ok(info) => {
+                info!(
+                    "[{}] {} ({}), firmware {}, API {}",
+                    device.name,
+                    info.product_name,
+                    info.product_type,
+                    info.firmware_version,
+                    info.api_version
+                );
+                Some(info)
+            }
+            Err(e) => {
+                warn!("[{}] Failed to read device information: {}", device.name, e);
+                None
+            }
+        };
+        if device.product.is_none() {
+            device.product = info.as_ref().and_then(DeviceInfo::product);
+            if device.product.is_none() {
+                warn!(
+                    "[{}] Could not detect the product, polling it as a P1 meter",
+                    device.name
+                );
+            }
+        }
+
+        let metrics = Arc::new(Metrics::with_options(MetricsOptions {
+            device: Some(device.name.clone()),
+            product: device.product.unwrap_or_default(),
+            ..self.options.clone()
+        })?);
+        if let Some(info) = &info {
+            metrics.set_device_info(info);
+        }
+
+        client = client
+            .read_only(config.read_only)
+            .parse_mode(config.parse_mode)
+            .product(device.product.unwrap_or_default());
+        if let Some(token) = config.device_api_token(&device)? {
+            client = client.api_v2(device.v2_url(), token);
+        }
+        if config.identify {
+            match client.identify().await {
+                Ok(()) => info!("[{}] Sent identify request to HomeWizard", device.name),
+                Err(e) => warn!("[{}] Failed to identify HomeWizard: {}", device.name, e),
+            }
+        }
+
+        let mut poller = Poller::new(
+            device.name.clone(),
+            client,
+            metrics,
+            self.output.clone(),
+            config.clone(),
+        )
+        .with_exposition(self.exposition.clone())
+        .with_events(self.events.clone())
+        .with_readiness(self.readiness.clone())
+        .with_rediscover(self.rediscover.clone(), info.map(|info| info.serial));
+        if let Some(policy) = self.overload {
+            poller = poller.with_overload(policy);
+        }
+        if let Some(influx) = &self.influx {
+            poller = poller.with_influx(influx.clone());
+        }
+        if let Some(mqtt) = &self.mqtt {
+            poller = poller.with_mqtt(mqtt.clone());
+        }
+        if let Some(pushgateway) = &self.pushgateway {
+            poller = poller.with_pushgateway(pushgateway.clone());
+        }
+        if self.standby {
+            poller = poller.standby();
+        }
+        if let Some(ttl) = self.on_demand {
+            poller = poller.on_demand(ttl);
+        }
+        Ok(poller)
+    }
+}
+
+/// Builds the HTTP router. Endpoints exposing meter data sit behind the IP
+/// allowlist and bearer authentication when configured; `/` and the
+/// liveness (`/health`, `/healthz`) and readiness (`/readyz`, `/ready`)
+/// probes stay open. The admin routes bring their own authentication but
+/// share the allowlist.
+fn router(
+    state: AppState,
+    auth: Option<HttpAuth>,
+    allowlist: Option<IpAllowlist>,
+    admin: Option<Router<AppState>>,
+) -> Router {
+    let mut protected = Router::new()
+        .route(
+            "/metrics",
+            get(metrics_handler)
+                .layer(axum::middleware::from_fn_with_state(
+                    state.device_metrics.clone(),
+                    openmetrics_on_request,
+                ))
+                .layer(axum::middleware::from_fn_with_state(
+                    state.pollers.clone(),
+                    refresh_on_scrape,
+                )),
+        )
+        .route("/metrics/{device}", get(device_metrics_handler))
+        .route("/api/recent", get(recent_handler))
+        .route("/api/homeassistant", get(home_assistant_handler))
+        .route("/json", get(json_handler));
+    if let Some(prober) = state.prober.clone() {
+        protected = protected.route("/probe", get(probe::handler).with_state(prober));
+    }
+    if let Some(auth) = auth {
+        protected = protected.route_layer(axum::middleware::from_fn_with_state(
+            Arc::new(auth),
+            auth::require_auth,
+        ));
+    }
+    if let Some(admin) = admin {
+        protected = protected.merge(admin);
+    }
+    // Added last so it runs first: disallowed clients get 403, not 401.
+    if let Some(allowlist) = allowlist {
+        protected = protected.route_layer(axum::middleware::from_fn_with_state(
+            Arc::new(allowlist),
+            allowlist::require_allowed_ip,
+        ));
+    }
+    // Scrapers sending `Accept-Encoding: gzip` or `deflate` get a
+    // compressed body.
+    let protected = protected.layer(CompressionLayer::new());
+
+    Router::new()
+        .merge(protected)
+        .route("/health", get(health_handler))
+        .route("/healthz", get(health_handler))
+        .route("/readyz", get(readyz_handler))
+        .route("/ready", get(readyz_handler))
+        .route("/", get(landing::handler))
+        .with_state(state)
+        // Requests are logged at debug level
+        .layer(TraceLayer::new_for_http())
+}
+
+/// The metrics rendered as of the scrape, so `data_age_seconds` keeps
+/// growing between polls. Nothing is served until a poll has published.
 async fn metrics_handler(
-    axum::extract::State(metrics): axum::extract::State<SharedMetrics>,
-) -> String {
-    let metrics_guard = metrics.read().await;
-    metrics_guard.clone()
+    State(metrics): State<SharedMetrics>,
+    State(device_metrics): State<DeviceMetrics>,
+) -> axum::response::Response {
+    let published = metrics.read().await.clone();
+    let devices = device_metrics.snapshot();
+    if published.is_empty() || devices.is_empty() {
+        return published.into_response();
+    }
+    match metrics::gather_all(&devices) {
+        Ok(text) => text.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}\n")).into_response(),
+    }
+}
+
+/// The metrics of one device, for jobs scraping each device separately.
+/// In `--scrape-mode on-demand` only that device is polled.
+async fn device_metrics_handler(
+    State(pollers): State<Pollers>,
+    Path(device): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
+    let Some(poller) = pollers.get(&device) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Unknown device {device:?}\n"),
+        )
+            .into_response();
+    };
+    poller.refresh().await;
+    let devices = [poller.metrics().clone()];
+    if wants_openmetrics(&headers) {
+        return (
+            [(axum::http::header::CONTENT_TYPE, openmetrics::CONTENT_TYPE)],
+            metrics::gather_all_openmetrics(&devices),
+        )
+            .into_response();
+    }
+    match metrics::gather_all(&devices) {
+        Ok(text) => text.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}\n")).into_response(),
+    }
+}
+
+/// In `--scrape-mode on-demand`, polls the devices before `/metrics`
+/// answers. Pollers on a fixed interval return right away.
+async fn refresh_on_scrape(
+    State(pollers): State<Pollers>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let mut refreshes = tokio::task::JoinSet::new();
+    for poller in pollers.pollers() {
+        refreshes.spawn(async move { poller.refresh().await });
+    }
+    refreshes.join_all().await;
+    next.run(request).await
+}
+
+/// Answers scrapers asking for `application/openmetrics-text` in that
+/// format; others fall through to the classic text exposition.
+async fn openmetrics_on_request(
+    State(device_metrics): State<DeviceMetrics>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    if !wants_openmetrics(request.headers()) {
+        return next.run(request).await;
+    }
+    (
+        [(axum::http::header::CONTENT_TYPE, openmetrics::CONTENT_TYPE)],
+        metrics::gather_all_openmetrics(&device_metrics.snapshot()),
+    )
+        .into_response()
+}
+
+/// Whether the scraper asks for `application/openmetrics-text`.
+fn wants_openmetrics(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get(axum::http::header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(openmetrics::accepts)
+}
+
+#[derive(Debug, Deserialize)]
+struct RecentQuery {
+    /// Only return samples newer than this Unix time in milliseconds
+    since: Option<i64>,
+}
+
+async fn recent_handler(
+    State(recent): State<SharedRecent>,
+    Query(query): Query<RecentQuery>,
+) -> Json<Vec<recent::Sample>> {
+    Json(recent.read().await.since(query.since))
 }
 
 async fn health_handler() -> &'static str {
     "OK"
 }
 
-async fn root_handler() -> &'static str {
-    "HomeWizard P1 Prometheus Exporter\n\nEndpoints:\n  /metrics - Prometheus metrics\n  /health  - Health check\n"
+async fn home_assistant_handler(
+    State(home_assistant): State<SharedHomeAssistant>,
+) -> Result<Json<HomeAssistantSensors>, (StatusCode, &'static str)> {
+    home_assistant
+        .read()
+        .await
+        .sensors()
+        .cloned()
+        .map(Json)
+        .ok_or((StatusCode::SERVICE_UNAVAILABLE, "No reading yet\n"))
+}
+
+/// The latest successful poll of the first device, with its timestamp.
+async fn json_handler(
+    State(latest_reading): State<LatestReading>,
+) -> Result<Json<scheduler::Reading>, (StatusCode, &'static str)> {
+    latest_reading
+        .borrow()
+        .clone()
+        .map(Json)
+        .ok_or((StatusCode::SERVICE_UNAVAILABLE, "No reading yet\n"))
+}
+
+/// Ready once recent polls satisfy the readiness policy, so a deployment
+/// pointing at the wrong device never receives traffic.
+async fn readyz_handler(State(readiness): State<SharedReadiness>) -> (StatusCode, String) {
+    match readiness.read().await.check(std::time::Instant::now()) {
+        Ok(()) => (StatusCode::OK, "READY\n".to_string()),
+        Err(reason) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("NOT READY: {reason}\n"),
+        ),
+    }
 }
 
 #[cfg(test)]
@@ -130,8 +900,10 @@ mod tests {
         Router::new()
             .route("/metrics", get(metrics_handler))
             .route("/health", get(health_handler))
-            .route("/", get(root_handler))
-            .with_state(shared_metrics)
+            .with_state(AppState {
+                metrics: shared_metrics,
+                ..test_state("")
+            })
     }
 
     #[tokio::test]
@@ -157,8 +929,8 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_root_handler() {
-        let app = create_test_app();
+    async fn test_landing_page() {
+        let app = router(test_state(""), None, None, None);
 
         let response = app
             .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
@@ -172,8 +944,10 @@ mod tests {
             .unwrap();
         let body_str = String::from_utf8(body.to_vec()).unwrap();
         assert!(body_str.contains("HomeWizard P1 Prometheus Exporter"));
-        assert!(body_str.contains("/metrics"));
-        assert!(body_str.contains("/health"));
+        assert!(body_str.contains(env!("CARGO_PKG_VERSION")));
+        assert!(body_str.contains(r#"<a href="/metrics">"#));
+        assert!(body_str.contains(r#"<a href="/healthz">"#));
+        assert!(body_str.contains(r#"<a href="/json">"#));
     }
 
     #[tokio::test]
@@ -205,7 +979,10 @@ mod tests {
         let shared_metrics: SharedMetrics = Arc::new(RwLock::new(String::new()));
         let app = Router::new()
             .route("/metrics", get(metrics_handler))
-            .with_state(shared_metrics);
+            .with_state(AppState {
+                metrics: shared_metrics,
+                ..test_state("")
+            });
 
         let response = app
             .oneshot(
@@ -254,7 +1031,10 @@ mod tests {
         for _ in 0..10 {
             let app = Router::new()
                 .route("/metrics", get(metrics_handler))
-                .with_state(shared_metrics.clone());
+                .with_state(AppState {
+                    metrics: shared_metrics.clone(),
+                    ..test_state("")
+                });
 
             let handle = tokio::spawn(async move {
                 let response = app
@@ -307,7 +1087,10 @@ mod tests {
 
         let app = Router::new()
             .route("/metrics", get(metrics_handler))
-            .with_state(shared_metrics.clone());
+            .with_state(AppState {
+                metrics: shared_metrics.clone(),
+                ..test_state("")
+            });
 
         // Get initial metrics
         let response = app
@@ -353,6 +1136,495 @@ mod tests {
         assert!(body_str.contains("updated_metric 2"));
     }
 
+    fn test_state(metrics: &str) -> AppState {
+        AppState {
+            metrics: Arc::new(RwLock::new(metrics.to_string())),
+            recent: Arc::new(RwLock::new(RecentSamples::new(
+                std::time::Duration::from_secs(600),
+                100,
+            ))),
+            readiness: Arc::new(RwLock::new(ReadinessGate::new(
+                readiness::ReadinessPolicy {
+                    min_successes: 1,
+                    window: 3,
+                    max_data_age: None,
+                },
+            ))),
+            home_assistant: SharedHomeAssistant::default(),
+            pollers: Pollers::default(),
+            device_metrics: DeviceMetrics::default(),
+            latest_reading: tokio::sync::watch::channel(None).1,
+            prober: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recent_handler_filters_by_since() {
+        let state = test_state("");
+        {
+            let mut recent = state.recent.write().await;
+            recent.push(recent::Sample::from_data(1000, &Default::default()));
+            recent.push(recent::Sample::from_data(2000, &Default::default()));
+        }
+        let app = router(state, None, None, None);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/recent?since=1000")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let samples: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(samples.as_array().unwrap().len(), 1);
+        assert_eq!(samples[0]["timestamp_ms"], 2000);
+    }
+
+    #[tokio::test]
+    async fn test_device_metrics_handler_serves_one_device() {
+        use clap::Parser;
+
+        let config = Config::parse_from(["homewizard-p1-exporter", "--host", "192.168.1.10"]);
+        let poller = |name: &str| {
+            let metrics = Arc::new(
+                Metrics::with_options(MetricsOptions {
+                    device: Some(name.to_string()),
+                    ..MetricsOptions::default()
+                })
+                .unwrap(),
+            );
+            metrics.record_poll_success();
+            let client = HomeWizardClient::new(
+                config::device_url("192.168.1.10"),
+                std::time::Duration::from_secs(5),
+            )
+            .unwrap();
+            Arc::new(Poller::new(
+                name,
+                client,
+                metrics,
+                Arc::new(RwLock::new(String::new())),
+                config.clone(),
+            ))
+        };
+        let state = AppState {
+            pollers: Arc::new(Fleet::new(
+                vec![poller("house"), poller("annex")],
+                DeviceMetrics::default(),
+            )),
+            ..test_state("")
+        };
+        let app = router(state, None, None, None);
+
+        let get = |uri: &'static str| {
+            app.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+        let response = get("/metrics/house").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("device=\"house\""));
+        assert!(!body.contains("device=\"annex\""));
+
+        let response = get("/metrics/garage").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_readyz_follows_poll_outcomes() {
+        let state = test_state("");
+        let readiness = state.readiness.clone();
+        let app = router(state, None, None, None);
+        let readyz = || {
+            Request::builder()
+                .uri("/readyz")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(readyz()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        readiness
+            .write()
+            .await
+            .record(true, std::time::Instant::now());
+        let response = app.oneshot(readyz()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_kubernetes_probe_aliases() {
+        let state = test_state("");
+        let readiness = state.readiness.clone();
+        let app = router(state, None, None, None);
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get("/healthz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(get("/ready")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        readiness
+            .write()
+            .await
+            .record(true, std::time::Instant::now());
+        let response = app.oneshot(get("/ready")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_home_assistant_handler() {
+        let state = test_state("");
+        let home_assistant = state.home_assistant.clone();
+        let app = router(state, None, None, None);
+        let request = || {
+            Request::builder()
+                .uri("/api/homeassistant")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let data = homewizard::HomeWizardData {
+            active_power_w: 321.0,
+            ..Default::default()
+        };
+        home_assistant.write().await.update(
+            &data,
+            chrono::NaiveDate::from_ymd_opt(2026, 10, 1).unwrap(),
+            1_790_000_000,
+        );
+
+        let response = app.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let sensors: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(sensors["power_w"], 321.0);
+        assert_eq!(sensors["energy_import_today_kwh"], 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_json_handler() {
+        let (readings, latest_reading) = tokio::sync::watch::channel(None);
+        let state = AppState {
+            latest_reading,
+            ..test_state("")
+        };
+        let app = router(state, None, None, None);
+        let request = || Request::builder().uri("/json").body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        readings.send_replace(Some(scheduler::Reading {
+            timestamp_ms: 1_790_000_000_000,
+            data: homewizard::HomeWizardData {
+                active_power_w: 321.0,
+                total_gas_m3: 1234.5,
+                ..Default::default()
+            },
+        }));
+
+        let response = app.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let reading: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reading["timestamp_ms"], 1_790_000_000_000i64);
+        assert_eq!(reading["data"]["active_power_w"], 321.0);
+        assert_eq!(reading["data"]["total_gas_m3"], 1234.5);
+    }
+
+    fn create_authenticated_app() -> Router {
+        router(
+            test_state("test_metric 42\n"),
+            Some(HttpAuth::new(vec!["secret".to_string()])),
+            None,
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_metrics_requires_bearer_token() {
+        let app = create_authenticated_app();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()["www-authenticate"], "Bearer");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_with_valid_bearer_token() {
+        let app = create_authenticated_app();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .header("Authorization", "Bearer secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "test_metric 42\n");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_with_basic_auth() {
+        let auth = HttpAuth::new(vec!["secret".to_string()]).with_basic("prometheus", "s3cret");
+        let app = router(test_state("test_metric 42\n"), Some(auth), None, None);
+        let request = |authorization: Option<&str>| {
+            let mut request = Request::builder().uri("/metrics");
+            if let Some(authorization) = authorization {
+                request = request.header("Authorization", authorization);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = app.clone().oneshot(request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let challenges: Vec<_> = response
+            .headers()
+            .get_all("WWW-Authenticate")
+            .iter()
+            .collect();
+        assert_eq!(challenges.len(), 2);
+
+        // prometheus:s3cret
+        let response = app
+            .clone()
+            .oneshot(request(Some("Basic cHJvbWV0aGV1czpzM2NyZXQ=")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app.oneshot(request(Some("Bearer secret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_compression() {
+        let metrics = "homewizard_p1_active_power_watts 321\n".repeat(20);
+        let app = router(test_state(&metrics), None, None, None);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .header("Accept-Encoding", "gzip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-Encoding"], "gzip");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.len() < metrics.len());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key("Content-Encoding"));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, metrics);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_openmetrics_negotiation() {
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let state = AppState {
+            device_metrics: DeviceMetrics::new(vec![metrics]),
+            ..test_state("homewizard_exporter_up 1\n")
+        };
+        let app = router(state, None, None, None);
+        let request = |accept: &str| {
+            Request::builder()
+                .uri("/metrics")
+                .header("Accept", accept)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()["Content-Type"],
+            openmetrics::CONTENT_TYPE
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("# TYPE homewizard_exporter_poll_success counter\n"));
+        assert!(body.contains("homewizard_exporter_poll_success_created "));
+        assert!(body.ends_with("# EOF\n"));
+
+        let response = app.oneshot(request("text/plain")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("# TYPE homewizard_exporter_poll_success_total counter\n"));
+        assert!(!body.ends_with("# EOF\n"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_rendered_as_of_scrape() {
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let state = AppState {
+            device_metrics: DeviceMetrics::new(vec![metrics.clone()]),
+            ..test_state("homewizard_p1_data_age_seconds 999\n")
+        };
+        let app = router(state, None, None, None);
+        let scrape = || async {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/metrics")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        metrics.record_poll_success();
+        let body = scrape().await;
+        assert!(body.contains("\nhomewizard_p1_data_age_seconds 0"));
+        assert!(body.contains("\nhomewizard_p1_up 1\n"));
+
+        // A failed poll shows at the next scrape, without a new render
+        metrics.record_poll_error("timeout");
+        let body = scrape().await;
+        assert!(body.contains("\nhomewizard_p1_up 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_health_open_with_authentication_enabled() {
+        let app = create_authenticated_app();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/health")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    fn request_from(uri: &str, addr: &str) -> Request<Body> {
+        let mut request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        request.extensions_mut().insert(axum::extract::ConnectInfo(
+            addr.parse::<SocketAddr>().unwrap(),
+        ));
+        request
+    }
+
+    fn create_allowlisted_app() -> Router {
+        router(
+            test_state("test_metric 42\n"),
+            None,
+            Some(
+                IpAllowlist::parse(&["192.168.1.10/32".to_string(), "127.0.0.1".to_string()])
+                    .unwrap(),
+            ),
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_metrics_allowed_from_allowlisted_ip() {
+        let app = create_allowlisted_app();
+
+        let response = app
+            .oneshot(request_from("/metrics", "192.168.1.10:54321"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_forbidden_from_other_ip() {
+        let app = create_allowlisted_app();
+
+        let response = app
+            .oneshot(request_from("/metrics", "192.168.1.11:54321"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_health_open_from_other_ip() {
+        let app = create_allowlisted_app();
+
+        let response = app
+            .oneshot(request_from("/health", "10.0.0.1:54321"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
     #[test]
     fn test_shared_metrics_type_alias() {
         let shared_metrics: SharedMetrics = Arc::new(RwLock::new("test".to_string()));
@@ -1,15 +1,1003 @@
-use crate::homewizard::HomeWizardData;
-use anyhow::Result;
-use prometheus::{Counter, CounterVec, Encoder, Gauge, GaugeVec, Opts, Registry, TextEncoder};
+use crate::config::WaterMode;
+use crate::cost::{Contract, CostTracker, MonthBaseline};
+use crate::fuse::FuseLimit;
+use crate::homewizard::{
+    DeviceInfo, ExternalSensor, HomeWizardData, PowerFailure, ProductType, SmrCapabilities,
+    SystemStatus, WaterReading, dsmr_unix_time,
+};
+use crate::netmetering::{NetMeteringConfig, NetMeteringTracker};
+use crate::openmetrics;
+use crate::timezone::{self, METER_TIMEZONE};
+use crate::total::{TotalCounter, TotalCounterVec};
+use crate::weather::{self, DailyGasTracker, DegreeDayOptions};
+use anyhow::{Result, anyhow};
+use chrono_tz::Tz;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    Counter, CounterVec, Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, Opts, Registry,
+    TextEncoder,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Tunables for how readings are turned into metrics.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsOptions {
+    /// Override for the gas staleness threshold; derived from the meter's
+    /// SMR version when unset.
+    pub gas_stale_threshold: Option<Duration>,
+    /// Representation(s) in which external water meters are exported.
+    pub water_mode: WaterMode,
+    /// Energy contract; cost metrics are only exported when set.
+    pub contract: Option<Contract>,
+    /// Net metering year; balance metrics are only exported when set.
+    pub net_metering: Option<NetMeteringConfig>,
+    /// Outdoor temperatures; degree-day metrics are only exported when set.
+    pub degree_days: Option<DegreeDayOptions>,
+    /// Main fuse rating; utilization metrics are only exported when set.
+    pub fuse: Option<FuseLimit>,
+    /// Export unknown numeric JSON fields as `homewizard_p1_raw_<field>`.
+    pub raw_passthrough: bool,
+    /// Also export the gas timestamp as the DSMR `YYMMDDhhmmss` number
+    /// (`homewizard_p1_gas_timestamp`).
+    pub gas_timestamp_raw: bool,
+    /// Count unknown and missing JSON fields (`--parse-mode report`).
+    pub schema_report: bool,
+    /// Export `homewizard_p1_leader` for active/passive failover.
+    pub failover: bool,
+    /// Value of the `device` label added to every metric.
+    pub device: Option<String>,
+    /// Constant labels added to every metric (`--label`).
+    pub labels: Vec<(String, String)>,
+    /// Names of the P1 metrics (`--metric-prefix`).
+    pub names: MetricNames,
+    /// Friendly name of external sensors by unique ID (`--sensor-name`),
+    /// exported as the `name` label.
+    pub sensor_names: HashMap<String, String>,
+    /// External sensors to export (`--sensor-include`, `--sensor-exclude`).
+    pub sensor_filter: SensorFilter,
+    /// Zone of daily and monthly rollovers (the host's local time when
+    /// unset) and of the meter's timestamps (`--timezone`).
+    pub timezone: Option<Tz>,
+    /// Kind of device, which decides the metric families.
+    pub product: ProductType,
+}
+
+/// Builds the exported metric names: the P1 meter's start with
+/// `homewizard_p1_` unless `--metric-prefix` says otherwise, and any family
+/// can be renamed with `--metric-rename`.
+#[derive(Debug, Clone)]
+pub struct MetricNames {
+    prefix: String,
+    /// New name by the name the family would otherwise get
+    renames: HashMap<String, String>,
+}
+
+impl Default for MetricNames {
+    fn default() -> Self {
+        Self::new(DEFAULT_METRIC_PREFIX)
+    }
+}
+
+/// Prefix of the P1 meter's metric names.
+pub const DEFAULT_METRIC_PREFIX: &str = "homewizard_p1_";
+
+impl MetricNames {
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            renames: HashMap::new(),
+        }
+    }
+
+    pub fn with_renames(self, renames: HashMap<String, String>) -> Self {
+        Self { renames, ..self }
+    }
+
+    /// The exported name of the metric family `name`.
+    pub fn name(&self, name: &str) -> String {
+        self.renames
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// The exported name of the P1 metric `name`, given without prefix.
+    pub fn p1(&self, name: &str) -> String {
+        self.name(&format!("{}{name}", self.prefix))
+    }
+
+    /// Metric name for an unknown device field, with characters Prometheus
+    /// does not allow replaced by underscores.
+    fn raw(&self, field: &str) -> String {
+        let field: String = field
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.p1(&format!("raw_{}", field.to_ascii_lowercase()))
+    }
+}
+
+/// Selects the external sensors to export, by unique ID or type. Sensors of
+/// neighbours sometimes show up on the meter bus.
+#[derive(Debug, Clone, Default)]
+pub struct SensorFilter {
+    /// When not empty, only matching sensors are exported
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl SensorFilter {
+    pub fn allows(&self, sensor: &ExternalSensor) -> bool {
+        let matches = |entry: &String| *entry == sensor.unique_id || *entry == sensor.sensor_type;
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+/// Accumulates outage durations from the meter's power failure log, which
+/// only keeps the most recent entries.
+#[derive(Debug, Default)]
+struct PowerFailureTracker {
+    seen: BTreeSet<i64>,
+    total_s: f64,
+}
+
+impl PowerFailureTracker {
+    /// Adds entries not seen before and returns the accumulated seconds.
+    fn observe(&mut self, log: &[PowerFailure]) -> f64 {
+        for failure in log {
+            if self.seen.insert(failure.end_timestamp) {
+                self.total_s += failure.duration_s;
+            }
+        }
+        // Entries that rolled out of the log never come back.
+        self.seen
+            .retain(|end| log.iter().any(|failure| failure.end_timestamp == *end));
+        self.total_s
+    }
+}
+
+/// Tracks when each gas meter's reading last changed, as observed by the
+/// exporter, so staleness does not depend on the meter's clock.
+#[derive(Debug, Default)]
+struct GasAgeTracker {
+    last_change: HashMap<String, (i64, Instant)>,
+}
+
+impl GasAgeTracker {
+    fn observe(&mut self, unique_id: &str, timestamp: i64, now: Instant) -> Duration {
+        let entry = self
+            .last_change
+            .entry(unique_id.to_string())
+            .or_insert((timestamp, now));
+        if entry.0 != timestamp {
+            *entry = (timestamp, now);
+        }
+        now.duration_since(entry.1)
+    }
+
+    fn retain(&mut self, unique_ids: &[&str]) {
+        self.last_change
+            .retain(|id, _| unique_ids.contains(&id.as_str()));
+    }
+}
+
+#[derive(Debug)]
+struct WaterState {
+    volume_m3: f64,
+    flow_lpm: f64,
+    last_change: Instant,
+    last_gap: Duration,
+    last_seen: Instant,
+}
+
+/// Derives the representation a water meter does not report: flow from
+/// successive volume readings, or volume by integrating flow over time.
+#[derive(Debug, Default)]
+struct WaterTracker {
+    sensors: HashMap<String, WaterState>,
+}
+
+impl WaterTracker {
+    /// Returns the (volume in m³, flow in L/min) pair for the sensor.
+    fn observe(&mut self, unique_id: &str, reading: WaterReading, now: Instant) -> (f64, f64) {
+        let Some(state) = self.sensors.get_mut(unique_id) else {
+            let (volume_m3, flow_lpm) = match reading {
+                WaterReading::Volume(volume) => (volume, 0.0),
+                WaterReading::Flow(flow) => (0.0, flow),
+            };
+            self.sensors.insert(
+                unique_id.to_string(),
+                WaterState {
+                    volume_m3,
+                    flow_lpm,
+                    last_change: now,
+                    last_gap: Duration::ZERO,
+                    last_seen: now,
+                },
+            );
+            return (volume_m3, flow_lpm);
+        };
+
+        match reading {
+            WaterReading::Volume(volume) if volume != state.volume_m3 => {
+                let gap = now.duration_since(state.last_change);
+                let minutes = gap.as_secs_f64() / 60.0;
+                state.flow_lpm = if minutes > 0.0 {
+                    ((volume - state.volume_m3) * 1_000.0 / minutes).max(0.0)
+                } else {
+                    0.0
+                };
+                state.volume_m3 = volume;
+                state.last_change = now;
+                state.last_gap = gap;
+            }
+            WaterReading::Volume(_) => {
+                // No change for longer than the gap the last flow was derived
+                // from means the tap has been closed since.
+                if now.duration_since(state.last_change) > state.last_gap {
+                    state.flow_lpm = 0.0;
+                }
+            }
+            WaterReading::Flow(flow) => {
+                let minutes = now.duration_since(state.last_seen).as_secs_f64() / 60.0;
+                state.volume_m3 += state.flow_lpm * minutes / 1_000.0;
+                state.flow_lpm = flow;
+            }
+        }
+        state.last_seen = now;
+
+        (state.volume_m3, state.flow_lpm)
+    }
+
+    fn retain(&mut self, unique_ids: &[&str]) {
+        self.sensors
+            .retain(|id, _| unique_ids.contains(&id.as_str()));
+    }
+}
+
+/// Cost gauges, registered once a contract is configured, at startup or on
+/// a reload, and unregistered when a reload removes it.
+struct CostMetrics {
+    registry: Registry,
+    month_to_date: GaugeVec,
+    projected: GaugeVec,
+    total: TotalCounterVec,
+    tracker: Mutex<CostTracker>,
+}
+
+impl CostMetrics {
+    fn register(registry: &Registry, names: &MetricNames, contract: Contract) -> Result<Self> {
+        let month_to_date = GaugeVec::new(
+            Opts::new(
+                names.p1("cost_month_to_date"),
+                "Energy cost so far this month by contract component",
+            ),
+            &["component"],
+        )?;
+        registry.register(Box::new(month_to_date.clone()))?;
+
+        let projected = GaugeVec::new(
+            Opts::new(
+                names.p1("cost_projected_month"),
+                "Projected energy cost for the whole month by contract component",
+            ),
+            &["component"],
+        )?;
+        registry.register(Box::new(projected.clone()))?;
+
+        let total = TotalCounterVec::new(
+            Opts::new(
+                names.p1("energy_cost_eur_total"),
+                "Energy cost since the exporter started by component; export is the compensation for exported energy",
+            ),
+            &["component"],
+        )?;
+        registry.register(Box::new(total.clone()))?;
+
+        Ok(Self {
+            registry: registry.clone(),
+            month_to_date,
+            projected,
+            total,
+            tracker: Mutex::new(CostTracker::new(contract)),
+        })
+    }
+
+    fn unregister(self) -> Result<()> {
+        self.registry.unregister(Box::new(self.month_to_date))?;
+        self.registry.unregister(Box::new(self.projected))?;
+        self.registry.unregister(Box::new(self.total))?;
+        Ok(())
+    }
+}
+
+/// Net metering gauges, registered only when a contract year is configured.
+struct NetMeteringMetrics {
+    import: Gauge,
+    export: Gauge,
+    net: Gauge,
+    bankable: Gauge,
+    tracker: Mutex<NetMeteringTracker>,
+}
+
+impl NetMeteringMetrics {
+    fn register(
+        registry: &Registry,
+        names: &MetricNames,
+        config: NetMeteringConfig,
+    ) -> Result<Self> {
+        let gauge = |name: String, help: &str| -> Result<Gauge> {
+            let gauge = Gauge::with_opts(Opts::new(name, help))?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+
+        Ok(Self {
+            import: gauge(
+                names.p1("net_metering_import_kwh"),
+                "Energy imported since the start of the contract year",
+            )?,
+            export: gauge(
+                names.p1("net_metering_export_kwh"),
+                "Energy exported since the start of the contract year",
+            )?,
+            net: gauge(
+                names.p1("net_metering_balance_kwh"),
+                "Import minus export since the start of the contract year",
+            )?,
+            bankable: gauge(
+                names.p1("net_metering_bankable_export_kwh"),
+                "Export that can still be netted against this contract year's import",
+            )?,
+            tracker: Mutex::new(NetMeteringTracker::new(config)),
+        })
+    }
+}
+
+/// Fuse utilization gauges, registered only when a fuse rating is configured.
+struct FuseMetrics {
+    utilization: GaugeVec,
+    near_limit: GaugeVec,
+    overload: GaugeVec,
+    limit: FuseLimit,
+}
+
+impl FuseMetrics {
+    fn register(registry: &Registry, names: &MetricNames, limit: FuseLimit) -> Result<Self> {
+        let utilization = GaugeVec::new(
+            Opts::new(
+                names.p1("fuse_utilization_percent"),
+                "Phase current as a percentage of the main fuse rating",
+            ),
+            &["phase"],
+        )?;
+        registry.register(Box::new(utilization.clone()))?;
+
+        let near_limit = GaugeVec::new(
+            Opts::new(
+                names.p1("fuse_near_limit"),
+                "Whether the phase current is near the main fuse rating (1 = yes)",
+            ),
+            &["phase"],
+        )?;
+        registry.register(Box::new(near_limit.clone()))?;
+
+        let overload = GaugeVec::new(
+            Opts::new(
+                names.p1("fuse_overload"),
+                "Whether the phase has been near the fuse rating for longer than the overload duration (1 = yes)",
+            ),
+            &["phase"],
+        )?;
+        registry.register(Box::new(overload.clone()))?;
+        for phase in ["l1", "l2", "l3"].into_iter().take(limit.rating.phases) {
+            overload.with_label_values(&[phase]).set(0.0);
+        }
+
+        Ok(Self {
+            utilization,
+            near_limit,
+            overload,
+            limit,
+        })
+    }
+
+    fn update(&self, data: &HomeWizardData) {
+        for (phase, percent) in self.limit.rating.utilization(data) {
+            self.utilization.with_label_values(&[phase]).set(percent);
+            let near = percent >= self.limit.near_limit_percent;
+            self.near_limit
+                .with_label_values(&[phase])
+                .set(if near { 1.0 } else { 0.0 });
+        }
+    }
+}
+
+/// Schema drift gauges, registered only with `--parse-mode report`.
+struct SchemaMetrics {
+    fields: GaugeVec,
+    /// Last reported drift, so changes are logged once
+    last: Mutex<(Vec<String>, Vec<&'static str>)>,
+}
+
+impl SchemaMetrics {
+    fn register(registry: &Registry, names: &MetricNames) -> Result<Self> {
+        let fields = GaugeVec::new(
+            Opts::new(
+                names.p1("schema_drift_fields"),
+                "Device JSON fields unknown to or missing from the data model",
+            ),
+            &["kind"],
+        )?;
+        registry.register(Box::new(fields.clone()))?;
+
+        Ok(Self {
+            fields,
+            last: Mutex::new(Default::default()),
+        })
+    }
+
+    fn update(&self, data: &HomeWizardData) -> Result<()> {
+        let unknown: Vec<String> = data.unknown_fields.keys().cloned().collect();
+        self.fields
+            .with_label_values(&["unknown"])
+            .set(unknown.len() as f64);
+        self.fields
+            .with_label_values(&["missing"])
+            .set(data.missing_fields.len() as f64);
+
+        let mut last = self
+            .last
+            .lock()
+            .map_err(|_| anyhow!("schema drift lock poisoned"))?;
+        if last.0 != unknown || last.1 != data.missing_fields {
+            if !unknown.is_empty() || !data.missing_fields.is_empty() {
+                warn!(
+                    "Device schema drift: unknown fields [{}], missing fields [{}]",
+                    unknown.join(", "),
+                    data.missing_fields.join(", ")
+                );
+            }
+            *last = (unknown, data.missing_fields.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Degree-day gauges, registered only when a weather location is configured.
+struct DegreeDayMetrics {
+    temperature: GaugeVec,
+    degree_days: GaugeVec,
+    gas_per_degree_day: Gauge,
+    options: DegreeDayOptions,
+    gas: Mutex<DailyGasTracker>,
+}
+
+impl DegreeDayMetrics {
+    fn register(
+        registry: &Registry,
+        names: &MetricNames,
+        options: DegreeDayOptions,
+    ) -> Result<Self> {
+        let temperature = GaugeVec::new(
+            Opts::new(
+                names.p1("outdoor_temperature_mean_celsius"),
+                "Daily mean outdoor temperature",
+            ),
+            &["day"],
+        )?;
+        registry.register(Box::new(temperature.clone()))?;
+
+        let degree_days = GaugeVec::new(
+            Opts::new(
+                names.p1("heating_degree_days"),
+                "Heating degree days relative to the base temperature",
+            ),
+            &["day"],
+        )?;
+        registry.register(Box::new(degree_days.clone()))?;
+
+        let gas_per_degree_day = Gauge::with_opts(Opts::new(
+            names.p1("gas_per_degree_day_m3"),
+            "Gas used per heating degree day on the last complete day",
+        ))?;
+        registry.register(Box::new(gas_per_degree_day.clone()))?;
+
+        Ok(Self {
+            temperature,
+            degree_days,
+            gas_per_degree_day,
+            options,
+            gas: Mutex::new(DailyGasTracker::default()),
+        })
+    }
+
+    fn update(&self, data: &HomeWizardData, today: chrono::NaiveDate) -> Result<()> {
+        let temperatures = self
+            .options
+            .temperatures
+            .lock()
+            .map_err(|_| anyhow!("temperature lock poisoned"))?
+            .clone();
+        let degree_days_on = |day| {
+            temperatures
+                .get(&day)
+                .map(|&mean| weather::heating_degree_days(self.options.base_temperature, mean))
+        };
+
+        for (label, day) in [("today", Some(today)), ("yesterday", today.pred_opt())] {
+            if let Some(&mean) = day.and_then(|day| temperatures.get(&day)) {
+                self.temperature.with_label_values(&[label]).set(mean);
+                self.degree_days
+                    .with_label_values(&[label])
+                    .set(weather::heating_degree_days(
+                        self.options.base_temperature,
+                        mean,
+                    ));
+            }
+        }
+
+        let last_day = self
+            .gas
+            .lock()
+            .map_err(|_| anyhow!("daily gas tracker lock poisoned"))?
+            .update(data, today);
+        if let Some((day, gas_m3)) = last_day
+            && let Some(degree_days) = degree_days_on(day)
+            && degree_days > 0.0
+        {
+            self.gas_per_degree_day.set(gas_m3 / degree_days);
+        }
+        Ok(())
+    }
+}
+
+/// Metric family of the HomeWizard Watermeter.
+struct WaterMeterMetrics {
+    total: TotalCounter,
+    flow: Gauge,
+    wifi_strength: Gauge,
+}
+
+impl WaterMeterMetrics {
+    fn register(registry: &Registry, names: &MetricNames) -> Result<Self> {
+        let total = TotalCounter::with_opts(Opts::new(
+            names.name("homewizard_water_total_m3"),
+            "Total water consumption in m3",
+        ))?;
+        registry.register(Box::new(total.clone()))?;
+
+        let flow = Gauge::with_opts(Opts::new(
+            names.name("homewizard_water_flow_lpm"),
+            "Current water flow in liters per minute",
+        ))?;
+        registry.register(Box::new(flow.clone()))?;
+
+        let wifi_strength = Gauge::with_opts(Opts::new(
+            names.name("homewizard_water_wifi_strength_percent"),
+            "WiFi signal strength percentage",
+        ))?;
+        registry.register(Box::new(wifi_strength.clone()))?;
+
+        Ok(Self {
+            total,
+            flow,
+            wifi_strength,
+        })
+    }
+
+    fn update(&self, data: &HomeWizardData) {
+        self.total.set(data.total_liter_m3);
+        self.flow.set(data.active_liter_lpm);
+        self.wifi_strength.set(data.wifi_strength);
+    }
+}
+
+/// Metric family of the HomeWizard Energy Socket.
+struct EnergySocketMetrics {
+    power_import_total: TotalCounter,
+    power_export_total: TotalCounter,
+    active_power: Gauge,
+    active_voltage: Gauge,
+    active_current: Gauge,
+    power_on: Gauge,
+    switch_lock: Gauge,
+    brightness: Gauge,
+    wifi_strength: Gauge,
+}
+
+impl EnergySocketMetrics {
+    fn register(registry: &Registry, names: &MetricNames) -> Result<Self> {
+        let counter = |name: &str, help: &str| -> Result<TotalCounter> {
+            let counter = TotalCounter::with_opts(Opts::new(names.name(name), help))?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+        let gauge = |name: &str, help: &str| -> Result<Gauge> {
+            let gauge = Gauge::with_opts(Opts::new(names.name(name), help))?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+
+        Ok(Self {
+            power_import_total: counter(
+                "homewizard_socket_power_import_total_kwh",
+                "Total power imported by the socket in kWh",
+            )?,
+            power_export_total: counter(
+                "homewizard_socket_power_export_total_kwh",
+                "Total power exported through the socket in kWh",
+            )?,
+            active_power: gauge(
+                "homewizard_socket_active_power_watts",
+                "Current active power in watts",
+            )?,
+            active_voltage: gauge(
+                "homewizard_socket_active_voltage_volts",
+                "Current voltage in volts",
+            )?,
+            active_current: gauge(
+                "homewizard_socket_active_current_amperes",
+                "Current current in amperes",
+            )?,
+            power_on: gauge(
+                "homewizard_socket_power_on",
+                "Whether the socket relay is switched on (1 = on)",
+            )?,
+            switch_lock: gauge(
+                "homewizard_socket_switch_lock",
+                "Whether the relay is locked in its current state (1 = locked)",
+            )?,
+            brightness: gauge(
+                "homewizard_socket_brightness",
+                "Status light brightness (0-255)",
+            )?,
+            wifi_strength: gauge(
+                "homewizard_socket_wifi_strength_percent",
+                "WiFi signal strength percentage",
+            )?,
+        })
+    }
+
+    fn update(&self, data: &HomeWizardData) {
+        self.power_import_total.set(data.total_power_import_kwh);
+        self.power_export_total.set(data.total_power_export_kwh);
+        self.active_power.set(data.active_power_w);
+        self.active_voltage.set(data.active_voltage_l1_v);
+        self.active_current.set(data.active_current_a);
+        self.wifi_strength.set(data.wifi_strength);
+        if let Some(state) = data.socket_state {
+            self.power_on.set(if state.power_on { 1.0 } else { 0.0 });
+            self.switch_lock
+                .set(if state.switch_lock { 1.0 } else { 0.0 });
+            self.brightness.set(state.brightness);
+        }
+    }
+}
+
+/// Metric family of the HomeWizard kWh Meter. Per-phase series are only
+/// exported for phases the meter reports, so a 1-phase meter has `l1` only.
+struct KwhMeterMetrics {
+    power_import_total: TotalCounter,
+    power_export_total: TotalCounter,
+    active_power: Gauge,
+    active_current: Gauge,
+    active_frequency: Gauge,
+    phase_power: GaugeVec,
+    phase_voltage: GaugeVec,
+    phase_current: GaugeVec,
+    wifi_strength: Gauge,
+}
+
+impl KwhMeterMetrics {
+    fn register(registry: &Registry, names: &MetricNames) -> Result<Self> {
+        let counter = |name: &str, help: &str| -> Result<TotalCounter> {
+            let counter = TotalCounter::with_opts(Opts::new(names.name(name), help))?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+        let gauge = |name: &str, help: &str| -> Result<Gauge> {
+            let gauge = Gauge::with_opts(Opts::new(names.name(name), help))?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        let phase_gauge = |name: &str, help: &str| -> Result<GaugeVec> {
+            let gauge = GaugeVec::new(Opts::new(names.name(name), help), &["phase"])?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+
+        Ok(Self {
+            power_import_total: counter(
+                "homewizard_kwh_power_import_total_kwh",
+                "Total power import in kWh",
+            )?,
+            power_export_total: counter(
+                "homewizard_kwh_power_export_total_kwh",
+                "Total power export in kWh",
+            )?,
+            active_power: gauge(
+                "homewizard_kwh_active_power_watts",
+                "Current active power in watts",
+            )?,
+            active_current: gauge(
+                "homewizard_kwh_active_current_amperes",
+                "Current total current in amperes",
+            )?,
+            active_frequency: gauge(
+                "homewizard_kwh_frequency_hertz",
+                "Current grid frequency in hertz",
+            )?,
+            phase_power: phase_gauge(
+                "homewizard_kwh_phase_active_power_watts",
+                "Current active power per phase in watts",
+            )?,
+            phase_voltage: phase_gauge(
+                "homewizard_kwh_phase_voltage_volts",
+                "Current voltage per phase in volts",
+            )?,
+            phase_current: phase_gauge(
+                "homewizard_kwh_phase_current_amperes",
+                "Current current per phase in amperes",
+            )?,
+            wifi_strength: gauge(
+                "homewizard_kwh_wifi_strength_percent",
+                "WiFi signal strength percentage",
+            )?,
+        })
+    }
+
+    fn update(&self, data: &HomeWizardData) {
+        self.power_import_total.set(data.total_power_import_kwh);
+        self.power_export_total.set(data.total_power_export_kwh);
+        self.active_power.set(data.active_power_w);
+        self.active_current.set(data.active_current_a);
+        self.active_frequency.set(data.active_frequency_hz);
+        self.wifi_strength.set(data.wifi_strength);
+
+        let three_phase = data.active_voltage_l2_v != 0.0 || data.active_voltage_l3_v != 0.0;
+        let phases = [
+            (
+                "l1",
+                data.active_power_l1_w,
+                data.active_voltage_l1_v,
+                data.active_current_l1_a,
+            ),
+            (
+                "l2",
+                data.active_power_l2_w,
+                data.active_voltage_l2_v,
+                data.active_current_l2_a,
+            ),
+            (
+                "l3",
+                data.active_power_l3_w,
+                data.active_voltage_l3_v,
+                data.active_current_l3_a,
+            ),
+        ];
+        for (phase, power, voltage, current) in
+            phases.into_iter().take(if three_phase { 3 } else { 1 })
+        {
+            self.phase_power.with_label_values(&[phase]).set(power);
+            self.phase_voltage.with_label_values(&[phase]).set(voltage);
+            self.phase_current.with_label_values(&[phase]).set(current);
+        }
+    }
+}
+
+/// Metric family of the HomeWizard Plug-In Battery.
+struct PluginBatteryMetrics {
+    state_of_charge: Gauge,
+    power: Gauge,
+    voltage: Gauge,
+    current: Gauge,
+    energy_import_total: TotalCounter,
+    energy_export_total: TotalCounter,
+    cycles_total: TotalCounter,
+}
+
+impl PluginBatteryMetrics {
+    fn register(registry: &Registry, names: &MetricNames) -> Result<Self> {
+        let counter = |name: &str, help: &str| -> Result<TotalCounter> {
+            let counter = TotalCounter::with_opts(Opts::new(names.name(name), help))?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+        let gauge = |name: &str, help: &str| -> Result<Gauge> {
+            let gauge = Gauge::with_opts(Opts::new(names.name(name), help))?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+
+        Ok(Self {
+            state_of_charge: gauge(
+                "homewizard_battery_state_of_charge_percent",
+                "Battery state of charge percentage",
+            )?,
+            power: gauge(
+                "homewizard_battery_power_watts",
+                "Battery power in watts (positive = charging, negative = discharging)",
+            )?,
+            voltage: gauge(
+                "homewizard_battery_voltage_volts",
+                "Grid voltage at the battery in volts",
+            )?,
+            current: gauge(
+                "homewizard_battery_current_amperes",
+                "Battery current in amperes",
+            )?,
+            energy_import_total: counter(
+                "homewizard_battery_energy_import_total_kwh",
+                "Total energy charged into the battery in kWh",
+            )?,
+            energy_export_total: counter(
+                "homewizard_battery_energy_export_total_kwh",
+                "Total energy discharged from the battery in kWh",
+            )?,
+            cycles_total: counter(
+                "homewizard_battery_cycles_total",
+                "Total full charge cycles",
+            )?,
+        })
+    }
+
+    fn update(&self, data: &HomeWizardData) {
+        self.state_of_charge.set(data.state_of_charge_pct);
+        self.power.set(data.active_power_w);
+        self.voltage.set(data.active_voltage_l1_v);
+        self.current.set(data.active_current_a);
+        self.energy_import_total.set(data.total_power_import_kwh);
+        self.energy_export_total.set(data.total_power_export_kwh);
+        self.cycles_total.set(data.battery_cycles);
+    }
+}
+
+/// Health of the exporter's own polling, so a dead meter shows up as
+/// `homewizard_exporter_up 0` instead of silently stale values.
+struct ExporterMetrics {
+    poll_success_total: Counter,
+    poll_errors_total: CounterVec,
+    last_poll_success: Gauge,
+    up: Gauge,
+    /// `up` under the device's own prefix, next to `data_age_seconds`;
+    /// `None` when `up` is renamed to that name
+    p1_up: Option<Gauge>,
+    fetch_duration: Histogram,
+    fetch_retries_total: Counter,
+    circuit_open: Gauge,
+}
+
+impl ExporterMetrics {
+    fn register(registry: &Registry, names: &MetricNames, created: SystemTime) -> Result<Self> {
+        let poll_success_total = Counter::with_opts(Opts::new(
+            names.name("homewizard_exporter_poll_success_total"),
+            "Successful polls of the device",
+        ))?;
+        registry.register(Box::new(poll_success_total.clone()))?;
+
+        let poll_errors_total = CounterVec::new(
+            Opts::new(
+                names.name("homewizard_exporter_poll_errors_total"),
+                "Failed polls of the device by error class",
+            ),
+            &["class"],
+        )?;
+        registry.register(Box::new(poll_errors_total.clone()))?;
+
+        let last_poll_success = Gauge::with_opts(Opts::new(
+            names.name("homewizard_exporter_last_poll_success_timestamp_seconds"),
+            "Unix time of the last successful poll",
+        ))?;
+        registry.register(Box::new(last_poll_success.clone()))?;
+
+        let up = Gauge::with_opts(Opts::new(
+            names.name("homewizard_exporter_up"),
+            "Whether the last poll of the device succeeded (1 = up)",
+        ))?;
+        registry.register(Box::new(up.clone()))?;
+
+        let p1_up = (names.p1("up") != names.name("homewizard_exporter_up"))
+            .then(|| -> Result<Gauge> {
+                let p1_up = Gauge::with_opts(Opts::new(
+                    names.p1("up"),
+                    "Whether the last poll succeeded (1 = up); otherwise the values are from the last successful poll",
+                ))?;
+                registry.register(Box::new(p1_up.clone()))?;
+                Ok(p1_up)
+            })
+            .transpose()?;
+
+        // The device answers in tens of milliseconds on a healthy Wi-Fi link;
+        // the upper buckets catch degradation before requests time out
+        let fetch_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                names.name("homewizard_exporter_fetch_duration_seconds"),
+                "Duration of fetching a reading from the device",
+            )
+            .buckets(vec![0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+        )?;
+        registry.register(Box::new(fetch_duration.clone()))?;
+
+        let fetch_retries_total = Counter::with_opts(Opts::new(
+            names.name("homewizard_exporter_fetch_retries_total"),
+            "Fetches retried after a transient failure within a poll",
+        ))?;
+        registry.register(Box::new(fetch_retries_total.clone()))?;
+
+        let circuit_open = Gauge::with_opts(Opts::new(
+            names.name("homewizard_exporter_circuit_open"),
+            "1 while the device is only probed after repeated failed polls",
+        ))?;
+        registry.register(Box::new(circuit_open.clone()))?;
+
+        // Values survive failed polls, so this tells fresh data from stale
+        let data_age = DataAge {
+            age: Gauge::with_opts(Opts::new(
+                names.p1("data_age_seconds"),
+                "Seconds since the last successful poll, or since startup before the first one",
+            ))?,
+            last_poll_success: last_poll_success.clone(),
+            created,
+        };
+        registry.register(Box::new(data_age))?;
+
+        Ok(Self {
+            poll_success_total,
+            poll_errors_total,
+            last_poll_success,
+            up,
+            p1_up,
+            fetch_duration,
+            fetch_retries_total,
+            circuit_open,
+        })
+    }
+}
+
+/// `data_age_seconds` as of the moment it is gathered, so scrapes between
+/// polls see the data getting older.
+struct DataAge {
+    age: Gauge,
+    last_poll_success: Gauge,
+    created: SystemTime,
+}
+
+impl Collector for DataAge {
+    fn desc(&self) -> Vec<&Desc> {
+        self.age.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let last_success = self.last_poll_success.get();
+        let since = if last_success > 0.0 {
+            UNIX_EPOCH + Duration::from_secs_f64(last_success)
+        } else {
+            self.created
+        };
+        let age = SystemTime::now().duration_since(since).unwrap_or_default();
+        self.age.set(age.as_secs_f64());
+        self.age.collect()
+    }
+}
 
 pub struct Metrics {
     // Power import metrics
-    power_import_total: Counter,
-    power_import_tariff: CounterVec,
+    power_import_total: TotalCounter,
+    power_import_tariff: TotalCounterVec,
 
     // Power export metrics
-    power_export_total: Counter,
-    power_export_tariff: CounterVec,
+    power_export_total: TotalCounter,
+    power_export_tariff: TotalCounterVec,
 
     // Current power metrics
     active_power: Gauge,
@@ -23,244 +1011,582 @@ pub struct Metrics {
     active_current_l1: Gauge,
     active_current_l2: Gauge,
     active_current_l3: Gauge,
+    frequency: Gauge,
     active_tariff: Gauge,
+    monthly_power_peak: Gauge,
+    monthly_power_peak_timestamp: Gauge,
 
     // Gas metrics
-    gas_total: Counter,
-    gas_timestamp: Gauge,
+    gas_total: TotalCounter,
+    gas_timestamp_seconds: Gauge,
+    gas_timestamp: Option<Gauge>,
     gas_meter_info: GaugeVec,
+    gas_meter_total: TotalCounterVec,
+    gas_meter_timestamp: GaugeVec,
+    gas_meter_reading_age: GaugeVec,
+    gas_meter_stale: GaugeVec,
+
+    // Water
+    water_total: TotalCounterVec,
+    water_flow: GaugeVec,
+
+    // District heating
+    heat_energy_total: TotalCounterVec,
+    warm_water_total: TotalCounterVec,
+
+    // SMR capabilities
+    smr_electricity_interval: Gauge,
+    smr_gas_interval: Gauge,
 
     // Network metrics
     wifi_strength: Gauge,
 
     // Power quality metrics
-    voltage_sag_l1_count: Counter,
-    voltage_sag_l2_count: Counter,
-    voltage_sag_l3_count: Counter,
-    voltage_swell_l1_count: Counter,
-    voltage_swell_l2_count: Counter,
-    voltage_swell_l3_count: Counter,
-    power_failures_any: Counter,
-    power_failures_long: Counter,
+    voltage_sag_l1_count: TotalCounter,
+    voltage_sag_l2_count: TotalCounter,
+    voltage_sag_l3_count: TotalCounter,
+    voltage_swell_l1_count: TotalCounter,
+    voltage_swell_l2_count: TotalCounter,
+    voltage_swell_l3_count: TotalCounter,
+    power_failures_any: TotalCounter,
+    power_failures_long: TotalCounter,
+    power_failure_duration: TotalCounter,
+    last_power_failure_duration: Gauge,
+    clock_drift: Gauge,
 
     // Info metric
     meter_info: GaugeVec,
+    active_source: GaugeVec,
+    device_info: GaugeVec,
+    /// Unlabelled; absent until the device reports them
+    cloud_enabled: GaugeVec,
+    uptime: GaugeVec,
+    restarts: Counter,
+    wifi_rssi: GaugeVec,
+    exporter: ExporterMetrics,
+    unchanged_polls: Counter,
 
     // External sensors
     external_sensor_value: GaugeVec,
     external_sensor_timestamp: GaugeVec,
 
+    cost: RwLock<Option<CostMetrics>>,
+    net_metering: Option<NetMeteringMetrics>,
+    degree_days: Option<DegreeDayMetrics>,
+    fuse: Option<FuseMetrics>,
+    schema: Option<SchemaMetrics>,
+    leader: Option<Gauge>,
+    watermeter: Option<WaterMeterMetrics>,
+    energy_socket: Option<EnergySocketMetrics>,
+    kwh_meter: Option<KwhMeterMetrics>,
+    plugin_battery: Option<PluginBatteryMetrics>,
+
     registry: Registry,
+    options: MetricsOptions,
+    gas_age: Mutex<GasAgeTracker>,
+    water: Mutex<WaterTracker>,
+    power_failure_log: Mutex<PowerFailureTracker>,
+    /// Gauges of the raw fields seen so far; `None` for a field whose
+    /// metric could not be registered
+    raw_fields: Mutex<HashMap<String, Option<Gauge>>>,
+    names: MetricNames,
+    /// When the exporter's own counters started counting
+    created: SystemTime,
 }
 
+/// Families the exporter counts itself since startup, next to the P1
+/// `unchanged_polls_total`. Meter totals started counting at an unknown
+/// time, so only these get an OpenMetrics `_created`.
+const EXPORTER_COUNTERS: &[&str] = &[
+    "homewizard_exporter_poll_success_total",
+    "homewizard_exporter_poll_errors_total",
+    "homewizard_exporter_fetch_duration_seconds",
+    "homewizard_exporter_fetch_retries_total",
+    "homewizard_device_restarts_total",
+];
+
 impl Metrics {
+    #[cfg(test)]
     pub fn new() -> Result<Self> {
-        let registry = Registry::new();
+        Self::with_options(MetricsOptions::default())
+    }
+
+    pub fn with_options(options: MetricsOptions) -> Result<Self> {
+        let labels: HashMap<String, String> = options
+            .device
+            .iter()
+            .map(|device| ("device".to_string(), device.clone()))
+            .chain(options.labels.iter().cloned())
+            .collect();
+        let registry = Registry::new_custom(None, Some(labels).filter(|l| !l.is_empty()))?;
+        let created = SystemTime::now();
+
+        let names = options.names.clone();
+
+        // P1 families are only exported for P1 meters; other products
+        // register their own.
+        let p1 = if options.product == ProductType::P1 {
+            registry.clone()
+        } else {
+            Registry::new()
+        };
 
         // Power import metrics
-        let power_import_total = Counter::with_opts(Opts::new(
-            "homewizard_p1_power_import_total_kwh",
+        let power_import_total = TotalCounter::with_opts(Opts::new(
+            names.p1("power_import_total_kwh"),
             "Total power imported in kWh",
         ))?;
-        registry.register(Box::new(power_import_total.clone()))?;
+        p1.register(Box::new(power_import_total.clone()))?;
 
-        let power_import_tariff = CounterVec::new(
+        let power_import_tariff = TotalCounterVec::new(
             Opts::new(
-                "homewizard_p1_power_import_tariff_kwh",
+                names.p1("power_import_tariff_kwh"),
                 "Power imported per tariff in kWh",
             ),
             &["tariff"],
         )?;
-        registry.register(Box::new(power_import_tariff.clone()))?;
+        p1.register(Box::new(power_import_tariff.clone()))?;
 
         // Power export metrics
-        let power_export_total = Counter::with_opts(Opts::new(
-            "homewizard_p1_power_export_total_kwh",
+        let power_export_total = TotalCounter::with_opts(Opts::new(
+            names.p1("power_export_total_kwh"),
             "Total power exported in kWh",
         ))?;
-        registry.register(Box::new(power_export_total.clone()))?;
+        p1.register(Box::new(power_export_total.clone()))?;
 
-        let power_export_tariff = CounterVec::new(
+        let power_export_tariff = TotalCounterVec::new(
             Opts::new(
-                "homewizard_p1_power_export_tariff_kwh",
+                names.p1("power_export_tariff_kwh"),
                 "Power exported per tariff in kWh",
             ),
             &["tariff"],
         )?;
-        registry.register(Box::new(power_export_tariff.clone()))?;
+        p1.register(Box::new(power_export_tariff.clone()))?;
 
         // Current power metrics
         let active_power = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_power_watts",
+            names.p1("active_power_watts"),
             "Current active power in watts",
         ))?;
-        registry.register(Box::new(active_power.clone()))?;
+        p1.register(Box::new(active_power.clone()))?;
 
         let active_power_l1 = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_power_l1_watts",
+            names.p1("active_power_l1_watts"),
             "Current active power L1 in watts",
         ))?;
-        registry.register(Box::new(active_power_l1.clone()))?;
+        p1.register(Box::new(active_power_l1.clone()))?;
 
         let active_power_l2 = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_power_l2_watts",
+            names.p1("active_power_l2_watts"),
             "Current active power L2 in watts",
         ))?;
-        registry.register(Box::new(active_power_l2.clone()))?;
+        p1.register(Box::new(active_power_l2.clone()))?;
 
         let active_power_l3 = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_power_l3_watts",
+            names.p1("active_power_l3_watts"),
             "Current active power L3 in watts",
         ))?;
-        registry.register(Box::new(active_power_l3.clone()))?;
+        p1.register(Box::new(active_power_l3.clone()))?;
 
         let active_voltage_l1 = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_voltage_l1_volts",
+            names.p1("active_voltage_l1_volts"),
             "Current active voltage L1 in volts",
         ))?;
-        registry.register(Box::new(active_voltage_l1.clone()))?;
+        p1.register(Box::new(active_voltage_l1.clone()))?;
 
         let active_voltage_l2 = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_voltage_l2_volts",
+            names.p1("active_voltage_l2_volts"),
             "Current active voltage L2 in volts",
         ))?;
-        registry.register(Box::new(active_voltage_l2.clone()))?;
+        p1.register(Box::new(active_voltage_l2.clone()))?;
 
         let active_voltage_l3 = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_voltage_l3_volts",
+            names.p1("active_voltage_l3_volts"),
             "Current active voltage L3 in volts",
         ))?;
-        registry.register(Box::new(active_voltage_l3.clone()))?;
+        p1.register(Box::new(active_voltage_l3.clone()))?;
 
         let active_current = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_current_amperes",
+            names.p1("active_current_amperes"),
             "Current active current in amperes",
         ))?;
-        registry.register(Box::new(active_current.clone()))?;
+        p1.register(Box::new(active_current.clone()))?;
 
         let active_current_l1 = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_current_l1_amperes",
+            names.p1("active_current_l1_amperes"),
             "Current active current L1 in amperes",
         ))?;
-        registry.register(Box::new(active_current_l1.clone()))?;
+        p1.register(Box::new(active_current_l1.clone()))?;
 
         let active_current_l2 = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_current_l2_amperes",
+            names.p1("active_current_l2_amperes"),
             "Current active current L2 in amperes",
         ))?;
-        registry.register(Box::new(active_current_l2.clone()))?;
+        p1.register(Box::new(active_current_l2.clone()))?;
 
         let active_current_l3 = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_current_l3_amperes",
+            names.p1("active_current_l3_amperes"),
             "Current active current L3 in amperes",
         ))?;
-        registry.register(Box::new(active_current_l3.clone()))?;
+        p1.register(Box::new(active_current_l3.clone()))?;
+
+        let frequency = Gauge::with_opts(Opts::new(
+            names.p1("frequency_hertz"),
+            "Grid frequency in hertz",
+        ))?;
+        p1.register(Box::new(frequency.clone()))?;
 
         let active_tariff = Gauge::with_opts(Opts::new(
-            "homewizard_p1_active_tariff",
-            "Currently active tariff (1 or 2)",
+            names.p1("active_tariff"),
+            "Currently active tariff (1-4)",
+        ))?;
+        p1.register(Box::new(active_tariff.clone()))?;
+
+        // Capacity tariff (Belgium)
+        let monthly_power_peak = Gauge::with_opts(Opts::new(
+            names.p1("monthly_power_peak_watts"),
+            "Highest quarter-hour average import power this month in watts",
+        ))?;
+        p1.register(Box::new(monthly_power_peak.clone()))?;
+
+        let monthly_power_peak_timestamp = Gauge::with_opts(Opts::new(
+            names.p1("monthly_power_peak_timestamp"),
+            "Timestamp of this month's power peak",
         ))?;
-        registry.register(Box::new(active_tariff.clone()))?;
+        p1.register(Box::new(monthly_power_peak_timestamp.clone()))?;
 
         // Gas metrics
-        let gas_total = Counter::with_opts(Opts::new(
-            "homewizard_p1_gas_total_m3",
+        let gas_total = TotalCounter::with_opts(Opts::new(
+            names.p1("gas_total_m3"),
             "Total gas consumption in m3",
         ))?;
-        registry.register(Box::new(gas_total.clone()))?;
+        p1.register(Box::new(gas_total.clone()))?;
 
-        let gas_timestamp = Gauge::with_opts(Opts::new(
-            "homewizard_p1_gas_timestamp",
-            "Timestamp of last gas meter reading",
+        let gas_timestamp_seconds = Gauge::with_opts(Opts::new(
+            names.p1("gas_timestamp_seconds"),
+            "Unix time of the last gas meter reading",
         ))?;
-        registry.register(Box::new(gas_timestamp.clone()))?;
+        p1.register(Box::new(gas_timestamp_seconds.clone()))?;
+
+        let gas_timestamp = options
+            .gas_timestamp_raw
+            .then(|| -> Result<Gauge> {
+                let gauge = Gauge::with_opts(Opts::new(
+                    names.p1("gas_timestamp"),
+                    "Timestamp of last gas meter reading as a DSMR YYMMDDhhmmss number",
+                ))?;
+                p1.register(Box::new(gauge.clone()))?;
+                Ok(gauge)
+            })
+            .transpose()?;
 
         let gas_meter_info = GaugeVec::new(
-            Opts::new("homewizard_p1_gas_meter_info", "Gas meter information"),
+            Opts::new(names.p1("gas_meter_info"), "Gas meter information"),
             &["unique_id"],
         )?;
-        registry.register(Box::new(gas_meter_info.clone()))?;
+        p1.register(Box::new(gas_meter_info.clone()))?;
 
-        // Network metrics
+        let gas_meter_total = TotalCounterVec::new(
+            Opts::new(
+                names.p1("gas_meter_total_m3"),
+                "Total gas consumption per gas meter in m3",
+            ),
+            &["unique_id"],
+        )?;
+        p1.register(Box::new(gas_meter_total.clone()))?;
+
+        let gas_meter_timestamp = GaugeVec::new(
+            Opts::new(
+                names.p1("gas_meter_timestamp"),
+                "Timestamp of last reading per gas meter",
+            ),
+            &["unique_id"],
+        )?;
+        p1.register(Box::new(gas_meter_timestamp.clone()))?;
+
+        let gas_meter_reading_age = GaugeVec::new(
+            Opts::new(
+                names.p1("gas_meter_reading_age_seconds"),
+                "Seconds since the gas meter reading last changed",
+            ),
+            &["unique_id"],
+        )?;
+        p1.register(Box::new(gas_meter_reading_age.clone()))?;
+
+        let gas_meter_stale = GaugeVec::new(
+            Opts::new(
+                names.p1("gas_meter_stale"),
+                "Whether the gas meter reading is older than the staleness threshold (1) or not (0)",
+            ),
+            &["unique_id"],
+        )?;
+        p1.register(Box::new(gas_meter_stale.clone()))?;
+
+        // Water
+        let water_total = TotalCounterVec::new(
+            Opts::new(
+                names.p1("water_total_m3"),
+                "Total water consumption per water meter in m3",
+            ),
+            &["unique_id"],
+        )?;
+        p1.register(Box::new(water_total.clone()))?;
+
+        let water_flow = GaugeVec::new(
+            Opts::new(
+                names.p1("water_flow_lpm"),
+                "Current water flow per water meter in liters per minute",
+            ),
+            &["unique_id"],
+        )?;
+        p1.register(Box::new(water_flow.clone()))?;
+
+        // District heating
+        let heat_energy_total = TotalCounterVec::new(
+            Opts::new(
+                names.p1("heat_energy_total_gj"),
+                "Total heat consumption per heat meter in GJ",
+            ),
+            &["unique_id"],
+        )?;
+        p1.register(Box::new(heat_energy_total.clone()))?;
+
+        let warm_water_total = TotalCounterVec::new(
+            Opts::new(
+                names.p1("warm_water_total_m3"),
+                "Total warm water consumption per warm water meter in m3",
+            ),
+            &["unique_id"],
+        )?;
+        p1.register(Box::new(warm_water_total.clone()))?;
+
+        // SMR capabilities
+        let smr_electricity_interval = Gauge::with_opts(Opts::new(
+            names.p1("smr_electricity_update_interval_seconds"),
+            "Electricity update interval of the meter based on its SMR version",
+        ))?;
+        p1.register(Box::new(smr_electricity_interval.clone()))?;
+
+        let smr_gas_interval = Gauge::with_opts(Opts::new(
+            names.p1("smr_gas_update_interval_seconds"),
+            "Gas update interval of the meter based on its SMR version",
+        ))?;
+        p1.register(Box::new(smr_gas_interval.clone()))?;
+
+        // Network metrics
         let wifi_strength = Gauge::with_opts(Opts::new(
-            "homewizard_p1_wifi_strength_percent",
+            names.p1("wifi_strength_percent"),
             "WiFi signal strength percentage",
         ))?;
-        registry.register(Box::new(wifi_strength.clone()))?;
+        p1.register(Box::new(wifi_strength.clone()))?;
 
-        let voltage_sag_l1_count = Counter::with_opts(Opts::new(
-            "homewizard_p1_voltage_sag_l1_count_total",
+        let voltage_sag_l1_count = TotalCounter::with_opts(Opts::new(
+            names.p1("voltage_sag_l1_count_total"),
             "Total voltage sag L1 events",
         ))?;
-        registry.register(Box::new(voltage_sag_l1_count.clone()))?;
+        p1.register(Box::new(voltage_sag_l1_count.clone()))?;
 
-        let voltage_sag_l2_count = Counter::with_opts(Opts::new(
-            "homewizard_p1_voltage_sag_l2_count_total",
+        let voltage_sag_l2_count = TotalCounter::with_opts(Opts::new(
+            names.p1("voltage_sag_l2_count_total"),
             "Total voltage sag L2 events",
         ))?;
-        registry.register(Box::new(voltage_sag_l2_count.clone()))?;
+        p1.register(Box::new(voltage_sag_l2_count.clone()))?;
 
-        let voltage_sag_l3_count = Counter::with_opts(Opts::new(
-            "homewizard_p1_voltage_sag_l3_count_total",
+        let voltage_sag_l3_count = TotalCounter::with_opts(Opts::new(
+            names.p1("voltage_sag_l3_count_total"),
             "Total voltage sag L3 events",
         ))?;
-        registry.register(Box::new(voltage_sag_l3_count.clone()))?;
+        p1.register(Box::new(voltage_sag_l3_count.clone()))?;
 
-        let voltage_swell_l1_count = Counter::with_opts(Opts::new(
-            "homewizard_p1_voltage_swell_l1_count_total",
+        let voltage_swell_l1_count = TotalCounter::with_opts(Opts::new(
+            names.p1("voltage_swell_l1_count_total"),
             "Total voltage swell L1 events",
         ))?;
-        registry.register(Box::new(voltage_swell_l1_count.clone()))?;
+        p1.register(Box::new(voltage_swell_l1_count.clone()))?;
 
-        let voltage_swell_l2_count = Counter::with_opts(Opts::new(
-            "homewizard_p1_voltage_swell_l2_count_total",
+        let voltage_swell_l2_count = TotalCounter::with_opts(Opts::new(
+            names.p1("voltage_swell_l2_count_total"),
             "Total voltage swell L2 events",
         ))?;
-        registry.register(Box::new(voltage_swell_l2_count.clone()))?;
+        p1.register(Box::new(voltage_swell_l2_count.clone()))?;
 
-        let voltage_swell_l3_count = Counter::with_opts(Opts::new(
-            "homewizard_p1_voltage_swell_l3_count_total",
+        let voltage_swell_l3_count = TotalCounter::with_opts(Opts::new(
+            names.p1("voltage_swell_l3_count_total"),
             "Total voltage swell L3 events",
         ))?;
-        registry.register(Box::new(voltage_swell_l3_count.clone()))?;
+        p1.register(Box::new(voltage_swell_l3_count.clone()))?;
 
-        let power_failures_any = Counter::with_opts(Opts::new(
-            "homewizard_p1_power_failures_any_total",
+        let power_failures_any = TotalCounter::with_opts(Opts::new(
+            names.p1("power_failures_any_total"),
             "Total power failures (any duration)",
         ))?;
-        registry.register(Box::new(power_failures_any.clone()))?;
+        p1.register(Box::new(power_failures_any.clone()))?;
 
-        let power_failures_long = Counter::with_opts(Opts::new(
-            "homewizard_p1_power_failures_long_total",
+        let power_failures_long = TotalCounter::with_opts(Opts::new(
+            names.p1("power_failures_long_total"),
             "Total long power failures",
         ))?;
-        registry.register(Box::new(power_failures_long.clone()))?;
+        p1.register(Box::new(power_failures_long.clone()))?;
+
+        let power_failure_duration = TotalCounter::with_opts(Opts::new(
+            names.p1("power_failure_duration_seconds_total"),
+            "Accumulated duration of logged long power failures in seconds",
+        ))?;
+        p1.register(Box::new(power_failure_duration.clone()))?;
+
+        let last_power_failure_duration = Gauge::with_opts(Opts::new(
+            names.p1("last_power_failure_duration_seconds"),
+            "Duration of the most recent logged long power failure in seconds",
+        ))?;
+        p1.register(Box::new(last_power_failure_duration.clone()))?;
+
+        let clock_drift = Gauge::with_opts(Opts::new(
+            names.p1("clock_drift_seconds"),
+            "Meter clock minus exporter clock in seconds",
+        ))?;
+        p1.register(Box::new(clock_drift.clone()))?;
 
         // Info metric
         let meter_info = GaugeVec::new(
-            Opts::new("homewizard_p1_meter_info", "Meter information"),
+            Opts::new(names.p1("meter_info"), "Meter information"),
             &["meter_id", "meter_model", "smr_version", "wifi_ssid"],
         )?;
-        registry.register(Box::new(meter_info.clone()))?;
+        p1.register(Box::new(meter_info.clone()))?;
 
-        // External sensors
-        let external_sensor_value = GaugeVec::new(
+        let active_source = GaugeVec::new(
+            Opts::new(
+                names.p1("active_source_info"),
+                "Endpoint the latest reading was taken from",
+            ),
+            &["source"],
+        )?;
+        registry.register(Box::new(active_source.clone()))?;
+
+        let device_info = GaugeVec::new(
+            Opts::new(
+                names.name("homewizard_device_info"),
+                "Product and firmware reported by the device's /api endpoint",
+            ),
+            &[
+                "product_type",
+                "product_name",
+                "serial",
+                "firmware_version",
+                "api_version",
+            ],
+        )?;
+        registry.register(Box::new(device_info.clone()))?;
+
+        let cloud_enabled = GaugeVec::new(
+            Opts::new(
+                names.name("homewizard_device_cloud_enabled"),
+                "Whether the device communicates with the HomeWizard cloud (1) or not (0)",
+            ),
+            &[],
+        )?;
+        registry.register(Box::new(cloud_enabled.clone()))?;
+
+        let uptime = GaugeVec::new(
+            Opts::new(
+                names.name("homewizard_device_uptime_seconds"),
+                "Seconds since the device booted, as reported over API v2",
+            ),
+            &[],
+        )?;
+        registry.register(Box::new(uptime.clone()))?;
+
+        let restarts = Counter::with_opts(Opts::new(
+            names.name("homewizard_device_restarts_total"),
+            "Device reboots seen as a drop in its uptime",
+        ))?;
+        registry.register(Box::new(restarts.clone()))?;
+
+        let wifi_rssi = GaugeVec::new(
             Opts::new(
-                "homewizard_p1_external_sensor_value",
-                "External sensor value",
+                match options.product {
+                    ProductType::P1 => names.p1("wifi_rssi_dbm"),
+                    ProductType::Watermeter => names.name("homewizard_water_wifi_rssi_dbm"),
+                    ProductType::EnergySocket => names.name("homewizard_socket_wifi_rssi_dbm"),
+                    ProductType::KwhMeter => names.name("homewizard_kwh_wifi_rssi_dbm"),
+                    ProductType::PluginBattery => names.name("homewizard_battery_wifi_rssi_dbm"),
+                },
+                "WiFi signal strength in dBm, as reported over API v2",
             ),
-            &["unique_id", "type", "unit"],
+            &[],
+        )?;
+        registry.register(Box::new(wifi_rssi.clone()))?;
+
+        let unchanged_polls = Counter::with_opts(Opts::new(
+            names.p1("unchanged_polls_total"),
+            "Polls skipped because the reading was identical to the previous one",
+        ))?;
+        registry.register(Box::new(unchanged_polls.clone()))?;
+
+        // External sensors
+        let external_sensor_value = GaugeVec::new(
+            Opts::new(names.p1("external_sensor_value"), "External sensor value"),
+            &["unique_id", "name", "type", "unit"],
         )?;
-        registry.register(Box::new(external_sensor_value.clone()))?;
+        p1.register(Box::new(external_sensor_value.clone()))?;
 
         let external_sensor_timestamp = GaugeVec::new(
             Opts::new(
-                "homewizard_p1_external_sensor_timestamp",
+                names.p1("external_sensor_timestamp"),
                 "External sensor timestamp",
             ),
-            &["unique_id", "type"],
+            &["unique_id", "name", "type"],
         )?;
-        registry.register(Box::new(external_sensor_timestamp.clone()))?;
+        p1.register(Box::new(external_sensor_timestamp.clone()))?;
+
+        let cost = RwLock::new(
+            options
+                .contract
+                .map(|contract| CostMetrics::register(&p1, &names, contract))
+                .transpose()?,
+        );
+        let net_metering = options
+            .net_metering
+            .map(|config| NetMeteringMetrics::register(&p1, &names, config))
+            .transpose()?;
+        let degree_days = options
+            .degree_days
+            .clone()
+            .map(|options| DegreeDayMetrics::register(&p1, &names, options))
+            .transpose()?;
+        let fuse = options
+            .fuse
+            .map(|limit| FuseMetrics::register(&p1, &names, limit))
+            .transpose()?;
+        let watermeter = (options.product == ProductType::Watermeter)
+            .then(|| WaterMeterMetrics::register(&registry, &names))
+            .transpose()?;
+        let energy_socket = (options.product == ProductType::EnergySocket)
+            .then(|| EnergySocketMetrics::register(&registry, &names))
+            .transpose()?;
+        let kwh_meter = (options.product == ProductType::KwhMeter)
+            .then(|| KwhMeterMetrics::register(&registry, &names))
+            .transpose()?;
+        let plugin_battery = (options.product == ProductType::PluginBattery)
+            .then(|| PluginBatteryMetrics::register(&registry, &names))
+            .transpose()?;
+        let schema = options
+            .schema_report
+            .then(|| SchemaMetrics::register(&registry, &names))
+            .transpose()?;
+        // Instances start as standby until they hold the lease.
+        let leader = options
+            .failover
+            .then(|| -> Result<Gauge> {
+                let leader = Gauge::with_opts(Opts::new(
+                    names.p1("leader"),
+                    "Whether this instance holds the failover lease and polls the device (1 = yes)",
+                ))?;
+                registry.register(Box::new(leader.clone()))?;
+                Ok(leader)
+            })
+            .transpose()?;
+
+        let exporter = ExporterMetrics::register(&registry, &names, created)?;
 
         Ok(Self {
             power_import_total,
@@ -278,10 +1604,24 @@ impl Metrics {
             active_current_l1,
             active_current_l2,
             active_current_l3,
+            frequency,
             active_tariff,
+            monthly_power_peak,
+            monthly_power_peak_timestamp,
             gas_total,
+            gas_timestamp_seconds,
             gas_timestamp,
             gas_meter_info,
+            gas_meter_total,
+            gas_meter_timestamp,
+            gas_meter_reading_age,
+            gas_meter_stale,
+            water_total,
+            water_flow,
+            heat_energy_total,
+            warm_water_total,
+            smr_electricity_interval,
+            smr_gas_interval,
             wifi_strength,
             voltage_sag_l1_count,
             voltage_sag_l2_count,
@@ -291,37 +1631,111 @@ impl Metrics {
             voltage_swell_l3_count,
             power_failures_any,
             power_failures_long,
+            power_failure_duration,
+            last_power_failure_duration,
+            clock_drift,
             meter_info,
+            active_source,
+            device_info,
+            cloud_enabled,
+            uptime,
+            restarts,
+            wifi_rssi,
+            exporter,
+            unchanged_polls,
             external_sensor_value,
             external_sensor_timestamp,
+            cost,
+            net_metering,
+            degree_days,
+            fuse,
+            schema,
+            leader,
+            watermeter,
+            energy_socket,
+            kwh_meter,
+            plugin_battery,
             registry,
+            options,
+            gas_age: Mutex::new(GasAgeTracker::default()),
+            water: Mutex::new(WaterTracker::default()),
+            power_failure_log: Mutex::new(PowerFailureTracker::default()),
+            raw_fields: Mutex::new(HashMap::new()),
+            names,
+            created,
         })
     }
 
     pub fn update(&self, data: &HomeWizardData) -> Result<()> {
+        match data.product {
+            ProductType::P1 => self.update_p1(data)?,
+            ProductType::Watermeter => {
+                if let Some(watermeter) = &self.watermeter {
+                    watermeter.update(data);
+                }
+            }
+            ProductType::EnergySocket => {
+                if let Some(energy_socket) = &self.energy_socket {
+                    energy_socket.update(data);
+                }
+            }
+            ProductType::KwhMeter => {
+                if let Some(kwh_meter) = &self.kwh_meter {
+                    kwh_meter.update(data);
+                }
+            }
+            ProductType::PluginBattery => {
+                if let Some(plugin_battery) = &self.plugin_battery {
+                    plugin_battery.update(data);
+                }
+            }
+        }
+
+        if self.options.raw_passthrough {
+            self.update_raw_fields(data)?;
+        }
+
+        if let Some(schema) = &self.schema {
+            schema.update(data)?;
+        }
+
+        Ok(())
+    }
+
+    fn update_p1(&self, data: &HomeWizardData) -> Result<()> {
+        let filtered;
+        let data = if self.options.sensor_filter.is_empty() {
+            data
+        } else {
+            filtered = HomeWizardData {
+                external: data
+                    .external
+                    .iter()
+                    .filter(|sensor| self.options.sensor_filter.allows(sensor))
+                    .cloned()
+                    .collect(),
+                ..data.clone()
+            };
+            &filtered
+        };
+
         // Update power import metrics
-        self.power_import_total.reset();
-        self.power_import_total.inc_by(data.total_power_import_kwh);
+        self.power_import_total.set(data.total_power_import_kwh);
 
-        self.power_import_tariff.reset();
-        self.power_import_tariff
-            .with_label_values(&["1"])
-            .inc_by(data.total_power_import_t1_kwh);
-        self.power_import_tariff
-            .with_label_values(&["2"])
-            .inc_by(data.total_power_import_t2_kwh);
+        self.power_import_tariff.replace(
+            data.import_tariffs()
+                .into_iter()
+                .map(|(tariff, total)| ([tariff], total)),
+        );
 
         // Update power export metrics
-        self.power_export_total.reset();
-        self.power_export_total.inc_by(data.total_power_export_kwh);
+        self.power_export_total.set(data.total_power_export_kwh);
 
-        self.power_export_tariff.reset();
-        self.power_export_tariff
-            .with_label_values(&["1"])
-            .inc_by(data.total_power_export_t1_kwh);
-        self.power_export_tariff
-            .with_label_values(&["2"])
-            .inc_by(data.total_power_export_t2_kwh);
+        self.power_export_tariff.replace(
+            data.export_tariffs()
+                .into_iter()
+                .map(|(tariff, total)| ([tariff], total)),
+        );
 
         // Update current power metrics
         self.active_power.set(data.active_power_w);
@@ -335,51 +1749,117 @@ impl Metrics {
         self.active_current_l1.set(data.active_current_l1_a);
         self.active_current_l2.set(data.active_current_l2_a);
         self.active_current_l3.set(data.active_current_l3_a);
+        self.frequency.set(data.active_frequency_hz);
         self.active_tariff.set(data.active_tariff as f64);
+        self.monthly_power_peak.set(data.monthly_power_peak_w);
+        self.monthly_power_peak_timestamp
+            .set(data.monthly_power_peak_timestamp as f64);
 
         // Update gas metrics
-        self.gas_total.reset();
-        self.gas_total.inc_by(data.total_gas_m3);
+        self.gas_total.set(data.total_gas_m3);
 
         // Update gas timestamp
-        self.gas_timestamp.set(data.gas_timestamp as f64);
+        if let Some(timestamp) = dsmr_unix_time(
+            data.gas_timestamp,
+            self.options.timezone.unwrap_or(METER_TIMEZONE),
+        ) {
+            self.gas_timestamp_seconds.set(timestamp as f64);
+        }
+        if let Some(gas_timestamp) = &self.gas_timestamp {
+            gas_timestamp.set(data.gas_timestamp as f64);
+        }
+
+        // Update SMR capabilities
+        let capabilities = SmrCapabilities::from_smr_version(data.smr_version);
+        self.smr_electricity_interval
+            .set(capabilities.electricity_interval.as_secs_f64());
+        self.smr_gas_interval
+            .set(capabilities.gas_interval.as_secs_f64());
+
+        // Update per gas meter metrics
+        let gas_meters = data.gas_meters();
+        let stale_threshold = self
+            .options
+            .gas_stale_threshold
+            .unwrap_or_else(|| capabilities.gas_stale_threshold());
+        let now = Instant::now();
+        let mut gas_age = self
+            .gas_age
+            .lock()
+            .map_err(|_| anyhow!("gas age tracker lock poisoned"))?;
+        gas_age.retain(
+            &gas_meters
+                .iter()
+                .map(|m| m.unique_id.as_str())
+                .collect::<Vec<_>>(),
+        );
 
-        // Update gas meter info
         self.gas_meter_info.reset();
-        self.gas_meter_info
-            .with_label_values(&[&data.gas_unique_id])
-            .set(1.0);
+        self.gas_meter_total.replace(
+            gas_meters
+                .iter()
+                .map(|meter| ([meter.unique_id.as_str()], meter.total_m3)),
+        );
+        self.gas_meter_timestamp.reset();
+        self.gas_meter_reading_age.reset();
+        self.gas_meter_stale.reset();
+        for meter in &gas_meters {
+            let age = gas_age.observe(&meter.unique_id, meter.timestamp, now);
+            self.gas_meter_reading_age
+                .with_label_values(&[&meter.unique_id])
+                .set(age.as_secs_f64());
+            self.gas_meter_stale
+                .with_label_values(&[&meter.unique_id])
+                .set(if age > stale_threshold { 1.0 } else { 0.0 });
+
+            self.gas_meter_info
+                .with_label_values(&[&meter.unique_id])
+                .set(1.0);
+            self.gas_meter_timestamp
+                .with_label_values(&[&meter.unique_id])
+                .set(meter.timestamp as f64);
+        }
 
         // Update network metrics
         self.wifi_strength.set(data.wifi_strength);
 
         // Update power quality metrics
-        self.voltage_sag_l1_count.reset();
-        self.voltage_sag_l1_count.inc_by(data.voltage_sag_l1_count);
+        self.voltage_sag_l1_count.set(data.voltage_sag_l1_count);
+
+        self.voltage_sag_l2_count.set(data.voltage_sag_l2_count);
 
-        self.voltage_sag_l2_count.reset();
-        self.voltage_sag_l2_count.inc_by(data.voltage_sag_l2_count);
+        self.voltage_sag_l3_count.set(data.voltage_sag_l3_count);
 
-        self.voltage_sag_l3_count.reset();
-        self.voltage_sag_l3_count.inc_by(data.voltage_sag_l3_count);
+        self.voltage_swell_l1_count.set(data.voltage_swell_l1_count);
 
-        self.voltage_swell_l1_count.reset();
-        self.voltage_swell_l1_count
-            .inc_by(data.voltage_swell_l1_count);
+        self.voltage_swell_l2_count.set(data.voltage_swell_l2_count);
 
-        self.voltage_swell_l2_count.reset();
-        self.voltage_swell_l2_count
-            .inc_by(data.voltage_swell_l2_count);
+        self.voltage_swell_l3_count.set(data.voltage_swell_l3_count);
 
-        self.voltage_swell_l3_count.reset();
-        self.voltage_swell_l3_count
-            .inc_by(data.voltage_swell_l3_count);
+        self.power_failures_any.set(data.any_power_fail_count);
 
-        self.power_failures_any.reset();
-        self.power_failures_any.inc_by(data.any_power_fail_count);
+        self.power_failures_long.set(data.long_power_fail_count);
 
-        self.power_failures_long.reset();
-        self.power_failures_long.inc_by(data.long_power_fail_count);
+        // Includes the telegram's age, up to one telegram interval.
+        if let Some(meter_time) = data.meter_time {
+            self.clock_drift
+                .set((meter_time - chrono::Utc::now().timestamp()) as f64);
+        }
+
+        // The failure log only comes with the telegram source.
+        if let Some(last) = data
+            .power_failure_log
+            .iter()
+            .max_by_key(|failure| failure.end_timestamp)
+        {
+            let total_s = self
+                .power_failure_log
+                .lock()
+                .map_err(|_| anyhow!("power failure tracker lock poisoned"))?
+                .observe(&data.power_failure_log);
+            self.power_failure_duration.set(total_s);
+            self.last_power_failure_duration.set(last.duration_s);
+        }
 
         // Update info metric
         self.meter_info.reset();
@@ -395,35 +1875,419 @@ impl Metrics {
         // Update external sensors
         self.external_sensor_value.reset();
         self.external_sensor_timestamp.reset();
+        self.water_flow.reset();
+        let mut water_total = Vec::new();
+        let mut heat_energy_total = Vec::new();
+        let mut warm_water_total = Vec::new();
+        let mut water = self
+            .water
+            .lock()
+            .map_err(|_| anyhow!("water tracker lock poisoned"))?;
+        water.retain(
+            &data
+                .external
+                .iter()
+                .map(|s| s.unique_id.as_str())
+                .collect::<Vec<_>>(),
+        );
         for sensor in &data.external {
+            if let Some(reading) = sensor.water_reading() {
+                let (volume_m3, flow_lpm) = water.observe(&sensor.unique_id, reading, now);
+                if self.options.water_mode.exports_volume() {
+                    water_total.push(([sensor.unique_id.as_str()], volume_m3));
+                }
+                if self.options.water_mode.exports_flow() {
+                    self.water_flow
+                        .with_label_values(&[&sensor.unique_id])
+                        .set(flow_lpm);
+                }
+            }
+
+            if let Some(gj) = sensor.heat_energy_gj() {
+                heat_energy_total.push(([sensor.unique_id.as_str()], gj));
+            }
+            if let Some(m3) = sensor.warm_water_m3() {
+                warm_water_total.push(([sensor.unique_id.as_str()], m3));
+            }
+
+            // Unmapped sensors are named by their ID
+            let name = self
+                .options
+                .sensor_names
+                .get(&sensor.unique_id)
+                .unwrap_or(&sensor.unique_id);
             self.external_sensor_value
-                .with_label_values(&[&sensor.unique_id, &sensor.sensor_type, &sensor.unit])
+                .with_label_values(&[&sensor.unique_id, name, &sensor.sensor_type, &sensor.unit])
                 .set(sensor.value);
 
             self.external_sensor_timestamp
-                .with_label_values(&[&sensor.unique_id, &sensor.sensor_type])
+                .with_label_values(&[&sensor.unique_id, name, &sensor.sensor_type])
                 .set(sensor.timestamp as f64);
         }
 
+        self.water_total.replace(water_total);
+        self.heat_energy_total.replace(heat_energy_total);
+        self.warm_water_total.replace(warm_water_total);
+
+        if let Some(cost) = self
+            .cost
+            .read()
+            .map_err(|_| anyhow!("cost metrics lock poisoned"))?
+            .as_ref()
+        {
+            let mut tracker = cost
+                .tracker
+                .lock()
+                .map_err(|_| anyhow!("cost tracker lock poisoned"))?;
+            let (month_to_date, projected) = match self.options.timezone {
+                Some(tz) => tracker.update(data, chrono::Utc::now().with_timezone(&tz)),
+                None => tracker.update(data, chrono::Local::now()),
+            };
+            for (component, value) in month_to_date.components() {
+                cost.month_to_date
+                    .with_label_values(&[component])
+                    .set(value);
+            }
+            for (component, value) in projected.components() {
+                cost.projected.with_label_values(&[component]).set(value);
+            }
+            cost.total.replace(
+                tracker
+                    .totals()
+                    .components()
+                    .map(|(component, value)| ([component], value)),
+            );
+        }
+
+        if let Some(net_metering) = &self.net_metering {
+            let balance = net_metering
+                .tracker
+                .lock()
+                .map_err(|_| anyhow!("net metering tracker lock poisoned"))?
+                .update(data, timezone::today(self.options.timezone));
+            net_metering.import.set(balance.import_kwh);
+            net_metering.export.set(balance.export_kwh);
+            net_metering.net.set(balance.net_kwh());
+            net_metering.bankable.set(balance.bankable_export_kwh());
+        }
+
+        if let Some(fuse) = &self.fuse {
+            fuse.update(data);
+        }
+
+        if let Some(degree_days) = &self.degree_days {
+            degree_days.update(data, timezone::today(self.options.timezone))?;
+        }
+
+        Ok(())
+    }
+
+    /// Records which source in the fallback chain produced the reading.
+    pub fn set_active_source(&self, source: &str) {
+        self.active_source.reset();
+        self.active_source.with_label_values(&[source]).set(1.0);
+    }
+
+    /// Records the product and firmware the device identified itself with.
+    pub fn set_device_info(&self, info: &DeviceInfo) {
+        self.device_info.reset();
+        self.device_info
+            .with_label_values(&[
+                info.product_type.as_str(),
+                &info.product_name,
+                &info.serial,
+                &info.firmware_version,
+                &info.api_version,
+            ])
+            .set(1.0);
+    }
+
+    /// Records the cloud setting, uptime and Wi-Fi RSSI from the device's
+    /// system endpoint.
+    pub fn set_system_status(&self, status: &SystemStatus) {
+        if let Some(enabled) = status.cloud_enabled {
+            self.cloud_enabled
+                .with_label_values::<&str>(&[])
+                .set(f64::from(u8::from(enabled)));
+        }
+        if let Some(uptime) = status.uptime_secs {
+            self.uptime.with_label_values::<&str>(&[]).set(uptime);
+        }
+        if let Some(rssi) = status.wifi_rssi_dbm {
+            self.wifi_rssi.with_label_values::<&str>(&[]).set(rssi);
+        }
+    }
+
+    /// Counts a reboot of the device.
+    pub fn record_device_restart(&self) {
+        self.restarts.inc();
+    }
+
+    /// Exports numeric fields unknown to the data model as gauges,
+    /// registering each the first time it is seen. A field whose metric
+    /// name is invalid or taken, e.g. by another field differing only in
+    /// punctuation, is skipped with a warning the first time.
+    fn update_raw_fields(&self, data: &HomeWizardData) -> Result<()> {
+        let mut raw_fields = self
+            .raw_fields
+            .lock()
+            .map_err(|_| anyhow!("raw field lock poisoned"))?;
+
+        for (field, value) in &data.unknown_fields {
+            let Some(value) = value.as_f64() else {
+                continue;
+            };
+            let gauge = raw_fields.entry(field.clone()).or_insert_with(|| {
+                match self.register_raw_field(field) {
+                    Ok(gauge) => Some(gauge),
+                    Err(e) => {
+                        warn!("Not exporting the device field {:?}: {}", field, e);
+                        None
+                    }
+                }
+            });
+            if let Some(gauge) = gauge {
+                gauge.set(value);
+            }
+        }
+        Ok(())
+    }
+
+    fn register_raw_field(&self, field: &str) -> prometheus::Result<Gauge> {
+        let gauge = Gauge::with_opts(Opts::new(
+            self.names.raw(field),
+            format!("Raw value of the device field {field:?}"),
+        ))?;
+        self.registry.register(Box::new(gauge.clone()))?;
+        Ok(gauge)
+    }
+
+    /// Marks whether this instance is the active one. Metrics from a
+    /// standby stop updating, so this tells them apart from live ones.
+    pub fn set_leader(&self, leader: bool) {
+        if let Some(gauge) = &self.leader {
+            gauge.set(if leader { 1.0 } else { 0.0 });
+        }
+    }
+
+    /// Switches the cost metrics to new prices, registering them when no
+    /// contract was configured before and removing them when `contract` is
+    /// `None`.
+    pub fn set_contract(&self, contract: Option<Contract>) -> Result<()> {
+        let mut cost = self.cost.write().unwrap_or_else(|e| e.into_inner());
+        let Some(contract) = contract else {
+            return cost.take().map_or(Ok(()), CostMetrics::unregister);
+        };
+        if let Some(cost) = cost.as_ref() {
+            cost.tracker
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .set_contract(contract);
+            return Ok(());
+        }
+        // Like at startup, only P1 meters export cost metrics
+        let registry = if self.options.product == ProductType::P1 {
+            self.registry.clone()
+        } else {
+            Registry::new()
+        };
+        *cost = Some(CostMetrics::register(
+            &registry,
+            &self.options.names,
+            contract,
+        )?);
         Ok(())
     }
 
+    /// The month tracked by the cost metrics, when they are exported.
+    pub fn cost_baseline(&self) -> Option<MonthBaseline> {
+        let cost = self.cost.read().unwrap_or_else(|e| e.into_inner());
+        cost.as_ref().and_then(|cost| {
+            cost.tracker
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .baseline()
+        })
+    }
+
+    /// Continues the month tracked before a restart.
+    pub fn restore_cost_baseline(&self, baseline: MonthBaseline) {
+        if let Some(cost) = self.cost.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            cost.tracker
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .restore(baseline);
+        }
+    }
+
+    /// Reflects an overload warning raised or cleared for `phase`.
+    pub fn set_fuse_overload(&self, phase: &str, active: bool) {
+        if let Some(fuse) = &self.fuse {
+            fuse.overload
+                .with_label_values(&[phase])
+                .set(if active { 1.0 } else { 0.0 });
+        }
+    }
+
+    /// Records a successful poll of the device.
+    pub fn record_poll_success(&self) {
+        self.exporter.poll_success_total.inc();
+        self.exporter.up.set(1.0);
+        if let Some(p1_up) = &self.exporter.p1_up {
+            p1_up.set(1.0);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.exporter.last_poll_success.set(now.as_secs_f64());
+    }
+
+    /// Records how long fetching a reading took, successful or not.
+    pub fn observe_fetch_duration(&self, duration: Duration) {
+        self.exporter.fetch_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Counts a fetch retried within a poll.
+    pub fn record_fetch_retry(&self) {
+        self.exporter.fetch_retries_total.inc();
+    }
+
+    /// Sets the state of the circuit breaker.
+    pub fn set_circuit_open(&self, open: bool) {
+        self.exporter.circuit_open.set(if open { 1.0 } else { 0.0 });
+    }
+
+    /// Records a failed poll of the device, labeled by error class.
+    pub fn record_poll_error(&self, class: &str) {
+        self.exporter
+            .poll_errors_total
+            .with_label_values(&[class])
+            .inc();
+        self.exporter.up.set(0.0);
+        if let Some(p1_up) = &self.exporter.p1_up {
+            p1_up.set(0.0);
+        }
+    }
+
+    /// Counts a poll whose reading matched the previous one. The metrics
+    /// are still updated and served; only the push sinks skip it.
+    pub fn record_unchanged_poll(&self) {
+        self.unchanged_polls.inc();
+    }
+
+    #[cfg(test)]
     pub fn gather(&self) -> Result<String> {
-        let encoder = TextEncoder::new();
-        let metric_families = self.registry.gather();
-        let mut buffer = Vec::new();
-        encoder.encode(&metric_families, &mut buffer)?;
-        Ok(String::from_utf8(buffer)?)
+        encode(&self.families())
+    }
+
+    /// The registry's families. It appends the `device` and `--label`
+    /// labels in hash order, so they are sorted here to keep the output
+    /// stable.
+    fn families(&self) -> Vec<MetricFamily> {
+        let mut families = self.registry.gather();
+        for metric in families.iter_mut().flat_map(|family| family.mut_metric()) {
+            let mut labels = metric.take_label();
+            labels.sort_by(|a, b| a.name().cmp(b.name()));
+            metric.set_label(labels);
+        }
+        families
     }
 }
 
+/// Renders the metrics of several devices as one exposition, merging
+/// families that share a name so each gets a single HELP and TYPE line.
+pub fn gather_all(devices: &[Arc<Metrics>]) -> Result<String> {
+    encode(&merge(devices))
+}
+
+/// Like [`gather_all`], in the OpenMetrics format.
+pub fn gather_all_openmetrics(devices: &[Arc<Metrics>]) -> String {
+    let created = devices
+        .iter()
+        .map(|metrics| metrics.created)
+        .min()
+        .and_then(|created| created.duration_since(UNIX_EPOCH).ok())
+        .map(|created| created.as_secs_f64());
+    let counters: Vec<String> = devices
+        .first()
+        .map(|metrics| {
+            EXPORTER_COUNTERS
+                .iter()
+                .map(|name| metrics.names.name(name))
+                .chain([metrics.names.p1("unchanged_polls_total")])
+                .collect()
+        })
+        .unwrap_or_default();
+    openmetrics::encode(&merge(devices), |family| {
+        created.filter(|_| counters.iter().any(|name| name == family))
+    })
+}
+
+/// The metrics of every polled device, shared by the sinks that render them
+/// all. Devices added or removed at runtime show up in every sink.
+#[derive(Clone, Default)]
+pub struct DeviceMetrics(Arc<std::sync::RwLock<Vec<Arc<Metrics>>>>);
+
+impl DeviceMetrics {
+    pub fn new(devices: Vec<Arc<Metrics>>) -> Self {
+        Self(Arc::new(std::sync::RwLock::new(devices)))
+    }
+
+    /// The devices' metrics at this moment.
+    pub fn snapshot(&self) -> Vec<Arc<Metrics>> {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn add(&self, metrics: Arc<Metrics>) {
+        self.0
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(metrics);
+    }
+
+    pub fn remove(&self, metrics: &Arc<Metrics>) {
+        self.0
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|existing| !Arc::ptr_eq(existing, metrics));
+    }
+}
+
+/// The metric families of all devices, for the push exporters.
+pub fn gather_families(devices: &[Arc<Metrics>]) -> Vec<MetricFamily> {
+    merge(devices)
+}
+
+fn merge(devices: &[Arc<Metrics>]) -> Vec<MetricFamily> {
+    let mut families: BTreeMap<String, MetricFamily> = BTreeMap::new();
+    for metrics in devices {
+        for mut family in metrics.families() {
+            match families.get_mut(family.name()) {
+                Some(merged) => merged.mut_metric().extend(family.take_metric()),
+                None => {
+                    families.insert(family.name().to_string(), family);
+                }
+            }
+        }
+    }
+    families.into_values().collect()
+}
+
+fn encode(metric_families: &[MetricFamily]) -> Result<String> {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(metric_families, &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::homewizard::{ExternalSensor, HomeWizardData};
+    use crate::homewizard::{ExternalSensor, HomeWizardData, PowerFailure, SocketState};
 
     fn create_test_data() -> HomeWizardData {
         HomeWizardData {
+            product: ProductType::P1,
             wifi_ssid: "TestNetwork".to_string(),
             wifi_strength: 75.5,
             smr_version: 50,
@@ -431,11 +2295,15 @@ mod tests {
             unique_id: "3c39e7aabbccddee".to_string(),
             active_tariff: 1,
             total_power_import_kwh: 1234.567,
-            total_power_import_t1_kwh: 800.123,
-            total_power_import_t2_kwh: 434.444,
+            total_power_import_t1_kwh: Some(800.123),
+            total_power_import_t2_kwh: Some(434.444),
+            total_power_import_t3_kwh: None,
+            total_power_import_t4_kwh: None,
             total_power_export_kwh: 89.012,
-            total_power_export_t1_kwh: 60.789,
-            total_power_export_t2_kwh: 28.223,
+            total_power_export_t1_kwh: Some(60.789),
+            total_power_export_t2_kwh: Some(28.223),
+            total_power_export_t3_kwh: None,
+            total_power_export_t4_kwh: None,
             active_power_w: 1500.0,
             active_power_l1_w: 750.0,
             active_power_l2_w: 500.0,
@@ -447,6 +2315,7 @@ mod tests {
             active_current_l1_a: 4.2,
             active_current_l2_a: 1.3,
             active_current_l3_a: 1.8,
+            active_frequency_hz: 50.02,
             voltage_sag_l1_count: 2.0,
             voltage_sag_l2_count: 2.0,
             voltage_sag_l3_count: 2.0,
@@ -455,6 +2324,8 @@ mod tests {
             voltage_swell_l3_count: 1.0,
             any_power_fail_count: 5.0,
             long_power_fail_count: 0.0,
+            monthly_power_peak_w: 4321.0,
+            monthly_power_peak_timestamp: 230101080000,
             total_gas_m3: 567.890,
             gas_timestamp: 1234567890,
             gas_unique_id: "aabbccddee112233".to_string(),
@@ -474,141 +2345,769 @@ mod tests {
                     unit: "m3".to_string(),
                 },
             ],
+            total_liter_m3: 0.0,
+            active_liter_lpm: 0.0,
+            socket_state: None,
+            state_of_charge_pct: 0.0,
+            battery_cycles: 0.0,
+            power_failure_log: vec![],
+            meter_time: None,
+            unknown_fields: Default::default(),
+            missing_fields: vec![],
         }
     }
 
     #[test]
-    fn test_metrics_creation() {
-        let metrics = Metrics::new();
-        assert!(metrics.is_ok());
+    fn test_metrics_creation() {
+        let metrics = Metrics::new();
+        assert!(metrics.is_ok());
+    }
+
+    #[test]
+    fn test_metrics_update() {
+        let metrics = Metrics::new().unwrap();
+        let data = create_test_data();
+
+        let result = metrics.update(&data);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_metrics_gather() {
+        let metrics = Metrics::new().unwrap();
+        let data = create_test_data();
+
+        metrics.update(&data).unwrap();
+        let result = metrics.gather();
+        assert!(result.is_ok());
+
+        let output = result.unwrap();
+        assert!(output.contains("homewizard_p1_power_import_total_kwh"));
+        assert!(output.contains("homewizard_p1_active_power_watts"));
+        assert!(output.contains("homewizard_p1_gas_total_m3"));
+        assert!(output.contains("homewizard_p1_wifi_strength_percent"));
+        assert!(output.contains("homewizard_p1_meter_info"));
+        assert!(output.contains("homewizard_p1_external_sensor_value"));
+    }
+
+    #[test]
+    fn test_metrics_power_import_values() {
+        let metrics = Metrics::new().unwrap();
+        let data = create_test_data();
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_power_import_total_kwh 1234.567"));
+        assert!(output.contains("homewizard_p1_power_import_tariff_kwh{tariff=\"1\"} 800.123"));
+        assert!(output.contains("homewizard_p1_power_import_tariff_kwh{tariff=\"2\"} 434.444"));
+    }
+
+    #[test]
+    fn test_metrics_power_export_values() {
+        let metrics = Metrics::new().unwrap();
+        let data = create_test_data();
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_power_export_total_kwh 89.012"));
+        assert!(output.contains("homewizard_p1_power_export_tariff_kwh{tariff=\"1\"} 60.789"));
+        assert!(output.contains("homewizard_p1_power_export_tariff_kwh{tariff=\"2\"} 28.223"));
+    }
+
+    #[test]
+    fn test_metrics_reported_tariffs_only() {
+        let metrics = Metrics::new().unwrap();
+        let data = HomeWizardData {
+            total_power_import_t1_kwh: Some(100.0),
+            total_power_import_t2_kwh: Some(200.0),
+            total_power_import_t3_kwh: Some(300.0),
+            total_power_import_t4_kwh: Some(400.0),
+            total_power_export_t1_kwh: Some(50.0),
+            total_power_export_t2_kwh: None,
+            ..create_test_data()
+        };
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_power_import_tariff_kwh{tariff=\"3\"} 300"));
+        assert!(output.contains("homewizard_p1_power_import_tariff_kwh{tariff=\"4\"} 400"));
+        assert!(output.contains("homewizard_p1_power_export_tariff_kwh{tariff=\"1\"} 50"));
+        assert!(!output.contains("homewizard_p1_power_export_tariff_kwh{tariff=\"2\"}"));
+    }
+
+    #[test]
+    fn test_metrics_active_power_values() {
+        let metrics = Metrics::new().unwrap();
+        let data = create_test_data();
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_active_power_watts 1500"));
+        assert!(output.contains("homewizard_p1_active_power_l1_watts 750"));
+        assert!(output.contains("homewizard_p1_active_power_l2_watts 500"));
+        assert!(output.contains("homewizard_p1_active_power_l3_watts 250"));
+        assert!(output.contains("homewizard_p1_active_voltage_l1_volts 230"));
+        assert!(output.contains("homewizard_p1_active_voltage_l2_volts 230"));
+        assert!(output.contains("homewizard_p1_active_voltage_l3_volts 230"));
+        assert!(output.contains("homewizard_p1_active_current_amperes 6.8"));
+        assert!(output.contains("homewizard_p1_active_current_l1_amperes 4.2"));
+        assert!(output.contains("homewizard_p1_active_current_l2_amperes 1.3"));
+        assert!(output.contains("homewizard_p1_active_current_l3_amperes 1.8"));
+        assert!(output.contains("homewizard_p1_frequency_hertz 50.02"));
+        assert!(output.contains("homewizard_p1_active_tariff 1"));
+        assert!(output.contains("homewizard_p1_monthly_power_peak_watts 4321"));
+        assert!(output.contains("homewizard_p1_monthly_power_peak_timestamp 230101080000"));
+    }
+
+    #[test]
+    fn test_metrics_gas_values() {
+        let metrics = Metrics::new().unwrap();
+        let mut data = create_test_data();
+        data.gas_timestamp = 250701143000;
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_gas_total_m3 567.89"));
+        assert!(output.contains("homewizard_p1_gas_timestamp_seconds 1751373000"));
+        assert!(!output.contains("homewizard_p1_gas_timestamp "));
+        assert!(output.contains("homewizard_p1_gas_meter_info{unique_id=\"aabbccddee112233\"} 1"));
+
+        let metrics = Metrics::with_options(MetricsOptions {
+            gas_timestamp_raw: true,
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        metrics.update(&data).unwrap();
+        assert!(
+            metrics
+                .gather()
+                .unwrap()
+                .contains("homewizard_p1_gas_timestamp 250701143000")
+        );
+    }
+
+    #[test]
+    fn test_metrics_multiple_gas_meters() {
+        let metrics = Metrics::new().unwrap();
+        let mut data = create_test_data();
+        data.external.push(ExternalSensor {
+            unique_id: "gas-annex".to_string(),
+            sensor_type: "gas_meter".to_string(),
+            timestamp: 1234560000,
+            value: 42.5,
+            unit: "m3".to_string(),
+        });
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(
+            output.contains(
+                "homewizard_p1_gas_meter_total_m3{unique_id=\"aabbccddee112233\"} 567.89"
+            )
+        );
+        assert!(output.contains("homewizard_p1_gas_meter_total_m3{unique_id=\"gas-annex\"} 42.5"));
+        assert!(
+            output
+                .contains("homewizard_p1_gas_meter_timestamp{unique_id=\"gas-annex\"} 1234560000")
+        );
+        assert!(output.contains("homewizard_p1_gas_meter_info{unique_id=\"gas-annex\"} 1"));
+    }
+
+    #[test]
+    fn test_metrics_smr_capabilities() {
+        let metrics = Metrics::new().unwrap();
+        let data = create_test_data();
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_smr_electricity_update_interval_seconds 1"));
+        assert!(output.contains("homewizard_p1_smr_gas_update_interval_seconds 300"));
+        assert!(output.contains("homewizard_p1_gas_meter_stale{unique_id=\"aabbccddee112233\"} 0"));
+    }
+
+    #[test]
+    fn test_metrics_gas_stale_with_zero_threshold() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            gas_stale_threshold: Some(Duration::ZERO),
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        let data = create_test_data();
+
+        metrics.update(&data).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_gas_meter_stale{unique_id=\"aabbccddee112233\"} 1"));
+    }
+
+    #[test]
+    fn test_gas_age_tracker() {
+        let mut tracker = GasAgeTracker::default();
+        let start = Instant::now();
+
+        assert_eq!(tracker.observe("gas", 100, start), Duration::ZERO);
+        assert_eq!(
+            tracker.observe("gas", 100, start + Duration::from_secs(60)),
+            Duration::from_secs(60)
+        );
+        // A new reading resets the age
+        assert_eq!(
+            tracker.observe("gas", 200, start + Duration::from_secs(90)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            tracker.observe("gas", 200, start + Duration::from_secs(120)),
+            Duration::from_secs(30)
+        );
+
+        tracker.retain(&[]);
+        assert_eq!(
+            tracker.observe("gas", 200, start + Duration::from_secs(150)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_metrics_wifi_values() {
+        let metrics = Metrics::new().unwrap();
+        let data = create_test_data();
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_wifi_strength_percent 75.5"));
+    }
+
+    #[test]
+    fn test_metrics_power_quality_values() {
+        let metrics = Metrics::new().unwrap();
+        let data = create_test_data();
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_voltage_sag_l1_count_total 2"));
+        assert!(output.contains("homewizard_p1_voltage_sag_l2_count_total 2"));
+        assert!(output.contains("homewizard_p1_voltage_sag_l3_count_total 2"));
+        assert!(output.contains("homewizard_p1_voltage_swell_l1_count_total 1"));
+        assert!(output.contains("homewizard_p1_voltage_swell_l2_count_total 1"));
+        assert!(output.contains("homewizard_p1_voltage_swell_l3_count_total 1"));
+        assert!(output.contains("homewizard_p1_power_failures_any_total 5"));
+        assert!(output.contains("homewizard_p1_power_failures_long_total 0"));
+    }
+
+    #[test]
+    fn test_metrics_power_failure_durations() {
+        let metrics = Metrics::new().unwrap();
+        let failure = |end_timestamp, duration_s| PowerFailure {
+            end_timestamp,
+            duration_s,
+        };
+        let mut data = HomeWizardData {
+            power_failure_log: vec![failure(241101101500, 240.0), failure(241201080000, 600.0)],
+            ..HomeWizardData::default()
+        };
+        metrics.update(&data).unwrap();
+
+        // The oldest entry rolls out as a new failure is logged.
+        data.power_failure_log = vec![failure(241201080000, 600.0), failure(250105120000, 30.0)];
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_power_failure_duration_seconds_total 870"));
+        assert!(output.contains("homewizard_p1_last_power_failure_duration_seconds 30"));
+    }
+
+    #[test]
+    fn test_metrics_clock_drift() {
+        let metrics = Metrics::new().unwrap();
+        let data = HomeWizardData {
+            meter_time: Some(chrono::Utc::now().timestamp() - 300),
+            ..HomeWizardData::default()
+        };
+
+        metrics.update(&data).unwrap();
+
+        let drift = metrics.clock_drift.get();
+        assert!((-301.0..=-299.0).contains(&drift), "drift was {drift}");
+    }
+
+    #[test]
+    fn test_metrics_raw_passthrough() {
+        let data = HomeWizardData {
+            unknown_fields: [
+                (
+                    "active_power_average_w".to_string(),
+                    serde_json::json!(412.5),
+                ),
+                ("new-field".to_string(), serde_json::json!(3)),
+                ("firmware_label".to_string(), serde_json::json!("x")),
+            ]
+            .into(),
+            ..HomeWizardData::default()
+        };
+
+        let disabled = Metrics::new().unwrap();
+        disabled.update(&data).unwrap();
+        assert!(!disabled.gather().unwrap().contains("homewizard_p1_raw_"));
+
+        let metrics = Metrics::with_options(MetricsOptions {
+            raw_passthrough: true,
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        metrics.update(&data).unwrap();
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_raw_active_power_average_w 412.5"));
+        assert!(output.contains("homewizard_p1_raw_new_field 3"));
+        assert!(!output.contains("firmware_label"));
+    }
+
+    #[test]
+    fn test_metrics_raw_passthrough_skips_colliding_field() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            raw_passthrough: true,
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        // Both become homewizard_p1_raw_new_field; only one is exported
+        let data = HomeWizardData {
+            unknown_fields: [
+                ("new-field".to_string(), serde_json::json!(3)),
+                ("new_field".to_string(), serde_json::json!(4)),
+                ("other_field".to_string(), serde_json::json!(5)),
+            ]
+            .into(),
+            ..HomeWizardData::default()
+        };
+
+        metrics.update(&data).unwrap();
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+        assert_eq!(output.matches("\nhomewizard_p1_raw_new_field ").count(), 1);
+        assert!(output.contains("homewizard_p1_raw_other_field 5"));
+        assert_eq!(metrics.raw_fields.lock().unwrap().len(), 3);
     }
 
     #[test]
-    fn test_metrics_update() {
-        let metrics = Metrics::new().unwrap();
-        let data = create_test_data();
+    fn test_metrics_schema_drift() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            schema_report: true,
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        let data = HomeWizardData {
+            unknown_fields: [("new_field".to_string(), serde_json::json!(1))].into(),
+            missing_fields: vec!["total_gas_m3", "gas_timestamp"],
+            ..HomeWizardData::default()
+        };
 
-        let result = metrics.update(&data);
-        assert!(result.is_ok());
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_schema_drift_fields{kind=\"unknown\"} 1"));
+        assert!(output.contains("homewizard_p1_schema_drift_fields{kind=\"missing\"} 2"));
+        assert!(
+            !Metrics::new()
+                .unwrap()
+                .gather()
+                .unwrap()
+                .contains("schema_drift")
+        );
     }
 
     #[test]
-    fn test_metrics_gather() {
+    fn test_metrics_leader() {
+        assert!(!Metrics::new().unwrap().gather().unwrap().contains("leader"));
+
+        let metrics = Metrics::with_options(MetricsOptions {
+            failover: true,
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        assert!(metrics.gather().unwrap().contains("homewizard_p1_leader 0"));
+
+        metrics.set_leader(true);
+        assert!(metrics.gather().unwrap().contains("homewizard_p1_leader 1"));
+    }
+
+    #[test]
+    fn test_metrics_meter_info_values() {
         let metrics = Metrics::new().unwrap();
         let data = create_test_data();
 
         metrics.update(&data).unwrap();
-        let result = metrics.gather();
-        assert!(result.is_ok());
+        let output = metrics.gather().unwrap();
 
-        let output = result.unwrap();
-        assert!(output.contains("homewizard_p1_power_import_total_kwh"));
-        assert!(output.contains("homewizard_p1_active_power_watts"));
-        assert!(output.contains("homewizard_p1_gas_total_m3"));
-        assert!(output.contains("homewizard_p1_wifi_strength_percent"));
-        assert!(output.contains("homewizard_p1_meter_info"));
-        assert!(output.contains("homewizard_p1_external_sensor_value"));
+        assert!(output.contains("homewizard_p1_meter_info{meter_id=\"3c39e7aabbccddee\",meter_model=\"ISKRA 2M550T-1012\",smr_version=\"50\",wifi_ssid=\"TestNetwork\"} 1"));
     }
 
     #[test]
-    fn test_metrics_power_import_values() {
+    fn test_metrics_active_source() {
         let metrics = Metrics::new().unwrap();
-        let data = create_test_data();
 
-        metrics.update(&data).unwrap();
+        metrics.set_active_source("v1");
+        metrics.set_active_source("telegram");
         let output = metrics.gather().unwrap();
 
-        assert!(output.contains("homewizard_p1_power_import_total_kwh 1234.567"));
-        assert!(output.contains("homewizard_p1_power_import_tariff_kwh{tariff=\"1\"} 800.123"));
-        assert!(output.contains("homewizard_p1_power_import_tariff_kwh{tariff=\"2\"} 434.444"));
+        assert!(output.contains("homewizard_p1_active_source_info{source=\"telegram\"} 1"));
+        assert!(!output.contains("source=\"v1\""));
     }
 
     #[test]
-    fn test_metrics_power_export_values() {
+    fn test_exporter_poll_metrics() {
         let metrics = Metrics::new().unwrap();
-        let data = create_test_data();
+        metrics.record_poll_success();
+        let output = metrics.gather().unwrap();
+        assert!(output.contains("homewizard_exporter_poll_success_total 1"));
+        assert!(output.contains("homewizard_exporter_up 1"));
+        assert!(!output.contains("homewizard_exporter_last_poll_success_timestamp_seconds 0"));
 
-        metrics.update(&data).unwrap();
+        metrics.record_poll_error("timeout");
+        metrics.record_poll_error("timeout");
         let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"homewizard_exporter_poll_errors_total{class="timeout"} 2"#));
+        assert!(output.contains("homewizard_exporter_up 0"));
+    }
 
-        assert!(output.contains("homewizard_p1_power_export_total_kwh 89.012"));
-        assert!(output.contains("homewizard_p1_power_export_tariff_kwh{tariff=\"1\"} 60.789"));
-        assert!(output.contains("homewizard_p1_power_export_tariff_kwh{tariff=\"2\"} 28.223"));
+    #[test]
+    fn test_data_age() {
+        let metrics = Metrics::new().unwrap();
+        metrics.record_poll_success();
+        let rendered = metrics.gather().unwrap();
+        assert!(rendered.contains("homewizard_p1_data_age_seconds 0"));
+
+        // The age grows between polls, as of each gather
+        let two_minutes_ago = SystemTime::now() - Duration::from_secs(120);
+        metrics.exporter.last_poll_success.set(
+            two_minutes_ago
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64(),
+        );
+        let gathered = metrics.gather().unwrap();
+        let line = gathered
+            .lines()
+            .find(|line| line.starts_with("homewizard_p1_data_age_seconds "))
+            .unwrap();
+        let age: f64 = line.rsplit(' ').next().unwrap().parse().unwrap();
+        assert!((120.0..130.0).contains(&age), "age {age}");
+
+        assert!(rendered.contains("homewizard_p1_up 1"));
+        metrics.record_poll_error("timeout");
+        assert!(metrics.gather().unwrap().contains("homewizard_p1_up 0"));
     }
 
     #[test]
-    fn test_metrics_active_power_values() {
+    fn test_fetch_duration_histogram() {
         let metrics = Metrics::new().unwrap();
-        let data = create_test_data();
+        metrics.observe_fetch_duration(Duration::from_millis(40));
+        metrics.observe_fetch_duration(Duration::from_millis(700));
+        let output = metrics.gather().unwrap();
 
-        metrics.update(&data).unwrap();
+        assert!(
+            output.contains(r#"homewizard_exporter_fetch_duration_seconds_bucket{le="0.05"} 1"#)
+        );
+        assert!(output.contains(r#"homewizard_exporter_fetch_duration_seconds_bucket{le="1"} 2"#));
+        assert!(output.contains("homewizard_exporter_fetch_duration_seconds_count 2"));
+    }
+
+    #[test]
+    fn test_system_status() {
+        let metrics = Metrics::new().unwrap();
         let output = metrics.gather().unwrap();
+        assert!(!output.contains("homewizard_device_cloud_enabled"));
+        assert!(!output.contains("homewizard_device_uptime_seconds"));
 
-        assert!(output.contains("homewizard_p1_active_power_watts 1500"));
-        assert!(output.contains("homewizard_p1_active_power_l1_watts 750"));
-        assert!(output.contains("homewizard_p1_active_power_l2_watts 500"));
-        assert!(output.contains("homewizard_p1_active_power_l3_watts 250"));
-        assert!(output.contains("homewizard_p1_active_voltage_l1_volts 230"));
-        assert!(output.contains("homewizard_p1_active_voltage_l2_volts 230"));
-        assert!(output.contains("homewizard_p1_active_voltage_l3_volts 230"));
-        assert!(output.contains("homewizard_p1_active_current_amperes 6.8"));
-        assert!(output.contains("homewizard_p1_active_current_l1_amperes 4.2"));
-        assert!(output.contains("homewizard_p1_active_current_l2_amperes 1.3"));
-        assert!(output.contains("homewizard_p1_active_current_l3_amperes 1.8"));
-        assert!(output.contains("homewizard_p1_active_tariff 1"));
+        metrics.set_system_status(&SystemStatus {
+            cloud_enabled: Some(false),
+            ..SystemStatus::default()
+        });
+        let output = metrics.gather().unwrap();
+        assert!(output.contains("homewizard_device_cloud_enabled 0"));
+        assert!(!output.contains("homewizard_device_uptime_seconds"));
+
+        assert!(!output.contains("homewizard_p1_wifi_rssi_dbm"));
+
+        metrics.set_system_status(&SystemStatus {
+            cloud_enabled: Some(true),
+            uptime_secs: Some(356.0),
+            wifi_rssi_dbm: Some(-67.0),
+        });
+        let output = metrics.gather().unwrap();
+        assert!(output.contains("homewizard_device_cloud_enabled 1"));
+        assert!(output.contains("homewizard_device_uptime_seconds 356"));
+        assert!(output.contains("homewizard_p1_wifi_rssi_dbm -67"));
     }
 
     #[test]
-    fn test_metrics_gas_values() {
+    fn test_device_info() {
         let metrics = Metrics::new().unwrap();
+        metrics.set_device_info(&DeviceInfo {
+            product_type: "HWE-P1".to_string(),
+            product_name: "P1 meter".to_string(),
+            serial: "3c39e7aabbcc".to_string(),
+            firmware_version: "4.19".to_string(),
+            api_version: "v1".to_string(),
+        });
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains(
+            r#"homewizard_device_info{api_version="v1",firmware_version="4.19",product_name="P1 meter",product_type="HWE-P1",serial="3c39e7aabbcc"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_metrics_static_labels() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            device: Some("house".to_string()),
+            labels: vec![("site".to_string(), "attic".to_string())],
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        metrics.update(&create_test_data()).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(
+            output.contains(r#"homewizard_p1_power_import_total_kwh{device="house",site="attic"}"#)
+        );
+        assert!(output.contains(
+            r#"homewizard_p1_power_import_tariff_kwh{device="house",site="attic",tariff="1"}"#
+        ));
+    }
+
+    #[test]
+    fn test_metrics_prefix_and_renames() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            names: MetricNames::new("p1_").with_renames(HashMap::from([(
+                "homewizard_exporter_up".to_string(),
+                "p1_up".to_string(),
+            )])),
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        metrics.update(&create_test_data()).unwrap();
+        metrics.record_poll_success();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("\np1_power_import_total_kwh "));
+        assert!(output.contains("\np1_data_age_seconds 0"));
+        assert!(output.contains("\np1_up 1"));
+        assert!(output.contains("homewizard_exporter_poll_success_total 1"));
+        assert!(!output.contains("homewizard_p1_"));
+    }
+
+    #[test]
+    fn test_metrics_cost_only_with_contract() {
         let data = create_test_data();
 
+        let metrics = Metrics::new().unwrap();
+        metrics.update(&data).unwrap();
+        assert!(!metrics.gather().unwrap().contains("homewizard_p1_cost"));
+
+        let metrics = Metrics::with_options(MetricsOptions {
+            contract: Some(Contract {
+                fixed_per_month: 30.0,
+                ..Contract::default()
+            }),
+            ..MetricsOptions::default()
+        })
+        .unwrap();
         metrics.update(&data).unwrap();
         let output = metrics.gather().unwrap();
 
-        assert!(output.contains("homewizard_p1_gas_total_m3 567.89"));
-        assert!(output.contains("homewizard_p1_gas_timestamp 1234567890"));
-        assert!(output.contains("homewizard_p1_gas_meter_info{unique_id=\"aabbccddee112233\"} 1"));
+        assert!(output.contains("homewizard_p1_cost_month_to_date{component=\"electricity\"} 0"));
+        assert!(output.contains("homewizard_p1_cost_projected_month{component=\"fixed\"} 30"));
+        assert!(output.contains("homewizard_p1_energy_cost_eur_total{component=\"import\"} 0"));
     }
 
     #[test]
-    fn test_metrics_wifi_values() {
+    fn test_set_contract_enables_cost_metrics() {
+        let data = create_test_data();
         let metrics = Metrics::new().unwrap();
+        metrics.update(&data).unwrap();
+
+        let contract = Contract {
+            fixed_per_month: 30.0,
+            ..Contract::default()
+        };
+        metrics.set_contract(Some(contract)).unwrap();
+        metrics.update(&data).unwrap();
+        assert!(
+            metrics
+                .gather()
+                .unwrap()
+                .contains("homewizard_p1_cost_projected_month{component=\"fixed\"} 30")
+        );
+
+        // Later reloads only change the prices
+        metrics
+            .set_contract(Some(Contract {
+                fixed_per_month: 40.0,
+                ..contract
+            }))
+            .unwrap();
+        metrics.update(&data).unwrap();
+        assert!(
+            metrics
+                .gather()
+                .unwrap()
+                .contains("homewizard_p1_cost_projected_month{component=\"fixed\"} 40")
+        );
+
+        // Removing the prices removes the cost metrics, until they are back
+        metrics.set_contract(None).unwrap();
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+        assert!(!output.contains("homewizard_p1_cost"));
+        assert!(!output.contains("homewizard_p1_energy_cost"));
+        assert!(metrics.cost_baseline().is_none());
+        metrics.set_contract(Some(contract)).unwrap();
+        metrics.update(&data).unwrap();
+        assert!(
+            metrics
+                .gather()
+                .unwrap()
+                .contains("homewizard_p1_cost_projected_month{component=\"fixed\"} 30")
+        );
+    }
+
+    #[test]
+    fn test_metrics_net_metering() {
         let data = create_test_data();
+        let metrics = Metrics::with_options(MetricsOptions {
+            net_metering: Some(NetMeteringConfig {
+                year_start: (1, 1),
+                start_import_kwh: Some(data.total_power_import_kwh - 100.0),
+                start_export_kwh: Some(data.total_power_export_kwh - 30.0),
+            }),
+            ..MetricsOptions::default()
+        })
+        .unwrap();
 
         metrics.update(&data).unwrap();
         let output = metrics.gather().unwrap();
 
-        assert!(output.contains("homewizard_p1_wifi_strength_percent 75.5"));
+        assert!(output.contains("homewizard_p1_net_metering_balance_kwh 70"));
+        assert!(output.contains("homewizard_p1_net_metering_bankable_export_kwh 70"));
     }
 
     #[test]
-    fn test_metrics_power_quality_values() {
-        let metrics = Metrics::new().unwrap();
-        let data = create_test_data();
+    fn test_metrics_degree_days() {
+        let today = chrono::Local::now().date_naive();
+        let options = DegreeDayOptions {
+            base_temperature: 18.0,
+            ..DegreeDayOptions::default()
+        };
+        options.temperatures.lock().unwrap().insert(today, 4.5);
+        let metrics = Metrics::with_options(MetricsOptions {
+            degree_days: Some(options),
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+
+        metrics.update(&create_test_data()).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(
+            output.contains("homewizard_p1_outdoor_temperature_mean_celsius{day=\"today\"} 4.5")
+        );
+        assert!(output.contains("homewizard_p1_heating_degree_days{day=\"today\"} 13.5"));
+        assert!(!output.contains("day=\"yesterday\""));
+    }
+
+    #[test]
+    fn test_metrics_fuse_utilization() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            fuse: Some(FuseLimit {
+                rating: "3x25A".parse().unwrap(),
+                near_limit_percent: 80.0,
+            }),
+            ..MetricsOptions::default()
+        })
+        .unwrap();
 
+        let data = HomeWizardData {
+            active_current_l1_a: 22.5,
+            active_current_l2_a: 5.0,
+            ..HomeWizardData::default()
+        };
         metrics.update(&data).unwrap();
         let output = metrics.gather().unwrap();
 
-        assert!(output.contains("homewizard_p1_voltage_sag_l1_count_total 2"));
-        assert!(output.contains("homewizard_p1_voltage_sag_l2_count_total 2"));
-        assert!(output.contains("homewizard_p1_voltage_sag_l3_count_total 2"));
-        assert!(output.contains("homewizard_p1_voltage_swell_l1_count_total 1"));
-        assert!(output.contains("homewizard_p1_voltage_swell_l2_count_total 1"));
-        assert!(output.contains("homewizard_p1_voltage_swell_l3_count_total 1"));
-        assert!(output.contains("homewizard_p1_power_failures_any_total 5"));
-        assert!(output.contains("homewizard_p1_power_failures_long_total 0"));
+        assert!(output.contains("homewizard_p1_fuse_utilization_percent{phase=\"l1\"} 90"));
+        assert!(output.contains("homewizard_p1_fuse_near_limit{phase=\"l1\"} 1"));
+        assert!(output.contains("homewizard_p1_fuse_near_limit{phase=\"l2\"} 0"));
+        assert!(output.contains("homewizard_p1_fuse_utilization_percent{phase=\"l3\"} 0"));
+        assert!(output.contains("homewizard_p1_fuse_overload{phase=\"l1\"} 0"));
+
+        metrics.set_fuse_overload("l1", true);
+        let output = metrics.gather().unwrap();
+        assert!(output.contains("homewizard_p1_fuse_overload{phase=\"l1\"} 1"));
     }
 
     #[test]
-    fn test_metrics_meter_info_values() {
+    fn test_metrics_unchanged_polls() {
         let metrics = Metrics::new().unwrap();
-        let data = create_test_data();
 
-        metrics.update(&data).unwrap();
+        metrics.record_unchanged_poll();
+        metrics.record_unchanged_poll();
         let output = metrics.gather().unwrap();
 
-        assert!(output.contains("homewizard_p1_meter_info{meter_id=\"3c39e7aabbccddee\",meter_model=\"ISKRA 2M550T-1012\",smr_version=\"50\",wifi_ssid=\"TestNetwork\"} 1"));
+        assert!(output.contains("homewizard_p1_unchanged_polls_total 2"));
+    }
+
+    #[test]
+    fn test_metrics_external_sensor_names() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            sensor_names: HashMap::from([("sensor123".to_string(), "water_garden".to_string())]),
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        metrics.update(&create_test_data()).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains(r#"homewizard_p1_external_sensor_value{name="water_garden","#));
+        assert!(output.contains(r#"homewizard_p1_external_sensor_timestamp{name="water_garden","#));
+        // Unmapped sensors keep their ID as name
+        assert!(output.contains(r#"homewizard_p1_external_sensor_value{name="sensor456","#));
+    }
+
+    #[test]
+    fn test_metrics_sensor_filter() {
+        let exported = |filter: SensorFilter| {
+            let metrics = Metrics::with_options(MetricsOptions {
+                sensor_filter: filter,
+                ..MetricsOptions::default()
+            })
+            .unwrap();
+            metrics.update(&create_test_data()).unwrap();
+            let output = metrics.gather().unwrap();
+            ["sensor123", "sensor456"].map(|id| output.contains(&format!("unique_id=\"{id}\"")))
+        };
+
+        assert_eq!(exported(SensorFilter::default()), [true, true]);
+        let include_type = SensorFilter {
+            include: vec!["water_meter".to_string()],
+            exclude: vec![],
+        };
+        assert_eq!(exported(include_type), [false, true]);
+        let exclude_id = SensorFilter {
+            include: vec![],
+            exclude: vec!["sensor456".to_string()],
+        };
+        assert_eq!(exported(exclude_id), [true, false]);
     }
 
     #[test]
@@ -641,6 +3140,118 @@ mod tests {
         assert!(output.contains("1234567890"));
     }
 
+    #[test]
+    fn test_metrics_district_heating() {
+        let metrics = Metrics::new().unwrap();
+        let mut data = create_test_data();
+        data.external.push(ExternalSensor {
+            unique_id: "heat123".to_string(),
+            sensor_type: "heat_meter".to_string(),
+            timestamp: 1234567890,
+            value: 42.125,
+            unit: "GJ".to_string(),
+        });
+        data.external.push(ExternalSensor {
+            unique_id: "warm456".to_string(),
+            sensor_type: "warm_water_meter".to_string(),
+            timestamp: 1234567890,
+            value: 18.25,
+            unit: "m3".to_string(),
+        });
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(
+            output.contains("homewizard_p1_heat_energy_total_gj{unique_id=\"heat123\"} 42.125")
+        );
+        assert!(output.contains("homewizard_p1_warm_water_total_m3{unique_id=\"warm456\"} 18.25"));
+        // The generic gauge keeps reporting the raw reading
+        assert!(output.contains("homewizard_p1_external_sensor_value{name=\"heat123\",type=\"heat_meter\",unique_id=\"heat123\",unit=\"GJ\"} 42.125"));
+    }
+
+    #[test]
+    fn test_metrics_water_volume_mode() {
+        let metrics = Metrics::new().unwrap();
+        let data = create_test_data();
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_water_total_m3{unique_id=\"sensor456\"} 123.456"));
+        assert!(!output.contains("homewizard_p1_water_flow_lpm"));
+    }
+
+    #[test]
+    fn test_metrics_water_flow_mode() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            water_mode: WaterMode::Flow,
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        let data = create_test_data();
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_p1_water_flow_lpm{unique_id=\"sensor456\"} 0"));
+        assert!(!output.contains("homewizard_p1_water_total_m3"));
+    }
+
+    #[test]
+    fn test_water_tracker_derives_flow_from_volume() {
+        let mut tracker = WaterTracker::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            tracker.observe("water", WaterReading::Volume(10.0), start),
+            (10.0, 0.0)
+        );
+        // 15 liters in 2 minutes
+        let (volume, flow) = tracker.observe(
+            "water",
+            WaterReading::Volume(10.015),
+            start + Duration::from_secs(120),
+        );
+        assert_eq!(volume, 10.015);
+        assert!((flow - 7.5).abs() < 1e-9);
+
+        // Unchanged within the previous gap keeps the flow
+        let (_, flow) = tracker.observe(
+            "water",
+            WaterReading::Volume(10.015),
+            start + Duration::from_secs(180),
+        );
+        assert!((flow - 7.5).abs() < 1e-9);
+
+        // Unchanged for longer than the previous gap means no flow
+        let (_, flow) = tracker.observe(
+            "water",
+            WaterReading::Volume(10.015),
+            start + Duration::from_secs(300),
+        );
+        assert_eq!(flow, 0.0);
+    }
+
+    #[test]
+    fn test_water_tracker_integrates_flow_into_volume() {
+        let mut tracker = WaterTracker::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            tracker.observe("water", WaterReading::Flow(6.0), start),
+            (0.0, 6.0)
+        );
+        // 6 L/min for 10 minutes is 60 liters
+        let (volume, flow) = tracker.observe(
+            "water",
+            WaterReading::Flow(0.0),
+            start + Duration::from_secs(600),
+        );
+        assert!((volume - 0.06).abs() < 1e-9);
+        assert_eq!(flow, 0.0);
+    }
+
     #[test]
     fn test_metrics_with_empty_external_sensors() {
         let metrics = Metrics::new().unwrap();
@@ -716,4 +3327,151 @@ mod tests {
         assert!(output.contains("homewizard_p1_power_import_total_kwh 999999.999"));
         assert!(output.contains("homewizard_p1_active_power_watts 99999"));
     }
+
+    #[test]
+    fn test_gather_all_labels_each_device() {
+        let device = |name: &str| {
+            Arc::new(
+                Metrics::with_options(MetricsOptions {
+                    device: Some(name.to_string()),
+                    ..MetricsOptions::default()
+                })
+                .unwrap(),
+            )
+        };
+        let house = device("house");
+        let annex = device("annex");
+        let mut data = create_test_data();
+        house.update(&data).unwrap();
+        data.active_power_w = 250.0;
+        annex.update(&data).unwrap();
+
+        let output = gather_all(&[house, annex]).unwrap();
+
+        assert!(output.contains("homewizard_p1_active_power_watts{device=\"house\"} 1500"));
+        assert!(output.contains("homewizard_p1_active_power_watts{device=\"annex\"} 250"));
+        assert_eq!(
+            output
+                .matches("# TYPE homewizard_p1_active_power_watts gauge")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_watermeter_exports_only_water_family() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            product: ProductType::Watermeter,
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        let data = HomeWizardData {
+            product: ProductType::Watermeter,
+            wifi_strength: 84.0,
+            total_liter_m3: 17.014,
+            active_liter_lpm: 3.5,
+            ..Default::default()
+        };
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_water_total_m3 17.014"));
+        assert!(output.contains("homewizard_water_flow_lpm 3.5"));
+        assert!(output.contains("homewizard_water_wifi_strength_percent 84"));
+        assert!(!output.contains("homewizard_p1_power_import_total_kwh"));
+    }
+
+    #[test]
+    fn test_energy_socket_metrics() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            product: ProductType::EnergySocket,
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        let data = HomeWizardData {
+            product: ProductType::EnergySocket,
+            total_power_import_kwh: 30.511,
+            active_power_w: 543.0,
+            active_voltage_l1_v: 231.5,
+            socket_state: Some(SocketState {
+                power_on: true,
+                switch_lock: false,
+                brightness: 255.0,
+            }),
+            ..Default::default()
+        };
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_socket_power_import_total_kwh 30.511"));
+        assert!(output.contains("homewizard_socket_active_power_watts 543"));
+        assert!(output.contains("homewizard_socket_active_voltage_volts 231.5"));
+        assert!(output.contains("homewizard_socket_power_on 1"));
+        assert!(output.contains("homewizard_socket_switch_lock 0"));
+        assert!(output.contains("homewizard_socket_brightness 255"));
+        assert!(!output.contains("homewizard_p1_active_power_watts"));
+    }
+
+    #[test]
+    fn test_kwh_meter_metrics() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            product: ProductType::KwhMeter,
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        let data = HomeWizardData {
+            product: ProductType::KwhMeter,
+            total_power_import_kwh: 12.5,
+            active_power_w: 450.0,
+            active_power_l1_w: 450.0,
+            active_voltage_l1_v: 229.8,
+            ..Default::default()
+        };
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_kwh_power_import_total_kwh 12.5"));
+        assert!(output.contains("homewizard_kwh_active_power_watts 450"));
+        assert!(output.contains(r#"homewizard_kwh_phase_voltage_volts{phase="l1"} 229.8"#));
+        assert!(!output.contains(r#"phase="l2""#));
+        assert!(!output.contains("homewizard_p1_active_power_watts"));
+
+        let data = HomeWizardData {
+            active_voltage_l2_v: 231.0,
+            active_voltage_l3_v: 230.0,
+            ..data
+        };
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+        assert!(output.contains(r#"homewizard_kwh_phase_voltage_volts{phase="l3"} 230"#));
+    }
+
+    #[test]
+    fn test_plugin_battery_metrics() {
+        let metrics = Metrics::with_options(MetricsOptions {
+            product: ProductType::PluginBattery,
+            ..MetricsOptions::default()
+        })
+        .unwrap();
+        let data = HomeWizardData {
+            product: ProductType::PluginBattery,
+            total_power_import_kwh: 123.456,
+            active_power_w: -404.0,
+            state_of_charge_pct: 42.0,
+            battery_cycles: 17.0,
+            ..Default::default()
+        };
+
+        metrics.update(&data).unwrap();
+        let output = metrics.gather().unwrap();
+
+        assert!(output.contains("homewizard_battery_state_of_charge_percent 42"));
+        assert!(output.contains("homewizard_battery_power_watts -404"));
+        assert!(output.contains("homewizard_battery_energy_import_total_kwh 123.456"));
+        assert!(output.contains("homewizard_battery_cycles_total 17"));
+        assert!(!output.contains("homewizard_p1_active_power_watts"));
+    }
 }
@@ -0,0 +1,472 @@
+//! MQTT sink (`--mqtt-host`): every reading is published field by field to
+//! `<topic prefix>/<device>/<field>`, and Home Assistant MQTT discovery
+//! config announces the main fields as sensors. With `--wal-dir`, readings
+//! published while the broker is unreachable are kept and replayed on
+//! reconnect.
+
+use anyhow::{Result, ensure};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::homewizard::HomeWizardData;
+use crate::wal::Wal;
+
+/// A field announced to Home Assistant.
+struct Sensor {
+    field: &'static str,
+    name: &'static str,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    state_class: &'static str,
+}
+
+const SENSORS: &[Sensor] = &[
+    Sensor {
+        field: "active_power_w",
+        name: "Power",
+        unit: Some("W"),
+        device_class: Some("power"),
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "active_power_l1_w",
+        name: "Power L1",
+        unit: Some("W"),
+        device_class: Some("power"),
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "active_power_l2_w",
+        name: "Power L2",
+        unit: Some("W"),
+        device_class: Some("power"),
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "active_power_l3_w",
+        name: "Power L3",
+        unit: Some("W"),
+        device_class: Some("power"),
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "active_voltage_l1_v",
+        name: "Voltage L1",
+        unit: Some("V"),
+        device_class: Some("voltage"),
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "active_voltage_l2_v",
+        name: "Voltage L2",
+        unit: Some("V"),
+        device_class: Some("voltage"),
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "active_voltage_l3_v",
+        name: "Voltage L3",
+        unit: Some("V"),
+        device_class: Some("voltage"),
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "active_current_l1_a",
+        name: "Current L1",
+        unit: Some("A"),
+        device_class: Some("current"),
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "active_current_l2_a",
+        name: "Current L2",
+        unit: Some("A"),
+        device_class: Some("current"),
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "active_current_l3_a",
+        name: "Current L3",
+        unit: Some("A"),
+        device_class: Some("current"),
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "total_power_import_kwh",
+        name: "Energy import",
+        unit: Some("kWh"),
+        device_class: Some("energy"),
+        state_class: "total_increasing",
+    },
+    Sensor {
+        field: "total_power_export_kwh",
+        name: "Energy export",
+        unit: Some("kWh"),
+        device_class: Some("energy"),
+        state_class: "total_increasing",
+    },
+    Sensor {
+        field: "total_gas_m3",
+        name: "Gas",
+        unit: Some("m³"),
+        device_class: Some("gas"),
+        state_class: "total_increasing",
+    },
+    Sensor {
+        field: "active_tariff",
+        name: "Tariff",
+        unit: None,
+        device_class: None,
+        state_class: "measurement",
+    },
+    Sensor {
+        field: "wifi_strength",
+        name: "Wi-Fi strength",
+        unit: Some("%"),
+        device_class: None,
+        state_class: "measurement",
+    },
+];
+
+#[derive(Debug, Clone)]
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<(String, String)>,
+    pub topic_prefix: String,
+    /// Home Assistant discovery prefix; `None` skips discovery
+    pub discovery_prefix: Option<String>,
+}
+
+/// Publishes readings; clones share one connection.
+#[derive(Clone)]
+pub struct MqttPublisher {
+    client: AsyncClient,
+    topic_prefix: String,
+    discovery_prefix: Option<String>,
+    /// Devices whose discovery config has been published
+    announced: Arc<Mutex<HashSet<String>>>,
+    /// Whether the broker acknowledged the current connection
+    connected: Arc<AtomicBool>,
+    /// Keeps the readings published while disconnected
+    wal: Option<Arc<Wal>>,
+}
+
+impl MqttPublisher {
+    /// Connects in the background, reconnecting as needed. The returned
+    /// event loop must be driven with [`MqttPublisher::run`].
+    pub fn new(settings: MqttSettings) -> (Self, EventLoop) {
+        let mut options = MqttOptions::new(
+            format!("homewizard-p1-exporter-{}", std::process::id()),
+            settings.host,
+            settings.port,
+        );
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some((username, password)) = settings.credentials {
+            options.set_credentials(username, password);
+        }
+        let status = status_topic(&settings.topic_prefix);
+        options.set_last_will(LastWill::new(status, "offline", QoS::AtLeastOnce, true));
+
+        let (client, event_loop) = AsyncClient::new(options, 256);
+        let publisher = Self {
+            client,
+            topic_prefix: settings.topic_prefix,
+            discovery_prefix: settings.discovery_prefix,
+            announced: Arc::default(),
+            connected: Arc::default(),
+            wal: None,
+        };
+        (publisher, event_loop)
+    }
+
+    pub fn wal(mut self, wal: Arc<Wal>) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    /// Drives the connection, marking the exporter online and replaying
+    /// buffered readings on every connect.
+    pub async fn run(self, mut event_loop: EventLoop) {
+        loop {
+            match event_loop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to the MQTT broker");
+                    self.connected.store(true, Ordering::Relaxed);
+                    let status = status_topic(&self.topic_prefix);
+                    if let Err(e) =
+                        self.client
+                            .try_publish(status, QoS::AtLeastOnce, true, "online")
+                    {
+                        warn!("Failed to publish MQTT status: {}", e);
+                    }
+                    if let Some(wal) = self.wal.clone() {
+                        let publisher = self.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = wal.replay(|record| publisher.send(record)).await {
+                                warn!("Failed to replay buffered MQTT readings: {}", e);
+                            }
+                        });
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.connected.store(false, Ordering::Relaxed);
+                    warn!("MQTT connection failed: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+
+    /// Publishes every field of `data`, announcing the device to Home
+    /// Assistant first if it has not been yet. Never waits for the broker:
+    /// messages that do not fit in the queue are dropped, unless
+    /// `--wal-dir` keeps them.
+    pub fn publish(&self, device: &str, data: &HomeWizardData) -> Result<()> {
+        let node = node_id(device);
+        let first = self
+            .announced
+            .lock()
+            .map(|mut announced| announced.insert(node.clone()))
+            .unwrap_or(false);
+        if first && let Some(discovery_prefix) = &self.discovery_prefix {
+            for (topic, config) in
+                discovery(discovery_prefix, &self.topic_prefix, &node, device, data)
+            {
+                self.client.try_publish(
+                    topic,
+                    QoS::AtLeastOnce,
+                    true,
+                    serde_json::to_vec(&config)?,
+                )?;
+            }
+            debug!("[{}] Published Home Assistant discovery config", device);
+        }
+
+        let messages: Vec<(String, String)> = fields(data)?
+            .into_iter()
+            .map(|(field, value)| (format!("{}/{}/{}", self.topic_prefix, node, field), value))
+            .collect();
+        let Some(wal) = self.wal.clone() else {
+            return self.publish_messages(messages);
+        };
+        let record = serde_json::to_vec(&messages)?;
+        let publisher = self.clone();
+        let device = device.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = wal.deliver(record, |record| publisher.send(record)).await {
+                debug!("[{}] Buffered MQTT reading: {}", device, e);
+            }
+        });
+        Ok(())
+    }
+
+    fn publish_messages(&self, messages: Vec<(String, String)>) -> Result<()> {
+        for (topic, value) in messages {
+            self.client
+                .try_publish(topic, QoS::AtMostOnce, false, value)?;
+        }
+        Ok(())
+    }
+
+    /// Publishes a buffered record, failing while the broker is away so
+    /// that it stays buffered.
+    async fn send(&self, record: Vec<u8>) -> Result<()> {
+        ensure!(
+            self.connected.load(Ordering::Relaxed),
+            "not connected to the MQTT broker"
+        );
+        self.publish_messages(serde_json::from_slice(&record)?)
+    }
+}
+
+fn status_topic(topic_prefix: &str) -> String {
+    format!("{topic_prefix}/status")
+}
+
+/// Device names as topic levels and Home Assistant object ids.
+fn node_id(device: &str) -> String {
+    device
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The scalar fields of a reading and their payloads.
+fn fields(data: &HomeWizardData) -> Result<Vec<(String, String)>> {
+    let Value::Object(object) = serde_json::to_value(data)? else {
+        return Ok(Vec::new());
+    };
+    Ok(object
+        .into_iter()
+        .filter_map(|(field, value)| match value {
+            Value::String(text) => Some((field, text)),
+            Value::Number(number) => Some((field, number.to_string())),
+            Value::Bool(flag) => Some((field, flag.to_string())),
+            _ => None,
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+struct DiscoveryConfig<'a> {
+    name: &'a str,
+    unique_id: String,
+    object_id: String,
+    state_topic: String,
+    availability_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'a str>,
+    state_class: &'a str,
+    device: DiscoveryDevice<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct DiscoveryDevice<'a> {
+    identifiers: Vec<String>,
+    name: String,
+    manufacturer: &'static str,
+    model: &'a str,
+}
+
+fn discovery<'a>(
+    discovery_prefix: &str,
+    topic_prefix: &str,
+    node: &str,
+    device: &str,
+    data: &'a HomeWizardData,
+) -> Vec<(String, DiscoveryConfig<'a>)> {
+    // The meter's own id survives renaming the device in the exporter
+    let id = if data.unique_id.is_empty() {
+        node.to_string()
+    } else {
+        data.unique_id.clone()
+    };
+    SENSORS
+        .iter()
+        .map(|sensor| {
+            let field = sensor.field;
+            let topic = format!("{discovery_prefix}/sensor/{node}/{field}/config");
+            let config = DiscoveryConfig {
+                name: sensor.name,
+                unique_id: format!("homewizard_p1_{id}_{field}"),
+                object_id: format!("homewizard_{node}_{field}"),
+                state_topic: format!("{topic_prefix}/{node}/{field}"),
+                availability_topic: status_topic(topic_prefix),
+                unit_of_measurement: sensor.unit,
+                device_class: sensor.device_class,
+                state_class: sensor.state_class,
+                device: DiscoveryDevice {
+                    identifiers: vec![format!("homewizard_p1_{id}")],
+                    name: format!("HomeWizard P1 {device}"),
+                    manufacturer: "HomeWizard",
+                    model: &data.meter_model,
+                },
+            };
+            (topic, config)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields() {
+        let data = HomeWizardData {
+            active_power_w: 250.5,
+            active_tariff: 2,
+            unique_id: "0011".to_string(),
+            ..HomeWizardData::default()
+        };
+        let fields = fields(&data).unwrap();
+        assert!(fields.contains(&("active_power_w".to_string(), "250.5".to_string())));
+        assert!(fields.contains(&("active_tariff".to_string(), "2".to_string())));
+        assert!(fields.contains(&("unique_id".to_string(), "0011".to_string())));
+    }
+
+    #[test]
+    fn test_discovery_config() {
+        let data = HomeWizardData {
+            unique_id: "0011".to_string(),
+            meter_model: "ISKRA".to_string(),
+            ..HomeWizardData::default()
+        };
+        let configs = discovery("homeassistant", "homewizard", "house", "house", &data);
+        assert_eq!(configs.len(), SENSORS.len());
+
+        let (topic, config) = &configs[0];
+        assert_eq!(topic, "homeassistant/sensor/house/active_power_w/config");
+        let json = serde_json::to_value(config).unwrap();
+        assert_eq!(json["state_topic"], "homewizard/house/active_power_w");
+        assert_eq!(json["availability_topic"], "homewizard/status");
+        assert_eq!(json["unique_id"], "homewizard_p1_0011_active_power_w");
+        assert_eq!(json["unit_of_measurement"], "W");
+        assert_eq!(json["device"]["identifiers"][0], "homewizard_p1_0011");
+
+        let (_, tariff) = configs
+            .iter()
+            .find(|(topic, _)| topic.contains("active_tariff"))
+            .unwrap();
+        assert!(serde_json::to_value(tariff).unwrap()["unit_of_measurement"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_buffers_readings_while_disconnected() {
+        let dir = std::env::temp_dir().join(format!("homewizard-mqtt-wal-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal = Arc::new(Wal::open(&dir, "mqtt", 1 << 20, Duration::from_secs(3600)).unwrap());
+        let (publisher, mut event_loop) = MqttPublisher::new(MqttSettings {
+            host: "localhost".to_string(),
+            port: 1883,
+            credentials: None,
+            topic_prefix: "homewizard".to_string(),
+            discovery_prefix: None,
+        });
+        let publisher = publisher.wal(wal.clone());
+        let record = serde_json::to_vec(&[("homewizard/house/active_power_w", "250")]).unwrap();
+
+        assert!(
+            wal.deliver(record, |record| publisher.send(record))
+                .await
+                .is_err()
+        );
+        event_loop.clean();
+        assert!(event_loop.pending.is_empty());
+
+        publisher.connected.store(true, Ordering::Relaxed);
+        wal.replay(|record| publisher.send(record)).await.unwrap();
+        event_loop.clean();
+        let topics: Vec<String> = event_loop
+            .pending
+            .iter()
+            .filter_map(|request| match request {
+                rumqttc::Request::Publish(publish) => Some(publish.topic.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(topics, ["homewizard/house/active_power_w"]);
+    }
+
+    #[test]
+    fn test_node_id() {
+        assert_eq!(node_id("192.168.1.10"), "192_168_1_10");
+        assert_eq!(node_id("house-p1"), "house-p1");
+    }
+}
@@ -0,0 +1,207 @@
+//! Yearly import/export balance for net metering (saldering).
+
+use anyhow::{Context, Result, bail};
+use chrono::{Datelike, NaiveDate};
+
+use crate::homewizard::HomeWizardData;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetMeteringConfig {
+    /// Month and day the contract year starts on
+    pub year_start: (u32, u32),
+    /// Meter totals at the start of the current contract year, as printed on
+    /// the annual bill. Without them counting starts at the first reading
+    pub start_import_kwh: Option<f64>,
+    pub start_export_kwh: Option<f64>,
+}
+
+/// Parses a `MM-DD` contract year start.
+pub fn parse_year_start(value: &str) -> Result<(u32, u32)> {
+    let (month, day) = value
+        .split_once('-')
+        .with_context(|| format!("Invalid contract year start {value:?}, expected MM-DD"))?;
+    let month: u32 = month
+        .parse()
+        .with_context(|| format!("Invalid month in {value:?}"))?;
+    let day: u32 = day
+        .parse()
+        .with_context(|| format!("Invalid day in {value:?}"))?;
+    // A leap year, so 02-29 is accepted; it falls back to 02-28 elsewhere.
+    if NaiveDate::from_ymd_opt(2024, month, day).is_none() {
+        bail!("Invalid contract year start {value:?}");
+    }
+    Ok((month, day))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NetMeteringBalance {
+    pub import_kwh: f64,
+    pub export_kwh: f64,
+}
+
+impl NetMeteringBalance {
+    /// Import minus export; positive when more was consumed than produced.
+    pub fn net_kwh(&self) -> f64 {
+        self.import_kwh - self.export_kwh
+    }
+
+    /// Export that can still be netted against this year's import.
+    pub fn bankable_export_kwh(&self) -> f64 {
+        self.net_kwh().max(0.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct YearStart {
+    date: NaiveDate,
+    import_kwh: f64,
+    export_kwh: f64,
+}
+
+#[derive(Debug)]
+pub struct NetMeteringTracker {
+    config: NetMeteringConfig,
+    start: Option<YearStart>,
+}
+
+impl NetMeteringTracker {
+    pub fn new(config: NetMeteringConfig) -> Self {
+        Self {
+            config,
+            start: None,
+        }
+    }
+
+    pub fn update(&mut self, data: &HomeWizardData, today: NaiveDate) -> NetMeteringBalance {
+        let year_start = self.year_start_for(today);
+
+        let start = match self.start {
+            Some(start)
+                if start.date == year_start
+                    && data.total_power_import_kwh >= start.import_kwh
+                    && data.total_power_export_kwh >= start.export_kwh =>
+            {
+                start
+            }
+            previous => {
+                // The configured readings belong to the contract year in
+                // progress at startup; later years start from the meter.
+                let configured = previous.is_none();
+                let start = YearStart {
+                    date: year_start,
+                    import_kwh: self
+                        .config
+                        .start_import_kwh
+                        .filter(|_| configured)
+                        .unwrap_or(data.total_power_import_kwh),
+                    export_kwh: self
+                        .config
+                        .start_export_kwh
+                        .filter(|_| configured)
+                        .unwrap_or(data.total_power_export_kwh),
+                };
+                self.start = Some(start);
+                start
+            }
+        };
+
+        NetMeteringBalance {
+            import_kwh: data.total_power_import_kwh - start.import_kwh,
+            export_kwh: data.total_power_export_kwh - start.export_kwh,
+        }
+    }
+
+    /// The most recent contract year start on or before `today`.
+    fn year_start_for(&self, today: NaiveDate) -> NaiveDate {
+        let (month, day) = self.config.year_start;
+        let start_in = |year: i32| {
+            NaiveDate::from_ymd_opt(year, month, day)
+                .or_else(|| NaiveDate::from_ymd_opt(year, month, day - 1))
+                .unwrap_or_default()
+        };
+        let this_year = start_in(today.year());
+        if this_year <= today {
+            this_year
+        } else {
+            start_in(today.year() - 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn reading(import_kwh: f64, export_kwh: f64) -> HomeWizardData {
+        HomeWizardData {
+            total_power_import_kwh: import_kwh,
+            total_power_export_kwh: export_kwh,
+            ..HomeWizardData::default()
+        }
+    }
+
+    fn config(start_import_kwh: Option<f64>, start_export_kwh: Option<f64>) -> NetMeteringConfig {
+        NetMeteringConfig {
+            year_start: (3, 1),
+            start_import_kwh,
+            start_export_kwh,
+        }
+    }
+
+    #[test]
+    fn test_parse_year_start() {
+        assert_eq!(parse_year_start("03-01").unwrap(), (3, 1));
+        assert_eq!(parse_year_start("02-29").unwrap(), (2, 29));
+        assert!(parse_year_start("13-01").is_err());
+        assert!(parse_year_start("0301").is_err());
+    }
+
+    #[test]
+    fn test_balance_from_configured_readings() {
+        let mut tracker = NetMeteringTracker::new(config(Some(1000.0), Some(400.0)));
+
+        let balance = tracker.update(&reading(3000.0, 3500.0), date(2026, 7, 1));
+
+        assert_eq!(balance.import_kwh, 2000.0);
+        assert_eq!(balance.export_kwh, 3100.0);
+        assert_eq!(balance.net_kwh(), -1100.0);
+        assert_eq!(balance.bankable_export_kwh(), 0.0);
+    }
+
+    #[test]
+    fn test_balance_without_configured_readings() {
+        let mut tracker = NetMeteringTracker::new(config(None, None));
+
+        tracker.update(&reading(3000.0, 1000.0), date(2026, 7, 1));
+        let balance = tracker.update(&reading(3100.0, 1040.0), date(2026, 7, 2));
+
+        assert_eq!(balance.net_kwh(), 60.0);
+        assert_eq!(balance.bankable_export_kwh(), 60.0);
+    }
+
+    #[test]
+    fn test_new_contract_year_starts_from_meter() {
+        let mut tracker = NetMeteringTracker::new(config(Some(1000.0), Some(400.0)));
+
+        tracker.update(&reading(3000.0, 3500.0), date(2027, 2, 28));
+        let balance = tracker.update(&reading(3010.0, 3500.0), date(2027, 3, 1));
+
+        assert_eq!(balance.import_kwh, 0.0);
+        assert_eq!(balance.export_kwh, 0.0);
+    }
+
+    #[test]
+    fn test_leap_day_start_in_common_year() {
+        let tracker = NetMeteringTracker::new(NetMeteringConfig {
+            year_start: (2, 29),
+            ..config(None, None)
+        });
+
+        assert_eq!(tracker.year_start_for(date(2027, 3, 1)), date(2027, 2, 28));
+        assert_eq!(tracker.year_start_for(date(2027, 1, 1)), date(2026, 2, 28));
+    }
+}
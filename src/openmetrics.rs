@@ -0,0 +1,230 @@
+//! OpenMetrics text exposition, served from `/metrics` to scrapers that ask
+//! for `application/openmetrics-text`.
+//!
+//! Differences from the classic text format: counter families are named
+//! without `_total` while their samples always carry it, untyped families
+//! are `unknown`, counters and histograms may carry a `_created` sample, and
+//! the exposition ends with `# EOF`.
+
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+use std::fmt::Write;
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Whether an `Accept` header prefers OpenMetrics over the classic format.
+pub fn accepts(accept: &str) -> bool {
+    accept.split(',').any(|media_type| {
+        media_type
+            .trim()
+            .starts_with("application/openmetrics-text")
+    })
+}
+
+/// Encodes `families`. `created` gives the Unix time a family started
+/// counting, for counters and histograms where it is known.
+pub fn encode(families: &[MetricFamily], created: impl Fn(&str) -> Option<f64>) -> String {
+    let mut output = String::new();
+    for family in families {
+        let name = family.name();
+        let field_type = family.get_field_type();
+        let family_name = match field_type {
+            MetricType::COUNTER => name.strip_suffix("_total").unwrap_or(name),
+            _ => name,
+        };
+        let type_name = match field_type {
+            MetricType::COUNTER => "counter",
+            MetricType::GAUGE => "gauge",
+            MetricType::HISTOGRAM => "histogram",
+            MetricType::SUMMARY => "summary",
+            MetricType::UNTYPED => "unknown",
+        };
+        let created = created(name);
+
+        let _ = writeln!(output, "# TYPE {family_name} {type_name}");
+        if !family.help().is_empty() {
+            let _ = writeln!(output, "# HELP {family_name} {}", escape(family.help()));
+        }
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+            match field_type {
+                MetricType::COUNTER => {
+                    sample(
+                        &mut output,
+                        family_name,
+                        "_total",
+                        labels,
+                        None,
+                        metric.get_counter().value(),
+                    );
+                    if let Some(created) = created {
+                        sample(&mut output, family_name, "_created", labels, None, created);
+                    }
+                }
+                MetricType::GAUGE => sample(
+                    &mut output,
+                    name,
+                    "",
+                    labels,
+                    None,
+                    metric.get_gauge().value(),
+                ),
+                MetricType::UNTYPED => {
+                    sample(&mut output, name, "", labels, None, metric.untyped.value())
+                }
+                MetricType::HISTOGRAM => histogram(&mut output, name, metric, created),
+                MetricType::SUMMARY => summary(&mut output, name, metric),
+            }
+        }
+    }
+    output.push_str("# EOF\n");
+    output
+}
+
+fn histogram(output: &mut String, name: &str, metric: &Metric, created: Option<f64>) {
+    let labels = metric.get_label();
+    let histogram = metric.get_histogram();
+    let mut has_inf = false;
+    for bucket in histogram.get_bucket() {
+        has_inf |= bucket.upper_bound() == f64::INFINITY;
+        let le = format_value(bucket.upper_bound());
+        let count = bucket.cumulative_count() as f64;
+        sample(output, name, "_bucket", labels, Some(("le", &le)), count);
+    }
+    let count = histogram.get_sample_count() as f64;
+    if !has_inf {
+        sample(output, name, "_bucket", labels, Some(("le", "+Inf")), count);
+    }
+    sample(output, name, "_count", labels, None, count);
+    sample(
+        output,
+        name,
+        "_sum",
+        labels,
+        None,
+        histogram.get_sample_sum(),
+    );
+    if let Some(created) = created {
+        sample(output, name, "_created", labels, None, created);
+    }
+}
+
+fn summary(output: &mut String, name: &str, metric: &Metric) {
+    let labels = metric.get_label();
+    let summary = metric.get_summary();
+    for quantile in summary.get_quantile() {
+        let q = format_value(quantile.quantile());
+        sample(
+            output,
+            name,
+            "",
+            labels,
+            Some(("quantile", &q)),
+            quantile.value(),
+        );
+    }
+    sample(
+        output,
+        name,
+        "_count",
+        labels,
+        None,
+        summary.sample_count() as f64,
+    );
+    sample(output, name, "_sum", labels, None, summary.sample_sum());
+}
+
+fn sample(
+    output: &mut String,
+    name: &str,
+    suffix: &str,
+    labels: &[LabelPair],
+    extra: Option<(&str, &str)>,
+    value: f64,
+) {
+    output.push_str(name);
+    output.push_str(suffix);
+    let pairs = labels
+        .iter()
+        .map(|pair| (pair.name(), pair.value()))
+        .chain(extra);
+    for (index, (label, label_value)) in pairs.enumerate() {
+        output.push(if index == 0 { '{' } else { ',' });
+        let _ = write!(output, "{label}=\"{}\"", escape(label_value));
+    }
+    if !labels.is_empty() || extra.is_some() {
+        output.push('}');
+    }
+    let _ = writeln!(output, " {}", format_value(value));
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escapes label values and help text.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Counter, CounterVec, Gauge, Histogram, HistogramOpts, Opts, Registry};
+
+    #[test]
+    fn test_accepts() {
+        assert!(accepts(
+            "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5"
+        ));
+        assert!(!accepts("text/plain;version=0.0.4"));
+        assert!(!accepts("*/*"));
+    }
+
+    #[test]
+    fn test_encode() {
+        let registry = Registry::new();
+        let polls = Counter::with_opts(Opts::new("polls_total", "Polls")).unwrap();
+        let energy =
+            CounterVec::new(Opts::new("energy_kwh", "Energy \"in\""), &["tariff"]).unwrap();
+        let power = Gauge::with_opts(Opts::new("power_watts", "Power")).unwrap();
+        let duration = Histogram::with_opts(
+            HistogramOpts::new("duration_seconds", "Duration").buckets(vec![0.1, 1.0]),
+        )
+        .unwrap();
+        registry.register(Box::new(polls.clone())).unwrap();
+        registry.register(Box::new(energy.clone())).unwrap();
+        registry.register(Box::new(power.clone())).unwrap();
+        registry.register(Box::new(duration.clone())).unwrap();
+        polls.inc_by(3.0);
+        energy.with_label_values(&["1"]).inc_by(12.5);
+        power.set(-250.0);
+        duration.observe(0.05);
+
+        let output = encode(&registry.gather(), |name| {
+            (name == "polls_total" || name == "duration_seconds").then_some(1_790_000_000.0)
+        });
+
+        assert!(output.contains("# TYPE polls counter\n# HELP polls Polls\npolls_total 3\n"));
+        assert!(output.contains("polls_created 1790000000\n"));
+        assert!(output.contains("# TYPE energy_kwh counter\n"));
+        assert!(output.contains("# HELP energy_kwh Energy \\\"in\\\"\n"));
+        assert!(output.contains("energy_kwh_total{tariff=\"1\"} 12.5\n"));
+        assert!(!output.contains("energy_kwh_created"));
+        assert!(output.contains("power_watts -250\n"));
+        assert!(output.contains("duration_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(output.contains("duration_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(output.contains("duration_seconds_count 1\n"));
+        assert!(output.contains("duration_seconds_created 1790000000\n"));
+        assert!(output.ends_with("# EOF\n"));
+    }
+}
@@ -0,0 +1,420 @@
+//! OpenTelemetry export (`--otlp-endpoint`): the metrics served at
+//! `/metrics` are pushed periodically to an OTLP/HTTP receiver such as the
+//! OpenTelemetry Collector, using the JSON encoding.
+//!
+//! Counters become cumulative monotonic sums, gauges and untyped metrics
+//! gauges, and histograms and summaries keep their type. Labels become
+//! data point attributes.
+
+use anyhow::Result;
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use crate::http;
+use crate::metrics::{self, DeviceMetrics};
+
+/// `AGGREGATION_TEMPORALITY_CUMULATIVE`
+const CUMULATIVE: u8 = 2;
+
+/// Pushes the metrics of all devices to an OTLP/HTTP endpoint.
+#[derive(Debug, Clone)]
+pub struct OtlpExporter {
+    client: http::Client,
+    url: String,
+    interval: Duration,
+}
+
+impl OtlpExporter {
+    /// `endpoint` is the receiver's base URL (`http://collector:4318`) or
+    /// its full metrics URL.
+    pub fn new(endpoint: &str, interval: Duration, timeout: Duration) -> Result<Self> {
+        let endpoint = endpoint.trim_end_matches('/');
+        let url = if endpoint.ends_with("/v1/metrics") {
+            endpoint.to_string()
+        } else {
+            format!("{endpoint}/v1/metrics")
+        };
+        Ok(Self {
+            client: http::Client::new(timeout)?,
+            url,
+            interval,
+        })
+    }
+
+    pub async fn run(self, devices: DeviceMetrics) {
+        let started = SystemTime::now();
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await; // Skip the immediate tick, before the first poll
+        loop {
+            ticker.tick().await;
+            let families = metrics::gather_families(&devices.snapshot());
+            let request = encode(&families, started, SystemTime::now());
+            match self.export(&request).await {
+                Ok(()) => debug!("Exported metrics to {}", self.url),
+                Err(e) => warn!("Failed to export metrics to {}: {}", self.url, e),
+            }
+        }
+    }
+
+    async fn export(&self, request: &ExportRequest<'_>) -> Result<(), http::Error> {
+        self.client
+            .post(&self.url)
+            .json(request)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportRequest<'a> {
+    resource_metrics: Vec<ResourceMetrics<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceMetrics<'a> {
+    resource: Resource<'a>,
+    scope_metrics: Vec<ScopeMetrics<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Resource<'a> {
+    attributes: Vec<KeyValue<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ScopeMetrics<'a> {
+    scope: Scope,
+    metrics: Vec<OtlpMetric<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Scope {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct KeyValue<'a> {
+    key: &'a str,
+    value: AnyValue<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AnyValue<'a> {
+    string_value: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct OtlpMetric<'a> {
+    name: &'a str,
+    description: &'a str,
+    #[serde(flatten)]
+    data: Data<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Data<'a> {
+    Gauge {
+        #[serde(rename = "dataPoints")]
+        data_points: Vec<NumberDataPoint<'a>>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Sum {
+        data_points: Vec<NumberDataPoint<'a>>,
+        aggregation_temporality: u8,
+        is_monotonic: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    Histogram {
+        data_points: Vec<HistogramDataPoint<'a>>,
+        aggregation_temporality: u8,
+    },
+    Summary {
+        #[serde(rename = "dataPoints")]
+        data_points: Vec<SummaryDataPoint<'a>>,
+    },
+}
+
+/// 64-bit integers are strings in the JSON encoding.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NumberDataPoint<'a> {
+    attributes: Vec<KeyValue<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_time_unix_nano: Option<String>,
+    time_unix_nano: String,
+    as_double: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HistogramDataPoint<'a> {
+    attributes: Vec<KeyValue<'a>>,
+    start_time_unix_nano: String,
+    time_unix_nano: String,
+    count: String,
+    sum: f64,
+    bucket_counts: Vec<String>,
+    explicit_bounds: Vec<f64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SummaryDataPoint<'a> {
+    attributes: Vec<KeyValue<'a>>,
+    time_unix_nano: String,
+    count: String,
+    sum: f64,
+    quantile_values: Vec<ValueAtQuantile>,
+}
+
+#[derive(Debug, Serialize)]
+struct ValueAtQuantile {
+    quantile: f64,
+    value: f64,
+}
+
+fn encode(families: &[MetricFamily], started: SystemTime, now: SystemTime) -> ExportRequest<'_> {
+    let start = unix_nanos(started);
+    let time = unix_nanos(now);
+    let metrics = families
+        .iter()
+        .map(|family| {
+            let points = family.get_metric();
+            let data = match family.get_field_type() {
+                MetricType::COUNTER => Data::Sum {
+                    data_points: number_points(points, Some(&start), &time, |metric| {
+                        metric.get_counter().value()
+                    }),
+                    aggregation_temporality: CUMULATIVE,
+                    is_monotonic: true,
+                },
+                MetricType::GAUGE => Data::Gauge {
+                    data_points: number_points(points, None, &time, |metric| {
+                        metric.get_gauge().value()
+                    }),
+                },
+                MetricType::UNTYPED => Data::Gauge {
+                    data_points: number_points(points, None, &time, |metric| {
+                        metric.untyped.value()
+                    }),
+                },
+                MetricType::HISTOGRAM => Data::Histogram {
+                    data_points: points
+                        .iter()
+                        .map(|metric| histogram_point(metric, &start, &time))
+                        .collect(),
+                    aggregation_temporality: CUMULATIVE,
+                },
+                MetricType::SUMMARY => Data::Summary {
+                    data_points: points
+                        .iter()
+                        .map(|metric| {
+                            let summary = metric.get_summary();
+                            SummaryDataPoint {
+                                attributes: attributes(metric.get_label()),
+                                time_unix_nano: time.clone(),
+                                count: summary.sample_count().to_string(),
+                                sum: summary.sample_sum(),
+                                quantile_values: summary
+                                    .get_quantile()
+                                    .iter()
+                                    .map(|quantile| ValueAtQuantile {
+                                        quantile: quantile.quantile(),
+                                        value: quantile.value(),
+                                    })
+                                    .collect(),
+                            }
+                        })
+                        .collect(),
+                },
+            };
+            OtlpMetric {
+                name: family.name(),
+                description: family.help(),
+                data,
+            }
+        })
+        .collect();
+
+    ExportRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Resource {
+                attributes: vec![
+                    KeyValue {
+                        key: "service.name",
+                        value: AnyValue {
+                            string_value: env!("CARGO_PKG_NAME"),
+                        },
+                    },
+                    KeyValue {
+                        key: "service.version",
+                        value: AnyValue {
+                            string_value: env!("CARGO_PKG_VERSION"),
+                        },
+                    },
+                ],
+            },
+            scope_metrics: vec![ScopeMetrics {
+                scope: Scope {
+                    name: env!("CARGO_PKG_NAME"),
+                    version: env!("CARGO_PKG_VERSION"),
+                },
+                metrics,
+            }],
+        }],
+    }
+}
+
+fn number_points<'a>(
+    points: &'a [Metric],
+    start: Option<&str>,
+    time: &str,
+    value: impl Fn(&Metric) -> f64,
+) -> Vec<NumberDataPoint<'a>> {
+    points
+        .iter()
+        .map(|metric| NumberDataPoint {
+            attributes: attributes(metric.get_label()),
+            start_time_unix_nano: start.map(str::to_string),
+            time_unix_nano: time.to_string(),
+            as_double: value(metric),
+        })
+        .collect()
+}
+
+/// Prometheus buckets count cumulatively, OTLP buckets each count their own
+/// range, with a final bucket above the last bound.
+fn histogram_point<'a>(metric: &'a Metric, start: &str, time: &str) -> HistogramDataPoint<'a> {
+    let histogram = metric.get_histogram();
+    let mut explicit_bounds = Vec::new();
+    let mut bucket_counts = Vec::new();
+    let mut below = 0;
+    for bucket in histogram.get_bucket() {
+        if bucket.upper_bound() == f64::INFINITY {
+            break;
+        }
+        explicit_bounds.push(bucket.upper_bound());
+        bucket_counts.push((bucket.cumulative_count() - below).to_string());
+        below = bucket.cumulative_count();
+    }
+    let count = histogram.get_sample_count();
+    bucket_counts.push((count - below).to_string());
+
+    HistogramDataPoint {
+        attributes: attributes(metric.get_label()),
+        start_time_unix_nano: start.to_string(),
+        time_unix_nano: time.to_string(),
+        count: count.to_string(),
+        sum: histogram.get_sample_sum(),
+        bucket_counts,
+        explicit_bounds,
+    }
+}
+
+fn attributes(labels: &[LabelPair]) -> Vec<KeyValue<'_>> {
+    labels
+        .iter()
+        .map(|label| KeyValue {
+            key: label.name(),
+            value: AnyValue {
+                string_value: label.value(),
+            },
+        })
+        .collect()
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{CounterVec, Gauge, Histogram, HistogramOpts, Opts, Registry};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let energy = CounterVec::new(Opts::new("energy_kwh", "Energy"), &["tariff"]).unwrap();
+        let power = Gauge::with_opts(Opts::new("power_watts", "Power")).unwrap();
+        let duration = Histogram::with_opts(
+            HistogramOpts::new("duration_seconds", "Duration").buckets(vec![0.1, 1.0]),
+        )
+        .unwrap();
+        registry.register(Box::new(energy.clone())).unwrap();
+        registry.register(Box::new(power.clone())).unwrap();
+        registry.register(Box::new(duration.clone())).unwrap();
+        energy.with_label_values(&["1"]).inc_by(12.5);
+        power.set(-250.0);
+        for seconds in [0.05, 0.5, 0.7, 3.0] {
+            duration.observe(seconds);
+        }
+        registry.gather()
+    }
+
+    #[test]
+    fn test_encode() {
+        let families = families();
+        let started = UNIX_EPOCH + Duration::from_secs(1_790_000_000);
+        let request = encode(&families, started, started + Duration::from_secs(60));
+        let json = serde_json::to_value(&request).unwrap();
+        let metrics = &json["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+
+        let duration = &metrics[0];
+        assert_eq!(duration["name"], "duration_seconds");
+        let point = &duration["histogram"]["dataPoints"][0];
+        assert_eq!(point["count"], "4");
+        assert_eq!(point["explicitBounds"], serde_json::json!([0.1, 1.0]));
+        assert_eq!(point["bucketCounts"], serde_json::json!(["1", "2", "1"]));
+
+        let energy = &metrics[1];
+        assert_eq!(energy["sum"]["isMonotonic"], true);
+        assert_eq!(energy["sum"]["aggregationTemporality"], 2);
+        let point = &energy["sum"]["dataPoints"][0];
+        assert_eq!(point["asDouble"], 12.5);
+        assert_eq!(point["startTimeUnixNano"], "1790000000000000000");
+        assert_eq!(point["timeUnixNano"], "1790000060000000000");
+        assert_eq!(point["attributes"][0]["key"], "tariff");
+        assert_eq!(point["attributes"][0]["value"]["stringValue"], "1");
+
+        let power = &metrics[2];
+        assert_eq!(power["gauge"]["dataPoints"][0]["asDouble"], -250.0);
+        assert!(power["gauge"]["dataPoints"][0]["startTimeUnixNano"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_export_posts_json() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/metrics"))
+            .and(header("content-type", "application/json"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let exporter = OtlpExporter::new(
+            &format!("{}/", mock_server.uri()),
+            Duration::from_secs(60),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let families = families();
+        let request = encode(&families, SystemTime::now(), SystemTime::now());
+        exporter.export(&request).await.unwrap();
+    }
+}
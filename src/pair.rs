@@ -0,0 +1,166 @@
+//! `pair` subcommand: creates an API v2 token. The device only hands one
+//! out within 30 seconds of its button being pressed, so the request is
+//! repeated until then.
+
+use anyhow::{Context, Result, bail, ensure};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config::{self, Config};
+use crate::homewizard::HomeWizardError;
+use crate::http;
+
+/// Time between attempts while waiting for the button.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize)]
+struct UserResponse {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// Pairs with the single `--host`, printing the token or, with `save`,
+/// storing it in `--config-file`. Creating a token changes the device, so
+/// this needs `--read-only false`.
+pub async fn run(config: &Config, name: &str, timeout: Duration, save: bool) -> Result<()> {
+    if config.read_only {
+        return Err(HomeWizardError::ReadOnly("create an API token").into());
+    }
+    let devices = config.devices()?;
+    ensure!(devices.len() == 1, "pair one device at a time");
+    let config_file = match (save, &config.config_file) {
+        (true, Some(path)) => Some(path),
+        (true, None) => bail!("--save requires --config-file"),
+        (false, _) => None,
+    };
+
+    let client = http::Client::for_device(config.http_timeout_duration())?;
+    let url = format!("https://{}/api/user", devices[0].host);
+    eprintln!(
+        "Press the button on {} within {}s...",
+        devices[0].host,
+        timeout.as_secs()
+    );
+    let token = request_token(&client, &url, name, timeout).await?;
+
+    match config_file {
+        Some(path) => {
+            config::save_setting(path, "HOMEWIZARD_API_TOKEN", &token)?;
+            eprintln!("Saved the token to {}", path.display());
+        }
+        None => println!("{token}"),
+    }
+    Ok(())
+}
+
+/// Asks for a token for user `local/<name>` until the button is pressed
+/// or `timeout` passes.
+async fn request_token(
+    client: &http::Client,
+    url: &str,
+    name: &str,
+    timeout: Duration,
+) -> Result<String> {
+    let deadline = Instant::now() + timeout;
+    let body = serde_json::json!({ "name": format!("local/{name}") });
+    loop {
+        let response = client
+            .post(url)
+            .header("x-api-version", "2")
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {url}"))?;
+        let status = response.status();
+        let text = response.text();
+        if status.is_success() {
+            let user: UserResponse =
+                serde_json::from_str(&text).context("Unexpected response from the device")?;
+            return Ok(user.token);
+        }
+
+        let error = serde_json::from_str::<ErrorResponse>(&text)
+            .map(|e| e.error)
+            .unwrap_or(text);
+        if error != "user:press-button" {
+            bail!("The device refused to pair: {} ({})", error, status);
+        }
+        if Instant::now() + RETRY_INTERVAL > deadline {
+            bail!("The button was not pressed within {}s", timeout.as_secs());
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_request_token_waits_for_button() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/user"))
+            .and(header("x-api-version", "2"))
+            .and(body_json(serde_json::json!({ "name": "local/exporter" })))
+            .respond_with(
+                ResponseTemplate::new(403).set_body_string(r#"{"error": "user:press-button"}"#),
+            )
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/user"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"token": "2D6B6E6C6D6A6B6C", "name": "local/exporter"}"#),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let client = http::Client::new(Duration::from_secs(5)).unwrap();
+        let url = format!("{}/api/user", mock_server.uri());
+        let token = request_token(&client, &url, "exporter", Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(token, "2D6B6E6C6D6A6B6C");
+    }
+
+    #[tokio::test]
+    async fn test_request_token_reports_other_errors() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/user"))
+            .respond_with(
+                ResponseTemplate::new(403)
+                    .set_body_string(r#"{"error": "user:creating-user-not-enabled"}"#),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let client = http::Client::new(Duration::from_secs(5)).unwrap();
+        let url = format!("{}/api/user", mock_server.uri());
+        let err = request_token(&client, &url, "exporter", Duration::from_secs(10))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("creating-user-not-enabled"));
+    }
+
+    #[tokio::test]
+    async fn test_run_refuses_in_read_only_mode() {
+        let config = Config::parse_from(["homewizard-p1-exporter", "--host", "192.168.1.10"]);
+        let err = run(&config, "test", Duration::from_secs(1), false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("read-only mode"), "{err}");
+    }
+}
@@ -0,0 +1,251 @@
+//! Blackbox-exporter style probing: `/probe?target=host` fetches and renders
+//! one device per request, so the devices can be listed on the Prometheus
+//! side instead of in `--host`. `label_<name>=value` parameters add labels
+//! allowed by `--probe-label`.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::http::uri::Authority;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::config::{Config, Device};
+use crate::homewizard::{DeviceInfo, HomeWizardClient};
+use crate::metrics::{self, Metrics, MetricsOptions};
+
+/// Fetches devices named by probe requests.
+pub struct Prober {
+    config: Config,
+    options: MetricsOptions,
+}
+
+impl Prober {
+    /// Trackers that need a history of readings (cost, net metering, degree
+    /// days) are left out of `options`: every probe starts from scratch.
+    pub fn new(config: Config, options: MetricsOptions) -> Self {
+        Self {
+            config,
+            options: MetricsOptions {
+                contract: None,
+                net_metering: None,
+                degree_days: None,
+                device: None,
+                ..options
+            },
+        }
+    }
+
+    /// Polls `target` (`host[/product]`) once and renders its metrics with
+    /// the extra `labels`. A failed poll still renders, with
+    /// `homewizard_exporter_up 0`.
+    pub async fn probe(
+        &self,
+        target: &str,
+        labels: &[(String, String)],
+    ) -> Result<String, (StatusCode, String)> {
+        let bad_request = |e: String| (StatusCode::BAD_REQUEST, format!("{e}\n"));
+        let allowed = self
+            .config
+            .probe_labels()
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}\n")))?;
+        if let Some((name, _)) = labels
+            .iter()
+            .find(|(name, _)| !allowed.iter().any(|allowed| allowed == name))
+        {
+            return Err(bad_request(format!(
+                "Label {name:?} is not allowed; list it in --probe-label"
+            )));
+        }
+        // Options such as token_file are for configured devices only
+        if target.contains(';') {
+            return Err(bad_request(format!(
+                "Invalid target {target:?}: device options are not accepted"
+            )));
+        }
+        let device = Device::parse_target(target, self.config.device_type)
+            .map_err(|e| bad_request(e.to_string()))?;
+        device
+            .host
+            .parse::<Authority>()
+            .map_err(|e| bad_request(format!("Invalid target {target:?}: {e}")))?;
+
+        let internal_error =
+            |e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}\n"));
+        let mut client = HomeWizardClient::new(device.url(), self.config.http_timeout_duration())
+            .map_err(internal_error)?
+            .parse_mode(self.config.parse_mode);
+        if let Some(token) = &self.config.api_token {
+            client = client.api_v2(device.v2_url(), token.clone());
+        }
+
+        let info = client.fetch_device_info().await.ok();
+        let product = device
+            .product
+            .or_else(|| info.as_ref().and_then(DeviceInfo::product))
+            .unwrap_or_default();
+        let client = client.product(product);
+        let metrics = Metrics::with_options(MetricsOptions {
+            product,
+            labels: [self.options.labels.as_slice(), labels].concat(),
+            ..self.options.clone()
+        })
+        .map_err(internal_error)?;
+        if let Some(info) = &info {
+            metrics.set_device_info(info);
+        }
+
+        let started = Instant::now();
+        let fetched = client.fetch_with_fallback(&self.config.sources).await;
+        metrics.observe_fetch_duration(started.elapsed());
+        match fetched {
+            Ok((data, source)) => {
+                metrics.record_poll_success();
+                metrics.set_active_source(source.as_str());
+                metrics.update(&data).map_err(internal_error)?;
+            }
+            Err(e) => metrics.record_poll_error(e.class()),
+        }
+
+        metrics::gather_all(&[Arc::new(metrics)]).map_err(internal_error)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProbeQuery {
+    /// Device to probe, `host[/product]`
+    target: String,
+    /// Other parameters; `label_<name>` ones become labels
+    #[serde(flatten)]
+    parameters: BTreeMap<String, String>,
+}
+
+pub async fn handler(
+    State(prober): State<Arc<Prober>>,
+    Query(query): Query<ProbeQuery>,
+) -> Result<String, (StatusCode, String)> {
+    let labels: Vec<(String, String)> = query
+        .parameters
+        .into_iter()
+        .filter_map(|(name, value)| Some((name.strip_prefix("label_")?.to_string(), value)))
+        .collect();
+    prober.probe(&query.target, &labels).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use tower::ServiceExt;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn prober() -> Prober {
+        let config = Config::parse_from(["homewizard-p1-exporter", "--host", "127.0.0.1"]);
+        Prober::new(config, MetricsOptions::default())
+    }
+
+    #[tokio::test]
+    async fn test_probe_renders_target() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"product_type": "HWE-P1", "product_name": "P1 meter", "serial": "3c39e7aabbcc", "firmware_version": "4.19", "api_version": "v1"}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../example-response.json")),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let target = mock_server.address().to_string();
+        let output = prober().probe(&target, &[]).await.unwrap();
+
+        assert!(output.contains("homewizard_p1_power_import_total_kwh"));
+        assert!(output.contains("homewizard_exporter_up 1"));
+        assert!(output.contains(r#"product_type="HWE-P1""#));
+    }
+
+    #[tokio::test]
+    async fn test_probe_adds_allowed_labels() {
+        let config = Config::parse_from([
+            "homewizard-p1-exporter",
+            "--host",
+            "127.0.0.1",
+            "--probe-label",
+            "site",
+        ]);
+        let prober = Prober::new(config, MetricsOptions::default());
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+        let target = format!("{}/p1", mock_server.address());
+
+        let site = [("site".to_string(), "garage".to_string())];
+        let output = prober.probe(&target, &site).await.unwrap();
+        assert!(output.contains(r#"homewizard_exporter_up{site="garage"} 0"#));
+
+        let rack = [("rack".to_string(), "a".to_string())];
+        let (status, _) = prober.probe(&target, &rack).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let app = axum::Router::new()
+            .route("/probe", axum::routing::get(handler))
+            .with_state(Arc::new(prober));
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri(format!("/probe?target={target}&label_site=attic&module=p1"))
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains(r#"site="attic""#));
+    }
+
+    #[tokio::test]
+    async fn test_probe_unreachable_target_reports_down() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let target = format!("{}/p1", mock_server.address());
+        let output = prober().probe(&target, &[]).await.unwrap();
+
+        assert!(output.contains("homewizard_exporter_up 0"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_rejects_invalid_target() {
+        let (status, _) = prober().probe("not a host", &[]).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = prober()
+            .probe("192.168.1.10/toaster", &[])
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = prober()
+            .probe("192.168.1.10;token=secret", &[])
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,101 @@
+//! Prometheus Pushgateway sink (`--pushgateway-url`): after every poll the
+//! rendered metrics replace the exporter's group on the gateway.
+
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use std::time::Duration;
+
+use crate::http;
+
+#[derive(Debug, Clone)]
+pub struct Pushgateway {
+    client: http::Client,
+    url: String,
+}
+
+impl Pushgateway {
+    /// Pushes to the group `job` (and `instance`, when set) on the gateway
+    /// at `base_url`.
+    pub fn new(
+        base_url: &str,
+        job: &str,
+        instance: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let mut url = format!(
+            "{}/metrics/{}",
+            base_url.trim_end_matches('/'),
+            grouping("job", job)
+        );
+        if let Some(instance) = instance {
+            url.push('/');
+            url.push_str(&grouping("instance", instance));
+        }
+        Ok(Self {
+            client: http::Client::new(timeout)?,
+            url,
+        })
+    }
+
+    /// Replaces the group's metrics with `metrics_text`.
+    pub async fn push(&self, metrics_text: String) -> Result<()> {
+        self.client
+            .request(http::Method::PUT, &self.url)
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(metrics_text.into_bytes())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// One `label/value` path segment. Values that do not fit in a path
+/// segment use the gateway's base64 form.
+fn grouping(label: &str, value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+    if plain {
+        format!("{label}/{value}")
+    } else {
+        format!("{label}@base64/{}", URL_SAFE_NO_PAD.encode(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_string, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_grouping() {
+        assert_eq!(grouping("job", "homewizard"), "job/homewizard");
+        assert_eq!(grouping("instance", "pi:9898"), "instance/pi:9898");
+        assert_eq!(grouping("instance", "a/b"), "instance@base64/YS9i");
+    }
+
+    #[tokio::test]
+    async fn test_push_replaces_group() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/metrics/job/homewizard/instance/house"))
+            .and(body_string("power_watts 250\n"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let gateway = Pushgateway::new(
+            &mock_server.uri(),
+            "homewizard",
+            Some("house"),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        gateway.push("power_watts 250\n".to_string()).await.unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+pub type SharedReadiness = Arc<RwLock<ReadinessGate>>;
+
+/// When the exporter counts as ready to be scraped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadinessPolicy {
+    /// Successful polls required within the window
+    pub min_successes: usize,
+    /// Number of most recent polls considered
+    pub window: usize,
+    /// Maximum age of the last successful poll
+    pub max_data_age: Option<Duration>,
+}
+
+/// Tracks recent poll outcomes and evaluates them against a policy.
+#[derive(Debug)]
+pub struct ReadinessGate {
+    policy: ReadinessPolicy,
+    outcomes: VecDeque<bool>,
+    last_success: Option<Instant>,
+}
+
+impl ReadinessGate {
+    pub fn new(policy: ReadinessPolicy) -> Self {
+        Self {
+            policy,
+            outcomes: VecDeque::with_capacity(policy.window),
+            last_success: None,
+        }
+    }
+
+    pub fn record(&mut self, success: bool, now: Instant) {
+        if self.outcomes.len() == self.policy.window {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(success);
+        if success {
+            self.last_success = Some(now);
+        }
+    }
+
+    /// Returns why the exporter is not ready, if it is not.
+    pub fn check(&self, now: Instant) -> Result<(), String> {
+        let successes = self.outcomes.iter().filter(|&&ok| ok).count();
+        if successes < self.policy.min_successes {
+            return Err(format!(
+                "{} of the last {} polls succeeded, {} required",
+                successes,
+                self.outcomes.len(),
+                self.policy.min_successes
+            ));
+        }
+
+        if let Some(max_age) = self.policy.max_data_age {
+            match self.last_success {
+                Some(at) if now.duration_since(at) <= max_age => {}
+                Some(at) => {
+                    return Err(format!(
+                        "last successful poll was {}s ago, at most {}s allowed",
+                        now.duration_since(at).as_secs(),
+                        max_age.as_secs()
+                    ));
+                }
+                None => return Err("no successful poll yet".to_string()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(min_successes: usize, window: usize) -> ReadinessPolicy {
+        ReadinessPolicy {
+            min_successes,
+            window,
+            max_data_age: None,
+        }
+    }
+
+    #[test]
+    fn test_not_ready_before_first_poll() {
+        let gate = ReadinessGate::new(policy(1, 3));
+        assert!(gate.check(Instant::now()).is_err());
+    }
+
+    #[test]
+    fn test_requires_m_of_last_n_successes() {
+        let now = Instant::now();
+        let mut gate = ReadinessGate::new(policy(2, 3));
+
+        gate.record(true, now);
+        assert!(gate.check(now).is_err());
+        gate.record(false, now);
+        gate.record(true, now);
+        assert!(gate.check(now).is_ok());
+
+        // The first success falls out of the window.
+        gate.record(false, now);
+        let reason = gate.check(now).unwrap_err();
+        assert_eq!(reason, "1 of the last 3 polls succeeded, 2 required");
+    }
+
+    #[test]
+    fn test_max_data_age() {
+        let start = Instant::now();
+        let mut gate = ReadinessGate::new(ReadinessPolicy {
+            max_data_age: Some(Duration::from_secs(30)),
+            ..policy(1, 10)
+        });
+
+        gate.record(true, start);
+        assert!(gate.check(start + Duration::from_secs(30)).is_ok());
+        assert!(gate.check(start + Duration::from_secs(31)).is_err());
+    }
+}
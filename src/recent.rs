@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::homewizard::HomeWizardData;
+
+pub type SharedRecent = Arc<RwLock<RecentSamples>>;
+
+/// A compact snapshot of one successful poll.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    /// Unix time of the poll in milliseconds
+    pub timestamp_ms: i64,
+    pub active_power_w: f64,
+    pub active_power_l1_w: f64,
+    pub active_power_l2_w: f64,
+    pub active_power_l3_w: f64,
+    pub total_power_import_kwh: f64,
+    pub total_power_export_kwh: f64,
+    pub total_gas_m3: f64,
+}
+
+impl Sample {
+    pub fn from_data(timestamp_ms: i64, data: &HomeWizardData) -> Self {
+        Self {
+            timestamp_ms,
+            active_power_w: data.active_power_w,
+            active_power_l1_w: data.active_power_l1_w,
+            active_power_l2_w: data.active_power_l2_w,
+            active_power_l3_w: data.active_power_l3_w,
+            total_power_import_kwh: data.total_power_import_kwh,
+            total_power_export_kwh: data.total_power_export_kwh,
+            total_gas_m3: data.total_gas_m3,
+        }
+    }
+}
+
+/// Ring buffer of the most recent polls, bounded both by age and by count so
+/// a short poll interval cannot grow it without limit.
+#[derive(Debug)]
+pub struct RecentSamples {
+    window: Duration,
+    max_samples: usize,
+    samples: VecDeque<Sample>,
+}
+
+impl RecentSamples {
+    pub fn new(window: Duration, max_samples: usize) -> Self {
+        Self {
+            window,
+            max_samples,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, sample: Sample) {
+        let cutoff = sample.timestamp_ms - self.window.as_millis() as i64;
+        self.samples.push_back(sample);
+
+        while self.samples.len() > self.max_samples {
+            self.samples.pop_front();
+        }
+        while self
+            .samples
+            .front()
+            .is_some_and(|oldest| oldest.timestamp_ms < cutoff)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Samples newer than `since_ms` (all of them when `None`), oldest first.
+    pub fn since(&self, since_ms: Option<i64>) -> Vec<Sample> {
+        self.samples
+            .iter()
+            .filter(|sample| since_ms.is_none_or(|since| sample.timestamp_ms > since))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp_ms: i64, power: f64) -> Sample {
+        Sample {
+            active_power_w: power,
+            ..Sample::from_data(timestamp_ms, &HomeWizardData::default())
+        }
+    }
+
+    #[test]
+    fn test_drops_samples_outside_window() {
+        let mut recent = RecentSamples::new(Duration::from_secs(60), 100);
+        recent.push(sample(0, 1.0));
+        recent.push(sample(30_000, 2.0));
+        recent.push(sample(61_000, 3.0));
+
+        let powers: Vec<f64> = recent
+            .since(None)
+            .iter()
+            .map(|s| s.active_power_w)
+            .collect();
+        assert_eq!(powers, vec![2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_caps_sample_count() {
+        let mut recent = RecentSamples::new(Duration::from_secs(3600), 3);
+        for i in 0..5 {
+            recent.push(sample(i * 1000, i as f64));
+        }
+
+        let powers: Vec<f64> = recent
+            .since(None)
+            .iter()
+            .map(|s| s.active_power_w)
+            .collect();
+        assert_eq!(powers, vec![2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn test_since_returns_newer_samples_only() {
+        let mut recent = RecentSamples::new(Duration::from_secs(3600), 100);
+        recent.push(sample(1000, 1.0));
+        recent.push(sample(2000, 2.0));
+        recent.push(sample(3000, 3.0));
+
+        assert_eq!(recent.since(Some(2000)), vec![sample(3000, 3.0)]);
+        assert!(recent.since(Some(3000)).is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+//! Configuration reload on SIGHUP: the command line, environment and
+//! `--config-file` are read again and applied to the running pollers, keeping
+//! the HTTP listener and metric state.
+
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+use crate::scheduler::{Fleet, Pollers};
+
+/// Reloads the configuration on every SIGHUP.
+#[cfg(unix)]
+pub async fn run(pollers: Pollers) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP, reload disabled: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading configuration");
+        let reloaded = match Config::reload() {
+            Ok(config) => apply(&config, &pollers).await,
+            Err(e) => Err(e),
+        };
+        match reloaded {
+            Ok(()) => info!("Configuration reloaded"),
+            Err(e) => warn!("Keeping the current configuration: {:#}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn run(_pollers: Pollers) {}
+
+/// Applies `config` to the pollers. Devices are matched by name and moved to
+/// their new address; new devices are polled and removed ones stopped.
+/// Devices changed through the admin API keep that change until the
+/// configuration agrees with it.
+pub async fn apply(config: &Config, pollers: &Fleet) -> anyhow::Result<()> {
+    config.validate_sources()?;
+    config.retry_policy()?;
+    config.contract()?;
+    let devices = config.devices()?;
+    // Devices added below are polled with the new configuration
+    pollers.set_config(config.clone());
+
+    for poller in pollers.pollers() {
+        let device = devices.iter().find(|device| device.name == poller.name());
+        let host = device.map(|device| device.host.as_str());
+        match device {
+            _ if pollers.overridden(poller.name(), host) => {
+                warn!(
+                    "[{}] Keeping the change made through the admin API",
+                    poller.name()
+                );
+            }
+            Some(device) => {
+                if poller.url().await != device.url() {
+                    poller.retarget(&device.host).await;
+                }
+                if let Err(e) = poller.reconfigure(config, device).await {
+                    warn!("[{}] Keeping the current client: {:#}", poller.name(), e);
+                }
+            }
+            None => {
+                pollers.remove(poller.name()).await;
+                continue;
+            }
+        }
+        poller.reload(config.clone());
+    }
+    for device in devices {
+        if pollers.get(&device.name).is_some() {
+            continue;
+        }
+        if pollers.overridden(&device.name, Some(&device.host)) {
+            warn!(
+                "[{}] Not polling the device removed through the admin API",
+                device.name
+            );
+            continue;
+        }
+        let name = device.name.clone();
+        if let Err(e) = pollers.add(device).await {
+            warn!("[{}] Failed to add device: {:#}", name, e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::device_url;
+    use crate::homewizard::HomeWizardClient;
+    use crate::metrics::{DeviceMetrics, Metrics, MetricsOptions};
+    use crate::scheduler::{Poller, PollerFactory};
+    use clap::Parser;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::RwLock;
+
+    fn config(host: &str) -> Config {
+        Config::parse_from(["homewizard-p1-exporter", "--host", host])
+    }
+
+    #[tokio::test]
+    async fn test_apply_moves_devices_by_name() {
+        let client =
+            HomeWizardClient::new(device_url("192.168.1.10"), Duration::from_secs(5)).unwrap();
+        let metrics = Arc::new(Metrics::with_options(MetricsOptions::default()).unwrap());
+        let poller = Arc::new(Poller::new(
+            "house",
+            client,
+            metrics,
+            Arc::new(RwLock::new(String::new())),
+            config("house=192.168.1.10"),
+        ));
+        let pollers = Fleet::new(vec![poller.clone()], DeviceMetrics::default());
+
+        apply(&config("house=192.168.1.20"), &pollers)
+            .await
+            .unwrap();
+        assert_eq!(poller.url().await, device_url("192.168.1.20"));
+
+        // Another name is a different device: the poller stays put
+        apply(&config("house=192.168.1.20,annex=192.168.1.30"), &pollers)
+            .await
+            .unwrap();
+        assert_eq!(poller.url().await, device_url("192.168.1.20"));
+
+        // An address set through the admin API survives reloads until the
+        // configuration agrees with it
+        poller.retarget("192.168.1.40").await;
+        pollers.set_override("house", Some("192.168.1.40".to_string()));
+        apply(&config("house=192.168.1.20"), &pollers)
+            .await
+            .unwrap();
+        assert_eq!(poller.url().await, device_url("192.168.1.40"));
+        apply(&config("house=192.168.1.40"), &pollers)
+            .await
+            .unwrap();
+        apply(&config("house=192.168.1.20"), &pollers)
+            .await
+            .unwrap();
+        assert_eq!(poller.url().await, device_url("192.168.1.20"));
+    }
+
+    #[tokio::test]
+    async fn test_apply_adds_and_removes_devices() {
+        let factory: PollerFactory = Arc::new(|config, device| {
+            Box::pin(async move {
+                let client = HomeWizardClient::new(device.url(), Duration::from_secs(5))?;
+                let metrics = Arc::new(Metrics::with_options(MetricsOptions::default())?);
+                let output = Arc::new(RwLock::new(String::new()));
+                Ok(Poller::new(
+                    device.name,
+                    client,
+                    metrics,
+                    output,
+                    (*config).clone(),
+                ))
+            })
+        });
+        let pollers = Fleet::new(Vec::new(), DeviceMetrics::default())
+            .with_factory(factory, config("house=192.168.1.10"));
+
+        apply(&config("house=192.168.1.10,annex=192.168.1.30"), &pollers)
+            .await
+            .unwrap();
+        let names: Vec<String> = pollers
+            .pollers()
+            .iter()
+            .map(|poller| poller.name().to_string())
+            .collect();
+        assert_eq!(names, ["house", "annex"]);
+
+        apply(&config("annex=192.168.1.30"), &pollers)
+            .await
+            .unwrap();
+        assert!(pollers.get("house").is_none());
+        assert!(pollers.get("annex").is_some());
+    }
+}
@@ -0,0 +1,358 @@
+//! Prometheus remote_write push (`--remote-write-url`), for exporters that
+//! the Prometheus server cannot scrape, such as behind NAT. Samples go to
+//! Grafana Cloud, Mimir, VictoriaMetrics or any other receiver of the
+//! remote_write 1.0 protocol: a snappy-compressed protobuf `WriteRequest`.
+
+use anyhow::Result;
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+use crate::http;
+use crate::metrics::{self, DeviceMetrics};
+use crate::wal::Wal;
+
+/// Pushes the metrics of all devices to a remote_write endpoint.
+#[derive(Debug, Clone)]
+pub struct RemoteWriter {
+    client: http::Client,
+    url: String,
+    basic_auth: Option<(String, String)>,
+    bearer_token: Option<String>,
+    headers: Vec<(String, String)>,
+    interval: Duration,
+    /// Keeps the pushes that failed, sent again before the next one
+    wal: Option<Arc<Wal>>,
+}
+
+impl RemoteWriter {
+    /// With `verify_certificates` off, a self-signed certificate is
+    /// accepted.
+    pub fn new(
+        url: &str,
+        interval: Duration,
+        timeout: Duration,
+        verify_certificates: bool,
+    ) -> Result<Self> {
+        let client = if verify_certificates {
+            http::Client::new(timeout)?
+        } else {
+            http::Client::unverified(timeout)?
+        };
+        Ok(Self {
+            client,
+            url: url.to_string(),
+            basic_auth: None,
+            bearer_token: None,
+            headers: Vec::new(),
+            interval,
+            wal: None,
+        })
+    }
+
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+        self.basic_auth = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    pub fn bearer_token(mut self, token: &str) -> Self {
+        self.bearer_token = Some(token.to_string());
+        self
+    }
+
+    /// Headers sent with every push, such as `X-Scope-OrgID`.
+    pub fn headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    pub fn wal(mut self, wal: Arc<Wal>) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    pub async fn run(self, devices: DeviceMetrics) {
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await; // Skip the immediate tick, before the first poll
+        loop {
+            ticker.tick().await;
+            let families = metrics::gather_families(&devices.snapshot());
+            let timestamp_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as i64;
+            match self.push(&families, timestamp_ms).await {
+                Ok(()) => debug!("Pushed metrics to {}", self.url),
+                Err(e) => warn!("Failed to push metrics to {}: {}", self.url, e),
+            }
+        }
+    }
+
+    async fn push(&self, families: &[MetricFamily], timestamp_ms: i64) -> Result<()> {
+        let body = snap::raw::Encoder::new().compress_vec(&encode(families, timestamp_ms))?;
+        match &self.wal {
+            Some(wal) => wal.deliver(body, |body| self.send(body)).await,
+            None => self.send(body).await,
+        }
+    }
+
+    async fn send(&self, body: Vec<u8>) -> Result<()> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("content-type", "application/x-protobuf")
+            .header("content-encoding", "snappy")
+            .header("x-prometheus-remote-write-version", "0.1.0")
+            .body(body);
+        if let Some((username, password)) = &self.basic_auth {
+            request = request.basic_auth(username, password);
+        }
+        if let Some(token) = &self.bearer_token {
+            request = request.bearer_auth(token);
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name.clone(), value.clone());
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// One series: its labels, `__name__` included, and its value.
+pub struct Series {
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+/// Flattens the families into series the way the text format does:
+/// histograms into `_bucket`, `_sum` and `_count`, summaries into their
+/// quantiles, `_sum` and `_count`.
+pub fn series(families: &[MetricFamily]) -> Vec<Series> {
+    let mut series = Vec::new();
+    for family in families {
+        let name = family.name();
+        for metric in family.get_metric() {
+            let mut push = |suffix: &str, extra: Option<(&str, String)>, value: f64| {
+                let mut labels = labels(metric.get_label());
+                labels.push(("__name__".to_string(), format!("{name}{suffix}")));
+                labels.extend(extra.map(|(label, value)| (label.to_string(), value)));
+                // Receivers expect the labels sorted by name
+                labels.sort();
+                series.push(Series { labels, value });
+            };
+            match family.get_field_type() {
+                MetricType::COUNTER => push("", None, metric.get_counter().value()),
+                MetricType::GAUGE => push("", None, metric.get_gauge().value()),
+                MetricType::UNTYPED => push("", None, metric.untyped.value()),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let count = histogram.get_sample_count() as f64;
+                    for bucket in histogram.get_bucket() {
+                        if bucket.upper_bound() != f64::INFINITY {
+                            let le = bucket.upper_bound().to_string();
+                            push(
+                                "_bucket",
+                                Some(("le", le)),
+                                bucket.cumulative_count() as f64,
+                            );
+                        }
+                    }
+                    push("_bucket", Some(("le", "+Inf".to_string())), count);
+                    push("_sum", None, histogram.get_sample_sum());
+                    push("_count", None, count);
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        let q = quantile.quantile().to_string();
+                        push("", Some(("quantile", q)), quantile.value());
+                    }
+                    push("_sum", None, summary.sample_sum());
+                    push("_count", None, summary.sample_count() as f64);
+                }
+            }
+        }
+    }
+    series
+}
+
+fn labels(pairs: &[LabelPair]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|pair| (pair.name().to_string(), pair.value().to_string()))
+        .collect()
+}
+
+/// Encodes a `WriteRequest` with one sample per series:
+///
+/// ```text
+/// message WriteRequest { repeated TimeSeries timeseries = 1; }
+/// message TimeSeries { repeated Label labels = 1; repeated Sample samples = 2; }
+/// message Label { string name = 1; string value = 2; }
+/// message Sample { double value = 1; int64 timestamp = 2; }
+/// ```
+fn encode(families: &[MetricFamily], timestamp_ms: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for series in series(families) {
+        let mut time_series = Vec::new();
+        for (name, value) in &series.labels {
+            let mut label = Vec::new();
+            bytes_field(&mut label, 1, name.as_bytes());
+            bytes_field(&mut label, 2, value.as_bytes());
+            bytes_field(&mut time_series, 1, &label);
+        }
+        let mut sample = Vec::new();
+        sample.push(1 << 3 | 1); // field 1, 64-bit
+        sample.extend_from_slice(&series.value.to_le_bytes());
+        sample.push(2 << 3); // field 2, varint
+        varint(&mut sample, timestamp_ms as u64);
+        bytes_field(&mut time_series, 2, &sample);
+        bytes_field(&mut request, 1, &time_series);
+    }
+    request
+}
+
+/// A length-delimited field.
+fn bytes_field(buffer: &mut Vec<u8>, field: u8, bytes: &[u8]) {
+    buffer.push(field << 3 | 2);
+    varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Gauge, Histogram, HistogramOpts, Opts, Registry};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_encode_write_request() {
+        let registry = Registry::new();
+        let gauge = Gauge::with_opts(Opts::new("a", "A")).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        gauge.set(1.0);
+
+        // Value 1.0 at timestamp 2
+        let mut expected = vec![0x0a, 0x1c, 0x0a, 0x0d, 0x0a, 0x08];
+        expected.extend(b"__name__");
+        expected.extend([0x12, 0x01, b'a', 0x12, 0x0b, 0x09]);
+        expected.extend(1.0f64.to_le_bytes());
+        expected.extend([0x10, 0x02]);
+
+        assert_eq!(encode(&registry.gather(), 2), expected);
+    }
+
+    #[test]
+    fn test_series_flattens_histograms() {
+        let registry = Registry::new();
+        let duration = Histogram::with_opts(
+            HistogramOpts::new("duration_seconds", "Duration")
+                .const_label("device", "house")
+                .buckets(vec![0.1]),
+        )
+        .unwrap();
+        registry.register(Box::new(duration.clone())).unwrap();
+        duration.observe(0.05);
+        duration.observe(0.5);
+
+        let series = series(&registry.gather());
+        let names: Vec<_> = series
+            .iter()
+            .map(|series| {
+                let labels: Vec<_> = series
+                    .labels
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect();
+                format!("{} {}", labels.join(","), series.value)
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "__name__=duration_seconds_bucket,device=house,le=0.1 1",
+                "__name__=duration_seconds_bucket,device=house,le=+Inf 2",
+                "__name__=duration_seconds_sum,device=house 0.55",
+                "__name__=duration_seconds_count,device=house 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut buffer = Vec::new();
+        varint(&mut buffer, 300);
+        assert_eq!(buffer, [0xac, 0x02]);
+    }
+
+    #[tokio::test]
+    async fn test_push_sends_snappy_protobuf() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/push"))
+            .and(header("content-encoding", "snappy"))
+            .and(header("content-type", "application/x-protobuf"))
+            .and(header("authorization", "Basic dXNlcjpzZWNyZXQ="))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let registry = Registry::new();
+        let gauge = Gauge::with_opts(Opts::new("power_watts", "Power")).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        let writer = RemoteWriter::new(
+            &format!("{}/api/v1/push", mock_server.uri()),
+            Duration::from_secs(30),
+            Duration::from_secs(5),
+            true,
+        )
+        .unwrap()
+        .basic_auth("user", "secret");
+        writer.push(&registry.gather(), 2).await.unwrap();
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body = snap::raw::Decoder::new()
+            .decompress_vec(&requests[0].body)
+            .unwrap();
+        assert_eq!(body, encode(&registry.gather(), 2));
+    }
+
+    #[tokio::test]
+    async fn test_push_sends_bearer_token_and_headers() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("authorization", "Bearer secret"))
+            .and(header("x-scope-orgid", "house"))
+            .and(header("x-custom", "1"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let writer = RemoteWriter::new(
+            &mock_server.uri(),
+            Duration::from_secs(30),
+            Duration::from_secs(5),
+            true,
+        )
+        .unwrap()
+        .bearer_token("secret")
+        .headers(vec![
+            ("X-Scope-OrgID".to_string(), "house".to_string()),
+            ("X-Custom".to_string(), "1".to_string()),
+        ]);
+        writer.push(&[], 2).await.unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+//! Retries of failed fetches within one poll, with exponential backoff, and
+//! the circuit breaker that spaces out polls of a device that stays down.
+
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use crate::homewizard::HomeWizardError;
+
+/// How often and how fast a failed fetch is retried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts per poll, the first one included
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubles with every further retry
+    pub base_delay: Duration,
+    /// Random extra delay, as a fraction of the backoff delay
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    /// A single attempt: failures wait for the next poll.
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (1-based). `random` in `[0, 1)`
+    /// picks the jitter.
+    pub fn delay(&self, retry: u32, random: f64) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+        backoff + backoff.mul_f64(self.jitter * random)
+    }
+
+    /// Runs `attempt` until it succeeds, fails with an error retrying will
+    /// not fix, or runs out of attempts. `on_retry` sees every error that is
+    /// retried and the delay before the retry.
+    pub async fn run<T, F, Fut>(
+        &self,
+        mut attempt: F,
+        mut on_retry: impl FnMut(&HomeWizardError, Duration),
+    ) -> Result<T, HomeWizardError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, HomeWizardError>>,
+    {
+        let mut retry = 0;
+        loop {
+            match attempt().await {
+                Err(e) if retry + 1 < self.max_attempts && is_transient(&e) => {
+                    retry += 1;
+                    let delay = self.delay(retry, random_fraction());
+                    on_retry(&e, delay);
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Stops polling an unreachable device every interval: after `threshold`
+/// consecutive failed polls the circuit opens and the device is only probed
+/// every `probe_interval`, until a probe succeeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    pub threshold: u32,
+    pub probe_interval: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn is_open(&self, consecutive_failures: u32) -> bool {
+        consecutive_failures >= self.threshold
+    }
+
+    /// Whether to poll now, `since_last_poll` after the previous poll.
+    pub fn allows(&self, consecutive_failures: u32, since_last_poll: Duration) -> bool {
+        !self.is_open(consecutive_failures) || since_last_poll >= self.probe_interval
+    }
+}
+
+/// Timeouts, connection failures and error statuses may pass; an answer that
+/// does not parse or a refused write will not.
+fn is_transient(error: &HomeWizardError) -> bool {
+    matches!(error.class(), "timeout" | "connection" | "http_status")
+}
+
+/// A number in `[0, 1)` that differs between calls, enough to spread the
+/// retries of several exporters.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            jitter: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_delay_doubles_with_jitter() {
+        let policy = policy(5);
+        assert_eq!(policy.delay(1, 0.0), Duration::from_millis(1));
+        assert_eq!(policy.delay(2, 0.0), Duration::from_millis(2));
+        assert_eq!(policy.delay(3, 0.0), Duration::from_millis(4));
+        assert_eq!(policy.delay(3, 0.5), Duration::from_millis(5));
+        assert!(random_fraction() < 1.0);
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker {
+            threshold: 3,
+            probe_interval: Duration::from_secs(60),
+        };
+        assert!(breaker.allows(2, Duration::from_secs(1)));
+        assert!(breaker.is_open(3));
+        assert!(!breaker.allows(3, Duration::from_secs(59)));
+        assert!(breaker.allows(3, Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn test_run_retries_transient_errors() {
+        let attempts = Cell::new(0);
+        let mut retries = Vec::new();
+        let result = policy(3)
+            .run(
+                || {
+                    attempts.set(attempts.get() + 1);
+                    async {
+                        match attempts.get() {
+                            1 | 2 => {
+                                Err(HomeWizardError::ParseError("HTTP status 503".to_string()))
+                            }
+                            _ => Ok("reading"),
+                        }
+                    }
+                },
+                |e, _| retries.push(e.class()),
+            )
+            .await;
+
+        assert_eq!(result.unwrap(), "reading");
+        assert_eq!(retries, vec!["http_status", "http_status"]);
+    }
+
+    #[tokio::test]
+    async fn test_run_gives_up() {
+        let attempts = Cell::new(0);
+        let result: Result<(), _> = policy(2)
+            .run(
+                || {
+                    attempts.set(attempts.get() + 1);
+                    async { Err(HomeWizardError::ParseError("HTTP status 503".to_string())) }
+                },
+                |_, _| {},
+            )
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 2);
+
+        // Parse errors are not retried
+        attempts.set(0);
+        let result: Result<(), _> = policy(3)
+            .run(
+                || {
+                    attempts.set(attempts.get() + 1);
+                    async { Err(HomeWizardError::ParseError("bad".to_string())) }
+                },
+                |_, _| {},
+            )
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
@@ -0,0 +1,1650 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, RwLock, mpsc, watch};
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+
+use crate::SharedMetrics;
+use crate::config::{Config, Device, device_url, device_v2_url};
+use crate::discover::Rediscover;
+use crate::events::{DeviceEvent, EventDetector, EventPublisher};
+use crate::fuse::{OverloadDetector, OverloadPolicy};
+use crate::homeassistant::{HomeAssistantState, SharedHomeAssistant};
+use crate::homewizard::{HomeWizardClient, HomeWizardData, SmrCapabilities, Source};
+use crate::influx::{self, InfluxWriter};
+use crate::metrics::{self, DeviceMetrics, Metrics};
+use crate::mqtt::MqttPublisher;
+use crate::pushgateway::Pushgateway;
+use crate::readiness::SharedReadiness;
+use crate::recent::{Sample, SharedRecent};
+use crate::textfile;
+use crate::timezone;
+
+/// Delay before a crashed poller is restarted.
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Health of a single poller, driven by poll outcomes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollerState {
+    /// No poll has completed yet
+    Starting,
+    /// The last poll succeeded
+    Healthy,
+    /// Polls have failed since the last success
+    Failing { consecutive_failures: u32 },
+}
+
+impl PollerState {
+    fn on_success(self) -> Self {
+        Self::Healthy
+    }
+
+    fn on_failure(self) -> Self {
+        match self {
+            Self::Failing {
+                consecutive_failures,
+            } => Self::Failing {
+                consecutive_failures: consecutive_failures.saturating_add(1),
+            },
+            Self::Starting | Self::Healthy => Self::Failing {
+                consecutive_failures: 1,
+            },
+        }
+    }
+}
+
+/// Outcome of the polls so far, as shown on the landing page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollStatus {
+    pub state: PollerState,
+    pub last_success: Option<chrono::DateTime<chrono::Local>>,
+}
+
+/// The pollers of all devices, as shared with the HTTP handlers.
+pub type Pollers = Arc<Fleet>;
+
+/// Polls triggered by scrapes rather than a fixed interval.
+struct OnDemand {
+    /// How long a poll satisfies further scrapes
+    ttl: Duration,
+    requested: Notify,
+    /// When the last requested poll completed
+    completed: watch::Sender<Option<Instant>>,
+}
+
+/// A successful poll, as handed to streaming outputs and `/json`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Reading {
+    /// Unix time of the poll in milliseconds
+    pub timestamp_ms: i64,
+    pub data: HomeWizardData,
+}
+
+/// Outputs that follow a single device, the first one: `/api/recent`,
+/// `/json` and the Home Assistant sensors. Handed to the next device when
+/// the first one is removed.
+#[derive(Clone, Default)]
+struct FirstDevice {
+    recent: Option<SharedRecent>,
+    readings: Option<watch::Sender<Option<Reading>>>,
+    home_assistant: Option<SharedHomeAssistant>,
+}
+
+impl FirstDevice {
+    fn is_empty(&self) -> bool {
+        self.recent.is_none() && self.readings.is_none() && self.home_assistant.is_none()
+    }
+
+    /// Forgets what the previous device reported.
+    async fn clear(&self) {
+        if let Some(recent) = &self.recent {
+            recent.write().await.clear();
+        }
+        if let Some(readings) = &self.readings {
+            readings.send_replace(None);
+        }
+        if let Some(home_assistant) = &self.home_assistant {
+            *home_assistant.write().await = HomeAssistantState::default();
+        }
+    }
+}
+
+/// Polls one device on its own cadence and publishes the rendered metrics.
+pub struct Poller {
+    name: String,
+    client: RwLock<HomeWizardClient>,
+    metrics: Arc<Metrics>,
+    /// Metrics of every device rendered into `output`, this one included
+    exposition: DeviceMetrics,
+    output: SharedMetrics,
+    /// Replaced on reload
+    config: std::sync::RwLock<Arc<Config>>,
+    events: EventPublisher,
+    pushgateway: Option<Pushgateway>,
+    influx: Option<InfluxWriter>,
+    mqtt: Option<MqttPublisher>,
+    first_device: Mutex<FirstDevice>,
+    readiness: Option<SharedReadiness>,
+    overload: Option<OverloadPolicy>,
+    on_demand: Option<OnDemand>,
+    rediscover: Option<Rediscover>,
+    /// Serial number from the device information, to rediscover it by
+    serial: Mutex<Option<String>>,
+    /// Cleared while another instance holds the failover lease
+    leader: AtomicBool,
+    status: Mutex<PollStatus>,
+    /// When the poll in progress started, for the systemd watchdog
+    busy_since: Mutex<Option<Instant>>,
+    last_reading: Mutex<Option<(HomeWizardData, Source)>>,
+    /// Uptime at the previous system status read, to detect reboots
+    last_uptime: Mutex<Option<f64>>,
+}
+
+impl Poller {
+    pub fn new(
+        name: impl Into<String>,
+        client: HomeWizardClient,
+        metrics: Arc<Metrics>,
+        output: SharedMetrics,
+        config: Config,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            client: RwLock::new(client),
+            exposition: DeviceMetrics::new(vec![metrics.clone()]),
+            metrics,
+            output,
+            config: std::sync::RwLock::new(Arc::new(config)),
+            events: EventPublisher::default(),
+            pushgateway: None,
+            influx: None,
+            mqtt: None,
+            first_device: Mutex::new(FirstDevice::default()),
+            readiness: None,
+            overload: None,
+            on_demand: None,
+            rediscover: None,
+            serial: Mutex::new(None),
+            leader: AtomicBool::new(true),
+            status: Mutex::new(PollStatus {
+                state: PollerState::Starting,
+                last_success: None,
+            }),
+            busy_since: Mutex::new(None),
+            last_reading: Mutex::new(None),
+            last_uptime: Mutex::new(None),
+        }
+    }
+
+    /// Renders the metrics of all `devices` into the shared output, so
+    /// several pollers can publish to one `/metrics`.
+    pub fn with_exposition(mut self, devices: DeviceMetrics) -> Self {
+        self.exposition = devices;
+        self
+    }
+
+    /// Sends events detected in this device's readings to `events`.
+    pub fn with_events(mut self, events: EventPublisher) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Pushes the metrics to a Pushgateway after every poll.
+    pub fn with_pushgateway(mut self, pushgateway: Pushgateway) -> Self {
+        self.pushgateway = Some(pushgateway);
+        self
+    }
+
+    /// Writes every changed reading to InfluxDB.
+    pub fn with_influx(mut self, influx: InfluxWriter) -> Self {
+        self.influx = Some(influx);
+        self
+    }
+
+    /// Publishes every changed reading over MQTT.
+    pub fn with_mqtt(mut self, mqtt: MqttPublisher) -> Self {
+        self.mqtt = Some(mqtt);
+        self
+    }
+
+    /// Records every successful poll in `recent`.
+    pub fn with_recent(mut self, recent: SharedRecent) -> Self {
+        self.first_device
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .recent = Some(recent);
+        self
+    }
+
+    /// Reports every poll outcome to `readiness`.
+    pub fn with_readiness(mut self, readiness: SharedReadiness) -> Self {
+        self.readiness = Some(readiness);
+        self
+    }
+
+    /// Sends every successful reading to `readings`.
+    pub fn with_readings(mut self, readings: watch::Sender<Option<Reading>>) -> Self {
+        self.first_device
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .readings = Some(readings);
+        self
+    }
+
+    /// Keeps the Home Assistant sensors up to date.
+    pub fn with_home_assistant(mut self, home_assistant: SharedHomeAssistant) -> Self {
+        self.first_device
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .home_assistant = Some(home_assistant);
+        self
+    }
+
+    /// Raises events when a phase stays near the fuse rating.
+    pub fn with_overload(mut self, policy: OverloadPolicy) -> Self {
+        self.overload = Some(policy);
+        self
+    }
+
+    /// Looks the device up by `serial` with `rediscover` after
+    /// `--rediscover-after` failed polls, and polls it where it was found.
+    /// Without a `serial` yet, the device is only looked up once `/api` is
+    /// read again.
+    pub fn with_rediscover(mut self, rediscover: Rediscover, serial: Option<String>) -> Self {
+        self.rediscover = Some(rediscover);
+        *self.serial.get_mut().unwrap_or_else(|e| e.into_inner()) = serial;
+        self
+    }
+
+    /// Polls only when [`Poller::refresh`] asks for a reading, reusing
+    /// readings younger than `ttl`.
+    pub fn on_demand(mut self, ttl: Duration) -> Self {
+        self.on_demand = Some(OnDemand {
+            ttl,
+            requested: Notify::new(),
+            completed: watch::Sender::new(None),
+        });
+        self
+    }
+
+    /// In on-demand mode, polls the device unless the last reading is still
+    /// fresh and waits for the poll to finish. Does nothing otherwise.
+    pub async fn refresh(&self) {
+        let Some(on_demand) = &self.on_demand else {
+            return;
+        };
+        let mut completed = on_demand.completed.subscribe();
+        if completed
+            .borrow_and_update()
+            .is_some_and(|at| at.elapsed() < on_demand.ttl)
+        {
+            return;
+        }
+
+        on_demand.requested.notify_one();
+        // Serve the previous output rather than hang the scrape
+        let config = self.config();
+        let wait = match config.device(&self.name) {
+            Some(device) => config.device_http_timeout(&device),
+            None => config.http_timeout_duration(),
+        } * 2;
+        if tokio::time::timeout(wait, completed.changed())
+            .await
+            .is_err()
+        {
+            warn!(
+                "[{}] On-demand poll did not finish in {:?}",
+                self.name, wait
+            );
+        }
+    }
+
+    /// Starts as a standby that only polls once [`Poller::set_leader`]
+    /// makes it active.
+    pub fn standby(self) -> Self {
+        self.leader.store(false, Ordering::Relaxed);
+        self.metrics.set_leader(false);
+        self
+    }
+
+    /// Switches between active polling and standby, and republishes the
+    /// metrics so the change is visible on the next scrape.
+    pub async fn set_leader(&self, leader: bool) {
+        self.leader.store(leader, Ordering::Relaxed);
+        self.metrics.set_leader(leader);
+        match self.render() {
+            Ok(metrics_text) => *self.output.write().await = metrics_text,
+            Err(e) => error!("[{}] Failed to gather metrics: {}", self.name, e),
+        }
+    }
+
+    /// Whether this instance holds the failover lease, or runs without
+    /// one.
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::Relaxed)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// This device's own metrics, without the other devices'.
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn first_device(&self) -> FirstDevice {
+        self.first_device
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn set_first_device(&self, outputs: FirstDevice) -> FirstDevice {
+        std::mem::replace(
+            &mut self.first_device.lock().unwrap_or_else(|e| e.into_inner()),
+            outputs,
+        )
+    }
+
+    /// Applies a reloaded configuration. The poll interval, sources and
+    /// prices take effect from the next poll; metric state is kept, except
+    /// for the cost metrics once the prices are removed.
+    pub fn reload(&self, config: Config) {
+        if let Ok(contract) = config.contract()
+            && let Err(e) = self.metrics.set_contract(contract)
+        {
+            warn!("[{}] Failed to apply prices: {:#}", self.name, e);
+        }
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
+
+    pub fn status(&self) -> PollStatus {
+        *self.status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// How long the poll in progress has been running; zero between polls.
+    pub fn busy_for(&self) -> Duration {
+        self.busy_since
+            .lock()
+            .ok()
+            .and_then(|busy_since| busy_since.map(|since| since.elapsed()))
+            .unwrap_or_default()
+    }
+
+    fn set_busy(&self, busy: bool) {
+        if let Ok(mut busy_since) = self.busy_since.lock() {
+            *busy_since = busy.then(Instant::now);
+        }
+    }
+
+    /// Data URL currently polled.
+    pub async fn url(&self) -> String {
+        self.client.read().await.url().to_string()
+    }
+
+    /// Points the poller at another device address. Metric state is kept
+    /// and the next poll uses the new address.
+    pub async fn retarget(&self, host: &str) {
+        let mut client = self.client.write().await;
+        *client = client.with_urls(device_url(host), device_v2_url(host));
+        // Another device's uptime says nothing about a reboot
+        if let Ok(mut last_uptime) = self.last_uptime.lock() {
+            *last_uptime = None;
+        }
+        info!("[{}] Now polling {}", self.name, client.url());
+    }
+
+    /// Applies the reloaded request timeout and API v2 token of `device`
+    /// from the next poll.
+    pub async fn reconfigure(&self, config: &Config, device: &Device) -> anyhow::Result<()> {
+        let timeout = config.device_http_timeout(device);
+        let token = config.device_api_token(device)?;
+        if self
+            .client
+            .write()
+            .await
+            .reconfigure(timeout, device.v2_url(), token)?
+        {
+            info!(
+                "[{}] Applied the new request timeout or API token",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Polls forever. The cadence starts at the device's or the configured
+    /// interval and follows the meter's SMR version once it is known, and a
+    /// reloaded `--poll-interval` or device `interval`.
+    pub async fn run(&self) {
+        // A restart after a panic leaves no poll in progress
+        self.set_busy(false);
+        let mut state = PollerState::Starting;
+        let mut detector = EventDetector::default();
+        let mut overload = self.overload.map(OverloadDetector::new);
+        let mut capabilities = None;
+        let mut poll_interval = self.config().device_poll_interval(&self.name, None);
+        let mut ticker = ticker(poll_interval);
+        ticker.tick().await; // First tick completes immediately
+        let mut last_poll = std::time::Instant::now();
+        // Read at startup
+        let mut device_info_at = std::time::Instant::now();
+        let mut system_at: Option<std::time::Instant> = None;
+        let mut circuit_open = false;
+
+        loop {
+            match &self.on_demand {
+                Some(on_demand) => on_demand.requested.notified().await,
+                None => {
+                    ticker.tick().await;
+                }
+            }
+            if !self.leader.load(Ordering::Relaxed) {
+                self.complete_on_demand(false);
+                continue;
+            }
+            let config = self.config();
+            let breaker = config.circuit_breaker();
+            if let (
+                Some(breaker),
+                PollerState::Failing {
+                    consecutive_failures,
+                },
+            ) = (breaker, state)
+                && !breaker.allows(consecutive_failures, last_poll.elapsed())
+            {
+                self.republish().await;
+                self.complete_on_demand(false);
+                continue;
+            }
+
+            let previous = state;
+            last_poll = std::time::Instant::now();
+            self.set_busy(true);
+            let data = self.poll_once().await;
+            self.set_busy(false);
+            state = match data {
+                Some(_) => state.on_success(),
+                None => state.on_failure(),
+            };
+            self.log_transition(previous, state);
+            if let PollerState::Failing {
+                consecutive_failures,
+            } = state
+                && config.rediscover_due(consecutive_failures)
+            {
+                self.rediscover().await;
+            }
+            let open = match (breaker, state) {
+                (
+                    Some(breaker),
+                    PollerState::Failing {
+                        consecutive_failures,
+                    },
+                ) => breaker.is_open(consecutive_failures),
+                _ => false,
+            };
+            if open != circuit_open {
+                circuit_open = open;
+                if let (true, Some(breaker)) = (open, breaker) {
+                    warn!(
+                        "[{}] Device unreachable after {} polls, probing every {:?}",
+                        self.name, breaker.threshold, breaker.probe_interval
+                    );
+                }
+                self.metrics.set_circuit_open(open);
+                self.republish().await;
+            }
+            if let Ok(mut status) = self.status.lock() {
+                status.state = state;
+                if data.is_some() {
+                    status.last_success = Some(chrono::Local::now());
+                }
+            }
+            if let Some(readiness) = &self.readiness {
+                readiness
+                    .write()
+                    .await
+                    .record(data.is_some(), std::time::Instant::now());
+            }
+            self.complete_on_demand(true);
+
+            if let Some(data) = data {
+                self.events.publish(&self.name, detector.detect(&data));
+                if let Some(overload) = &mut overload {
+                    let changes = overload.observe(&data, std::time::Instant::now());
+                    for change in &changes {
+                        self.metrics.set_fuse_overload(change.phase, change.active);
+                    }
+                    self.events
+                        .publish(&self.name, changes.into_iter().map(Into::into).collect());
+                }
+
+                capabilities = Some(SmrCapabilities::from_smr_version(data.smr_version));
+
+                if config
+                    .device_info_interval_duration()
+                    .is_some_and(|interval| device_info_at.elapsed() >= interval)
+                {
+                    device_info_at = std::time::Instant::now();
+                    self.refresh_device_info().await;
+                }
+                if config
+                    .system_interval_duration()
+                    .is_some_and(|interval| system_at.is_none_or(|at| at.elapsed() >= interval))
+                {
+                    system_at = Some(std::time::Instant::now());
+                    self.refresh_system_status().await;
+                }
+            }
+
+            let interval = config.device_poll_interval(&self.name, capabilities.as_ref());
+            if interval != poll_interval {
+                info!("[{}] Polling every {:?}", self.name, interval);
+                poll_interval = interval;
+                ticker = self::ticker(poll_interval);
+                ticker.tick().await;
+            }
+        }
+    }
+
+    /// Looks the device up by serial number and polls it at its new
+    /// address, if it moved.
+    async fn rediscover(&self) {
+        let Some(rediscover) = &self.rediscover else {
+            return;
+        };
+        let Some(serial) = self.serial.lock().ok().and_then(|serial| serial.clone()) else {
+            return;
+        };
+        match rediscover(serial.clone()).await {
+            Ok(Some(host)) if device_url(&host) != self.url().await => {
+                info!("[{}] Found device {} at {}", self.name, serial, host);
+                self.retarget(&host).await;
+            }
+            Ok(Some(_)) => debug!("[{}] Device {} has not moved", self.name, serial),
+            Ok(None) => warn!("[{}] Device {} not found through mDNS", self.name, serial),
+            Err(e) => warn!("[{}] Failed to rediscover the device: {:#}", self.name, e),
+        }
+    }
+
+    /// Re-reads `/api` so `homewizard_device_info` follows firmware
+    /// updates. Published with the next poll.
+    async fn refresh_device_info(&self) {
+        let client = self.client.read().await.clone();
+        match client.fetch_device_info().await {
+            Ok(info) => {
+                self.metrics.set_device_info(&info);
+                if let Ok(mut serial) = self.serial.lock() {
+                    *serial = Some(info.serial);
+                }
+            }
+            Err(e) => debug!(
+                "[{}] Failed to refresh device information: {}",
+                self.name, e
+            ),
+        }
+    }
+
+    /// Reads the cloud setting and uptime, counting a reboot when the
+    /// uptime went down. Published with the next poll.
+    async fn refresh_system_status(&self) {
+        let client = self.client.read().await.clone();
+        match client.fetch_system().await {
+            Ok(status) => {
+                self.metrics.set_system_status(&status);
+                if let Some(uptime) = status.uptime_secs {
+                    let previous = self
+                        .last_uptime
+                        .lock()
+                        .ok()
+                        .and_then(|mut last| last.replace(uptime));
+                    if previous.is_some_and(|previous| uptime < previous) {
+                        self.metrics.record_device_restart();
+                        self.events
+                            .publish(&self.name, vec![DeviceEvent::restart(uptime)]);
+                    }
+                }
+            }
+            Err(e) => debug!("[{}] Failed to read system status: {}", self.name, e),
+        }
+    }
+
+    /// Fetches one reading and publishes it. Returns the reading when the
+    /// poll succeeded.
+    async fn poll_once(&self) -> Option<HomeWizardData> {
+        let client = self.client.read().await.clone();
+        let started = std::time::Instant::now();
+        let config = self.config();
+        // Validated at startup and on reload
+        let retry = config.retry_policy().unwrap_or_default();
+        let fetched = retry
+            .run(
+                || client.fetch_with_fallback(&config.sources),
+                |e, delay| {
+                    debug!(
+                        "[{}] Fetch failed, retrying in {:?}: {}",
+                        self.name, delay, e
+                    );
+                    self.metrics.record_fetch_retry();
+                },
+            )
+            .await;
+        self.metrics.observe_fetch_duration(started.elapsed());
+        let (data, source) = match fetched {
+            Ok(reading) => reading,
+            Err(e) => {
+                warn!(
+                    "[{}] Failed to fetch data from HomeWizard: {}",
+                    self.name, e
+                );
+                // Publish `up` going to 0 right away, and make sure the
+                // next reading is published even if it did not change
+                self.metrics.record_poll_error(e.class());
+                self.forget();
+                self.republish().await;
+                return None;
+            }
+        };
+        self.metrics.record_poll_success();
+        debug!(
+            "[{}] Successfully fetched data from HomeWizard ({})",
+            self.name,
+            source.as_str()
+        );
+
+        // Unchanged readings still update the metrics: gas age, water flow
+        // decay, clock drift and the cost projection depend on the time
+        let unchanged = self.is_unchanged(&data, source);
+        if unchanged {
+            debug!("[{}] Reading unchanged, skipping sinks", self.name);
+            self.metrics.record_unchanged_poll();
+        }
+        self.metrics.set_active_source(source.as_str());
+        if let Err(e) = self.metrics.update(&data) {
+            error!("[{}] Failed to update metrics: {}", self.name, e);
+            return None;
+        }
+
+        match self.render() {
+            Ok(metrics_text) => {
+                if let Some(path) = &config.textfile_output
+                    && let Err(e) = textfile::write_atomic(path, &metrics_text).await
+                {
+                    warn!(
+                        "[{}] Failed to write textfile {}: {}",
+                        self.name,
+                        path.display(),
+                        e
+                    );
+                }
+                if !unchanged {
+                    self.push(&metrics_text);
+                }
+                *self.output.write().await = metrics_text;
+                self.remember(&data, source);
+                let now = chrono::Local::now();
+                let now_ms = now.timestamp_millis();
+                let first_device = self.first_device();
+                if let Some(recent) = &first_device.recent {
+                    recent.write().await.push(Sample::from_data(now_ms, &data));
+                }
+                if let Some(home_assistant) = &first_device.home_assistant {
+                    home_assistant.write().await.update(
+                        &data,
+                        timezone::today(self.config().timezone),
+                        now.timestamp(),
+                    );
+                }
+                if !unchanged {
+                    self.publish(&data, now_ms);
+                }
+                Some(data)
+            }
+            Err(e) => {
+                error!("[{}] Failed to gather metrics: {}", self.name, e);
+                None
+            }
+        }
+    }
+
+    fn render(&self) -> anyhow::Result<String> {
+        metrics::gather_all(&self.exposition.snapshot())
+    }
+
+    /// Publishes the current metric state without a new reading.
+    async fn republish(&self) {
+        match self.render() {
+            Ok(metrics_text) => {
+                self.push(&metrics_text);
+                *self.output.write().await = metrics_text;
+            }
+            Err(e) => error!("[{}] Failed to gather metrics: {}", self.name, e),
+        }
+    }
+
+    /// Sends a new reading to MQTT, InfluxDB and the reading stream; repeats
+    /// are not sent again.
+    fn publish(&self, data: &HomeWizardData, now_ms: i64) {
+        if let Some(mqtt) = &self.mqtt
+            && let Err(e) = mqtt.publish(&self.name, data)
+        {
+            warn!("[{}] Failed to publish over MQTT: {}", self.name, e);
+        }
+        if let Some(writer) = self.influx.clone() {
+            let line = influx::render(data, now_ms * 1_000_000);
+            let name = self.name.clone();
+            tokio::spawn(async move {
+                if let Err(e) = writer.write(line).await {
+                    warn!("[{}] Failed to write to InfluxDB: {}", name, e);
+                }
+            });
+        }
+        if let Some(readings) = &self.first_device().readings {
+            readings.send_replace(Some(Reading {
+                timestamp_ms: now_ms,
+                data: data.clone(),
+            }));
+        }
+    }
+
+    /// Pushes to the Pushgateway in the background so a slow gateway never
+    /// delays polling.
+    fn push(&self, metrics_text: &str) {
+        if let Some(pushgateway) = self.pushgateway.clone() {
+            let name = self.name.clone();
+            let metrics_text = metrics_text.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = pushgateway.push(metrics_text).await {
+                    warn!("[{}] Failed to push to the Pushgateway: {}", name, e);
+                }
+            });
+        }
+    }
+
+    /// Whether the reading equals the previous one, in which case the reading
+    /// metrics are not updated and the Pushgateway, MQTT, InfluxDB and the
+    /// reading stream are skipped; the output is still re-rendered so the
+    /// exporter counters and `up` stay current. Meters on SMR 4 and older
+    /// only update every 10 seconds, so fast polling mostly sees repeats.
+    fn is_unchanged(&self, data: &HomeWizardData, source: Source) -> bool {
+        self.last_reading.lock().is_ok_and(|last| {
+            last.as_ref()
+                .is_some_and(|(last_data, last_source)| last_data == data && *last_source == source)
+        })
+    }
+
+    /// Releases scrapes waiting in [`Poller::refresh`]; `polled` tells
+    /// whether the output now holds a fresh reading.
+    fn complete_on_demand(&self, polled: bool) {
+        if let Some(on_demand) = &self.on_demand {
+            on_demand.completed.send_modify(|completed| {
+                if polled {
+                    *completed = Some(Instant::now());
+                }
+            });
+        }
+    }
+
+    /// Drops the remembered reading after a failed poll.
+    fn forget(&self) {
+        if let Ok(mut last) = self.last_reading.lock() {
+            *last = None;
+        }
+    }
+
+    /// Remembers a reading once it has been published.
+    fn remember(&self, data: &HomeWizardData, source: Source) {
+        if let Ok(mut last) = self.last_reading.lock() {
+            *last = Some((data.clone(), source));
+        }
+    }
+
+    fn log_transition(&self, previous: PollerState, current: PollerState) {
+        match (previous, current) {
+            (PollerState::Starting, PollerState::Healthy) => {
+                info!("[{}] First successful poll", self.name)
+            }
+            (
+                PollerState::Failing {
+                    consecutive_failures,
+                },
+                PollerState::Healthy,
+            ) => info!(
+                "[{}] Recovered after {} failed polls",
+                self.name, consecutive_failures
+            ),
+            _ => {}
+        }
+    }
+}
+
+fn ticker(period: Duration) -> Interval {
+    let mut ticker = tokio::time::interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker
+}
+
+/// Builds the poller of a device added at runtime, wired to the same sinks
+/// as the devices polled since startup.
+pub type PollerFactory = Arc<
+    dyn Fn(Arc<Config>, Device) -> Pin<Box<dyn Future<Output = anyhow::Result<Poller>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// A change to the set of supervised pollers.
+enum Change {
+    Start(Arc<Poller>),
+    Stop(String),
+}
+
+/// The pollers of all devices. Devices added or removed at runtime, by the
+/// admin API or a reload, are started and stopped by the [`Scheduler`].
+pub struct Fleet {
+    pollers: std::sync::RwLock<Vec<Arc<Poller>>>,
+    metrics: DeviceMetrics,
+    factory: Option<PollerFactory>,
+    /// The configuration devices added at runtime are polled with
+    config: std::sync::RwLock<Option<Arc<Config>>>,
+    /// Devices the admin API changed: the host each was pointed at, or
+    /// `None` once removed. Reloads leave them alone until the
+    /// configuration agrees.
+    overrides: Mutex<HashMap<String, Option<String>>>,
+    /// The first device's outputs while no device is polled
+    first_device: Mutex<FirstDevice>,
+    changes: mpsc::UnboundedSender<Change>,
+    /// Taken by the scheduler when it starts
+    pending: Mutex<Option<mpsc::UnboundedReceiver<Change>>>,
+}
+
+impl Default for Fleet {
+    fn default() -> Self {
+        Self::new(Vec::new(), DeviceMetrics::default())
+    }
+}
+
+impl Fleet {
+    /// The pollers of the devices configured at startup, whose metrics are
+    /// in `metrics`.
+    pub fn new(pollers: Vec<Arc<Poller>>, metrics: DeviceMetrics) -> Self {
+        let (changes, pending) = mpsc::unbounded_channel();
+        Self {
+            pollers: std::sync::RwLock::new(pollers),
+            metrics,
+            factory: None,
+            config: std::sync::RwLock::new(None),
+            overrides: Mutex::new(HashMap::new()),
+            first_device: Mutex::new(FirstDevice::default()),
+            changes,
+            pending: Mutex::new(Some(pending)),
+        }
+    }
+
+    /// Enables adding devices at runtime, polled with `config` until a
+    /// reload replaces it.
+    pub fn with_factory(mut self, factory: PollerFactory, config: Config) -> Self {
+        self.factory = Some(factory);
+        self.set_config(config);
+        self
+    }
+
+    /// The current configuration, once [`Fleet::with_factory`] set one.
+    pub fn config(&self) -> Option<Arc<Config>> {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn set_config(&self, config: Config) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(config));
+    }
+
+    pub fn pollers(&self) -> Vec<Arc<Poller>> {
+        self.pollers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Poller>> {
+        self.pollers()
+            .into_iter()
+            .find(|poller| poller.name() == name)
+    }
+
+    /// Builds a poller for `device` and starts polling it.
+    pub async fn add(&self, device: Device) -> anyhow::Result<Arc<Poller>> {
+        let (Some(factory), Some(config)) = (&self.factory, self.config()) else {
+            anyhow::bail!("Devices cannot be added at runtime");
+        };
+        anyhow::ensure!(
+            self.get(&device.name).is_none(),
+            "Device {:?} is already polled",
+            device.name
+        );
+        let name = device.name.clone();
+        let poller = Arc::new(factory(config, device).await?);
+        {
+            let mut pollers = self.pollers.write().unwrap_or_else(|e| e.into_inner());
+            // Another request may have added it while this one identified it
+            anyhow::ensure!(
+                pollers.iter().all(|existing| existing.name() != name),
+                "Device {name:?} is already polled"
+            );
+            if pollers.is_empty() {
+                let outputs = std::mem::take(
+                    &mut *self.first_device.lock().unwrap_or_else(|e| e.into_inner()),
+                );
+                poller.set_first_device(outputs);
+            }
+            pollers.push(poller.clone());
+        }
+        self.metrics.add(poller.metrics().clone());
+        let _ = self.changes.send(Change::Start(poller.clone()));
+        info!("[{}] Polling {}", name, poller.url().await);
+        Ok(poller)
+    }
+
+    /// Stops polling the device named `name` and drops its metrics.
+    pub async fn remove(&self, name: &str) -> Option<Arc<Poller>> {
+        let poller = {
+            let mut pollers = self.pollers.write().unwrap_or_else(|e| e.into_inner());
+            let index = pollers.iter().position(|poller| poller.name() == name)?;
+            pollers.remove(index)
+        };
+        let _ = self.changes.send(Change::Stop(name.to_string()));
+        self.metrics.remove(poller.metrics());
+        self.hand_over_first_device(&poller).await;
+        match self.pollers().first() {
+            Some(other) => other.republish().await,
+            None => poller.output.write().await.clear(),
+        }
+        info!("[{}] Stopped polling", name);
+        Some(poller)
+    }
+
+    /// Moves the first device's outputs from `removed`, if it had them, to
+    /// the device that is now first, or keeps them for the next one added.
+    /// What `removed` reported is cleared either way.
+    async fn hand_over_first_device(&self, removed: &Poller) {
+        let outputs = removed.set_first_device(FirstDevice::default());
+        if outputs.is_empty() {
+            return;
+        }
+        outputs.clear().await;
+        match self.pollers().first() {
+            Some(next) => {
+                next.set_first_device(outputs);
+            }
+            None => *self.first_device.lock().unwrap_or_else(|e| e.into_inner()) = outputs,
+        }
+    }
+
+    /// Records that the admin API pointed `name` at `host`, or removed it.
+    pub fn set_override(&self, name: &str, host: Option<String>) {
+        if let Ok(mut overrides) = self.overrides.lock() {
+            overrides.insert(name.to_string(), host);
+        }
+    }
+
+    /// Whether a reload must leave `name` alone: the admin API changed it
+    /// and `host`, its address in the reloaded configuration (`None` when
+    /// absent), disagrees. An override the configuration caught up with,
+    /// such as a persisted one, is dropped.
+    pub fn overridden(&self, name: &str, host: Option<&str>) -> bool {
+        let Ok(mut overrides) = self.overrides.lock() else {
+            return false;
+        };
+        match overrides.get(name) {
+            Some(target) if target.as_deref() == host => {
+                overrides.remove(name);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+}
+
+/// Owns the device pollers and keeps each of them running independently.
+pub struct Scheduler {
+    fleet: Pollers,
+}
+
+impl Scheduler {
+    pub fn new(fleet: Pollers) -> Self {
+        Self { fleet }
+    }
+
+    /// Runs all pollers under one-for-one supervision: a poller that stops
+    /// or panics is restarted on its own without affecting the others.
+    /// Pollers added to or removed from the fleet are started and stopped.
+    pub async fn run(self) {
+        let unit = |poller: Arc<Poller>| -> (String, Start) {
+            let name = poller.name().to_string();
+            let start: Start = Arc::new(move || {
+                let poller = poller.clone();
+                Box::pin(async move { poller.run().await })
+            });
+            (name, start)
+        };
+        let units = self.fleet.pollers().into_iter().map(unit).collect();
+        let Some(mut pending) = self
+            .fleet
+            .pending
+            .lock()
+            .ok()
+            .and_then(|mut pending| pending.take())
+        else {
+            error!("The pollers are already supervised");
+            return;
+        };
+        let (changes, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(change) = pending.recv().await {
+                let change = match change {
+                    Change::Start(poller) => {
+                        let (name, start) = unit(poller);
+                        (name, Some(start))
+                    }
+                    Change::Stop(name) => (name, None),
+                };
+                if changes.send(change).is_err() {
+                    break;
+                }
+            }
+        });
+
+        supervise(units, receiver, RESTART_DELAY).await;
+    }
+}
+
+/// Starts one run of a supervised unit.
+type Start = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Runs every unit as its own task, restarting any that exits after
+/// `restart_delay`. `changes` starts a unit (`Some`) or stops the one of
+/// that name (`None`). Returns once no units are left and no more changes
+/// can arrive.
+async fn supervise(
+    units: Vec<(String, Start)>,
+    mut changes: mpsc::UnboundedReceiver<(String, Option<Start>)>,
+    restart_delay: Duration,
+) {
+    // Panicked tasks only report their task id, so keep the id -> unit map.
+    let mut tasks = JoinSet::new();
+    let mut running = HashMap::new();
+    for (name, start) in units {
+        spawn_unit(&mut tasks, &mut running, name, start, Duration::ZERO);
+    }
+    let mut open = true;
+
+    loop {
+        tokio::select! {
+            change = changes.recv(), if open => match change {
+                Some((name, Some(start))) => {
+                    spawn_unit(&mut tasks, &mut running, name, start, Duration::ZERO);
+                }
+                Some((name, None)) => {
+                    running.retain(|_, (running, _, handle)| {
+                        let stop = *running == name;
+                        if stop {
+                            handle.abort();
+                        }
+                        !stop
+                    });
+                }
+                None => open = false,
+            },
+            Some(result) = tasks.join_next_with_id(), if !tasks.is_empty() => {
+                let (id, reason) = match result {
+                    Ok((id, _)) => (id, "stopped"),
+                    Err(e) if e.is_panic() => (e.id(), "panicked"),
+                    Err(_) => continue,
+                };
+                let Some((name, start, _)) = running.remove(&id) else {
+                    continue;
+                };
+                error!(
+                    "Poller {} {}, restarting in {:?}",
+                    name, reason, restart_delay
+                );
+                spawn_unit(&mut tasks, &mut running, name, start, restart_delay);
+            },
+            else => return,
+        }
+    }
+}
+
+/// Units by task id, with the handle that stops them.
+type Running = HashMap<tokio::task::Id, (String, Start, AbortHandle)>;
+
+/// Starts `start` after `delay` as a task of `tasks`.
+fn spawn_unit(
+    tasks: &mut JoinSet<()>,
+    running: &mut Running,
+    name: String,
+    start: Start,
+    delay: Duration,
+) {
+    let run = start.clone();
+    let handle = tasks.spawn(async move {
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        run().await;
+    });
+    running.insert(handle.id(), (name, start, handle));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homewizard::DeviceInfo;
+    use crate::metrics::MetricsOptions;
+    use crate::recent::RecentSamples;
+    use clap::Parser;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_poller_state_transitions() {
+        let state = PollerState::Starting;
+        assert_eq!(state.on_success(), PollerState::Healthy);
+
+        let failing = state.on_failure();
+        assert_eq!(
+            failing,
+            PollerState::Failing {
+                consecutive_failures: 1
+            }
+        );
+        assert_eq!(
+            failing.on_failure(),
+            PollerState::Failing {
+                consecutive_failures: 2
+            }
+        );
+        assert_eq!(failing.on_success(), PollerState::Healthy);
+        assert_eq!(
+            PollerState::Healthy.on_failure(),
+            PollerState::Failing {
+                consecutive_failures: 1
+            }
+        );
+    }
+
+    fn poller_for(uri: String, output: SharedMetrics) -> Poller {
+        let config = Config::parse_from(["homewizard-p1-exporter", "--host", "127.0.0.1"]);
+        let client =
+            HomeWizardClient::new(format!("{uri}/api/v1/data"), Duration::from_secs(5)).unwrap();
+        let metrics = Arc::new(Metrics::with_options(MetricsOptions::default()).unwrap());
+        Poller::new("test", client, metrics, output, config)
+    }
+
+    #[tokio::test]
+    async fn test_poll_once_publishes_metrics() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../example-response.json")),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        let recent: SharedRecent = Arc::new(RwLock::new(RecentSamples::new(
+            Duration::from_secs(600),
+            100,
+        )));
+        let poller = poller_for(mock_server.uri(), output.clone()).with_recent(recent.clone());
+
+        let data = poller.poll_once().await;
+        assert!(data.is_some());
+        assert_eq!(recent.read().await.since(None).len(), 1);
+        assert!(
+            output
+                .read()
+                .await
+                .contains("homewizard_p1_power_import_total_kwh")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poll_once_failure_publishes_down() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let output: SharedMetrics = Arc::new(RwLock::new("previous 1\n".to_string()));
+        let poller = poller_for(mock_server.uri(), output.clone());
+
+        assert!(poller.poll_once().await.is_none());
+        let output = output.read().await;
+        assert!(output.contains("homewizard_exporter_up 0"));
+        assert!(output.contains(r#"homewizard_exporter_poll_errors_total{class="http_status"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn test_poll_once_retries_transient_failure() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../example-response.json")),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        let poller = poller_for(mock_server.uri(), output.clone());
+        poller.reload(Config::parse_from([
+            "homewizard-p1-exporter",
+            "--host",
+            "127.0.0.1",
+            "--retry-max-attempts",
+            "2",
+            "--retry-base-delay-ms",
+            "1",
+        ]));
+
+        assert!(poller.poll_once().await.is_some());
+        let output = output.read().await;
+        assert!(output.contains("homewizard_exporter_fetch_retries_total 1"));
+        assert!(output.contains("homewizard_exporter_poll_success_total 1"));
+        assert!(!output.contains("homewizard_exporter_poll_errors_total{"));
+    }
+
+    #[tokio::test]
+    async fn test_poll_once_skips_unchanged_reading() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../example-response.json")),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        let recent: SharedRecent = Arc::new(RwLock::new(RecentSamples::new(
+            Duration::from_secs(600),
+            100,
+        )));
+        let poller = poller_for(mock_server.uri(), output.clone()).with_recent(recent.clone());
+
+        assert!(poller.poll_once().await.is_some());
+        *output.write().await = "marker 1\n".to_string();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(poller.poll_once().await.is_some());
+
+        // The repeat reading still re-renders the output and is recorded
+        let output = output.read().await;
+        assert!(output.contains("homewizard_p1_unchanged_polls_total 1"));
+        assert!(output.contains("homewizard_exporter_poll_success_total 2"));
+        assert_eq!(recent.read().await.since(None).len(), 2);
+
+        // and keeps the time-derived metrics current
+        let age: f64 = output
+            .lines()
+            .find(|line| line.starts_with("homewizard_p1_gas_meter_reading_age_seconds{"))
+            .and_then(|line| line.rsplit(' ').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(age >= 0.02, "age {age}");
+    }
+
+    #[tokio::test]
+    async fn test_on_demand_polls_on_refresh() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../example-response.json")),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        let poller = Arc::new(
+            poller_for(mock_server.uri(), output.clone()).on_demand(Duration::from_secs(60)),
+        );
+        let running = tokio::spawn({
+            let poller = poller.clone();
+            async move { poller.run().await }
+        });
+
+        // Nothing is polled until a scrape asks for a reading
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(output.read().await.is_empty());
+
+        poller.refresh().await;
+        assert!(
+            output
+                .read()
+                .await
+                .contains("homewizard_p1_power_import_total_kwh")
+        );
+        assert_eq!(poller.status().state, PollerState::Healthy);
+        assert!(poller.status().last_success.is_some());
+
+        // A second scrape within the TTL reuses the reading
+        poller.refresh().await;
+        running.abort();
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_skips_polls() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        let poller =
+            Arc::new(poller_for(mock_server.uri(), output.clone()).on_demand(Duration::ZERO));
+        poller.reload(Config::parse_from([
+            "homewizard-p1-exporter",
+            "--host",
+            "127.0.0.1",
+            "--breaker-threshold",
+            "2",
+        ]));
+        let running = tokio::spawn({
+            let poller = poller.clone();
+            async move { poller.run().await }
+        });
+
+        poller.refresh().await;
+        assert!(
+            output
+                .read()
+                .await
+                .contains("homewizard_exporter_circuit_open 0")
+        );
+        poller.refresh().await;
+        assert!(
+            output
+                .read()
+                .await
+                .contains("homewizard_exporter_circuit_open 1")
+        );
+
+        // Until the probe interval has passed, scrapes leave the device alone
+        poller.refresh().await;
+        poller.refresh().await;
+        running.abort();
+    }
+
+    #[tokio::test]
+    async fn test_refresh_device_info_follows_firmware_update() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"product_type": "HWE-P1", "product_name": "P1 meter", "serial": "3c39e7aabbcc", "firmware_version": "6.00", "api_version": "v1"}"#,
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../example-response.json")),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        let poller = poller_for(mock_server.uri(), output.clone());
+        poller.metrics.set_device_info(&DeviceInfo {
+            product_type: "HWE-P1".to_string(),
+            product_name: "P1 meter".to_string(),
+            serial: "3c39e7aabbcc".to_string(),
+            firmware_version: "5.18".to_string(),
+            api_version: "v1".to_string(),
+        });
+
+        poller.refresh_device_info().await;
+        assert!(poller.poll_once().await.is_some());
+        let output = output.read().await;
+        assert!(output.contains(r#"firmware_version="6.00""#));
+        assert!(!output.contains(r#"firmware_version="5.18""#));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_system_status_counts_restarts() {
+        let mock_server = MockServer::start().await;
+        for uptime in [1000, 1060, 5] {
+            Mock::given(method("GET"))
+                .and(path("/api/v1/system"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                    r#"{{"cloud_enabled": true, "uptime_s": {uptime}}}"#
+                )))
+                .up_to_n_times(1)
+                .mount(&mock_server)
+                .await;
+        }
+
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        let poller = poller_for(mock_server.uri(), output);
+        let restarts = || {
+            poller
+                .metrics
+                .gather()
+                .unwrap()
+                .lines()
+                .find(|line| line.starts_with("homewizard_device_restarts_total "))
+                .map(str::to_string)
+        };
+
+        poller.refresh_system_status().await;
+        poller.refresh_system_status().await;
+        assert_eq!(
+            restarts().as_deref(),
+            Some("homewizard_device_restarts_total 0")
+        );
+        poller.refresh_system_status().await;
+        assert_eq!(
+            restarts().as_deref(),
+            Some("homewizard_device_restarts_total 1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retarget_switches_device() {
+        let old_device = MockServer::start().await;
+        let new_device = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&old_device)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../example-response.json")),
+            )
+            .mount(&new_device)
+            .await;
+
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        let poller = poller_for(old_device.uri(), output);
+        assert!(poller.poll_once().await.is_none());
+
+        let new_host = new_device.address().to_string();
+        poller.retarget(&new_host).await;
+
+        assert_eq!(poller.url().await, format!("http://{new_host}/api/v1/data"));
+        assert!(poller.poll_once().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rediscover_follows_device_to_new_address() {
+        let old_device = MockServer::start().await;
+        let new_device = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&old_device)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/data"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../example-response.json")),
+            )
+            .mount(&new_device)
+            .await;
+
+        let new_host = new_device.address().to_string();
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let rediscover: Rediscover = {
+            let new_host = new_host.clone();
+            let lookups = lookups.clone();
+            Arc::new(move |serial| {
+                lookups.fetch_add(1, Ordering::SeqCst);
+                let host = (serial == "3c39e7aabbcc").then(|| new_host.clone());
+                Box::pin(async move { Ok(host) })
+            })
+        };
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        let poller = poller_for(old_device.uri(), output)
+            .with_rediscover(rediscover, Some("3c39e7aabbcc".to_string()));
+        assert!(poller.poll_once().await.is_none());
+
+        poller.rediscover().await;
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert_eq!(poller.url().await, device_url(&new_host));
+        assert!(poller.poll_once().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_removing_first_device_hands_over_its_outputs() {
+        let output: SharedMetrics = Arc::new(RwLock::new(String::new()));
+        let recent: SharedRecent = Arc::new(RwLock::new(RecentSamples::new(
+            Duration::from_secs(600),
+            100,
+        )));
+        let readings = watch::Sender::new(None);
+        let first = poller_for("http://192.168.1.10".to_string(), output.clone())
+            .with_recent(recent.clone())
+            .with_readings(readings.clone());
+        let second = Arc::new(poller_for("http://192.168.1.20".to_string(), output));
+        let data = HomeWizardData::default();
+        recent.write().await.push(Sample::from_data(1000, &data));
+        readings.send_replace(Some(Reading {
+            timestamp_ms: 1000,
+            data,
+        }));
+        let fleet = Fleet::new(
+            vec![Arc::new(first), second.clone()],
+            DeviceMetrics::default(),
+        );
+
+        fleet.remove("test").await;
+        // The first device's polls are gone and the next device reports
+        assert!(recent.read().await.since(None).is_empty());
+        assert!(readings.borrow().is_none());
+        assert!(second.first_device().recent.is_some());
+
+        // Without devices the outputs wait for the next one added
+        fleet.remove("test").await;
+        assert!(second.first_device().is_empty());
+        assert!(!fleet.first_device.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_supervise_restarts_panicking_unit() {
+        let starts = Arc::new(AtomicUsize::new(0));
+        let counter = starts.clone();
+        let start: Start = Arc::new(move || {
+            let counter = counter.clone();
+            Box::pin(async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("poller crashed");
+                }
+                std::future::pending::<()>().await;
+            })
+        });
+        let (_changes, receiver) = mpsc::unbounded_channel();
+
+        let _ = tokio::time::timeout(
+            Duration::from_millis(200),
+            supervise(
+                vec![("flaky".to_string(), start)],
+                receiver,
+                Duration::from_millis(10),
+            ),
+        )
+        .await;
+
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_supervise_starts_and_stops_units() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let counter = running.clone();
+        let start: Start = Arc::new(move || {
+            let counter = counter.clone();
+            Box::pin(async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                std::future::pending::<()>().await;
+            })
+        });
+        let (changes, receiver) = mpsc::unbounded_channel();
+        let supervisor = tokio::spawn(supervise(Vec::new(), receiver, Duration::from_millis(10)));
+
+        changes.send(("added".to_string(), Some(start))).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(running.load(Ordering::SeqCst), 1);
+
+        // A stopped unit is not restarted
+        changes.send(("added".to_string(), None)).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(running.load(Ordering::SeqCst), 1);
+
+        drop(changes);
+        tokio::time::timeout(Duration::from_secs(1), supervisor)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+//! `config schema` subcommand: prints a JSON Schema of the `--config-file`
+//! settings, built from the command line options so it never falls behind
+//! them. Each setting is named after its environment variable.
+
+use anyhow::Result;
+use clap::{Arg, ArgAction};
+use serde_json::{Map, Value, json};
+use std::any::TypeId;
+
+use crate::config;
+
+pub fn run() -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&schema())?);
+    Ok(())
+}
+
+fn schema() -> Value {
+    let command = config::global_command();
+    let properties: Map<String, Value> = command
+        .get_arguments()
+        .filter_map(|arg| {
+            let env = arg.get_env()?.to_str()?;
+            Some((env.to_string(), property(arg)))
+        })
+        .collect();
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "homewizard-p1-exporter configuration",
+        "description": "Settings of --config-file, named after their environment variables",
+        "type": "object",
+        "properties": properties,
+        "additionalProperties": false,
+    })
+}
+
+/// The schema of one option's value, with its flag, default and help.
+fn property(arg: &Arg) -> Value {
+    let mut property = Map::new();
+    if let Some(help) = arg.get_help() {
+        property.insert("description".into(), help.to_string().into());
+    }
+    if let Some(long) = arg.get_long() {
+        property.insert("x-flag".into(), format!("--{long}").into());
+    }
+
+    let value_type = value_type(arg);
+    let list = arg.get_value_delimiter().is_some() || matches!(arg.get_action(), ArgAction::Append);
+    let choices: Vec<Value> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().into())
+        .collect();
+    let mut item = Map::new();
+    item.insert("type".into(), value_type.into());
+    if !choices.is_empty() && value_type != "boolean" {
+        item.insert("enum".into(), choices.into());
+    }
+
+    let defaults: Vec<Value> = arg
+        .get_default_values()
+        .iter()
+        .filter_map(|value| value.to_str())
+        .map(|value| typed(value, value_type))
+        .collect();
+    if list {
+        property.insert("type".into(), "array".into());
+        property.insert("items".into(), item.into());
+        if !defaults.is_empty() {
+            property.insert("default".into(), defaults.into());
+        }
+    } else {
+        property.extend(item);
+        if let Some(default) = defaults.into_iter().next() {
+            property.insert("default".into(), default);
+        } else if value_type == "boolean" {
+            property.insert("default".into(), false.into());
+        }
+    }
+    property.into()
+}
+
+/// JSON Schema type of the values `arg` parses into.
+fn value_type(arg: &Arg) -> &'static str {
+    if matches!(arg.get_action(), ArgAction::SetTrue) {
+        return "boolean";
+    }
+    let type_id = arg.get_value_parser().type_id();
+    let is = |id: TypeId| type_id == id;
+    if [
+        TypeId::of::<u8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<u64>(),
+        TypeId::of::<usize>(),
+        TypeId::of::<i32>(),
+        TypeId::of::<i64>(),
+    ]
+    .into_iter()
+    .any(is)
+    {
+        "integer"
+    } else if is(TypeId::of::<f64>()) || is(TypeId::of::<f32>()) {
+        "number"
+    } else if is(TypeId::of::<bool>()) {
+        "boolean"
+    } else {
+        "string"
+    }
+}
+
+/// `value`, a default from the command line definition, as a JSON value of
+/// `value_type`.
+fn typed(value: &str, value_type: &str) -> Value {
+    match value_type {
+        "integer" => value
+            .parse::<i64>()
+            .map_or_else(|_| value.into(), Value::from),
+        "number" => value
+            .parse::<f64>()
+            .map_or_else(|_| value.into(), Value::from),
+        "boolean" => value
+            .parse::<bool>()
+            .map_or_else(|_| value.into(), Value::from),
+        _ => value.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_describes_settings() {
+        let schema = schema();
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["additionalProperties"], false);
+        let properties = &schema["properties"];
+
+        let host = &properties["HOMEWIZARD_HOST"];
+        assert_eq!(host["type"], "array");
+        assert_eq!(host["items"]["type"], "string");
+        assert_eq!(host["x-flag"], "--host");
+
+        assert_eq!(properties["METRICS_PORT"]["type"], "integer");
+        assert_eq!(properties["METRICS_PORT"]["default"], 9898);
+        assert_eq!(properties["ENABLE_PROBE"]["type"], "boolean");
+        assert_eq!(properties["ENABLE_PROBE"]["default"], false);
+        assert_eq!(properties["LOG_FORMAT"]["type"], "string");
+        assert!(
+            properties["LOG_FORMAT"]["enum"]
+                .as_array()
+                .unwrap()
+                .contains(&json!("json"))
+        );
+        assert!(
+            properties["POLL_INTERVAL"]["description"]
+                .as_str()
+                .is_some_and(|help| !help.is_empty())
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Exporter state kept across restarts (`--state-file`), saved every
+//! minute and restored on startup:
+//!
+//! - the recent polls of the first device, so `/api/recent` is not empty
+//!   after a restart or upgrade. HomeWizard devices keep no history of
+//!   their own to fetch it from: API v1 and v2 only serve the latest
+//!   measurement.
+//! - the meter totals the daily and monthly aggregates count from (the
+//!   Home Assistant "today" values, month-to-date cost and its
+//!   projection), so they include what was used while the exporter was
+//!   down instead of restarting from the first reading after it.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::cost::MonthBaseline;
+use crate::homeassistant::{DayStart, SharedHomeAssistant};
+use crate::recent::{Sample, SharedRecent};
+use crate::scheduler::Pollers;
+use crate::textfile;
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What is saved, as JSON.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// Recent polls of the first device, oldest first
+    pub recent: Vec<Sample>,
+    /// Start of the day of the Home Assistant "today" values
+    pub day_start: Option<DayStart>,
+    /// Month tracked by the cost metrics, by device
+    pub months: BTreeMap<String, MonthBaseline>,
+}
+
+/// Where the state is saved from and restored into.
+#[derive(Clone)]
+pub struct Tracked {
+    pub recent: SharedRecent,
+    pub home_assistant: SharedHomeAssistant,
+    pub pollers: Pollers,
+}
+
+impl Tracked {
+    async fn state(&self) -> State {
+        State {
+            recent: self.recent.read().await.since(None),
+            day_start: self.home_assistant.read().await.day_start(),
+            months: self
+                .pollers
+                .pollers()
+                .iter()
+                .filter_map(|poller| {
+                    let baseline = poller.metrics().cost_baseline()?;
+                    Some((poller.name().to_string(), baseline))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// The saved state; empty before the first save.
+    pub fn load(&self) -> Result<State> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.path.display()));
+            }
+        };
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", self.path.display()))
+    }
+
+    pub async fn save(&self, state: &State) -> Result<()> {
+        textfile::write_atomic(&self.path, &serde_json::to_string(state)?)
+            .await
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    /// Restores the saved state, before the first poll.
+    pub async fn restore(&self, tracked: &Tracked) -> Result<()> {
+        let state = self.load()?;
+        if !state.recent.is_empty() {
+            info!(
+                "Restored {} recent polls from {}",
+                state.recent.len(),
+                self.path.display()
+            );
+        }
+        let mut recent = tracked.recent.write().await;
+        for sample in state.recent {
+            recent.push(sample);
+        }
+        if let Some(day_start) = state.day_start {
+            tracked.home_assistant.write().await.restore(day_start);
+        }
+        for poller in tracked.pollers.pollers() {
+            if let Some(&baseline) = state.months.get(poller.name()) {
+                poller.metrics().restore_cost_baseline(baseline);
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the state every minute.
+    pub async fn run(self, tracked: Tracked) {
+        let mut ticker = tokio::time::interval(SAVE_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await; // Nothing new to save yet
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.save(&tracked.state().await).await {
+                warn!("Failed to save the state: {:#}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homeassistant::HomeAssistantState;
+    use crate::homewizard::HomeWizardData;
+    use crate::metrics::DeviceMetrics;
+    use crate::recent::RecentSamples;
+    use crate::scheduler::Fleet;
+    use chrono::NaiveDate;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    fn tracked() -> Tracked {
+        Tracked {
+            recent: Arc::new(RwLock::new(RecentSamples::new(
+                Duration::from_secs(600),
+                100,
+            ))),
+            home_assistant: Arc::new(RwLock::new(HomeAssistantState::default())),
+            pollers: Arc::new(Fleet::new(Vec::new(), DeviceMetrics::default())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restores_recent_polls_and_day_start() {
+        let path =
+            std::env::temp_dir().join(format!("homewizard-state-{}.json", std::process::id()));
+        let file = StateFile::new(&path);
+        let _ = std::fs::remove_file(&path);
+
+        let empty = tracked();
+        file.restore(&empty).await.unwrap();
+        assert!(empty.recent.read().await.since(None).is_empty());
+        assert!(empty.home_assistant.read().await.day_start().is_none());
+
+        let data = HomeWizardData {
+            active_power_w: 250.0,
+            total_power_import_kwh: 100.0,
+            ..HomeWizardData::default()
+        };
+        let day = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+        let before = tracked();
+        before
+            .recent
+            .write()
+            .await
+            .push(Sample::from_data(1000, &data));
+        before.home_assistant.write().await.update(&data, day, 1);
+        file.save(&before.state().await).await.unwrap();
+
+        let after = tracked();
+        file.restore(&after).await.unwrap();
+        assert_eq!(
+            after.recent.read().await.since(None),
+            [Sample::from_data(1000, &data)]
+        );
+        let later = HomeWizardData {
+            total_power_import_kwh: 103.0,
+            ..data
+        };
+        after.home_assistant.write().await.update(&later, day, 2);
+        assert_eq!(
+            after
+                .home_assistant
+                .read()
+                .await
+                .sensors()
+                .unwrap()
+                .energy_import_today_kwh,
+            3.0
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
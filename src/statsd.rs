@@ -0,0 +1,276 @@
+//! StatsD emitter (`--statsd-host`): every `--statsd-interval` seconds the
+//! metrics are sent over UDP, gauges as `|g` and counters as the `|c`
+//! increment since the previous send. With the DogStatsD format labels
+//! become tags, which Datadog and Telegraf's statsd input understand.
+
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use prometheus::proto::{MetricFamily, MetricType};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::{debug, warn};
+
+use crate::metrics::{self, DeviceMetrics};
+use crate::remote_write::{self, Series};
+
+/// Keeps datagrams below a typical MTU.
+const MAX_DATAGRAM: usize = 1400;
+
+/// How labels are sent.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatsdFormat {
+    /// DogStatsD tags: `name:1|g|#device:house`
+    #[default]
+    Dogstatsd,
+    /// Plain StatsD: label values appended to the name,
+    /// `name.house:1|g`
+    Plain,
+}
+
+pub struct StatsdEmitter {
+    /// `host:port` of the StatsD server
+    address: String,
+    format: StatsdFormat,
+    prefix: String,
+    /// Extra `key:value` tags sent with every metric
+    tags: Vec<String>,
+    interval: Duration,
+    /// Last value of every counter series, to send increments
+    counters: HashMap<String, f64>,
+}
+
+impl StatsdEmitter {
+    pub fn new(
+        address: String,
+        format: StatsdFormat,
+        prefix: &str,
+        tags: Vec<String>,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            address,
+            format,
+            prefix: prefix.trim_matches('.').to_string(),
+            tags,
+            interval,
+            counters: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self, devices: DeviceMetrics) {
+        let mut socket = None;
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await; // Skip the immediate tick, before the first poll
+        loop {
+            ticker.tick().await;
+            let lines = self.render(&metrics::gather_families(&devices.snapshot()));
+            if socket.is_none() {
+                match connect(&self.address).await {
+                    Ok(connected) => socket = Some(connected),
+                    Err(e) => {
+                        warn!("Failed to reach StatsD at {}: {}", self.address, e);
+                        continue;
+                    }
+                }
+            }
+            let Some(connected) = &socket else { continue };
+            match send(connected, &lines).await {
+                Ok(()) => debug!("Sent {} metrics to {}", lines.len(), self.address),
+                Err(e) => {
+                    warn!("Failed to send metrics to {}: {}", self.address, e);
+                    // Resolve the address again next time
+                    socket = None;
+                }
+            }
+        }
+    }
+
+    /// One line per series. Counters are sent as their increment since the
+    /// previous call, so the first call only records them.
+    fn render(&mut self, families: &[MetricFamily]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for family in families {
+            let counter = family.get_field_type() == MetricType::COUNTER;
+            for series in remote_write::series(std::slice::from_ref(family)) {
+                if !series.value.is_finite() {
+                    continue;
+                }
+                let name = self.name(&series);
+                let tags = self.tags(&series);
+                let (value, kind) = if counter {
+                    let previous = self.counters.insert(format!("{name}{tags}"), series.value);
+                    match previous {
+                        None => continue,
+                        // A reset (restart of the device's metrics) counts from zero
+                        Some(previous) if series.value < previous => (series.value, "c"),
+                        Some(previous) => (series.value - previous, "c"),
+                    }
+                } else {
+                    (series.value, "g")
+                };
+                lines.push(format!("{name}:{value}|{kind}{tags}"));
+            }
+        }
+        lines
+    }
+
+    fn name(&self, series: &Series) -> String {
+        let mut name = self.prefix.clone();
+        let metric = series
+            .labels
+            .iter()
+            .find(|(label, _)| label == "__name__")
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default();
+        let mut components = vec![metric.to_string()];
+        if self.format == StatsdFormat::Plain {
+            components.extend(
+                labels(series).map(|(_, value)| sanitize(value, &['.', ':', '|', '@', '#'])),
+            );
+        }
+        for component in components {
+            if !name.is_empty() {
+                name.push('.');
+            }
+            name.push_str(&component);
+        }
+        name
+    }
+
+    fn tags(&self, series: &Series) -> String {
+        if self.format == StatsdFormat::Plain {
+            return String::new();
+        }
+        let tags: Vec<String> = labels(series)
+            .map(|(label, value)| format!("{label}:{}", sanitize(value, &[',', '|', '#'])))
+            .chain(self.tags.iter().cloned())
+            .collect();
+        if tags.is_empty() {
+            String::new()
+        } else {
+            format!("|#{}", tags.join(","))
+        }
+    }
+}
+
+fn labels(series: &Series) -> impl Iterator<Item = &(String, String)> {
+    series
+        .labels
+        .iter()
+        .filter(|(label, _)| label != "__name__")
+}
+
+/// Replaces the characters that delimit fields of the protocol.
+fn sanitize(value: &str, reserved: &[char]) -> String {
+    value
+        .chars()
+        .map(|c| if reserved.contains(&c) { '_' } else { c })
+        .collect()
+}
+
+async fn connect(address: &str) -> Result<UdpSocket> {
+    let address = tokio::net::lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("{address} did not resolve"))?;
+    let local = if address.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(address).await?;
+    Ok(socket)
+}
+
+/// Sends the lines newline-separated, packed into datagrams of at most
+/// [`MAX_DATAGRAM`] bytes.
+async fn send(socket: &UdpSocket, lines: &[String]) -> Result<()> {
+    let mut datagram = String::new();
+    for line in lines {
+        if !datagram.is_empty() && datagram.len() + line.len() + 1 > MAX_DATAGRAM {
+            socket.send(datagram.as_bytes()).await?;
+            datagram.clear();
+        }
+        if !datagram.is_empty() {
+            datagram.push('\n');
+        }
+        datagram.push_str(line);
+    }
+    if !datagram.is_empty() {
+        socket.send(datagram.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Counter, GaugeVec, Opts, Registry};
+
+    fn registry() -> (Registry, Counter) {
+        let registry = Registry::new();
+        let power = GaugeVec::new(
+            Opts::new("homewizard_p1_power_watts", "Power").const_label("device", "house"),
+            &["phase"],
+        )
+        .unwrap();
+        let polls = Counter::with_opts(Opts::new("polls_total", "Polls")).unwrap();
+        registry.register(Box::new(power.clone())).unwrap();
+        registry.register(Box::new(polls.clone())).unwrap();
+        power.with_label_values(&["l1"]).set(250.5);
+        polls.inc_by(3.0);
+        (registry, polls)
+    }
+
+    fn emitter(format: StatsdFormat) -> StatsdEmitter {
+        StatsdEmitter::new(
+            "127.0.0.1:8125".to_string(),
+            format,
+            "",
+            vec!["env:home".to_string()],
+            Duration::from_secs(10),
+        )
+    }
+
+    #[test]
+    fn test_render_dogstatsd_sends_counter_increments() {
+        let (registry, polls) = registry();
+        let mut emitter = emitter(StatsdFormat::Dogstatsd);
+        assert_eq!(
+            emitter.render(&registry.gather()),
+            ["homewizard_p1_power_watts:250.5|g|#device:house,phase:l1,env:home"]
+        );
+
+        polls.inc_by(2.0);
+        let lines = emitter.render(&registry.gather());
+        assert!(lines.contains(&"polls_total:2|c|#env:home".to_string()));
+    }
+
+    #[test]
+    fn test_render_plain_appends_label_values() {
+        let (registry, _) = registry();
+        let mut emitter = emitter(StatsdFormat::Plain);
+        emitter.prefix = "homewizard".to_string();
+        assert_eq!(
+            emitter.render(&registry.gather()),
+            ["homewizard.homewizard_p1_power_watts.house.l1:250.5|g"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_packs_lines() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = connect(&server.local_addr().unwrap().to_string())
+            .await
+            .unwrap();
+        send(&socket, &["a:1|g".to_string(), "b:2|c".to_string()])
+            .await
+            .unwrap();
+        let mut buffer = [0; 64];
+        let received = server.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..received], b"a:1|g\nb:2|c");
+    }
+}
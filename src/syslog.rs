@@ -0,0 +1,109 @@
+//! Log lines sent to the local syslog daemon (`--log-target syslog`), one
+//! datagram per event with the priority taken from its level.
+
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// `daemon` facility
+const FACILITY: u8 = 3;
+const SOCKET: &str = "/dev/log";
+
+pub struct Syslog {
+    socket: UnixDatagram,
+    tag: String,
+}
+
+impl Syslog {
+    pub fn connect() -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(SOCKET)?;
+        Ok(Self::with_socket(socket))
+    }
+
+    fn with_socket(socket: UnixDatagram) -> Self {
+        Self {
+            socket,
+            tag: format!("{}[{}]", env!("CARGO_PKG_NAME"), std::process::id()),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = Message<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.message(Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.message(*meta.level())
+    }
+}
+
+impl Syslog {
+    fn message(&self, level: Level) -> Message<'_> {
+        let mut buffer = Vec::with_capacity(256);
+        let _ = write!(buffer, "<{}>{}: ", FACILITY * 8 + severity(level), self.tag);
+        Message {
+            socket: &self.socket,
+            buffer,
+        }
+    }
+}
+
+/// Syslog severity of a tracing level.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// One event, sent when the formatter is done with it.
+pub struct Message<'a> {
+    socket: &'a UnixDatagram,
+    buffer: Vec<u8>,
+}
+
+impl Write for Message<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Message<'_> {
+    fn drop(&mut self) {
+        if self.buffer.last() == Some(&b'\n') {
+            self.buffer.pop();
+        }
+        // Nowhere left to report a failure
+        let _ = self.socket.send(&self.buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_priority_and_tag() {
+        let (daemon, exporter) = UnixDatagram::pair().unwrap();
+        let syslog = Syslog::with_socket(exporter);
+
+        writeln!(syslog.message(Level::WARN), "Poll failed").unwrap();
+        let mut buffer = [0; 256];
+        let received = daemon.recv(&mut buffer).unwrap();
+        let message = std::str::from_utf8(&buffer[..received]).unwrap();
+        assert!(message.starts_with("<28>homewizard-p1-exporter["));
+        assert!(message.ends_with("]: Poll failed"));
+    }
+}
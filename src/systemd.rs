@@ -0,0 +1,154 @@
+//! systemd integration for `Type=notify` units: `READY=1` once a device has
+//! been polled successfully, and watchdog pings while no poll is stuck, so
+//! `WatchdogSec=` restarts a hung exporter. Active when systemd sets
+//! `NOTIFY_SOCKET`.
+
+use std::time::Duration;
+
+use crate::scheduler::Pollers;
+
+/// Notifies systemd of readiness and keeps its watchdog fed.
+#[cfg(target_os = "linux")]
+pub async fn run(pollers: Pollers) {
+    use tracing::{info, warn};
+
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let notifier = match Notifier::connect(&socket) {
+        Ok(notifier) => notifier,
+        Err(e) => {
+            warn!("Failed to connect to the systemd notify socket: {}", e);
+            return;
+        }
+    };
+    let watchdog = watchdog_timeout(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+    );
+    if let Some(timeout) = watchdog {
+        info!("systemd watchdog enabled with a {:?} timeout", timeout);
+    }
+
+    let mut ready = false;
+    loop {
+        tokio::time::sleep(match (ready, watchdog) {
+            (true, Some(timeout)) => timeout / 2,
+            _ => Duration::from_secs(1),
+        })
+        .await;
+
+        if !ready
+            && pollers
+                .pollers()
+                .iter()
+                .any(|poller| poller.status().last_success.is_some())
+        {
+            ready = true;
+            match notifier.notify("READY=1") {
+                Ok(()) => info!("Notified systemd that the exporter is ready"),
+                Err(e) => warn!("Failed to notify systemd: {}", e),
+            }
+        }
+        let Some(timeout) = watchdog else {
+            if ready {
+                return;
+            }
+            continue;
+        };
+        // Withholding the ping lets systemd restart the exporter
+        match pollers
+            .pollers()
+            .into_iter()
+            .find(|poller| poller.busy_for() >= timeout)
+        {
+            Some(stuck) => warn!(
+                "[{}] Poll stuck for {:?}, skipping the watchdog ping",
+                stuck.name(),
+                stuck.busy_for()
+            ),
+            None => {
+                if let Err(e) = notifier.notify("WATCHDOG=1") {
+                    warn!("Failed to ping the systemd watchdog: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn run(_pollers: Pollers) {}
+
+/// Datagram socket to the service manager.
+#[cfg(target_os = "linux")]
+struct Notifier {
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+#[cfg(target_os = "linux")]
+impl Notifier {
+    /// Connects to a socket path, or to an abstract socket for paths
+    /// starting with `@`.
+    fn connect(path: &std::ffi::OsStr) -> std::io::Result<Self> {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let socket = UnixDatagram::unbound()?;
+        match path.as_bytes().strip_prefix(b"@") {
+            Some(name) => socket.connect_addr(&SocketAddr::from_abstract_name(name)?)?,
+            None => socket.connect(path)?,
+        }
+        Ok(Self { socket })
+    }
+
+    fn notify(&self, state: &str) -> std::io::Result<()> {
+        self.socket.send(state.as_bytes()).map(|_| ())
+    }
+}
+
+/// The watchdog timeout from `WATCHDOG_USEC`, unless `WATCHDOG_PID` names
+/// another process.
+fn watchdog_timeout(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(std::process::id())) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_timeout() {
+        let own_pid = std::process::id().to_string();
+        assert_eq!(
+            watchdog_timeout(Some("30000000"), None),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            watchdog_timeout(Some("30000000"), Some(&own_pid)),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(watchdog_timeout(Some("30000000"), Some("1")), None);
+        assert_eq!(watchdog_timeout(Some("0"), None), None);
+        assert_eq!(watchdog_timeout(None, None), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_notifier_sends_datagrams() {
+        let path = std::env::temp_dir().join(format!("homewizard-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier::connect(path.as_os_str()).unwrap();
+        notifier.notify("READY=1").unwrap();
+        let mut buffer = [0; 64];
+        let received = systemd.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..received], b"READY=1");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::homewizard::{ExternalSensor, HomeWizardData, PowerFailure};
+
+/// Errors decoding a raw DSMR (P1) telegram from `/api/v1/telegram`.
+#[derive(Error, Debug, PartialEq)]
+pub enum TelegramError {
+    #[error("telegram has no header line")]
+    MissingHeader,
+
+    #[error("CRC mismatch: telegram says {expected:04X}, computed {computed:04X}")]
+    CrcMismatch { expected: u16, computed: u16 },
+
+    #[error("telegram has no {0} reading")]
+    MissingField(&'static str),
+}
+
+/// One COSEM object line: `1-0:1.8.1(001234.567*kWh)`.
+#[derive(Debug, Clone)]
+struct CosemObject {
+    values: Vec<String>,
+}
+
+impl CosemObject {
+    /// Numeric part of the value at `index`, without its `*unit` suffix.
+    fn number(&self, index: usize) -> Option<f64> {
+        let value = self.values.get(index)?;
+        value.split('*').next()?.parse().ok()
+    }
+
+    fn unit(&self, index: usize) -> Option<&str> {
+        self.values.get(index)?.split_once('*').map(|(_, u)| u)
+    }
+
+    fn text(&self, index: usize) -> Option<&str> {
+        self.values.get(index).map(String::as_str)
+    }
+}
+
+/// A parsed telegram: header line plus COSEM objects keyed by OBIS code.
+#[derive(Debug, Clone)]
+pub struct Telegram {
+    header: String,
+    objects: HashMap<String, CosemObject>,
+}
+
+impl Telegram {
+    pub fn parse(raw: &str) -> Result<Self, TelegramError> {
+        verify_crc(raw)?;
+
+        let mut lines = raw.lines().map(str::trim).filter(|l| !l.is_empty());
+        let header = lines
+            .next()
+            .and_then(|l| l.strip_prefix('/'))
+            .ok_or(TelegramError::MissingHeader)?
+            .to_string();
+
+        let mut objects = HashMap::new();
+        for line in lines {
+            if line.starts_with('!') {
+                break;
+            }
+            let Some(open) = line.find('(') else {
+                continue;
+            };
+            let obis = line[..open].to_string();
+            let values = line[open..]
+                .split(')')
+                .filter_map(|v| v.strip_prefix('('))
+                .map(str::to_string)
+                .collect();
+            objects.insert(obis, CosemObject { values });
+        }
+
+        Ok(Self { header, objects })
+    }
+
+    fn number(&self, obis: &str) -> Option<f64> {
+        self.objects.get(obis).and_then(|o| o.number(0))
+    }
+
+    fn text(&self, obis: &str) -> Option<&str> {
+        self.objects.get(obis).and_then(|o| o.text(0))
+    }
+
+    /// Power in watts from a `kW` delivered/returned OBIS pair.
+    fn net_power_w(&self, delivered: &str, returned: &str) -> f64 {
+        (self.number(delivered).unwrap_or_default() - self.number(returned).unwrap_or_default())
+            * 1_000.0
+    }
+
+    /// Reading of the M-Bus devices on channels 1-4.
+    fn mbus_devices(&self) -> Vec<ExternalSensor> {
+        (1..=4)
+            .filter_map(|channel| {
+                let device_type = self.number(&format!("0-{channel}:24.1.0"))? as u32;
+                let sensor_type = match device_type {
+                    3 => "gas_meter",
+                    4 => "heat_meter",
+                    6 => "warm_water_meter",
+                    7 => "water_meter",
+                    _ => return None,
+                };
+                let reading = self.objects.get(&format!("0-{channel}:24.2.1"))?;
+                Some(ExternalSensor {
+                    unique_id: self
+                        .text(&format!("0-{channel}:96.1.0"))
+                        .unwrap_or_default()
+                        .to_string(),
+                    sensor_type: sensor_type.to_string(),
+                    timestamp: dsmr_timestamp(reading.text(0)?)?,
+                    value: reading.number(1)?,
+                    unit: reading.unit(1).unwrap_or_default().to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Entries of the long power failure log: `1-0:99.97.0(count)(OBIS)`
+    /// followed by an `(end)(duration*s)` pair per failure.
+    fn power_failure_log(&self) -> Vec<PowerFailure> {
+        let Some(log) = self.objects.get("1-0:99.97.0") else {
+            return Vec::new();
+        };
+        let count = log.number(0).unwrap_or_default() as usize;
+        (0..count)
+            .filter_map(|i| {
+                Some(PowerFailure {
+                    end_timestamp: dsmr_timestamp(log.text(2 + 2 * i)?)?,
+                    duration_s: log.number(3 + 2 * i)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Maps the telegram onto the same data model as the JSON API. Fields
+    /// the telegram does not carry (Wi-Fi) are left at their defaults.
+    pub fn to_data(&self) -> Result<HomeWizardData, TelegramError> {
+        let import_t1 = self
+            .number("1-0:1.8.1")
+            .ok_or(TelegramError::MissingField("import T1"))?;
+        let import_t2 = self.number("1-0:1.8.2");
+        let import_t3 = self.number("1-0:1.8.3");
+        let import_t4 = self.number("1-0:1.8.4");
+        let export_t1 = self.number("1-0:2.8.1");
+        let export_t2 = self.number("1-0:2.8.2");
+        let export_t3 = self.number("1-0:2.8.3");
+        let export_t4 = self.number("1-0:2.8.4");
+        let total = |tariffs: [Option<f64>; 4]| tariffs.into_iter().flatten().sum::<f64>();
+
+        let current_l1 = self.number("1-0:31.7.0").unwrap_or_default();
+        let current_l2 = self.number("1-0:51.7.0").unwrap_or_default();
+        let current_l3 = self.number("1-0:71.7.0").unwrap_or_default();
+
+        let external = self.mbus_devices();
+        let gas = external.iter().find(|s| s.sensor_type == "gas_meter");
+
+        Ok(HomeWizardData {
+            smr_version: self
+                .number("1-3:0.2.8")
+                .or_else(|| self.number("0-0:96.1.4"))
+                .unwrap_or_default() as i32,
+            meter_model: self.header.clone(),
+            unique_id: self.text("0-0:96.1.1").unwrap_or_default().to_string(),
+            active_tariff: self.number("0-0:96.14.0").unwrap_or_default() as i32,
+            total_power_import_kwh: total([Some(import_t1), import_t2, import_t3, import_t4]),
+            total_power_import_t1_kwh: Some(import_t1),
+            total_power_import_t2_kwh: import_t2,
+            total_power_import_t3_kwh: import_t3,
+            total_power_import_t4_kwh: import_t4,
+            total_power_export_kwh: total([export_t1, export_t2, export_t3, export_t4]),
+            total_power_export_t1_kwh: export_t1,
+            total_power_export_t2_kwh: export_t2,
+            total_power_export_t3_kwh: export_t3,
+            total_power_export_t4_kwh: export_t4,
+            active_power_w: self.net_power_w("1-0:1.7.0", "1-0:2.7.0"),
+            active_power_l1_w: self.net_power_w("1-0:21.7.0", "1-0:22.7.0"),
+            active_power_l2_w: self.net_power_w("1-0:41.7.0", "1-0:42.7.0"),
+            active_power_l3_w: self.net_power_w("1-0:61.7.0", "1-0:62.7.0"),
+            active_voltage_l1_v: self.number("1-0:32.7.0").unwrap_or_default(),
+            active_voltage_l2_v: self.number("1-0:52.7.0").unwrap_or_default(),
+            active_voltage_l3_v: self.number("1-0:72.7.0").unwrap_or_default(),
+            active_current_a: current_l1 + current_l2 + current_l3,
+            active_current_l1_a: current_l1,
+            active_current_l2_a: current_l2,
+            active_current_l3_a: current_l3,
+            voltage_sag_l1_count: self.number("1-0:32.32.0").unwrap_or_default(),
+            voltage_sag_l2_count: self.number("1-0:52.32.0").unwrap_or_default(),
+            voltage_sag_l3_count: self.number("1-0:72.32.0").unwrap_or_default(),
+            voltage_swell_l1_count: self.number("1-0:32.36.0").unwrap_or_default(),
+            voltage_swell_l2_count: self.number("1-0:52.36.0").unwrap_or_default(),
+            voltage_swell_l3_count: self.number("1-0:72.36.0").unwrap_or_default(),
+            any_power_fail_count: self.number("0-0:96.7.21").unwrap_or_default(),
+            long_power_fail_count: self.number("0-0:96.7.9").unwrap_or_default(),
+            total_gas_m3: gas.map(|g| g.value).unwrap_or_default(),
+            gas_timestamp: gas.map(|g| g.timestamp).unwrap_or_default(),
+            gas_unique_id: gas.map(|g| g.unique_id.clone()).unwrap_or_default(),
+            external,
+            power_failure_log: self.power_failure_log(),
+            meter_time: self.text("0-0:1.0.0").and_then(dsmr_unix_time),
+            ..HomeWizardData::default()
+        })
+    }
+}
+
+/// DSMR timestamps are `YYMMDDhhmmss` followed by `S` (summer) or `W`
+/// (winter). The JSON API reports them as the bare number, so do the same.
+fn dsmr_timestamp(value: &str) -> Option<i64> {
+    value.trim_end_matches(['S', 'W']).parse().ok()
+}
+
+/// Converts a DSMR timestamp to Unix time. Meters report local time with
+/// `W` for winter (UTC+1) and `S` for summer (UTC+2) time.
+fn dsmr_unix_time(value: &str) -> Option<i64> {
+    let (digits, offset_hours) = match value.strip_suffix('S') {
+        Some(digits) => (digits, 2),
+        None => (value.strip_suffix('W')?, 1),
+    };
+    let local = chrono::NaiveDateTime::parse_from_str(digits, "%y%m%d%H%M%S").ok()?;
+    Some(local.and_utc().timestamp() - offset_hours * 3600)
+}
+
+/// Checks the CRC16 trailer (`!XXXX`) when present. DSMR 2/3 telegrams
+/// have no CRC and are accepted as-is.
+fn verify_crc(raw: &str) -> Result<(), TelegramError> {
+    let Some(bang) = raw.rfind('!') else {
+        return Ok(());
+    };
+    let trailer = raw[bang + 1..].trim();
+    if trailer.is_empty() {
+        return Ok(());
+    }
+    let Ok(expected) = u16::from_str_radix(trailer, 16) else {
+        return Ok(());
+    };
+
+    let start = raw.find('/').unwrap_or(0);
+    let computed = crc16(&raw.as_bytes()[start..=bang]);
+    if computed != expected {
+        return Err(TelegramError::CrcMismatch { expected, computed });
+    }
+    Ok(())
+}
+
+/// CRC-16/ARC as specified by DSMR 4+.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TELEGRAM: &str = "/ISK5\\2M550T-1012\r
+\r
+1-3:0.2.8(50)\r
+0-0:1.0.0(241231120000W)\r
+0-0:96.1.1(4530303434303037313331363530363138)\r
+1-0:1.8.1(001234.567*kWh)\r
+1-0:1.8.2(000987.654*kWh)\r
+1-0:2.8.1(000012.345*kWh)\r
+1-0:2.8.2(000023.456*kWh)\r
+0-0:96.14.0(0002)\r
+1-0:1.7.0(01.193*kW)\r
+1-0:2.7.0(00.000*kW)\r
+0-0:96.7.21(00005)\r
+0-0:96.7.9(00002)\r
+1-0:99.97.0(1)(0-0:96.7.19)(241101101500W)(0000000240*s)\r
+1-0:32.32.0(00003)\r
+1-0:32.36.0(00001)\r
+1-0:32.7.0(231.2*V)\r
+1-0:31.7.0(005*A)\r
+1-0:21.7.0(01.193*kW)\r
+1-0:22.7.0(00.000*kW)\r
+0-1:24.1.0(003)\r
+0-1:96.1.0(4730303339303031363532303530323136)\r
+0-1:24.2.1(241231115500W)(01234.567*m3)\r
+!";
+
+    fn with_crc(body: &str) -> String {
+        format!("{body}{:04X}\r\n", crc16(body.as_bytes()))
+    }
+
+    #[test]
+    fn test_parse_telegram_to_data() {
+        let telegram = Telegram::parse(&with_crc(TELEGRAM)).unwrap();
+        let data = telegram.to_data().unwrap();
+
+        assert_eq!(data.meter_model, "ISK5\\2M550T-1012");
+        assert_eq!(data.smr_version, 50);
+        assert_eq!(data.unique_id, "4530303434303037313331363530363138");
+        assert_eq!(data.active_tariff, 2);
+        assert_eq!(data.total_power_import_t1_kwh, Some(1234.567));
+        assert_eq!(data.total_power_import_t2_kwh, Some(987.654));
+        assert!((data.total_power_import_kwh - 2222.221).abs() < 1e-9);
+        assert_eq!(data.total_power_export_t2_kwh, Some(23.456));
+        assert_eq!(data.active_power_w, 1193.0);
+        assert_eq!(data.active_power_l1_w, 1193.0);
+        assert_eq!(data.active_voltage_l1_v, 231.2);
+        assert_eq!(data.active_current_l1_a, 5.0);
+        assert_eq!(data.active_current_a, 5.0);
+        assert_eq!(data.voltage_sag_l1_count, 3.0);
+        assert_eq!(data.voltage_swell_l1_count, 1.0);
+        assert_eq!(data.any_power_fail_count, 5.0);
+        assert_eq!(data.long_power_fail_count, 2.0);
+        assert_eq!(
+            data.power_failure_log,
+            vec![PowerFailure {
+                end_timestamp: 241101101500,
+                duration_s: 240.0
+            }]
+        );
+        // 2024-12-31 12:00:00 CET
+        assert_eq!(data.meter_time, Some(1735642800));
+        assert_eq!(data.total_gas_m3, 1234.567);
+        assert_eq!(data.gas_timestamp, 241231115500);
+        assert_eq!(data.gas_unique_id, "4730303339303031363532303530323136");
+        assert_eq!(data.external.len(), 1);
+        assert_eq!(data.external[0].sensor_type, "gas_meter");
+        assert_eq!(data.external[0].unit, "m3");
+        assert_eq!(data.wifi_ssid, "");
+    }
+
+    #[test]
+    fn test_parse_empty_power_failure_log() {
+        let raw = TELEGRAM.replace(
+            "1-0:99.97.0(1)(0-0:96.7.19)(241101101500W)(0000000240*s)",
+            "1-0:99.97.0(0)(0-0:96.7.19)",
+        );
+        let data = Telegram::parse(&raw).unwrap().to_data().unwrap();
+        assert!(data.power_failure_log.is_empty());
+    }
+
+    #[test]
+    fn test_dsmr_unix_time() {
+        // 2025-07-01 14:30:00 CEST
+        assert_eq!(dsmr_unix_time("250701143000S"), Some(1751373000));
+        assert_eq!(dsmr_unix_time("250701143000"), None);
+        assert_eq!(dsmr_unix_time("garbageW"), None);
+    }
+
+    #[test]
+    fn test_parse_telegram_without_crc() {
+        let telegram = Telegram::parse(TELEGRAM).unwrap();
+        assert!(telegram.to_data().is_ok());
+    }
+
+    #[test]
+    fn test_parse_telegram_crc_mismatch() {
+        let raw = format!("{TELEGRAM}0000\r\n");
+        assert!(matches!(
+            Telegram::parse(&raw),
+            Err(TelegramError::CrcMismatch { expected: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_telegram_without_header() {
+        assert_eq!(
+            Telegram::parse("1-0:1.8.1(001234.567*kWh)\r\n!").unwrap_err(),
+            TelegramError::MissingHeader
+        );
+    }
+
+    #[test]
+    fn test_telegram_without_import_reading() {
+        let telegram = Telegram::parse("/XMX5\r\n\r\n1-3:0.2.8(50)\r\n!").unwrap();
+        assert_eq!(
+            telegram.to_data().unwrap_err(),
+            TelegramError::MissingField("import T1")
+        );
+    }
+
+    #[test]
+    fn test_crc16_reference_value() {
+        // CRC-16/ARC check value
+        assert_eq!(crc16(b"123456789"), 0xBB3D);
+    }
+}
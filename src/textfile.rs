@@ -0,0 +1,60 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Temporary file next to `path`. It must live on the same filesystem for
+/// the rename to be atomic, and must not end in `.prom` so node_exporter's
+/// textfile collector never reads a half-written file.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Replaces `path` with `contents` atomically (write to a temporary file,
+/// then rename over the target).
+pub async fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    let temp = temp_path(path);
+    tokio::fs::write(&temp, contents).await?;
+    if let Err(e) = tokio::fs::rename(&temp, path).await {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_path_is_not_a_prom_file() {
+        let temp = temp_path(Path::new("/var/lib/node_exporter/homewizard.prom"));
+        assert_eq!(
+            temp,
+            Path::new("/var/lib/node_exporter/homewizard.prom.tmp")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_atomic_replaces_file() {
+        let dir = std::env::temp_dir().join(format!("homewizard-textfile-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("homewizard.prom");
+
+        write_atomic(&path, "first 1\n").await.unwrap();
+        write_atomic(&path, "second 2\n").await.unwrap();
+
+        assert_eq!(
+            tokio::fs::read_to_string(&path).await.unwrap(),
+            "second 2\n"
+        );
+        assert!(!temp_path(&path).exists());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_atomic_missing_directory() {
+        let path = Path::new("/nonexistent/homewizard.prom");
+        assert!(write_atomic(path, "metric 1\n").await.is_err());
+    }
+}
@@ -0,0 +1,16 @@
+//! The time zone of calendar-based features (`--timezone`): daily and
+//! monthly rollovers and the meter's DSMR timestamps.
+
+use chrono::{Local, NaiveDate, Utc};
+use chrono_tz::Tz;
+
+/// Zone DSMR meters keep their clock in when `--timezone` is not set.
+pub const METER_TIMEZONE: Tz = chrono_tz::Europe::Amsterdam;
+
+/// Today's date in `timezone`, or in the host's local time when unset.
+pub fn today(timezone: Option<Tz>) -> NaiveDate {
+    match timezone {
+        Some(tz) => Utc::now().with_timezone(&tz).date_naive(),
+        None => Local::now().date_naive(),
+    }
+}
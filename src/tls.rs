@@ -0,0 +1,124 @@
+//! HTTPS for the metrics server (`--tls-cert`/`--tls-key`), optionally
+//! requiring client certificates (`--tls-client-ca`).
+
+use anyhow::{Context, Result, ensure};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Builds the server configuration from a PEM certificate chain and a PEM
+/// private key (PKCS#8, PKCS#1 or SEC1). With `client_ca`, only clients
+/// presenting a certificate issued by one of its CAs can connect.
+pub fn server_config(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<ServerConfig> {
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read TLS certificate {}", cert.display()))?;
+    ensure!(
+        !chain.is_empty(),
+        "No certificate found in {}",
+        cert.display()
+    );
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Failed to read TLS key {}", key.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match client_ca {
+        Some(path) => {
+            let roots = Arc::new(client_roots(path)?);
+            let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider)
+                .build()
+                .with_context(|| format!("Invalid client CA bundle {}", path.display()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(chain, key)
+        .context("TLS certificate and key do not match")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+fn client_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(path)
+        .with_context(|| format!("Failed to read client CA bundle {}", path.display()))?
+    {
+        let cert =
+            cert.with_context(|| format!("Failed to read client CA bundle {}", path.display()))?;
+        roots
+            .add(cert)
+            .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+    }
+    ensure!(
+        !roots.is_empty(),
+        "No CA certificate found in {}",
+        path.display()
+    );
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    struct TempFiles(Vec<PathBuf>);
+
+    impl TempFiles {
+        fn write(&mut self, name: &str, contents: &str) -> PathBuf {
+            let path = std::env::temp_dir().join(format!(
+                "homewizard-tls-{}-{}",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(&path, contents).unwrap();
+            self.0.push(path.clone());
+            path
+        }
+    }
+
+    impl Drop for TempFiles {
+        fn drop(&mut self) {
+            for path in &self.0 {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_server_config_loads_pem_files() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let mut files = TempFiles(Vec::new());
+        let cert = files.write("cert.pem", &certified.cert.pem());
+        let key = files.write("key.pem", &certified.key_pair.serialize_pem());
+
+        let config = server_config(&cert, &key, None).unwrap();
+        assert!(config.alpn_protocols.contains(&b"http/1.1".to_vec()));
+
+        // A key file without a key, and a certificate file without a
+        // certificate, are both rejected
+        assert!(server_config(&cert, &cert, None).is_err());
+        assert!(server_config(&key, &key, None).is_err());
+        assert!(server_config(Path::new("/nonexistent/cert.pem"), &key, None).is_err());
+    }
+
+    #[test]
+    fn test_server_config_with_client_ca() {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let ca = rcgen::generate_simple_self_signed(vec!["scrapers".to_string()]).unwrap();
+        let mut files = TempFiles(Vec::new());
+        let cert = files.write("mtls-cert.pem", &certified.cert.pem());
+        let key = files.write("mtls-key.pem", &certified.key_pair.serialize_pem());
+        let client_ca = files.write("mtls-ca.pem", &ca.cert.pem());
+
+        assert!(server_config(&cert, &key, Some(&client_ca)).is_ok());
+        // A bundle without certificates would reject every client
+        assert!(server_config(&cert, &key, Some(&key)).is_err());
+    }
+}
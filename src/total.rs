@@ -0,0 +1,160 @@
+//! Counters for totals the device reports as absolute readings.
+//!
+//! A [`prometheus::Counter`] can only follow a meter reading with `reset()`
+//! followed by `inc_by()`, which exposes zero to a scrape that lands in
+//! between and looks like a counter reset to `rate()`. These collectors keep
+//! the latest reading and encode it directly as counter samples.
+
+use prometheus::Opts;
+use prometheus::core::{Collector, Desc, Describer};
+use prometheus::proto::{Counter, LabelPair, Metric, MetricFamily, MetricType};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// A counter holding the latest total reported by the device.
+#[derive(Clone)]
+pub struct TotalCounter(TotalCounterVec);
+
+impl TotalCounter {
+    pub fn with_opts(opts: Opts) -> prometheus::Result<Self> {
+        let counter = Self(TotalCounterVec::new(opts, &[])?);
+        counter.set(0.0);
+        Ok(counter)
+    }
+
+    pub fn set(&self, value: f64) {
+        self.0.replace([([] as [&str; 0], value)]);
+    }
+}
+
+impl Collector for TotalCounter {
+    fn desc(&self) -> Vec<&Desc> {
+        self.0.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.0.collect()
+    }
+}
+
+/// A labeled counter holding the latest total of each series. Series are
+/// replaced as a whole, so series that disappear from a reading are dropped
+/// without a window in which the family is empty.
+#[derive(Clone)]
+pub struct TotalCounterVec {
+    desc: Desc,
+    samples: Arc<RwLock<BTreeMap<Vec<String>, f64>>>,
+}
+
+impl TotalCounterVec {
+    pub fn new(opts: Opts, label_names: &[&str]) -> prometheus::Result<Self> {
+        let desc = opts
+            .variable_labels(label_names.iter().map(|name| name.to_string()).collect())
+            .describe()?;
+        Ok(Self {
+            desc,
+            samples: Arc::default(),
+        })
+    }
+
+    /// Replaces all series with `samples`, given as label values in the
+    /// order of the label names, and the total.
+    pub fn replace<I, L, S>(&self, samples: I)
+    where
+        I: IntoIterator<Item = (L, f64)>,
+        L: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let samples = samples
+            .into_iter()
+            .map(|(labels, value)| (labels.into_iter().map(Into::into).collect(), value))
+            .collect();
+        *self.samples.write().unwrap_or_else(|e| e.into_inner()) = samples;
+    }
+
+    fn metric(&self, label_values: &[String], value: f64) -> Metric {
+        let mut labels: Vec<LabelPair> = self
+            .desc
+            .variable_labels
+            .iter()
+            .zip(label_values)
+            .map(|(name, value)| {
+                let mut pair = LabelPair::default();
+                pair.set_name(name.clone());
+                pair.set_value(value.clone());
+                pair
+            })
+            .chain(self.desc.const_label_pairs.iter().cloned())
+            .collect();
+        labels.sort();
+
+        let mut counter = Counter::default();
+        counter.set_value(value);
+        let mut metric = Metric::from_label(labels);
+        metric.set_counter(counter);
+        metric
+    }
+}
+
+impl Collector for TotalCounterVec {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let samples = self.samples.read().unwrap_or_else(|e| e.into_inner());
+        let mut family = MetricFamily::default();
+        family.set_name(self.desc.fq_name.clone());
+        family.set_help(self.desc.help.clone());
+        family.set_field_type(MetricType::COUNTER);
+        family.set_metric(
+            samples
+                .iter()
+                .map(|(labels, value)| self.metric(labels, *value))
+                .collect(),
+        );
+        vec![family]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Encoder, Registry, TextEncoder};
+
+    fn render(registry: &Registry) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn test_total_counter_follows_reading() {
+        let registry = Registry::new();
+        let counter = TotalCounter::with_opts(Opts::new("total_kwh", "Total")).unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+
+        assert!(render(&registry).contains("# TYPE total_kwh counter\ntotal_kwh 0\n"));
+        counter.set(12.5);
+        assert!(render(&registry).contains("total_kwh 12.5\n"));
+    }
+
+    #[test]
+    fn test_total_counter_vec_replaces_series() {
+        let registry = Registry::new();
+        let counter = TotalCounterVec::new(Opts::new("tariff_kwh", "Tariff"), &["tariff"]).unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+
+        counter.replace([(["1"], 10.0), (["2"], 20.0)]);
+        let output = render(&registry);
+        assert!(output.contains(r#"tariff_kwh{tariff="1"} 10"#));
+        assert!(output.contains(r#"tariff_kwh{tariff="2"} 20"#));
+
+        counter.replace([(["1"], 11.0)]);
+        let output = render(&registry);
+        assert!(output.contains(r#"tariff_kwh{tariff="1"} 11"#));
+        assert!(!output.contains(r#"tariff="2""#));
+    }
+}
@@ -0,0 +1,240 @@
+//! Measurements from the HomeWizard API v2 (`/api/measurement`).
+//!
+//! API v2 is served over HTTPS and requires a bearer token. Its field names
+//! differ from `/api/v1/data` and timestamps are ISO 8601 strings, so
+//! readings are mapped into the v1 data model here.
+
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::homewizard::{ExternalSensor, HomeWizardData};
+
+/// Response of `/api/measurement` for a P1 meter. Every field is optional:
+/// the device leaves out what the meter does not report.
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct Measurement {
+    pub protocol_version: i32,
+    pub meter_model: String,
+    pub unique_id: String,
+    /// Meter time without a UTC offset, so it is not mapped to
+    /// `meter_time`
+    pub timestamp: Option<String>,
+    pub tariff: i32,
+    pub energy_import_kwh: f64,
+    pub energy_import_t1_kwh: Option<f64>,
+    pub energy_import_t2_kwh: Option<f64>,
+    pub energy_import_t3_kwh: Option<f64>,
+    pub energy_import_t4_kwh: Option<f64>,
+    pub energy_export_kwh: f64,
+    pub energy_export_t1_kwh: Option<f64>,
+    pub energy_export_t2_kwh: Option<f64>,
+    pub energy_export_t3_kwh: Option<f64>,
+    pub energy_export_t4_kwh: Option<f64>,
+    pub power_w: f64,
+    pub power_l1_w: f64,
+    pub power_l2_w: f64,
+    pub power_l3_w: f64,
+    pub voltage_l1_v: f64,
+    pub voltage_l2_v: f64,
+    pub voltage_l3_v: f64,
+    pub current_a: f64,
+    pub current_l1_a: f64,
+    pub current_l2_a: f64,
+    pub current_l3_a: f64,
+    pub frequency_hz: f64,
+    pub voltage_sag_l1_count: f64,
+    pub voltage_sag_l2_count: f64,
+    pub voltage_sag_l3_count: f64,
+    pub voltage_swell_l1_count: f64,
+    pub voltage_swell_l2_count: f64,
+    pub voltage_swell_l3_count: f64,
+    pub any_power_fail_count: f64,
+    pub long_power_fail_count: f64,
+    pub monthly_power_peak_w: f64,
+    pub monthly_power_peak_timestamp: Option<String>,
+    /// Plug-In Battery only
+    pub state_of_charge_pct: f64,
+    /// Plug-In Battery only
+    pub cycles: f64,
+    pub external: Vec<ExternalMeasurement>,
+    /// Fields not mapped into the data model
+    #[serde(flatten)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
+}
+
+/// An M-Bus device on the P1 port, as reported by API v2.
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct ExternalMeasurement {
+    pub unique_id: String,
+    #[serde(rename = "type")]
+    pub sensor_type: String,
+    pub timestamp: Option<String>,
+    pub value: f64,
+    pub unit: String,
+}
+
+/// Converts an API v2 timestamp (`2024-06-28T14:00:00`) into the DSMR
+/// `YYMMDDhhmmss` number API v1 reports. Unparseable timestamps become 0.
+fn dsmr_timestamp(timestamp: Option<&str>) -> i64 {
+    timestamp
+        .and_then(|t| NaiveDateTime::parse_from_str(t, "%Y-%m-%dT%H:%M:%S").ok())
+        .and_then(|t| t.format("%y%m%d%H%M%S").to_string().parse().ok())
+        .unwrap_or_default()
+}
+
+impl Measurement {
+    /// Maps the measurement into the v1 data model. API v2 only reports gas
+    /// as an external device, so the first gas meter becomes the primary
+    /// one.
+    pub fn into_data(self) -> HomeWizardData {
+        let external: Vec<ExternalSensor> = self
+            .external
+            .into_iter()
+            .map(|sensor| ExternalSensor {
+                timestamp: dsmr_timestamp(sensor.timestamp.as_deref()),
+                unique_id: sensor.unique_id,
+                sensor_type: sensor.sensor_type,
+                value: sensor.value,
+                unit: sensor.unit,
+            })
+            .collect();
+        let gas = external.iter().find(|s| s.sensor_type == "gas_meter");
+
+        HomeWizardData {
+            smr_version: self.protocol_version,
+            meter_model: self.meter_model,
+            unique_id: self.unique_id,
+            active_tariff: self.tariff,
+            total_power_import_kwh: self.energy_import_kwh,
+            total_power_import_t1_kwh: self.energy_import_t1_kwh,
+            total_power_import_t2_kwh: self.energy_import_t2_kwh,
+            total_power_import_t3_kwh: self.energy_import_t3_kwh,
+            total_power_import_t4_kwh: self.energy_import_t4_kwh,
+            total_power_export_kwh: self.energy_export_kwh,
+            total_power_export_t1_kwh: self.energy_export_t1_kwh,
+            total_power_export_t2_kwh: self.energy_export_t2_kwh,
+            total_power_export_t3_kwh: self.energy_export_t3_kwh,
+            total_power_export_t4_kwh: self.energy_export_t4_kwh,
+            active_power_w: self.power_w,
+            active_power_l1_w: self.power_l1_w,
+            active_power_l2_w: self.power_l2_w,
+            active_power_l3_w: self.power_l3_w,
+            active_voltage_l1_v: self.voltage_l1_v,
+            active_voltage_l2_v: self.voltage_l2_v,
+            active_voltage_l3_v: self.voltage_l3_v,
+            active_current_a: self.current_a,
+            active_current_l1_a: self.current_l1_a,
+            active_current_l2_a: self.current_l2_a,
+            active_current_l3_a: self.current_l3_a,
+            active_frequency_hz: self.frequency_hz,
+            voltage_sag_l1_count: self.voltage_sag_l1_count,
+            voltage_sag_l2_count: self.voltage_sag_l2_count,
+            voltage_sag_l3_count: self.voltage_sag_l3_count,
+            voltage_swell_l1_count: self.voltage_swell_l1_count,
+            voltage_swell_l2_count: self.voltage_swell_l2_count,
+            voltage_swell_l3_count: self.voltage_swell_l3_count,
+            any_power_fail_count: self.any_power_fail_count,
+            long_power_fail_count: self.long_power_fail_count,
+            monthly_power_peak_w: self.monthly_power_peak_w,
+            monthly_power_peak_timestamp: dsmr_timestamp(
+                self.monthly_power_peak_timestamp.as_deref(),
+            ),
+            total_gas_m3: gas.map(|g| g.value).unwrap_or_default(),
+            gas_timestamp: gas.map(|g| g.timestamp).unwrap_or_default(),
+            gas_unique_id: gas.map(|g| g.unique_id.clone()).unwrap_or_default(),
+            state_of_charge_pct: self.state_of_charge_pct,
+            battery_cycles: self.cycles,
+            unknown_fields: self.unknown_fields,
+            external,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measurement_into_data() {
+        let measurement: Measurement = serde_json::from_str(
+            r#"{
+                "protocol_version": 50,
+                "meter_model": "ISKRA 2M550T-101",
+                "unique_id": "00112233445566778899AABBCCDDEEFF",
+                "timestamp": "2024-06-28T14:12:34",
+                "tariff": 2,
+                "energy_import_kwh": 13779.338,
+                "energy_import_t1_kwh": 10830.511,
+                "energy_import_t2_kwh": 2948.827,
+                "energy_export_kwh": 1.5,
+                "power_w": -543,
+                "power_l1_w": -676,
+                "voltage_l1_v": 235.4,
+                "current_l1_a": -4,
+                "any_power_fail_count": 4,
+                "monthly_power_peak_w": 1111.0,
+                "monthly_power_peak_timestamp": "2024-06-04T10:11:22",
+                "external": [
+                    {
+                        "unique_id": "4E47475955",
+                        "type": "gas_meter",
+                        "timestamp": "2024-06-28T14:00:00",
+                        "value": 2569.646,
+                        "unit": "m3"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let data = measurement.into_data();
+        assert_eq!(data.smr_version, 50);
+        assert_eq!(data.active_tariff, 2);
+        assert_eq!(data.total_power_import_kwh, 13779.338);
+        assert_eq!(data.total_power_export_kwh, 1.5);
+        assert_eq!(data.active_power_w, -543.0);
+        assert_eq!(data.active_voltage_l1_v, 235.4);
+        assert_eq!(data.active_current_l1_a, -4.0);
+        assert_eq!(data.monthly_power_peak_w, 1111.0);
+        assert_eq!(data.monthly_power_peak_timestamp, 240604101122);
+        assert_eq!(data.total_gas_m3, 2569.646);
+        assert_eq!(data.gas_timestamp, 240628140000);
+        assert_eq!(data.gas_unique_id, "4E47475955");
+        assert_eq!(data.gas_meters().len(), 1);
+        assert!(data.unknown_fields.is_empty());
+    }
+
+    #[test]
+    fn test_battery_measurement_into_data() {
+        let measurement: Measurement = serde_json::from_str(
+            r#"{
+                "energy_import_kwh": 123.456,
+                "energy_export_kwh": 98.765,
+                "power_w": -404,
+                "voltage_l1_v": 230.1,
+                "current_a": 1.76,
+                "frequency_hz": 49.98,
+                "state_of_charge_pct": 42,
+                "cycles": 17
+            }"#,
+        )
+        .unwrap();
+
+        let data = measurement.into_data();
+        assert_eq!(data.state_of_charge_pct, 42.0);
+        assert_eq!(data.battery_cycles, 17.0);
+        assert_eq!(data.active_power_w, -404.0);
+        assert!(data.unknown_fields.is_empty());
+    }
+
+    #[test]
+    fn test_dsmr_timestamp() {
+        assert_eq!(dsmr_timestamp(Some("2024-01-02T03:04:05")), 240102030405);
+        assert_eq!(dsmr_timestamp(Some("yesterday")), 0);
+        assert_eq!(dsmr_timestamp(None), 0);
+    }
+}
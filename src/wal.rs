@@ -0,0 +1,318 @@
+//! On-disk buffer for push sinks (`--wal-dir`). What remote_write, InfluxDB
+//! or MQTT fail to deliver is appended to a file per sink and replayed in
+//! order once the sink answers again, so a short outage does not leave a
+//! hole in remote storage. The file is bounded in size and age; the oldest
+//! records go first.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Undelivered records of one sink, one `<unix ms> <base64>` line each.
+#[derive(Debug)]
+pub struct Wal {
+    path: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+    /// Held while delivering, so records leave in the order they came
+    lock: Mutex<()>,
+}
+
+struct Record {
+    at_ms: i64,
+    data: Vec<u8>,
+}
+
+impl Record {
+    fn line(&self) -> String {
+        format!("{} {}\n", self.at_ms, STANDARD.encode(&self.data))
+    }
+}
+
+impl Wal {
+    /// The buffer of `sink` in `dir`, which is created if missing.
+    pub fn open(dir: &Path, sink: &str, max_bytes: u64, max_age: Duration) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Self {
+            path: dir.join(format!("{sink}.wal")),
+            max_bytes,
+            max_age,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Sends `record` with `send` after every record buffered before it.
+    /// When a send fails, that record and all after it, `record` included,
+    /// stay buffered and the error is returned.
+    pub async fn deliver<F, Fut>(&self, record: Vec<u8>, send: F) -> Result<()>
+    where
+        F: Fn(Vec<u8>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let _delivering = self.lock.lock().await;
+        let mut pending = self.load().await?;
+        if pending.is_empty() {
+            // The common case: nothing buffered, so the disk is not touched
+            let result = send(record.clone()).await;
+            if result.is_err() {
+                self.store(&[Record::now(record)]).await?;
+            }
+            return result;
+        }
+        pending.push(Record::now(record));
+        self.send_pending(pending, send).await
+    }
+
+    /// Sends the buffered records, e.g. once a connection is back.
+    pub async fn replay<F, Fut>(&self, send: F) -> Result<()>
+    where
+        F: Fn(Vec<u8>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let _delivering = self.lock.lock().await;
+        let pending = self.load().await?;
+        if pending.is_empty() {
+            return Ok(());
+        }
+        self.send_pending(pending, send).await
+    }
+
+    async fn send_pending<F, Fut>(&self, pending: Vec<Record>, send: F) -> Result<()>
+    where
+        F: Fn(Vec<u8>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let total = pending.len();
+        let mut sent = 0;
+        let mut result = Ok(());
+        for record in &pending {
+            if let Err(e) = send(record.data.clone()).await {
+                result = Err(e);
+                break;
+            }
+            sent += 1;
+        }
+        self.store(&pending[sent..]).await?;
+        if sent > 0 {
+            info!(
+                "Replayed {} of {} buffered records from {}",
+                sent,
+                total,
+                self.path.display()
+            );
+        }
+        result
+    }
+
+    /// The buffered records younger than the age limit.
+    async fn load(&self) -> Result<Vec<Record>> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.path.display()));
+            }
+        };
+        let oldest = now_ms() - self.max_age.as_millis() as i64;
+        let records: Vec<Record> = contents
+            .lines()
+            .filter_map(|line| {
+                let (at_ms, data) = line.split_once(' ')?;
+                Some(Record {
+                    at_ms: at_ms.parse().ok()?,
+                    data: STANDARD.decode(data).ok()?,
+                })
+            })
+            .collect();
+        let total = records.len();
+        let records: Vec<Record> = records
+            .into_iter()
+            .filter(|record| record.at_ms >= oldest)
+            .collect();
+        if records.len() < total {
+            warn!(
+                "Dropped {} buffered records older than {:?} from {}",
+                total - records.len(),
+                self.max_age,
+                self.path.display()
+            );
+        }
+        Ok(records)
+    }
+
+    /// Replaces the buffer with `records`, dropping the oldest beyond the
+    /// size limit.
+    async fn store(&self, records: &[Record]) -> Result<()> {
+        let lines: Vec<String> = records.iter().map(Record::line).collect();
+        let mut size = 0;
+        let keep = lines
+            .iter()
+            .rev()
+            .take_while(|line| {
+                size += line.len() as u64;
+                size <= self.max_bytes
+            })
+            .count();
+        if keep < lines.len() {
+            warn!(
+                "Dropped {} buffered records over {} bytes from {}",
+                lines.len() - keep,
+                self.max_bytes,
+                self.path.display()
+            );
+        }
+        let lines = &lines[lines.len() - keep..];
+        if lines.is_empty() {
+            return match tokio::fs::remove_file(&self.path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(e).with_context(|| format!("Failed to remove {}", self.path.display()))
+                }
+                _ => Ok(()),
+            };
+        }
+        let temporary = self.path.with_extension("wal.tmp");
+        tokio::fs::write(&temporary, lines.concat())
+            .await
+            .with_context(|| format!("Failed to write {}", temporary.display()))?;
+        tokio::fs::rename(&temporary, &self.path)
+            .await
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+impl Record {
+    fn now(data: Vec<u8>) -> Self {
+        Self {
+            at_ms: now_ms(),
+            data,
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex as StdMutex};
+
+    fn wal(name: &str, max_bytes: u64) -> Wal {
+        let dir =
+            std::env::temp_dir().join(format!("homewizard-wal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Wal::open(&dir, "sink", max_bytes, Duration::from_secs(3600)).unwrap()
+    }
+
+    /// A sink recording what it received, failing while `down` is set.
+    #[derive(Clone, Default)]
+    struct Sink {
+        received: Arc<StdMutex<Vec<String>>>,
+        down: Arc<StdMutex<bool>>,
+    }
+
+    impl Sink {
+        fn send(&self, data: Vec<u8>) -> impl Future<Output = Result<()>> + use<> {
+            let sink = self.clone();
+            async move {
+                anyhow::ensure!(!*sink.down.lock().unwrap(), "sink down");
+                sink.received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(data).unwrap());
+                Ok(())
+            }
+        }
+
+        fn received(&self) -> Vec<String> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_buffers_during_outage_and_replays_in_order() {
+        let wal = wal("order", 1 << 20);
+        let sink = Sink::default();
+
+        wal.deliver(b"1".to_vec(), |data| sink.send(data))
+            .await
+            .unwrap();
+        *sink.down.lock().unwrap() = true;
+        assert!(
+            wal.deliver(b"2".to_vec(), |data| sink.send(data))
+                .await
+                .is_err()
+        );
+        assert!(
+            wal.deliver(b"3".to_vec(), |data| sink.send(data))
+                .await
+                .is_err()
+        );
+        assert!(wal.path.exists());
+
+        *sink.down.lock().unwrap() = false;
+        wal.deliver(b"4".to_vec(), |data| sink.send(data))
+            .await
+            .unwrap();
+        assert_eq!(sink.received(), ["1", "2", "3", "4"]);
+        assert!(!wal.path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_replay_after_reconnect() {
+        let wal = wal("replay", 1 << 20);
+        let sink = Sink::default();
+        *sink.down.lock().unwrap() = true;
+        assert!(
+            wal.deliver(b"1".to_vec(), |data| sink.send(data))
+                .await
+                .is_err()
+        );
+
+        *sink.down.lock().unwrap() = false;
+        wal.replay(|data| sink.send(data)).await.unwrap();
+        assert_eq!(sink.received(), ["1"]);
+    }
+
+    #[tokio::test]
+    async fn test_drops_oldest_records_over_size_limit() {
+        // Each record is a `<ms> <base64>` line of about 20 bytes
+        let wal = wal("size", 50);
+        let sink = Sink::default();
+        *sink.down.lock().unwrap() = true;
+        for record in ["a", "b", "c", "d"] {
+            let _ = wal.deliver(record.into(), |data| sink.send(data)).await;
+        }
+
+        *sink.down.lock().unwrap() = false;
+        wal.replay(|data| sink.send(data)).await.unwrap();
+        assert_eq!(sink.received(), ["c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn test_drops_records_over_age_limit() {
+        let wal = wal("age", 1 << 20);
+        tokio::fs::write(
+            &wal.path,
+            format!("{} {}\n", now_ms() - 7_200_000, STANDARD.encode("old")),
+        )
+        .await
+        .unwrap();
+        let sink = Sink::default();
+
+        wal.deliver(b"new".to_vec(), |data| sink.send(data))
+            .await
+            .unwrap();
+        assert_eq!(sink.received(), ["new"]);
+    }
+}
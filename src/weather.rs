@@ -0,0 +1,222 @@
+//! Outdoor temperatures from Open-Meteo, used to normalize gas consumption
+//! by heating degree days.
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::homewizard::HomeWizardData;
+use crate::http;
+
+/// Daily mean outdoor temperature by local date.
+pub type SharedTemperatures = Arc<Mutex<BTreeMap<NaiveDate, f64>>>;
+
+/// Settings for the degree-day metrics.
+#[derive(Debug, Clone, Default)]
+pub struct DegreeDayOptions {
+    /// Daily mean temperature above which no heating is needed
+    pub base_temperature: f64,
+    pub temperatures: SharedTemperatures,
+}
+
+/// Heating degree days for a day with the given mean temperature.
+pub fn heating_degree_days(base_temperature: f64, mean_temperature: f64) -> f64 {
+    (base_temperature - mean_temperature).max(0.0)
+}
+
+#[derive(Debug, Deserialize)]
+struct ForecastResponse {
+    daily: Daily,
+}
+
+#[derive(Debug, Deserialize)]
+struct Daily {
+    time: Vec<String>,
+    temperature_2m_mean: Vec<Option<f64>>,
+}
+
+/// Client for the Open-Meteo forecast API.
+#[derive(Debug, Clone)]
+pub struct OpenMeteo {
+    client: http::Client,
+    url: String,
+}
+
+impl OpenMeteo {
+    pub fn new(base_url: &str, latitude: f64, longitude: f64, timeout: Duration) -> Result<Self> {
+        let client = http::Client::new(timeout)?;
+        // Yesterday and today, in the location's own timezone.
+        let url = format!(
+            "{}/v1/forecast?latitude={latitude}&longitude={longitude}\
+             &daily=temperature_2m_mean&timezone=auto&past_days=1&forecast_days=1",
+            base_url.trim_end_matches('/')
+        );
+        Ok(Self { client, url })
+    }
+
+    /// Daily mean temperatures for yesterday and today. Today's value is
+    /// partly forecast until the day is over.
+    pub async fn daily_mean_temperatures(&self) -> Result<Vec<(NaiveDate, f64)>> {
+        let body = self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text();
+        let response: ForecastResponse =
+            serde_json::from_str(&body).context("Invalid Open-Meteo response")?;
+
+        Ok(response
+            .daily
+            .time
+            .into_iter()
+            .zip(response.daily.temperature_2m_mean)
+            .filter_map(|(date, mean)| Some((date.parse().ok()?, mean?)))
+            .collect())
+    }
+}
+
+/// Refreshes `temperatures` every `interval`. Failures keep the previous
+/// values.
+pub async fn run(open_meteo: OpenMeteo, temperatures: SharedTemperatures, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match open_meteo.daily_mean_temperatures().await {
+            Ok(days) => {
+                debug!("Fetched daily mean temperatures: {:?}", days);
+                if let Ok(mut temperatures) = temperatures.lock() {
+                    temperatures.extend(days);
+                    // Only yesterday and today are ever looked up.
+                    while temperatures.len() > 2 {
+                        temperatures.pop_first();
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to fetch outdoor temperatures: {:#}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DayStart {
+    day: NaiveDate,
+    gas_m3: f64,
+    /// Tracking began during this day, so its total is incomplete
+    partial: bool,
+}
+
+/// Gas used per local day, from the first reading of the day.
+#[derive(Debug, Default)]
+pub struct DailyGasTracker {
+    today: Option<DayStart>,
+    yesterday: Option<(NaiveDate, f64)>,
+}
+
+impl DailyGasTracker {
+    /// Records a reading and returns the gas used on the last complete day,
+    /// if it was observed from start to end.
+    pub fn update(&mut self, data: &HomeWizardData, day: NaiveDate) -> Option<(NaiveDate, f64)> {
+        match self.today {
+            Some(start) if start.day == day && data.total_gas_m3 >= start.gas_m3 => {}
+            Some(start) if start.day != day => {
+                self.yesterday = (!start.partial && data.total_gas_m3 >= start.gas_m3)
+                    .then_some((start.day, data.total_gas_m3 - start.gas_m3));
+                self.today = Some(DayStart {
+                    day,
+                    gas_m3: data.total_gas_m3,
+                    partial: false,
+                });
+            }
+            // First reading, or the totals dropped after a meter swap.
+            _ => {
+                self.yesterday = None;
+                self.today = Some(DayStart {
+                    day,
+                    gas_m3: data.total_gas_m3,
+                    partial: true,
+                });
+            }
+        }
+        self.yesterday
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 1, d).unwrap()
+    }
+
+    fn reading(gas_m3: f64) -> HomeWizardData {
+        HomeWizardData {
+            total_gas_m3: gas_m3,
+            ..HomeWizardData::default()
+        }
+    }
+
+    #[test]
+    fn test_heating_degree_days() {
+        assert_eq!(heating_degree_days(18.0, 3.5), 14.5);
+        assert_eq!(heating_degree_days(18.0, 21.0), 0.0);
+    }
+
+    #[test]
+    fn test_daily_gas_needs_a_complete_day() {
+        let mut tracker = DailyGasTracker::default();
+
+        // Started mid-day: the first day is not reported.
+        assert_eq!(tracker.update(&reading(100.0), day(1)), None);
+        assert_eq!(tracker.update(&reading(102.0), day(2)), None);
+        assert_eq!(tracker.update(&reading(105.0), day(2)), None);
+        assert_eq!(tracker.update(&reading(108.5), day(3)), Some((day(2), 6.5)));
+        assert_eq!(tracker.update(&reading(109.0), day(3)), Some((day(2), 6.5)));
+    }
+
+    #[test]
+    fn test_daily_gas_meter_swap() {
+        let mut tracker = DailyGasTracker::default();
+        tracker.update(&reading(100.0), day(1));
+        tracker.update(&reading(110.0), day(2));
+        tracker.update(&reading(120.0), day(3));
+
+        assert_eq!(tracker.update(&reading(1.0), day(3)), None);
+        assert_eq!(tracker.update(&reading(2.0), day(4)), None);
+    }
+
+    #[tokio::test]
+    async fn test_open_meteo_daily_means() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/v1/forecast"))
+            .and(query_param("latitude", "52.37"))
+            .and(query_param("daily", "temperature_2m_mean"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "latitude": 52.37,
+                "longitude": 4.9,
+                "daily": {
+                    "time": ["2026-01-01", "2026-01-02"],
+                    "temperature_2m_mean": [3.4, null]
+                }
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let open_meteo =
+            OpenMeteo::new(&mock_server.uri(), 52.37, 4.9, Duration::from_secs(5)).unwrap();
+
+        let days = open_meteo.daily_mean_temperatures().await.unwrap();
+        assert_eq!(days, vec![(day(1), 3.4)]);
+    }
+}